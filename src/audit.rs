@@ -0,0 +1,203 @@
+use crate::state::{self, state_path};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The log is rotated to `<path>.1` once it grows past this size.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// One evaluated push, as recorded in the append-only audit log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Event {
+    pub id: String,
+    pub timestamp: DateTime<Utc>,
+    pub repo: String,
+    pub remote: String,
+    /// Project the remote points at, e.g. "github.com/acme/api".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    /// URL given with `check --remote-url`, which the push was judged by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+    pub branch: String,
+    pub force: bool,
+    /// The force push used --force-with-lease rather than --force.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub force_with_lease: bool,
+    /// The push deleted the branch on the remote.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deletion: bool,
+    /// The push skipped the repo's pre-push hook with --no-verify.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub no_verify: bool,
+    /// "allow", "block", or "skipped"
+    pub decision: String,
+    pub rule: String,
+    /// Raw hook command; omitted when capture is disabled or for `check`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Working directory the hook ran in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Claude session whose hook call made the push.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    /// Subagent of that session that made the push; None for the main agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<state::Agent>,
+}
+
+impl Event {
+    pub fn new(
+        repo: &str,
+        remote: &str,
+        branch: &str,
+        force: bool,
+        decision: &str,
+        rule: &str,
+    ) -> Self {
+        let timestamp = Utc::now();
+        Self {
+            id: format!("{:x}", timestamp.timestamp_nanos_opt().unwrap_or_default()),
+            timestamp,
+            repo: repo.to_string(),
+            remote: remote.to_string(),
+            destination: None,
+            remote_url: None,
+            branch: branch.to_string(),
+            force,
+            force_with_lease: false,
+            deletion: false,
+            no_verify: false,
+            decision: decision.to_string(),
+            rule: rule.to_string(),
+            command: None,
+            cwd: None,
+            session: None,
+            agent: None,
+        }
+    }
+
+    pub fn blocked(&self) -> bool {
+        self.decision == "block"
+    }
+}
+
+pub fn audit_path() -> PathBuf {
+    state_path().with_extension("audit.jsonl")
+}
+
+fn rotated_path() -> PathBuf {
+    let mut p = audit_path().into_os_string();
+    p.push(".1");
+    PathBuf::from(p)
+}
+
+/// Whether raw hook commands are written to the log.
+/// Set PUSH_GUARD_AUDIT_COMMANDS=0 to keep only the decision record.
+pub fn capture_commands() -> bool {
+    !matches!(
+        std::env::var("PUSH_GUARD_AUDIT_COMMANDS").as_deref(),
+        Ok("0") | Ok("false")
+    )
+}
+
+/// Appends an event, rotating the log when it exceeds the size cap.
+pub fn append(event: &Event) -> Result<()> {
+    if !state::writable() {
+        return Ok(());
+    }
+    let path = audit_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+    }
+    if fs::metadata(&path).is_ok_and(|m| m.len() > MAX_LOG_BYTES) {
+        fs::rename(&path, rotated_path()).context("Failed to rotate audit log")?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(event)?)
+        .with_context(|| format!("Failed to write audit log {}", path.display()))
+}
+
+/// Counts the blocks by `rule` of the push `like` describes (same session,
+/// repo, remote and branch) that end `events`, newer than `since`: how many
+/// times in a row that session was just told the same thing. Any other
+/// decision for the push ends the run.
+pub fn block_streak(events: &[Event], like: &Event, since: DateTime<Utc>) -> usize {
+    events
+        .iter()
+        .rev()
+        .filter(|e| {
+            (&e.session, &e.repo, &e.remote, &e.branch)
+                == (&like.session, &like.repo, &like.remote, &like.branch)
+        })
+        .take_while(|e| e.timestamp > since && e.blocked() && e.rule == like.rule)
+        .count()
+}
+
+/// Reads all events, oldest first. Unparseable lines are skipped.
+pub fn read_all() -> Vec<Event> {
+    [rotated_path(), audit_path()]
+        .iter()
+        .filter_map(|p| fs::read_to_string(p).ok())
+        .flat_map(|contents| {
+            contents
+                .lines()
+                .filter_map(|l| serde_json::from_str(l).ok())
+                .collect::<Vec<Event>>()
+        })
+        .collect()
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_round_trips_without_command() {
+        let e = Event::new("/repo", "origin", "main", false, "block", "default_branch");
+        let json = serde_json::to_string(&e).unwrap();
+        assert!(!json.contains("command"));
+        let back: Event = serde_json::from_str(&json).unwrap();
+        assert!(back.blocked());
+        assert_eq!(back.rule, "default_branch");
+    }
+
+    #[test]
+    fn block_streak_counts_the_latest_identical_blocks() {
+        let now = Utc::now();
+        let event = |branch: &str, decision: &str, rule: &str, minutes_ago: i64| Event {
+            timestamp: now - chrono::Duration::minutes(minutes_ago),
+            session: Some("s1".to_string()),
+            ..Event::new("/repo", "origin", branch, false, decision, rule)
+        };
+        let like = event("feature", "block", "untracked", 0);
+        let since = now - chrono::Duration::minutes(10);
+        let mut events = vec![
+            event("feature", "block", "untracked", 30),
+            event("feature", "allow", "authorized", 20),
+            event("feature", "block", "untracked", 5),
+            event("other", "block", "untracked", 4),
+            event("feature", "block", "untracked", 3),
+        ];
+        assert_eq!(block_streak(&events, &like, since), 2);
+        // Blocks by another rule, or outside the window, end the run
+        events.push(event("feature", "block", "force_push", 2));
+        assert_eq!(block_streak(&events, &like, since), 0);
+        events.push(event("feature", "block", "untracked", 1));
+        assert_eq!(block_streak(&events, &like, since), 1);
+        assert_eq!(block_streak(&events, &like, now), 0);
+        let other_session = Event { session: None, ..like };
+        assert_eq!(block_streak(&events, &other_session, since), 0);
+    }
+}
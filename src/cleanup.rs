@@ -0,0 +1,228 @@
+use crate::shorten_home;
+use crate::state::{Entry, Kind, State};
+use crate::timefmt;
+use chrono::Utc;
+use std::collections::BTreeMap;
+
+/// Why `clean` or `prune` removes an entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    /// The repo is no longer on disk, or no longer the repository it was.
+    Stale,
+    /// `clean --repo` was asked to remove all of the repo's entries.
+    Requested,
+    /// An authorization with no uses left.
+    Exhausted,
+    /// An authorization unused since the `--unused-for` cutoff.
+    Unused,
+    /// The local branch was deleted.
+    Gone,
+    /// The branch is merged into its remote's default branch.
+    Merged,
+}
+
+impl Reason {
+    /// The reason code in JSON output and the text renderer.
+    pub fn code(self) -> &'static str {
+        match self {
+            Reason::Stale => "stale",
+            Reason::Requested => "requested",
+            Reason::Exhausted => "exhausted",
+            Reason::Unused => "unused",
+            Reason::Gone => "gone",
+            Reason::Merged => "merged",
+        }
+    }
+}
+
+/// One entry a cleanup removes, or would remove in a dry run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Removal {
+    pub repo: String,
+    /// The list the entry is in; None when the whole repo is removed.
+    pub kind: Option<Kind>,
+    pub branch: Option<String>,
+    pub reason: Reason,
+    /// What the reason means for this entry, e.g. "last used 40d ago".
+    pub detail: String,
+}
+
+impl Removal {
+    /// Removal of every entry of `repo` at once.
+    pub fn repo(repo: &str, reason: Reason, detail: &str) -> Self {
+        Self { repo: repo.to_string(), kind: None, branch: None, reason, detail: detail.into() }
+    }
+
+    /// One removal per entry `branch` has in the `kinds` lists of `repo`.
+    /// Looked up before the entries are removed.
+    pub fn entries(
+        state: &State,
+        repo: &str,
+        branch: &str,
+        kinds: &[Kind],
+        reason: Reason,
+        detail: &str,
+    ) -> Vec<Self> {
+        let lists = [(Kind::Tracked, &state.tracked), (Kind::Authorized, &state.authorized)];
+        lists
+            .into_iter()
+            .filter(|(kind, _)| kinds.contains(kind))
+            .filter(|(_, list)| {
+                list.get(repo).is_some_and(|l| l.iter().any(|e| e.branch == branch))
+            })
+            .map(|(kind, _)| Self {
+                repo: repo.to_string(),
+                kind: Some(kind),
+                branch: Some(branch.to_string()),
+                reason,
+                detail: detail.to_string(),
+            })
+            .collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "repo": self.repo,
+            "kind": match self.kind {
+                Some(Kind::Tracked) => "tracked",
+                Some(Kind::Authorized) => "authorized",
+                None => "repo",
+            },
+            "branch": self.branch,
+            "reason": self.reason.code(),
+            "detail": self.detail,
+        })
+    }
+
+    fn tag(&self) -> &'static str {
+        match self.kind {
+            Some(Kind::Tracked) => "[claude]",
+            Some(Kind::Authorized) => "[authorized]",
+            None => "[repo]",
+        }
+    }
+}
+
+/// Why an authorization counts as unused: when it was last used, or when it
+/// was created if it never was.
+pub fn unused_detail(entry: &Entry) -> String {
+    let now = Utc::now();
+    match (entry.last_used_at, entry.created_at) {
+        (Some(used), _) => format!("last used {}", timefmt::relative(used, now)),
+        (None, Some(created)) => format!("created {}, never used", timefmt::relative(created, now)),
+        (None, None) => "never used".to_string(),
+    }
+}
+
+/// Renders a dry run's `removals` diff-style: the repos in order, each with
+/// one aligned "- [tag] branch  reason: detail" line per removal, then a
+/// count by reason. With `color`, the "- " markers are red.
+pub fn render(removals: &[Removal], color: bool) -> String {
+    if removals.is_empty() {
+        return "Nothing to remove.\n".to_string();
+    }
+    let mut repos: BTreeMap<&str, Vec<&Removal>> = BTreeMap::new();
+    for removal in removals {
+        repos.entry(&removal.repo).or_default().push(removal);
+    }
+    let branch = |r: &Removal| r.branch.clone().unwrap_or_else(|| "(all entries)".to_string());
+    let tag_width = removals.iter().map(|r| r.tag().len()).max().unwrap_or(0);
+    let branch_width = removals.iter().map(|r| branch(r).chars().count()).max().unwrap_or(0);
+    let marker = if color { "\x1b[31m- \x1b[0m" } else { "- " };
+
+    let mut out = String::new();
+    for (repo, removals) in &repos {
+        out.push_str(&format!("{}\n", shorten_home(repo)));
+        for r in removals {
+            out.push_str(&format!(
+                "  {}{:tw$}  {:bw$}  {}: {}\n",
+                marker,
+                r.tag(),
+                branch(r),
+                r.reason.code(),
+                r.detail,
+                tw = tag_width,
+                bw = branch_width,
+            ));
+        }
+    }
+    let mut counts: BTreeMap<Reason, usize> = BTreeMap::new();
+    for removal in removals {
+        *counts.entry(removal.reason).or_default() += 1;
+    }
+    let counts: Vec<String> =
+        counts.iter().map(|(reason, n)| format!("{} {}", n, reason.code())).collect();
+    out.push_str(&format!(
+        "Would remove {} from {} ({})\n",
+        plural(removals.len(), "entry", "entries"),
+        plural(repos.len(), "repo", "repos"),
+        counts.join(", ")
+    ));
+    out
+}
+
+fn plural(n: usize, one: &str, many: &str) -> String {
+    format!("{} {}", n, if n == 1 { one } else { many })
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn removal(repo: &str, kind: Kind, branch: &str, reason: Reason, detail: &str) -> Removal {
+        Removal {
+            repo: repo.to_string(),
+            kind: Some(kind),
+            branch: Some(branch.to_string()),
+            reason,
+            detail: detail.to_string(),
+        }
+    }
+
+    #[test]
+    fn render_groups_and_aligns_a_mixed_dry_run() {
+        let removals = [
+            removal("/repo/b", Kind::Tracked, "old-feature", Reason::Gone, "local branch deleted"),
+            Removal::repo("/repo/gone", Reason::Stale, "no longer exists"),
+            removal("/repo/b", Kind::Authorized, "wip", Reason::Exhausted, "no uses left"),
+            removal("/repo/b", Kind::Tracked, "done", Reason::Merged, "merged into main"),
+            removal("/repo/b", Kind::Authorized, "idle", Reason::Unused, "last used 40d ago"),
+        ];
+        assert_eq!(
+            render(&removals, false),
+            "/repo/b\n\
+             \x20 - [claude]      old-feature    gone: local branch deleted\n\
+             \x20 - [authorized]  wip            exhausted: no uses left\n\
+             \x20 - [claude]      done           merged: merged into main\n\
+             \x20 - [authorized]  idle           unused: last used 40d ago\n\
+             /repo/gone\n\
+             \x20 - [repo]        (all entries)  stale: no longer exists\n\
+             Would remove 5 entries from 2 repos \
+             (1 stale, 1 exhausted, 1 unused, 1 gone, 1 merged)\n"
+        );
+        let colored = render(&removals[1..2], true);
+        assert!(colored.contains("  \x1b[31m- \x1b[0m[repo]  (all entries)  stale"));
+        assert!(colored.ends_with("Would remove 1 entry from 1 repo (1 stale)\n"));
+        assert_eq!(render(&[], false), "Nothing to remove.\n");
+    }
+
+    #[test]
+    fn entries_are_found_in_the_requested_lists() {
+        let mut state = State::default();
+        state.track("/repo", "feature");
+        state.authorize("/repo", "feature", None);
+        let both = [Kind::Tracked, Kind::Authorized];
+        let found = Removal::entries(&state, "/repo", "feature", &both, Reason::Gone, "deleted");
+        let kinds: Vec<_> = found.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, [Some(Kind::Tracked), Some(Kind::Authorized)]);
+        let tracked = [Kind::Tracked];
+        let merged = Removal::entries(&state, "/repo", "feature", &tracked, Reason::Merged, "");
+        assert_eq!(merged.len(), 1);
+        assert!(Removal::entries(&state, "/repo", "other", &both, Reason::Gone, "").is_empty());
+        assert_eq!(found[1].to_json()["kind"], "authorized");
+        let stale = Removal::repo("/r", Reason::Stale, "no longer exists").to_json();
+        assert_eq!((stale["kind"].as_str(), stale["branch"].is_null()), (Some("repo"), true));
+    }
+}
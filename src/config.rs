@@ -0,0 +1,1851 @@
+use crate::state::state_path;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Datelike, FixedOffset, Local, NaiveDateTime, NaiveTime, Utc, Weekday};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Per-repo config file, at the repository root.
+pub const REPO_CONFIG_FILE: &str = ".push-guard.toml";
+
+/// Org policy, user and per-repo settings. All layers share this schema;
+/// list-valued keys are combined across layers.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Branch globs (e.g. `release/*`) that always require authorization,
+    /// even when Claude created the branch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected_branches: Vec<String>,
+    /// Set to false to skip evaluation entirely.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Repositories in which evaluation is skipped, as `config set enabled
+    /// false --repo` records them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_repos: Vec<String>,
+    /// Directory prefixes under which all pushes are allowed without evaluation.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_dirs: Vec<String>,
+    /// Keep blocking force pushes inside trusted directories.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_rule_in_trusted_dirs: Option<bool>,
+    /// Allow --force-with-lease (but never bare --force) to tracked branches
+    /// that are not the remote's default branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_force_with_lease_on_tracked: Option<bool>,
+    /// Branch name prefixes (e.g. `claude/`) whose branches count as created
+    /// by Claude without being tracked, unless protected or the default branch.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_tracked_prefixes: Vec<String>,
+    /// Only count branches as created by Claude within the session that
+    /// created them (or when tracked manually).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_scoped_tracking: Option<bool>,
+    /// Globs of push options (`git push -o ...`) that require authorization.
+    /// Defaults to `merge_request.merge*`; set to an empty list to allow all.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risky_push_options: Option<Vec<String>>,
+    /// Remote URL globs (e.g. `github.com[:/]me/*`) whose non-force pushes are
+    /// allowed regardless of tracked state.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_remote_urls: Vec<String>,
+    /// Remote URL globs that `allow-remote` grants never apply to, so a
+    /// granted remote whose URL is changed to one of them needs no revoking.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected_remote_urls: Vec<String>,
+    /// Remote URL globs never to push to, whatever the branch; no
+    /// authorization overrides them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_remote_urls: Vec<String>,
+    /// Block pushes that GitHub branch protection would reject, looked up
+    /// through the `gh` CLI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub github_protection: Option<bool>,
+    /// Never contact remotes: skip checking remote HEADs and GitHub branch
+    /// protection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offline: Option<bool>,
+    /// Hours a remote's default branch read from `refs/remotes/<remote>/HEAD`
+    /// is trusted before it is checked with `git ls-remote` (default 24; 0
+    /// never checks).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_head_max_age_hours: Option<u32>,
+    /// Run `git remote set-head` when a check finds the remote's HEAD moved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub update_remote_head: Option<bool>,
+    /// Where tracked/authorized entries live: "global" (the state file, default)
+    /// or "repo" (`<gitdir>/push-guard.json` of each repository).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub storage: Option<String>,
+    /// "hostname" keeps the global state file's entries under a key per host,
+    /// so a file synced between machines holds each machine's entries apart;
+    /// "none" (default) stores them flat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_namespace: Option<String>,
+    /// Treat branch names differing only in case as the same branch when
+    /// matching state and the default branch (for case-insensitive filesystems).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_insensitive_branches: Option<bool>,
+    /// Expand git aliases (e.g. `git pushf`) before detecting pushes and branch
+    /// creations (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resolve_git_aliases: Option<bool>,
+    /// Keep blocking force pushes that PUSH_GUARD_ALLOW covers, so that only
+    /// PUSH_GUARD_DISABLE bypasses the force rule (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force_rule_ignores_env_allow: Option<bool>,
+    /// Block pushes while a rebase or merge is unfinished in the repo, unless
+    /// authorized (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_during_rebase_or_merge: Option<bool>,
+    /// Require authorization for pushes whose destination branch is named
+    /// differently from the local branch (`git push origin feature:main`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renamed_push_requires_authorization: Option<bool>,
+    /// Require authorization for pushes made by subagents (e.g. of the Task
+    /// tool), even to branches Claude created.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subagents_require_authorization: Option<bool>,
+    /// Require authorization for pushes with `--no-verify`, which skips the
+    /// repo's pre-push hook.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_no_verify: Option<bool>,
+    /// Allow non-force pushes to remotes whose URL is a local path or
+    /// "file://" URL, even to untracked branches (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_local_remotes: Option<bool>,
+    /// Allow non-force pushes that create a branch the remote does not have
+    /// yet (as of the last fetch), even when untracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allow_new_branch_publish: Option<bool>,
+    /// Prefix of the feature branch names suggested when a push to the default
+    /// branch is blocked (default "claude/").
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch_prefix: Option<String>,
+    /// What to do with pushes whose target branch could not be determined:
+    /// "block" (default) or "allow".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub empty_branch: Option<String>,
+    /// What to do with pushes whose refspecs are only known at run time
+    /// (`"$BRANCH"`, `$(...)`, `--stdin`): "block" (default) or "warn".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic_refspecs: Option<String>,
+    /// Make `check` ask on the terminal whether to allow a blocked push.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interactive: Option<bool>,
+    /// URL that block, authorize and revoke events are POSTed to as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    /// Commands that push indirectly (`make publish`), checked like the push
+    /// they stand for.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command_rules: Vec<CommandRule>,
+    /// Print informational messages, such as why evaluation was skipped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<bool>,
+    /// Print a one-line notice for every allowed push, naming the rule that
+    /// allowed it, so transcripts show pushes that went through.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub announce_allows: Option<bool>,
+    /// Start the hook's block output with a `PUSH_GUARD_BLOCK rule=...` line
+    /// per blocked push, for instructions to key on (default true).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason_codes: Option<bool>,
+    /// Identical blocks of the same push in a row, within
+    /// `repeat_block_window_minutes`, after which the hook prints a terse
+    /// "stop retrying" message instead (default 3; 0 never shortens).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_block_limit: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_block_window_minutes: Option<u32>,
+    /// Report pushes that would be blocked as warnings and let them through,
+    /// e.g. for demos.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub warn_only: Option<bool>,
+    /// Name of the `[profiles]` entry layered over the user config, unless
+    /// PUSH_GUARD_PROFILE names another. Only read from the user config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_profile: Option<String>,
+    /// `[message]` templates replacing the built-in block messages.
+    #[serde(default, skip_serializing_if = "Messages::is_empty")]
+    pub message: Messages,
+    /// `[restricted_hours]` during which pushes to default and protected
+    /// branches are blocked, even when authorized.
+    #[serde(default, skip_serializing_if = "RestrictedHours::is_empty")]
+    pub restricted_hours: RestrictedHours,
+    /// `[remotes."<name or URL glob>"]` settings for pushes to matching remotes.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remotes: BTreeMap<String, RemoteConfig>,
+    /// `[profiles.<name>]` tables of config keys, switched between with
+    /// `active_profile`. Only read from the user config.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profiles: BTreeMap<String, Config>,
+}
+
+/// Settings applying to pushes to one remote, keyed in `[remotes]` by the
+/// remote's name or by a glob of its URL (matched like `trusted_remote_urls`).
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// Branch globs protected on this remote, on top of `protected_branches`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected: Vec<String>,
+}
+
+/// Times at which pushes to default and protected branches are blocked: all
+/// day on `days`, and every day from `after` until midnight and from midnight
+/// until `before`.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct RestrictedHours {
+    /// Weekdays, e.g. "Sat" or "Saturday".
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub days: Vec<String>,
+    /// Time of day as "HH:MM".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<String>,
+    /// "local" (default), "utc", or a UTC offset such as "+02:00".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+}
+
+impl RestrictedHours {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Returns true when `now` falls within the restricted hours.
+    pub fn restricts(&self, now: DateTime<Utc>) -> bool {
+        let Some(local) = self.local_time(now) else {
+            return false;
+        };
+        let time = local.time();
+        self.days.iter().any(|d| d.parse::<Weekday>().is_ok_and(|d| d == local.weekday()))
+            || self.after.as_deref().and_then(parse_time).is_some_and(|t| time >= t)
+            || self.before.as_deref().and_then(parse_time).is_some_and(|t| time < t)
+    }
+
+    /// Returns `now` as the wall-clock time of the configured timezone.
+    fn local_time(&self, now: DateTime<Utc>) -> Option<NaiveDateTime> {
+        match self.timezone.as_deref().unwrap_or("local") {
+            "local" => Some(now.with_timezone(&Local).naive_local()),
+            "utc" | "UTC" => Some(now.naive_utc()),
+            offset => {
+                let offset = offset.parse::<FixedOffset>().ok()?;
+                Some(now.with_timezone(&offset).naive_local())
+            }
+        }
+    }
+
+    /// Describes the restricted hours for block messages, e.g. "on Sat, Sun,
+    /// after 18:00 and before 08:00 (local time)".
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.days.is_empty() {
+            parts.push(format!("on {}", self.days.join(", ")));
+        }
+        let times: Vec<String> = [("after", &self.after), ("before", &self.before)]
+            .into_iter()
+            .filter_map(|(word, t)| t.as_ref().map(|t| format!("{} {}", word, t)))
+            .collect();
+        if !times.is_empty() {
+            parts.push(times.join(" and "));
+        }
+        let zone = match self.timezone.as_deref().unwrap_or("local") {
+            "local" => "local time".to_string(),
+            "utc" | "UTC" => "UTC".to_string(),
+            offset => format!("UTC{}", offset),
+        };
+        format!("{} ({})", parts.join(", "), zone)
+    }
+
+    fn merge(&mut self, over: RestrictedHours) {
+        for day in over.days {
+            if !self.days.contains(&day) {
+                self.days.push(day);
+            }
+        }
+        self.after = over.after.or(self.after.take());
+        self.before = over.before.or(self.before.take());
+        self.timezone = over.timezone.or(self.timezone.take());
+    }
+
+    /// Fails on days, times or timezones that can't be read.
+    fn validate(&self) -> Result<()> {
+        if let Some(day) = self.days.iter().find(|d| d.parse::<Weekday>().is_err()) {
+            bail!("restricted_hours.days: '{}' is not a weekday", day);
+        }
+        for (key, time) in [("after", &self.after), ("before", &self.before)] {
+            if let Some(t) = time.as_deref().filter(|t| parse_time(t).is_none()) {
+                bail!("restricted_hours.{}: '{}' is not a time of day (HH:MM)", key, t);
+            }
+        }
+        match self.timezone.as_deref() {
+            None | Some("local" | "utc" | "UTC") => {}
+            Some(tz) if tz.parse::<FixedOffset>().is_ok() => {}
+            Some(tz) => bail!(
+                "restricted_hours.timezone: '{}' is not \"local\", \"utc\" or an offset \
+                 such as \"+02:00\"",
+                tz
+            ),
+        }
+        Ok(())
+    }
+}
+
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M").ok()
+}
+
+/// Push option pattern that requires authorization unless configured otherwise.
+const DEFAULT_RISKY_PUSH_OPTION: &str = "merge_request.merge*";
+
+/// A `[[command_rules]]` entry: a command glob and the push it stands for.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandRule {
+    /// Glob matched against each simple command, e.g. "make publish*".
+    pub pattern: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// Defaults to the current branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub force: bool,
+    /// "block" to block matching commands outright instead of evaluating the push.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decision: Option<String>,
+    /// Message shown when a "block" rule fires.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CommandRule {
+    pub fn blocks(&self) -> bool {
+        self.decision.as_deref() == Some("block")
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Err(e) = glob::Pattern::new(&self.pattern) {
+            bail!("command rule pattern '{}' is not a valid glob: {}", self.pattern, e);
+        }
+        if self.pattern.trim().is_empty() {
+            bail!("command rule pattern must not be empty");
+        }
+        match self.decision.as_deref() {
+            None | Some("block") => {}
+            Some(other) => bail!(
+                "command rule '{}' has unknown decision '{}' (expected \"block\")",
+                self.pattern,
+                other
+            ),
+        }
+        if self.message.is_some() && !self.blocks() {
+            bail!("command rule '{}' has a message but does not block", self.pattern);
+        }
+        Ok(())
+    }
+}
+
+// ── Message templates ─────────────────────────────────────────────────────────
+
+/// Placeholders every message template may use.
+pub const PLACEHOLDERS: &[&str] = &["branch", "remote", "repo", "default_branch", "authorize_cmd"];
+
+/// Block messages that can be replaced through `[message]`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MessageKind {
+    DefaultBranch,
+    Force,
+    Untracked,
+    /// Protected branches, which need authorization even when tracked.
+    Denied,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 4] = [
+        MessageKind::DefaultBranch,
+        MessageKind::Force,
+        MessageKind::Untracked,
+        MessageKind::Denied,
+    ];
+
+    /// Key in the `[message]` table.
+    pub fn key(self) -> &'static str {
+        match self {
+            MessageKind::DefaultBranch => "default_branch",
+            MessageKind::Force => "force",
+            MessageKind::Untracked => "untracked",
+            MessageKind::Denied => "denied",
+        }
+    }
+
+    /// Placeholders only this message can fill, besides `PLACEHOLDERS`.
+    fn extra_placeholders(self) -> &'static [&'static str] {
+        match self {
+            // The suggested feature branch
+            MessageKind::DefaultBranch => &["suggested"],
+            // The force flag used, and the history-rewrite explanation (or "")
+            MessageKind::Force => &["flag", "context"],
+            MessageKind::Untracked => &[],
+            // The protected pattern the branch matched
+            MessageKind::Denied => &["pattern"],
+        }
+    }
+
+    pub fn default_template(self) -> &'static str {
+        match self {
+            MessageKind::DefaultBranch => {
+                "'{branch}' is the default branch of '{remote}'.\n\
+                 Recommendation: push to a feature branch instead:\n  \
+                 git checkout -b {suggested} && git push -u {remote} {suggested}\n\
+                 To push to '{branch}' directly, say \"I authorize\"."
+            }
+            MessageKind::Force => {
+                "Force push ({flag}) to '{branch}' requires explicit user authorization.\n\
+                 {context}Say \"I authorize\" to proceed."
+            }
+            MessageKind::Untracked => {
+                "Branch '{branch}' was not created by me and has no authorization.\n\
+                 To authorize: say \"authorize push to {branch}\"\n\
+                 To revoke later: push-guard revoke --repo '{repo}' --branch '{branch}'"
+            }
+            MessageKind::Denied => {
+                "'{branch}' matches protected branch pattern '{pattern}'.\n\
+                 Protected branches require authorization even when I created them.\n\
+                 To authorize: say \"authorize push to {branch}\""
+            }
+        }
+    }
+}
+
+/// The `[message]` table. Unset keys use the built-in message.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Messages {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_branch: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub force: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub untracked: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denied: Option<String>,
+}
+
+impl Messages {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn get(&self, kind: MessageKind) -> Option<&String> {
+        match kind {
+            MessageKind::DefaultBranch => self.default_branch.as_ref(),
+            MessageKind::Force => self.force.as_ref(),
+            MessageKind::Untracked => self.untracked.as_ref(),
+            MessageKind::Denied => self.denied.as_ref(),
+        }
+    }
+
+    /// Returns the configured template for `kind`, else the built-in one.
+    pub fn template(&self, kind: MessageKind) -> &str {
+        self.get(kind).map_or(kind.default_template(), String::as_str)
+    }
+
+    /// Every template, with the built-in ones filled in.
+    pub fn effective(&self) -> Messages {
+        let template = |kind| Some(self.template(kind).to_string());
+        Messages {
+            default_branch: template(MessageKind::DefaultBranch),
+            force: template(MessageKind::Force),
+            untracked: template(MessageKind::Untracked),
+            denied: template(MessageKind::Denied),
+        }
+    }
+
+    fn merge(&mut self, over: Messages) {
+        self.default_branch = over.default_branch.or(self.default_branch.take());
+        self.force = over.force.or(self.force.take());
+        self.untracked = over.untracked.or(self.untracked.take());
+        self.denied = over.denied.or(self.denied.take());
+    }
+
+    /// Fails on templates using placeholders their message cannot fill.
+    fn validate(&self) -> Result<()> {
+        for kind in MessageKind::ALL {
+            let Some(template) = self.get(kind) else { continue };
+            let allowed: Vec<&str> =
+                PLACEHOLDERS.iter().chain(kind.extra_placeholders()).copied().collect();
+            for name in placeholders(template)
+                .with_context(|| format!("message.{} is not a valid template", kind.key()))?
+            {
+                if !allowed.contains(&name) {
+                    bail!(
+                        "message.{} uses unknown placeholder '{{{}}}' (expected one of {})",
+                        kind.key(),
+                        name,
+                        allowed.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the placeholder names in `template`. `{{` and `}}` stand for
+/// literal braces.
+fn placeholders(template: &str) -> Result<Vec<&str>> {
+    let mut names = Vec::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        let (brace, after) = (&rest[i..i + 1], &rest[i + 1..]);
+        if after.starts_with(brace) {
+            rest = &after[1..];
+            continue;
+        }
+        if brace == "}" {
+            bail!("unmatched '}}' (write '}}}}' for a literal brace)");
+        }
+        let Some(end) = after.find('}') else {
+            bail!("unclosed '{{' (write '{{{{' for a literal brace)");
+        };
+        names.push(&after[..end]);
+        rest = &after[end + 1..];
+    }
+    Ok(names)
+}
+
+/// Substitutes `{name}` placeholders in a validated `template` with `vars`.
+pub fn render(template: &str, vars: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let (brace, after) = (&rest[i..i + 1], &rest[i + 1..]);
+        if after.starts_with(brace) || brace == "}" {
+            out.push_str(brace);
+            rest = after.strip_prefix(brace).unwrap_or(after);
+            continue;
+        }
+        let end = after.find('}').unwrap_or(after.len());
+        let name = &after[..end];
+        match vars.iter().find(|(n, _)| *n == name) {
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[i..(i + end + 2).min(rest.len())]),
+        }
+        rest = after.get(end + 1..).unwrap_or("");
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Value type of a config key, used to coerce `config set` input.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyKind {
+    Bool,
+    List,
+    /// One of a fixed set of strings.
+    Choice(&'static [&'static str]),
+    /// Any string.
+    Str,
+    /// A whole number.
+    Number,
+}
+
+/// Every settable key and its type.
+pub const KEYS: &[(&str, KeyKind)] = &[
+    ("protected_branches", KeyKind::List),
+    ("enabled", KeyKind::Bool),
+    ("disabled_repos", KeyKind::List),
+    ("trusted_dirs", KeyKind::List),
+    ("force_rule_in_trusted_dirs", KeyKind::Bool),
+    ("allow_force_with_lease_on_tracked", KeyKind::Bool),
+    ("auto_tracked_prefixes", KeyKind::List),
+    ("session_scoped_tracking", KeyKind::Bool),
+    ("trusted_remote_urls", KeyKind::List),
+    ("protected_remote_urls", KeyKind::List),
+    ("denied_remote_urls", KeyKind::List),
+    ("risky_push_options", KeyKind::List),
+    ("github_protection", KeyKind::Bool),
+    ("offline", KeyKind::Bool),
+    ("remote_head_max_age_hours", KeyKind::Number),
+    ("update_remote_head", KeyKind::Bool),
+    ("storage", KeyKind::Choice(&["global", "repo"])),
+    ("state_namespace", KeyKind::Choice(&["none", "hostname"])),
+    ("case_insensitive_branches", KeyKind::Bool),
+    ("resolve_git_aliases", KeyKind::Bool),
+    ("force_rule_ignores_env_allow", KeyKind::Bool),
+    ("block_during_rebase_or_merge", KeyKind::Bool),
+    ("renamed_push_requires_authorization", KeyKind::Bool),
+    ("subagents_require_authorization", KeyKind::Bool),
+    ("block_no_verify", KeyKind::Bool),
+    ("allow_local_remotes", KeyKind::Bool),
+    ("allow_new_branch_publish", KeyKind::Bool),
+    ("branch_prefix", KeyKind::Str),
+    ("empty_branch", KeyKind::Choice(&["block", "allow"])),
+    ("dynamic_refspecs", KeyKind::Choice(&["block", "warn"])),
+    ("interactive", KeyKind::Bool),
+    ("webhook_url", KeyKind::Str),
+    ("verbose", KeyKind::Bool),
+    ("announce_allows", KeyKind::Bool),
+    ("reason_codes", KeyKind::Bool),
+    ("repeat_block_limit", KeyKind::Number),
+    ("repeat_block_window_minutes", KeyKind::Number),
+    ("warn_only", KeyKind::Bool),
+    ("active_profile", KeyKind::Str),
+    ("restricted_hours.days", KeyKind::List),
+    ("restricted_hours.after", KeyKind::Str),
+    ("restricted_hours.before", KeyKind::Str),
+    ("restricted_hours.timezone", KeyKind::Str),
+    ("message.default_branch", KeyKind::Str),
+    ("message.force", KeyKind::Str),
+    ("message.untracked", KeyKind::Str),
+    ("message.denied", KeyKind::Str),
+];
+
+pub fn user_config_path() -> PathBuf {
+    if let Ok(p) = std::env::var("PUSH_GUARD_CONFIG_FILE") {
+        return PathBuf::from(p);
+    }
+    state_path().with_file_name("config.toml")
+}
+
+pub fn repo_config_path(repo: &str) -> PathBuf {
+    Path::new(repo).join(REPO_CONFIG_FILE)
+}
+
+/// Expands a leading `~` and canonicalizes when the path exists.
+fn canonical(path: &str) -> PathBuf {
+    let expanded = match path.strip_prefix("~/") {
+        Some(rest) => dirs::home_dir().unwrap_or_default().join(rest),
+        None => PathBuf::from(path),
+    };
+    fs::canonicalize(&expanded).unwrap_or(expanded)
+}
+
+fn check_key(key: &str) -> Result<KeyKind> {
+    match KEYS.iter().find(|(k, _)| *k == key) {
+        Some((_, kind)) => Ok(*kind),
+        None => match closest_key(key) {
+            Some(near) => bail!("Unknown config key '{}'; did you mean '{}'?", key, near),
+            None => bail!("Unknown config key '{}'", key),
+        },
+    }
+}
+
+/// Returns the known key closest to a mistyped `key`, if any is close enough,
+/// or the first key it abbreviates.
+fn closest_key(key: &str) -> Option<&'static str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut prev = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let substitute = prev + usize::from(ca != *cb);
+                prev = row[j + 1];
+                row[j + 1] = substitute.min(row[j] + 1).min(prev + 1);
+            }
+        }
+        row[b.len()]
+    }
+    KEYS.iter()
+        .map(|(k, _)| (distance(key, k), *k))
+        .filter(|(d, _)| *d <= (key.len() / 3).max(2))
+        .min()
+        .map(|(_, k)| k)
+        .or_else(|| KEYS.iter().map(|(k, _)| *k).find(|k| key.len() >= 4 && k.starts_with(key)))
+}
+
+/// Removes `key` from the config file at `path`. Returns false when it was not set.
+pub fn unset_key(path: &Path, key: &str) -> Result<bool> {
+    check_key(key)?;
+    let mut table = toml::Table::try_from(Config::load_file(path)?)?;
+    let removed = match key.split_once('.') {
+        Some((section, name)) => match table.get_mut(section) {
+            Some(toml::Value::Table(t)) => t.remove(name).is_some(),
+            _ => false,
+        },
+        None => table.remove(key).is_some(),
+    };
+    if removed {
+        let config: Config = table.try_into()?;
+        config.save_file(path)?;
+    }
+    Ok(removed)
+}
+
+/// Parses the `config set` value of the boolean `key`.
+pub fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => bail!("'{}' expects true or false, got '{}'", key, value),
+    }
+}
+
+/// Coerces the `config set` input `value` to the type of `key`.
+fn coerce(key: &str, value: &str) -> Result<toml::Value> {
+    Ok(match check_key(key)? {
+        KeyKind::Bool => toml::Value::Boolean(parse_bool(key, value)?),
+        KeyKind::Choice(choices) => {
+            if !choices.contains(&value) {
+                bail!("'{}' expects one of {}, got '{}'", key, choices.join(", "), value);
+            }
+            toml::Value::String(value.to_string())
+        }
+        KeyKind::Str => toml::Value::String(value.to_string()),
+        KeyKind::Number => match value.parse::<u32>() {
+            Ok(n) => toml::Value::Integer(n.into()),
+            Err(_) => bail!("'{}' expects a whole number, got '{}'", key, value),
+        },
+        KeyKind::List => toml::Value::Array(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .map(|v| toml::Value::String(v.to_string()))
+                .collect(),
+        ),
+    })
+}
+
+/// Sets `key` to the already coerced `value` in `table`.
+fn insert_key(table: &mut toml::Table, key: &str, value: toml::Value) {
+    match key.split_once('.') {
+        Some((section, name)) => {
+            let entry = table
+                .entry(section)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let toml::Value::Table(t) = entry {
+                t.insert(name.to_string(), value);
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+        }
+    }
+}
+
+/// Sets `key` in the config file at `path`, coercing `value` to the key's type.
+pub fn set_key(path: &Path, key: &str, value: &str) -> Result<()> {
+    let value = coerce(key, value)?;
+    let mut table = toml::Table::try_from(Config::load_file(path)?)?;
+    insert_key(&mut table, key, value);
+    let config: Config = table.try_into()?;
+    config.message.validate()?;
+    config.restricted_hours.validate()?;
+    config.save_file(path)
+}
+
+/// Fails when setting `key` to `value` in a repo config would loosen the
+/// policy, which only the user config and the org policy may do.
+pub fn check_repo_key(key: &str, value: &str) -> Result<()> {
+    let mut table = toml::Table::new();
+    insert_key(&mut table, key, coerce(key, value)?);
+    let config: Config = table.try_into()?;
+    if !config.loosening_keys()?.is_empty() {
+        bail!(
+            "'{} = {}' would loosen the policy, which the repo config cannot do; \
+             set it in the user config instead (without --repo)",
+            key,
+            value
+        );
+    }
+    Ok(())
+}
+
+/// Records in the user config whether pushes in `repo` are checked: the
+/// per-repo `enabled` switch, kept out of the repo so it can't turn itself off.
+pub fn set_repo_enabled(repo: &str, enabled: bool) -> Result<()> {
+    let path = user_config_path();
+    let mut config = Config::load_file(&path)?;
+    let repo = canonical(repo);
+    config.disabled_repos.retain(|r| canonical(r) != repo);
+    if !enabled {
+        config.disabled_repos.push(repo.to_string_lossy().into_owned());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+    }
+    config.save_file(&path)
+}
+
+/// Returns the profile PUSH_GUARD_PROFILE selects over `active_profile`, if set.
+pub fn env_profile() -> Option<String> {
+    std::env::var("PUSH_GUARD_PROFILE").ok().filter(|p| !p.is_empty())
+}
+
+/// Returns true when PUSH_GUARD_DISABLE=1 turns off evaluation for this process.
+pub fn env_disabled() -> bool {
+    std::env::var("PUSH_GUARD_DISABLE").is_ok_and(|v| v == "1")
+}
+
+/// Returns the PUSH_GUARD_ALLOW entry that pre-authorizes pushing `branch` in
+/// `repo`, if any.
+pub fn env_allowed(repo: &str, branch: &str) -> Option<String> {
+    let spec = std::env::var("PUSH_GUARD_ALLOW").ok()?;
+    allow_entry(&spec, repo, branch).map(str::to_string)
+}
+
+/// Finds the entry of a comma-separated "repo-path:branch-glob" list matching
+/// `repo` and `branch`. Repo paths are compared canonicalized.
+fn allow_entry<'a>(spec: &'a str, repo: &str, branch: &str) -> Option<&'a str> {
+    let repo = canonical(repo);
+    spec.split(',').map(str::trim).find(|entry| {
+        // Branch names cannot contain ':', so the last one separates the parts
+        entry.rsplit_once(':').is_some_and(|(r, b)| {
+            !r.is_empty() && canonical(r) == repo && glob_matches(b, branch)
+        })
+    })
+}
+
+/// Returns true when the remote URL `url` matches the glob `pattern`, either
+/// as a whole or, for patterns leaving out the scheme and user
+/// ("github.com[:/]me/*"), from the start of its host.
+fn url_matches(pattern: &str, url: &str) -> bool {
+    glob_matches(pattern, url)
+        || split_url(url).is_some_and(|(host, path)| {
+            [':', '/'].iter().any(|sep| glob_matches(pattern, &format!("{}{}{}", host, sep, path)))
+        })
+}
+
+/// Splits a remote URL into its host, lowercased and without user, port or
+/// the trailing dot of a fully qualified name, and the path after it: "ssh://git@Host:22/a/b" and "git@host:a/b" both give
+/// ("host", "a/b"). Local paths and `file://` URLs have no host.
+pub fn split_url(url: &str) -> Option<(String, String)> {
+    let (authority, path) = match url.split_once("://") {
+        Some((scheme, _)) if scheme.eq_ignore_ascii_case("file") => return None,
+        Some((_, rest)) => rest.split_once('/').unwrap_or((rest, "")),
+        // scp-like syntax has a colon before any slash
+        None => url.split_once(':').filter(|(authority, _)| !authority.contains('/'))?,
+    };
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']')?.0,
+        None => host.split_once(':').map_or(host, |(host, _)| host),
+    };
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.is_empty() {
+        return None;
+    }
+    Some((host.to_ascii_lowercase(), path.trim_start_matches('/').to_string()))
+}
+
+/// Modification times of the user and repo config files a config was read from.
+type Stamps = (Option<SystemTime>, Option<SystemTime>);
+type LoadKey = (Option<PathBuf>, PathBuf, Option<PathBuf>, Option<String>);
+type LoadCache = HashMap<LoadKey, (Stamps, Config)>;
+
+thread_local! {
+    /// Configs returned by `Config::load`, keyed by the org policy, user and
+    /// repo config paths and the profile selected by the environment.
+    static LOADED: RefCell<LoadCache> = RefCell::new(HashMap::new());
+    /// Org policies read by `load_policy`, keyed by path.
+    static POLICIES: RefCell<HashMap<PathBuf, Config>> = RefCell::new(HashMap::new());
+}
+
+/// Returns the org-wide policy file PUSH_GUARD_POLICY_FILE names, if set.
+pub fn policy_path() -> Option<PathBuf> {
+    std::env::var_os("PUSH_GUARD_POLICY_FILE").filter(|p| !p.is_empty()).map(PathBuf::from)
+}
+
+/// Reads the org policy at `path`, the config layer below the user config.
+/// Each file is read once per process. A missing or invalid policy is
+/// reported once and counts as empty, so a broken shared file never stops
+/// the hook; profiles in it are ignored.
+pub fn load_policy(path: &Path) -> Config {
+    if let Some(policy) = POLICIES.with(|cache| cache.borrow().get(path).cloned()) {
+        return policy;
+    }
+    let policy = if !path.exists() {
+        eprintln!(
+            "push-guard: warning: org policy {} (PUSH_GUARD_POLICY_FILE) does not exist; \
+             ignoring it",
+            path.display()
+        );
+        Config::default()
+    } else {
+        match Config::load_file(path) {
+            Ok(policy) => Config { profiles: BTreeMap::new(), active_profile: None, ..policy },
+            Err(e) => {
+                eprintln!(
+                    "push-guard: warning: ignoring the org policy and using the built-in \
+                     defaults instead: {:#}",
+                    e
+                );
+                Config::default()
+            }
+        }
+    };
+    POLICIES.with(|cache| cache.borrow_mut().insert(path.to_path_buf(), policy.clone()));
+    policy
+}
+
+/// Returns true when `text` matches the glob `pattern` (`*`, `?`, `[...]`).
+/// Invalid patterns never match.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    glob::Pattern::new(pattern).is_ok_and(|p| p.matches(text))
+}
+
+impl Config {
+    pub fn load_file(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config from {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("Invalid config in {}", path.display()))?;
+        config.validate().with_context(|| format!("Invalid config in {}", path.display()))?;
+        for (name, profile) in &config.profiles {
+            if !profile.profiles.is_empty() || profile.active_profile.is_some() {
+                bail!(
+                    "Invalid config in {}: profile '{}' cannot define or select profiles",
+                    path.display(),
+                    name
+                );
+            }
+            profile.validate().with_context(|| {
+                format!("Invalid config in {}, profile '{}'", path.display(), name)
+            })?;
+        }
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for rule in &self.command_rules {
+            rule.validate()?;
+        }
+        self.message.validate()?;
+        self.restricted_hours.validate()
+    }
+
+    pub fn save_file(&self, path: &Path) -> Result<()> {
+        let contents = toml::to_string_pretty(self)?;
+        LOADED.with(|cache| cache.borrow_mut().clear());
+        fs::write(path, contents)
+            .with_context(|| format!("Failed to write config to {}", path.display()))
+    }
+
+    /// Loads the org policy, if PUSH_GUARD_POLICY_FILE names one, layered with
+    /// the user config and its active profile and then with what tightens the
+    /// policy in the config of `repo`, if given (see `tighten`). Results are
+    /// cached per process until the user or repo file changes, since a single
+    /// hook call consults the config many times.
+    pub fn load(repo: Option<&str>) -> Result<Self> {
+        let policy = policy_path();
+        let user = user_config_path();
+        let repo_path = repo.map(repo_config_path);
+        let profile = env_profile();
+        let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+        let stamps = (modified(&user), repo_path.as_deref().and_then(modified));
+        let key = (policy.clone(), user.clone(), repo_path.clone(), profile.clone());
+        let cached = LOADED.with(|cache| {
+            cache.borrow().get(&key).filter(|(s, _)| *s == stamps).map(|(_, c)| c.clone())
+        });
+        if let Some(config) = cached {
+            return Ok(config);
+        }
+        let mut config =
+            Self::load_layers(policy.as_deref(), &user, repo_path.as_deref(), profile)?;
+        if repo.is_some_and(|r| config.disables(r)) {
+            config.enabled = Some(false);
+        }
+        LOADED.with(|cache| cache.borrow_mut().insert(key, (stamps, config.clone())));
+        Ok(config)
+    }
+
+    /// Layers the config files `load` reads, lowest first. A repo config that
+    /// can't be read is reported and ignored, like an invalid org policy.
+    fn load_layers(
+        policy: Option<&Path>,
+        user: &Path,
+        repo: Option<&Path>,
+        profile: Option<String>,
+    ) -> Result<Self> {
+        let mut config = policy.map(load_policy).unwrap_or_default();
+        let mut user_config = Self::load_file(user)?;
+        user_config
+            .apply_profile(profile)
+            .with_context(|| format!("Invalid config in {}", user.display()))?;
+        let profiles = std::mem::take(&mut user_config.profiles);
+        let active_profile = user_config.active_profile.clone();
+        config.merge(user_config);
+        config.profiles = profiles;
+        config.active_profile = active_profile;
+        if let Some(path) = repo {
+            match Self::load_file(path) {
+                Ok(repo_config) => {
+                    // Unlike the other ignored keys, this one looks harmless to set here
+                    if repo_config.case_insensitive_branches.is_some() {
+                        eprintln!(
+                            "push-guard: warning: ignoring case_insensitive_branches in the repo \
+                             config: it would let tracked and authorized branches match pushes \
+                             to other branches; set it in the user config instead"
+                        );
+                    }
+                    config.tighten(repo_config)
+                }
+                Err(e) => eprintln!("push-guard: warning: ignoring the repo config: {:#}", e),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Layers the repo config `repo` over this config, keeping only what
+    /// tightens the policy: the file is part of the checkout, so whoever can
+    /// commit to it, Claude included, could otherwise switch the checks off.
+    /// Patterns, command rules and templates are added, switches count only
+    /// when set to their stricter value, and keys that loosen the policy
+    /// (`enabled`, `trusted_dirs`, `warn_only`, ...) are ignored.
+    fn tighten(&mut self, repo: Config) {
+        fn strict(value: Option<bool>, stricter: bool) -> Option<bool> {
+            value.filter(|v| *v == stricter)
+        }
+        // Listing options replaces the default ones, so add to those instead
+        if repo.risky_push_options.is_some() && self.risky_push_options.is_none() {
+            self.risky_push_options = Some(vec![DEFAULT_RISKY_PUSH_OPTION.to_string()]);
+        }
+        // Hours already restricted are not narrowed
+        let restricted_hours = match self.restricted_hours.is_empty() {
+            true => repo.restricted_hours,
+            false => RestrictedHours::default(),
+        };
+        self.merge(Config {
+            protected_branches: repo.protected_branches,
+            protected_remote_urls: repo.protected_remote_urls,
+            denied_remote_urls: repo.denied_remote_urls,
+            risky_push_options: repo.risky_push_options,
+            command_rules: repo.command_rules,
+            remotes: repo.remotes,
+            message: repo.message,
+            restricted_hours,
+            force_rule_in_trusted_dirs: strict(repo.force_rule_in_trusted_dirs, true),
+            session_scoped_tracking: strict(repo.session_scoped_tracking, true),
+            github_protection: strict(repo.github_protection, true),
+            force_rule_ignores_env_allow: strict(repo.force_rule_ignores_env_allow, true),
+            block_during_rebase_or_merge: strict(repo.block_during_rebase_or_merge, true),
+            renamed_push_requires_authorization: strict(
+                repo.renamed_push_requires_authorization,
+                true,
+            ),
+            subagents_require_authorization: strict(repo.subagents_require_authorization, true),
+            block_no_verify: strict(repo.block_no_verify, true),
+            allow_force_with_lease_on_tracked: strict(
+                repo.allow_force_with_lease_on_tracked,
+                false,
+            ),
+            allow_local_remotes: strict(repo.allow_local_remotes, false),
+            allow_new_branch_publish: strict(repo.allow_new_branch_publish, false),
+            warn_only: strict(repo.warn_only, false),
+            empty_branch: repo.empty_branch.filter(|v| v == "block"),
+            dynamic_refspecs: repo.dynamic_refspecs.filter(|v| v == "block"),
+            // Where entries are kept and how messages read don't loosen anything
+            storage: repo.storage,
+            branch_prefix: repo.branch_prefix,
+            verbose: repo.verbose,
+            announce_allows: repo.announce_allows,
+            reason_codes: repo.reason_codes,
+            ..Default::default()
+        });
+    }
+
+    /// Returns the keys set in this repo config that `tighten` ignores.
+    pub fn loosening_keys(&self) -> Result<Vec<String>> {
+        let mut kept = Config::default();
+        kept.tighten(self.clone());
+        let kept = kept.keys()?;
+        Ok(self.keys()?.into_iter().filter(|k| !kept.contains(k)).collect())
+    }
+
+    /// Returns each key set in any config layer `load(repo)` reads with the
+    /// layers setting it, lowest first: "org policy", "user", "profile
+    /// <name>" and "repo", whose ignored keys are left out. Single values
+    /// come from the last of them.
+    pub fn sources(repo: Option<&str>) -> Result<BTreeMap<String, Vec<String>>> {
+        let mut layers: Vec<(String, Config)> = Vec::new();
+        if let Some(path) = policy_path() {
+            layers.push(("org policy".to_string(), load_policy(&path)));
+        }
+        let mut user = Self::load_file(&user_config_path())?;
+        let profile = match env_profile().or_else(|| user.active_profile.clone()) {
+            Some(name) => Some((format!("profile {}", name), user.profile(&name)?.clone())),
+            None => None,
+        };
+        user.profiles.clear();
+        layers.push(("user".to_string(), user));
+        layers.extend(profile);
+        if let Some(r) = repo {
+            if let Ok(repo_config) = Self::load_file(&repo_config_path(r)) {
+                let mut kept = Config::default();
+                kept.tighten(repo_config);
+                layers.push(("repo".to_string(), kept));
+            }
+        }
+        let mut sources: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (layer, config) in &layers {
+            for key in config.keys()? {
+                sources.entry(key).or_default().push(layer.clone());
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Returns the profile called `name`.
+    pub fn profile(&self, name: &str) -> Result<&Config> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile),
+            None if self.profiles.is_empty() => {
+                bail!("Unknown profile '{}'; no [profiles] are defined", name)
+            }
+            None => {
+                let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                bail!("Unknown profile '{}'; defined profiles: {}", name, names.join(", "))
+            }
+        }
+    }
+
+    /// Layers the profile `selected` (or else `active_profile`) over this
+    /// config and records it as the active one.
+    fn apply_profile(&mut self, selected: Option<String>) -> Result<()> {
+        let Some(name) = selected.or_else(|| self.active_profile.clone()) else {
+            return Ok(());
+        };
+        let profile = self.profile(&name)?.clone();
+        self.merge(profile);
+        self.active_profile = Some(name);
+        Ok(())
+    }
+
+    /// Returns the keys set in this config, with those of sections such as
+    /// `[restricted_hours]` as `section.key`.
+    pub fn keys(&self) -> Result<Vec<String>> {
+        let table = toml::Table::try_from(self)?;
+        let mut keys = Vec::new();
+        for (key, value) in table {
+            match value {
+                toml::Value::Table(section) => {
+                    keys.extend(section.keys().map(|k| format!("{}.{}", key, k)));
+                }
+                _ => keys.push(key),
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Layers `over` on top of this config: lists are combined, scalars set in
+    /// `over` win. Profiles are not layered.
+    pub fn merge(&mut self, over: Config) {
+        fn union(into: &mut Vec<String>, from: Vec<String>) {
+            for v in from {
+                if !into.contains(&v) {
+                    into.push(v);
+                }
+            }
+        }
+        union(&mut self.protected_branches, over.protected_branches);
+        union(&mut self.disabled_repos, over.disabled_repos);
+        union(&mut self.trusted_dirs, over.trusted_dirs);
+        union(&mut self.trusted_remote_urls, over.trusted_remote_urls);
+        union(&mut self.protected_remote_urls, over.protected_remote_urls);
+        union(&mut self.denied_remote_urls, over.denied_remote_urls);
+        union(&mut self.auto_tracked_prefixes, over.auto_tracked_prefixes);
+        if let Some(over_options) = over.risky_push_options {
+            union(self.risky_push_options.get_or_insert_with(Vec::new), over_options);
+        }
+        for rule in over.command_rules {
+            if !self.command_rules.contains(&rule) {
+                self.command_rules.push(rule);
+            }
+        }
+        self.enabled = over.enabled.or(self.enabled);
+        self.force_rule_in_trusted_dirs =
+            over.force_rule_in_trusted_dirs.or(self.force_rule_in_trusted_dirs);
+        self.allow_force_with_lease_on_tracked = over
+            .allow_force_with_lease_on_tracked
+            .or(self.allow_force_with_lease_on_tracked);
+        self.session_scoped_tracking =
+            over.session_scoped_tracking.or(self.session_scoped_tracking);
+        self.github_protection = over.github_protection.or(self.github_protection);
+        self.offline = over.offline.or(self.offline);
+        self.remote_head_max_age_hours =
+            over.remote_head_max_age_hours.or(self.remote_head_max_age_hours);
+        self.update_remote_head = over.update_remote_head.or(self.update_remote_head);
+        if over.storage.is_some() {
+            self.storage = over.storage;
+        }
+        if over.state_namespace.is_some() {
+            self.state_namespace = over.state_namespace;
+        }
+        self.case_insensitive_branches =
+            over.case_insensitive_branches.or(self.case_insensitive_branches);
+        self.resolve_git_aliases = over.resolve_git_aliases.or(self.resolve_git_aliases);
+        self.force_rule_ignores_env_allow =
+            over.force_rule_ignores_env_allow.or(self.force_rule_ignores_env_allow);
+        self.block_during_rebase_or_merge =
+            over.block_during_rebase_or_merge.or(self.block_during_rebase_or_merge);
+        self.renamed_push_requires_authorization = over
+            .renamed_push_requires_authorization
+            .or(self.renamed_push_requires_authorization);
+        self.subagents_require_authorization =
+            over.subagents_require_authorization.or(self.subagents_require_authorization);
+        self.block_no_verify = over.block_no_verify.or(self.block_no_verify);
+        self.allow_local_remotes = over.allow_local_remotes.or(self.allow_local_remotes);
+        self.allow_new_branch_publish =
+            over.allow_new_branch_publish.or(self.allow_new_branch_publish);
+        if over.branch_prefix.is_some() {
+            self.branch_prefix = over.branch_prefix;
+        }
+        if over.empty_branch.is_some() {
+            self.empty_branch = over.empty_branch;
+        }
+        if over.dynamic_refspecs.is_some() {
+            self.dynamic_refspecs = over.dynamic_refspecs;
+        }
+        self.interactive = over.interactive.or(self.interactive);
+        if over.webhook_url.is_some() {
+            self.webhook_url = over.webhook_url;
+        }
+        self.verbose = over.verbose.or(self.verbose);
+        self.announce_allows = over.announce_allows.or(self.announce_allows);
+        self.reason_codes = over.reason_codes.or(self.reason_codes);
+        self.repeat_block_limit = over.repeat_block_limit.or(self.repeat_block_limit);
+        self.repeat_block_window_minutes =
+            over.repeat_block_window_minutes.or(self.repeat_block_window_minutes);
+        self.warn_only = over.warn_only.or(self.warn_only);
+        self.message.merge(over.message);
+        self.restricted_hours.merge(over.restricted_hours);
+        for (key, remote) in over.remotes {
+            union(&mut self.remotes.entry(key).or_default().protected, remote.protected);
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Returns true when `disabled_repos` lists `repo`.
+    pub fn disables(&self, repo: &str) -> bool {
+        let repo = canonical(repo);
+        self.disabled_repos.iter().any(|r| canonical(r) == repo)
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline.unwrap_or(false)
+    }
+
+    /// How long a remote HEAD answer is trusted, or None when it is never checked.
+    pub fn remote_head_max_age(&self) -> Option<chrono::Duration> {
+        match self.remote_head_max_age_hours.unwrap_or(24) {
+            0 => None,
+            hours => Some(chrono::Duration::hours(hours.into())),
+        }
+    }
+
+    /// How many identical blocks in a row, within how long, make the hook
+    /// switch to its terse message; None when it never does.
+    pub fn repeat_block_limit(&self) -> Option<(usize, chrono::Duration)> {
+        let window = self.repeat_block_window_minutes.unwrap_or(10);
+        match self.repeat_block_limit.unwrap_or(3) {
+            0 => None,
+            n => Some((n as usize, chrono::Duration::minutes(window.into()))),
+        }
+    }
+
+    pub fn blocks_during_rebase_or_merge(&self) -> bool {
+        self.block_during_rebase_or_merge.unwrap_or(true)
+    }
+
+    pub fn allows_empty_branch(&self) -> bool {
+        self.empty_branch.as_deref() == Some("allow")
+    }
+
+    pub fn warns_on_dynamic_refspecs(&self) -> bool {
+        self.dynamic_refspecs.as_deref() == Some("warn")
+    }
+
+    pub fn stores_in_repo(&self) -> bool {
+        self.storage.as_deref() == Some("repo")
+    }
+
+    pub fn namespaces_by_host(&self) -> bool {
+        self.state_namespace.as_deref() == Some("hostname")
+    }
+
+    pub fn branch_prefix(&self) -> &str {
+        self.branch_prefix.as_deref().unwrap_or("claude/")
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.interactive.unwrap_or(false)
+    }
+
+    pub fn is_verbose(&self) -> bool {
+        self.verbose.unwrap_or(false)
+            || std::env::var("PUSH_GUARD_VERBOSE").is_ok_and(|v| v == "1")
+    }
+
+    /// Returns the trusted directory containing `repo`, if any.
+    pub fn trusted_dir(&self, repo: &str) -> Option<&str> {
+        let repo = canonical(repo);
+        self.trusted_dirs
+            .iter()
+            .find(|d| repo.starts_with(canonical(d)))
+            .map(String::as_str)
+    }
+
+    /// Returns the first trusted remote URL pattern matching `url`. Patterns may
+    /// omit the scheme and user, so "github.com[:/]me/*" matches both
+    /// "git@github.com:me/x.git" and "https://github.com/me/x".
+    pub fn trusted_remote(&self, url: &str) -> Option<&str> {
+        self.trusted_remote_urls.iter().find(|p| url_matches(p, url)).map(String::as_str)
+    }
+
+    /// Returns the protected remote URL pattern matching `url`, matched like
+    /// `trusted_remote`.
+    pub fn protected_remote(&self, url: &str) -> Option<&str> {
+        self.protected_remote_urls.iter().find(|p| url_matches(p, url)).map(String::as_str)
+    }
+
+    /// Returns the denied remote URL pattern matching `url`, matched like
+    /// `trusted_remote`.
+    pub fn denied_remote(&self, url: &str) -> Option<&str> {
+        self.denied_remote_urls.iter().find(|p| url_matches(p, url)).map(String::as_str)
+    }
+
+    /// Returns the auto-tracked prefix `branch` starts with, if any. Empty
+    /// prefixes never match, so they can't make every branch tracked.
+    pub fn auto_tracked_prefix(&self, branch: &str, ignore_case: bool) -> Option<&str> {
+        let starts_with = |prefix: &str| {
+            if ignore_case {
+                branch.to_lowercase().starts_with(&prefix.to_lowercase())
+            } else {
+                branch.starts_with(prefix)
+            }
+        };
+        self.auto_tracked_prefixes
+            .iter()
+            .find(|p| !p.is_empty() && branch.len() > p.len() && starts_with(p))
+            .map(String::as_str)
+    }
+
+    /// Returns the value of `key` rendered as TOML, or None when unset.
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        check_key(key)?;
+        let table = toml::Table::try_from(self)?;
+        let value = match key.split_once('.') {
+            Some((section, name)) => table.get(section).and_then(|t| t.get(name)),
+            None => table.get(key),
+        };
+        Ok(value.map(|v| v.to_string()))
+    }
+
+    /// Returns the risky push option pattern matching `option`, if any.
+    pub fn risky_push_option(&self, option: &str) -> Option<&str> {
+        match &self.risky_push_options {
+            Some(patterns) => patterns
+                .iter()
+                .find(|p| glob_matches(p, option))
+                .map(String::as_str),
+            None => glob_matches(DEFAULT_RISKY_PUSH_OPTION, option)
+                .then_some(DEFAULT_RISKY_PUSH_OPTION),
+        }
+    }
+
+    /// Returns the command rules whose pattern matches `command`, a single
+    /// simple command.
+    pub fn matching_command_rules<'a>(
+        &'a self,
+        command: &'a str,
+    ) -> impl Iterator<Item = &'a CommandRule> {
+        self.command_rules
+            .iter()
+            .filter(move |r| glob_matches(&r.pattern, command.trim()))
+    }
+
+    /// Returns the first protected pattern matching `branch`.
+    /// Returns the branch globs protected for pushes to `remote` (whose URL is
+    /// `url`): `protected_branches`, then those of every matching `[remotes]`
+    /// entry, without duplicates.
+    pub fn protected_on(&self, remote: &str, url: Option<&str>) -> Vec<&str> {
+        let mut patterns: Vec<&str> = self.protected_branches.iter().map(String::as_str).collect();
+        // URL globs name a host or path; plain keys are remote names
+        let applies = |key: &str| {
+            key == remote || (key.contains('/') && url.is_some_and(|u| url_matches(key, u)))
+        };
+        for (_, entry) in self.remotes.iter().filter(|(key, _)| applies(key)) {
+            for p in &entry.protected {
+                if !patterns.contains(&p.as_str()) {
+                    patterns.push(p);
+                }
+            }
+        }
+        patterns
+    }
+
+    /// Returns the first glob protected for pushes to `remote` (see
+    /// `protected_on`) that matches `branch`.
+    pub fn protected_pattern_on(
+        &self,
+        branch: &str,
+        remote: &str,
+        url: Option<&str>,
+    ) -> Option<&str> {
+        self.protected_on(remote, url).into_iter().find(|p| glob_matches(p, branch))
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches_common_patterns() {
+        assert!(glob_matches("release/*", "release/1.0"));
+        assert!(glob_matches("prod-*", "prod-eu"));
+        assert!(!glob_matches("release/*", "feature/release"));
+        assert!(!glob_matches("[", "["));
+    }
+
+    #[test]
+    fn auto_tracked_prefixes_match_branch_starts() {
+        let config = Config {
+            auto_tracked_prefixes: vec!["".into(), "claude/".into(), "ai/".into()],
+            ..Default::default()
+        };
+        assert_eq!(config.auto_tracked_prefix("claude/fix-login", false), Some("claude/"));
+        assert_eq!(config.auto_tracked_prefix("ai/x", false), Some("ai/"));
+        assert_eq!(config.auto_tracked_prefix("claude/", false), None);
+        assert_eq!(config.auto_tracked_prefix("main", false), None);
+        assert_eq!(config.auto_tracked_prefix("Claude/x", false), None);
+        assert_eq!(config.auto_tracked_prefix("Claude/x", true), Some("claude/"));
+    }
+
+    #[test]
+    fn active_profile_layers_over_the_base_config() {
+        let mut config: Config = toml::from_str(
+            r#"
+            protected_branches = ["release/*"]
+            block_no_verify = false
+            active_profile = "strict"
+            [profiles.strict]
+            protected_branches = ["prod"]
+            block_no_verify = true
+            [profiles.demo]
+            warn_only = true
+            [profiles.demo.restricted_hours]
+            days = ["sat"]
+            "#,
+        )
+        .unwrap();
+        let base = config.clone();
+        config.apply_profile(None).unwrap();
+        assert_eq!(config.protected_branches, vec!["release/*", "prod"]);
+        assert_eq!(config.block_no_verify, Some(true));
+        // Repo overrides still win over the profile
+        config.merge(Config { block_no_verify: Some(false), ..Default::default() });
+        assert_eq!(config.block_no_verify, Some(false));
+
+        let mut demo = base.clone();
+        demo.apply_profile(Some("demo".into())).unwrap();
+        assert_eq!(demo.active_profile.as_deref(), Some("demo"));
+        assert_eq!(demo.warn_only, Some(true));
+        assert_eq!(demo.block_no_verify, Some(false));
+        let keys = demo.profile("demo").unwrap().keys().unwrap();
+        assert_eq!(keys, ["restricted_hours.days", "warn_only"]);
+
+        let err = base.clone().apply_profile(Some("prod".into())).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown profile 'prod'; defined profiles: demo, strict");
+        let mut none = Config::default();
+        none.apply_profile(None).unwrap();
+        assert_eq!(none, Config::default());
+    }
+
+    #[test]
+    fn merge_unions_protected_branches() {
+        let mut user = Config { protected_branches: vec!["release/*".into()], ..Default::default() };
+        user.merge(Config {
+            protected_branches: vec!["release/*".into(), "prod-*".into()],
+            ..Default::default()
+        });
+        assert_eq!(user.protected_branches, vec!["release/*", "prod-*"]);
+        assert_eq!(user.protected_pattern_on("prod-us", "origin", None), Some("prod-*"));
+        assert_eq!(user.protected_pattern_on("feature", "origin", None), None);
+    }
+
+    #[test]
+    fn protected_branches_per_remote_name_and_url() {
+        let mut config: Config = toml::from_str(
+            r#"
+            protected_branches = ["prod"]
+            [remotes.upstream]
+            protected = ["main", "release/*"]
+            [remotes."github.com[:/]acme/*"]
+            protected = ["stable", "prod"]
+            "#,
+        )
+        .unwrap();
+        config.merge(toml::from_str("[remotes.upstream]\nprotected = [\"next\"]").unwrap());
+        let acme = Some("git@github.com:acme/api.git");
+        let upstream = ["prod", "stable", "main", "release/*", "next"];
+        assert_eq!(config.protected_on("upstream", acme), upstream);
+        assert_eq!(config.protected_on("fork", Some("git@github.com:me/api.git")), ["prod"]);
+        assert_eq!(config.protected_pattern_on("release/2", "upstream", None), Some("release/*"));
+        assert_eq!(config.protected_pattern_on("release/2", "fork", None), None);
+        assert!(toml::from_str::<Config>("[remotes.x]\nprotect = []").is_err());
+    }
+
+    #[test]
+    fn merge_repo_scalars_override_user() {
+        let mut user = Config { enabled: Some(true), verbose: Some(true), ..Default::default() };
+        user.merge(Config { enabled: Some(false), ..Default::default() });
+        assert!(!user.is_enabled());
+        assert_eq!(user.verbose, Some(true));
+    }
+
+    #[test]
+    fn trusted_dir_matches_by_path_component() {
+        let config = Config { trusted_dirs: vec!["/scratch/clones".into()], ..Default::default() };
+        assert_eq!(config.trusted_dir("/scratch/clones/repo"), Some("/scratch/clones"));
+        assert_eq!(config.trusted_dir("/scratch/clones-other/repo"), None);
+    }
+
+    #[test]
+    fn trusted_remote_ignores_scheme_and_user() {
+        let config = Config {
+            trusted_remote_urls: vec!["github.com[:/]me/*".into()],
+            ..Default::default()
+        };
+        assert!(config.trusted_remote("git@github.com:me/tool.git").is_some());
+        assert!(config.trusted_remote("https://github.com/me/tool").is_some());
+        assert!(config.trusted_remote("https://github.com/org/tool").is_none());
+        assert!(config.trusted_remote("https://notgithub.com/me/tool").is_none());
+        let config = Config {
+            protected_remote_urls: vec!["github.com[:/]acme/*".into()],
+            ..Default::default()
+        };
+        let url = "git@github.com:acme/api.git";
+        assert_eq!(config.protected_remote(url), Some("github.com[:/]acme/*"));
+        assert!(config.protected_remote("ssh://nas/backup/api.git").is_none());
+        // The host is matched from its start, never somewhere in the path
+        let config = Config {
+            denied_remote_urls: vec!["github.com[:/]*".into()],
+            ..Default::default()
+        };
+        assert!(config.denied_remote("ssh://git@github.com:22/acme/api.git").is_some());
+        for url in [
+            "https://evil.example/github.com/acme/api.git",
+            "git@evil.example:github.com/acme/api.git",
+            "ssh://evil.example/x/github.com:acme/api.git",
+            "https://github.com.evil.example/acme/api.git",
+            "/srv/mirrors/github.com/acme/api.git",
+        ] {
+            assert!(config.denied_remote(url).is_none(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn split_url_finds_the_host() {
+        let split = |url| split_url(url).map(|(host, path)| format!("{} {}", host, path));
+        let https = "https://me@GitHub.com:443/acme/api.git";
+        assert_eq!(split(https).unwrap(), "github.com acme/api.git");
+        assert_eq!(split("git@github.com:acme/api").unwrap(), "github.com acme/api");
+        assert_eq!(split("https://github.com./acme/api").unwrap(), "github.com acme/api");
+        assert_eq!(split("ssh://[::1]:2222/srv/api").unwrap(), "::1 srv/api");
+        assert_eq!(split("nas:backup/api.git").unwrap(), "nas backup/api.git");
+        assert_eq!(split("/srv/git/a:b"), None);
+        assert_eq!(split("../api.git"), None);
+        assert_eq!(split("file:///srv/api.git"), None);
+    }
+
+    #[test]
+    fn restricted_hours_follow_the_configured_clock() {
+        let hours = RestrictedHours {
+            days: vec!["Sat".into(), "sunday".into()],
+            after: Some("18:00".into()),
+            before: Some("08:00".into()),
+            timezone: Some("+02:00".into()),
+        };
+        // 2026-10-14 is a Wednesday; times are UTC, two hours behind the policy
+        let at = |time: &str| -> DateTime<Utc> { time.parse().unwrap() };
+        assert!(!hours.restricts(at("2026-10-14T10:00:00Z")));
+        assert!(!hours.restricts(at("2026-10-14T15:59:00Z")));
+        assert!(hours.restricts(at("2026-10-14T16:00:00Z")));
+        assert!(hours.restricts(at("2026-10-14T05:59:00Z")));
+        assert!(!hours.restricts(at("2026-10-14T06:00:00Z")));
+        // Saturday noon, and Friday 23:00 UTC, which is already Saturday there
+        assert!(hours.restricts(at("2026-10-17T10:00:00Z")));
+        assert!(hours.restricts(at("2026-10-16T23:00:00Z")));
+        assert_eq!(
+            hours.describe(),
+            "on Sat, sunday, after 18:00 and before 08:00 (UTC+02:00)"
+        );
+        assert!(hours.validate().is_ok());
+        assert!(!RestrictedHours::default().restricts(at("2026-10-17T10:00:00Z")));
+
+        let invalid = |hours: RestrictedHours| hours.validate().unwrap_err().to_string();
+        let err = invalid(RestrictedHours { days: vec!["Caturday".into()], ..Default::default() });
+        assert!(err.contains("'Caturday' is not a weekday"), "{}", err);
+        let err = invalid(RestrictedHours { after: Some("6pm".into()), ..Default::default() });
+        assert!(err.contains("restricted_hours.after"), "{}", err);
+        let err = invalid(RestrictedHours { timezone: Some("CET".into()), ..Default::default() });
+        assert!(err.contains("restricted_hours.timezone"), "{}", err);
+    }
+
+    #[test]
+    fn org_policy_is_the_lowest_layer() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let write = |name: &str, contents: &str| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        let policy = write(
+            "policy.toml",
+            r#"
+            protected_branches = ["release/*"]
+            block_no_verify = true
+            verbose = true
+            warn_only = true
+            active_profile = "ignored"
+            [message]
+            force = "Org policy forbids force pushes to {branch}"
+            "#,
+        );
+        let user = write("user.toml", "block_no_verify = false\nverbose = false\n");
+        let repo = write("repo.toml", "protected_branches = [\"prod\"]\nverbose = true\n");
+        let config = Config::load_layers(Some(&policy), &user, Some(&repo), None).unwrap();
+        assert_eq!(config.protected_branches, ["release/*", "prod"]);
+        // User overrides the policy, and the repo overrides both
+        assert_eq!(config.block_no_verify, Some(false));
+        assert_eq!(config.verbose, Some(true));
+        assert_eq!(config.warn_only, Some(true));
+        assert!(config.message.force.as_deref().unwrap().starts_with("Org policy"));
+        assert_eq!(config.active_profile, None);
+
+        // Invalid or missing policies count as empty instead of failing
+        let invalid = write("invalid.toml", "protected_branches = 3\n");
+        let missing = dir.path().join("missing.toml");
+        for policy in [invalid, missing] {
+            let config = Config::load_layers(Some(&policy), &user, None, None).unwrap();
+            assert_eq!(config.block_no_verify, Some(false));
+            assert!(config.protected_branches.is_empty());
+        }
+    }
+
+    #[test]
+    fn repo_layer_only_tightens() {
+        let mut config = Config {
+            block_no_verify: Some(false),
+            allow_local_remotes: Some(true),
+            ..Default::default()
+        };
+        let repo: Config = toml::from_str(
+            r#"
+            enabled = false
+            trusted_dirs = ["/"]
+            warn_only = true
+            case_insensitive_branches = true
+            empty_branch = "allow"
+            allow_local_remotes = false
+            block_no_verify = true
+            risky_push_options = ["deploy*"]
+            protected_branches = ["prod"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            repo.loosening_keys().unwrap(),
+            ["case_insensitive_branches", "empty_branch", "enabled", "trusted_dirs", "warn_only"]
+        );
+        config.tighten(repo);
+        assert!(config.is_enabled());
+        assert!(config.trusted_dirs.is_empty());
+        assert_eq!(config.warn_only, None);
+        assert_eq!(config.case_insensitive_branches, None);
+        assert!(!config.allows_empty_branch());
+        assert_eq!(config.allow_local_remotes, Some(false));
+        assert_eq!(config.block_no_verify, Some(true));
+        assert_eq!(config.protected_branches, ["prod"]);
+        // The default risky option still applies
+        assert!(config.risky_push_option("merge_request.merge").is_some());
+        assert!(config.risky_push_option("deploy=prod").is_some());
+    }
+
+    #[test]
+    fn set_key_coerces_values() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        set_key(&path, "enabled", "false").unwrap();
+        set_key(&path, "trusted_dirs", "/a, /b").unwrap();
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.enabled, Some(false));
+        assert_eq!(config.trusted_dirs, vec!["/a", "/b"]);
+        assert_eq!(config.get("enabled").unwrap().as_deref(), Some("false"));
+        assert!(set_key(&path, "enabled", "maybe").is_err());
+        set_key(&path, "storage", "repo").unwrap();
+        assert!(Config::load_file(&path).unwrap().stores_in_repo());
+        assert!(set_key(&path, "storage", "cloud").is_err());
+        set_key(&path, "webhook_url", "http://127.0.0.1:9/hook").unwrap();
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.webhook_url.as_deref(), Some("http://127.0.0.1:9/hook"));
+        assert!(set_key(&path, "nonsense", "1").is_err());
+    }
+
+    #[test]
+    fn unset_key_removes_only_that_key() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        set_key(&path, "verbose", "true").unwrap();
+        set_key(&path, "message.force", "No force on {branch}").unwrap();
+        assert!(unset_key(&path, "message.force").unwrap());
+        assert!(!unset_key(&path, "message.force").unwrap());
+        assert!(!unset_key(&path, "enabled").unwrap());
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.verbose, Some(true));
+        assert_eq!(config.get("message.force").unwrap(), None);
+    }
+
+    #[test]
+    fn unknown_keys_suggest_the_closest_one() {
+        let err = check_key("protected_branch").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'protected_branches'?"), "{}", err);
+        let err = check_key("mesage.force").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'message.force'?"), "{}", err);
+        let err = check_key("webhook").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'webhook_url'?"), "{}", err);
+        assert_eq!(check_key("color").unwrap_err().to_string(), "Unknown config key 'color'");
+    }
+
+    #[test]
+    fn load_sees_changes_to_cached_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().to_str().unwrap();
+        set_key(&repo_config_path(repo), "branch_prefix", "a/").unwrap();
+        assert_eq!(Config::load(Some(repo)).unwrap().branch_prefix(), "a/");
+        set_key(&repo_config_path(repo), "branch_prefix", "b/").unwrap();
+        assert_eq!(Config::load(Some(repo)).unwrap().branch_prefix(), "b/");
+    }
+
+    #[test]
+    fn allow_entry_matches_repo_and_branch_glob() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = dir.path().to_str().unwrap();
+        let spec = format!("/elsewhere:main, {}/.:release/*", repo);
+        let entry = format!("{}/.:release/*", repo);
+        assert_eq!(allow_entry(&spec, repo, "release/1.0"), Some(entry.as_str()));
+        assert_eq!(allow_entry(&spec, repo, "main"), None);
+        assert_eq!(allow_entry(&spec, "/elsewhere", "main"), Some("/elsewhere:main"));
+        assert_eq!(allow_entry("main", repo, "main"), None);
+        assert_eq!(allow_entry(":main", repo, "main"), None);
+    }
+
+    #[test]
+    fn command_rules_are_validated_and_matched() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        let rules = "[[command_rules]]\npattern = \"make publish*\"\nbranch = \"main\"\n\
+                     [[command_rules]]\npattern = \"./scripts/ship.sh\"\ndecision = \"block\"\n";
+        fs::write(&path, rules).unwrap();
+        let config = Config::load_file(&path).unwrap();
+        let matched: Vec<_> = config.matching_command_rules(" make publish PROD=1").collect();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].branch.as_deref(), Some("main"));
+        assert!(config.matching_command_rules("make test").next().is_none());
+        assert!(config.matching_command_rules("./scripts/ship.sh").next().unwrap().blocks());
+
+        for bad in [
+            "[[command_rules]]\npattern = \"[oops\"\n",
+            "[[command_rules]]\npattern = \"make\"\ndecision = \"allow\"\n",
+            "[[command_rules]]\npattern = \"make\"\nmessage = \"no\"\n",
+            "[[command_rules]]\npattern = \"make\"\nbranches = \"main\"\n",
+        ] {
+            fs::write(&path, bad).unwrap();
+            assert!(Config::load_file(&path).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn risky_push_options_default_and_override() {
+        let mut config = Config::default();
+        let option = "merge_request.merge_when_pipeline_succeeds";
+        assert_eq!(config.risky_push_option(option), Some("merge_request.merge*"));
+        assert_eq!(config.risky_push_option("merge_request.create"), None);
+        config.merge(Config { risky_push_options: Some(vec![]), ..Default::default() });
+        assert_eq!(config.risky_push_option(option), None);
+        config.merge(Config {
+            risky_push_options: Some(vec!["ci.skip".into()]),
+            ..Default::default()
+        });
+        assert_eq!(config.risky_push_option("ci.skip"), Some("ci.skip"));
+    }
+
+    #[test]
+    fn message_templates_render_placeholders() {
+        let vars = [
+            ("branch", "main"),
+            ("remote", "origin"),
+            ("repo", "/r"),
+            ("default_branch", "main"),
+            ("authorize_cmd", "devtool authorize main"),
+            ("suggested", "claude/fix"),
+            ("flag", "--force"),
+            ("context", ""),
+            ("pattern", "rel*"),
+        ];
+        let messages = Messages::default();
+        let render_kind = |kind| render(messages.template(kind), &vars);
+        assert!(render_kind(MessageKind::DefaultBranch)
+            .contains("git checkout -b claude/fix && git push -u origin claude/fix"));
+        assert!(render_kind(MessageKind::Force).starts_with("Force push (--force) to 'main'"));
+        assert!(render_kind(MessageKind::Untracked).contains("revoke --repo '/r' --branch 'main'"));
+        assert!(render_kind(MessageKind::Denied).contains("protected branch pattern 'rel*'"));
+        let template = "Run `{authorize_cmd}` ({{branch}} is {branch} on {remote}/{default_branch})";
+        assert_eq!(
+            render(template, &vars),
+            "Run `devtool authorize main` ({branch} is main on origin/main)"
+        );
+    }
+
+    #[test]
+    fn message_templates_reject_unknown_placeholders() {
+        let messages = |force: &str| Messages { force: Some(force.into()), ..Default::default() };
+        assert!(messages("{flag} on {branch}{context}").validate().is_ok());
+        assert!(messages("{{literal}}").validate().is_ok());
+        let err = messages("push to {brnach}").validate().unwrap_err().to_string();
+        assert!(err.contains("message.force uses unknown placeholder '{brnach}'"), "{}", err);
+        // Rule-specific placeholders belong to their own message only
+        assert!(messages("{pattern}").validate().is_err());
+        assert!(messages("unclosed {branch").validate().is_err());
+        assert!(messages("stray }").validate().is_err());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+        set_key(&path, "message.untracked", "Run `{authorize_cmd}`").unwrap();
+        assert!(set_key(&path, "message.untracked", "{nope}").is_err());
+        let config = Config::load_file(&path).unwrap();
+        assert_eq!(config.message.template(MessageKind::Untracked), "Run `{authorize_cmd}`");
+        assert_eq!(
+            config.get("message.untracked").unwrap().as_deref(),
+            Some("\"Run `{authorize_cmd}`\"")
+        );
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(toml::from_str::<Config>("protected = [\"main\"]").is_err());
+    }
+}
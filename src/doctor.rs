@@ -0,0 +1,277 @@
+use crate::git::GitInfo;
+use crate::settings;
+use crate::state::{self, State, StateLock, STATE_VERSION};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// Outcome of one diagnostic, with a remediation hint unless it passed.
+pub struct Check {
+    pub status: Status,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(message: String) -> Self {
+        Self { status: Status::Pass, message, hint: None }
+    }
+
+    fn warn(message: String, hint: &str) -> Self {
+        Self { status: Status::Warn, message, hint: Some(hint.to_string()) }
+    }
+
+    fn fail(message: String, hint: &str) -> Self {
+        Self { status: Status::Fail, message, hint: Some(hint.to_string()) }
+    }
+}
+
+/// Runs every diagnostic for `repo`, else for the enclosing repository (or only
+/// the global ones outside a checkout).
+pub fn run(repo: Option<&str>) -> Vec<Check> {
+    let repo = repo.map(str::to_string).or_else(|| GitInfo::cwd().repo_key());
+    let mut checks = vec![git_check()];
+    checks.extend(state_checks(&state::state_path()));
+    checks.extend(hook_checks(repo.as_deref()));
+    if let Some(r) = &repo {
+        checks.push(remote_head_check(r));
+    }
+    checks
+}
+
+/// Prints `checks` and returns true when any of them failed.
+pub fn report(checks: &[Check]) -> bool {
+    for check in checks {
+        let label = match check.status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => "FAIL",
+        };
+        println!("{}  {}", label, check.message);
+        if let Some(hint) = &check.hint {
+            println!("      → {}", hint);
+        }
+    }
+    checks.iter().any(|c| c.status == Status::Fail)
+}
+
+// ── Git ───────────────────────────────────────────────────────────────────────
+
+fn git_check() -> Check {
+    let version = std::process::Command::new("git")
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string());
+    match version {
+        Some(v) => Check::pass(format!("git found ({})", v)),
+        None => Check::fail(
+            "git executable not found".to_string(),
+            "install git, or add it to the PATH the hook runs with",
+        ),
+    }
+}
+
+// ── State file ────────────────────────────────────────────────────────────────
+
+/// Checks that the state file at `path` can be read, parsed, written and locked.
+fn state_checks(path: &Path) -> Vec<Check> {
+    let mut checks = Vec::new();
+    if path.exists() {
+        match std::fs::read_to_string(path) {
+            Err(e) => {
+                checks.push(Check::fail(
+                    format!("state file {} is not readable: {}", path.display(), e),
+                    "fix its permissions, or set PUSH_GUARD_STATE_FILE to a readable path",
+                ));
+                return checks;
+            }
+            Ok(contents) => {
+                checks.push(Check::pass(format!("state file {} is readable", path.display())));
+                checks.push(schema_check(&contents));
+            }
+        }
+        if let Some(check) = read_only_check() {
+            checks.push(check);
+            return checks;
+        }
+        if let Err(e) = std::fs::OpenOptions::new().append(true).open(path) {
+            checks.push(Check::fail(
+                format!("state file {} is not writable: {}", path.display(), e),
+                "fix its permissions; tracking and authorizing need to save state",
+            ));
+        }
+    } else {
+        checks.push(Check::pass(format!(
+            "state file {} does not exist yet; it is created on first save",
+            path.display()
+        )));
+        if let Some(check) = read_only_check() {
+            checks.push(check);
+            return checks;
+        }
+    }
+    match StateLock::acquire() {
+        Ok(_lock) => checks.push(Check::pass("state lock can be acquired".to_string())),
+        Err(e) => checks.push(Check::fail(
+            format!("state lock cannot be acquired: {:#}", e),
+            "make the state directory writable, or remove a leftover .lock file \
+             if no push-guard is running",
+        )),
+    }
+    checks
+}
+
+/// Reports a read-only state directory, in which case writing and locking
+/// are not worth checking.
+fn read_only_check() -> Option<Check> {
+    let reason = state::read_only_reason()?;
+    Some(Check::warn(
+        format!(
+            "state is read-only ({}); pushes are checked against it, but nothing is recorded",
+            reason
+        ),
+        "make the state directory writable, or point PUSH_GUARD_STATE_FILE at a writable path, \
+         to track and authorize branches",
+    ))
+}
+
+fn schema_check(contents: &str) -> Check {
+    if contents.trim().is_empty() {
+        return Check::pass("state file is empty".to_string());
+    }
+    if let Err(e) = State::parse(contents) {
+        return Check::fail(
+            format!("state file cannot be parsed: {:#}", e),
+            "restore it from a backup or `push-guard export`, or move it aside",
+        );
+    }
+    let version = serde_json::from_str::<serde_json::Value>(contents)
+        .ok()
+        .and_then(|v| v["version"].as_u64())
+        .unwrap_or(0);
+    let current = u64::from(STATE_VERSION);
+    if version > current {
+        Check::warn(
+            format!("state schema version {} is newer than supported version {}", version, current),
+            "upgrade push-guard; this version can only read the state",
+        )
+    } else if version < current {
+        Check::warn(
+            format!("state schema version {} (current is {})", version, current),
+            "it is upgraded automatically on the next save",
+        )
+    } else {
+        Check::pass(format!("state schema version {}", version))
+    }
+}
+
+// ── Remote HEAD ───────────────────────────────────────────────────────────────
+
+/// Checks that `repo`'s push remote knows its default branch without a network
+/// call; otherwise pushes to it are not recognized as default-branch pushes.
+fn remote_head_check(repo: &str) -> Check {
+    let git = GitInfo::for_repo(repo);
+    let remote = git.tracking_info().map_or_else(|| "origin".to_string(), |(r, _)| r);
+    let head = git.run(&["symbolic-ref", "--short", &format!("refs/remotes/{}/HEAD", remote)]);
+    if let Some(branch) = head.as_deref().and_then(|h| h.strip_prefix(&format!("{}/", remote))) {
+        return Check::pass(format!("{}/HEAD resolves to '{}' in {}", remote, branch, repo));
+    }
+    if git.remote_url(&remote).is_none() {
+        return Check::warn(
+            format!("{} has no remote '{}'", repo, remote),
+            "default-branch protection applies once the repo has a remote",
+        );
+    }
+    Check::fail(
+        format!("{}/HEAD is not set in {}", remote, repo),
+        &format!("run `git remote set-head {} --auto` in the repo", remote),
+    )
+}
+
+// ── Hook registration ─────────────────────────────────────────────────────────
+
+const PRINT_CONFIG_HINT: &str = "merge the output of `push-guard hook --print-config`";
+
+/// Checks that a Claude settings file registers the hook, that the binary it
+/// runs exists, and that it is this version of push-guard.
+fn hook_checks(repo: Option<&str>) -> Vec<Check> {
+    let mut checks = Vec::new();
+    let mut registrations = Vec::new();
+    let files: Vec<_> =
+        settings::settings_files(repo).into_iter().filter(|p| p.exists()).collect();
+    if files.is_empty() {
+        checks.push(Check::warn(
+            "no Claude settings file found".to_string(),
+            &format!("create ~/.claude/settings.json and {}", PRINT_CONFIG_HINT),
+        ));
+        return checks;
+    }
+    for path in files {
+        let parsed = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok());
+        match parsed {
+            Some(json) => registrations.extend(
+                settings::registered_commands(&json).into_iter().map(|c| (path.clone(), c)),
+            ),
+            None => checks.push(Check::fail(
+                format!("{} is not valid JSON", path.display()),
+                "fix the file; Claude Code ignores settings it cannot parse",
+            )),
+        }
+    }
+    if registrations.is_empty() {
+        checks.push(Check::fail(
+            "push-guard hook is not registered in any Claude settings file".to_string(),
+            &format!("{} ({})", PRINT_CONFIG_HINT, "usually ~/.claude/settings.json"),
+        ));
+        return checks;
+    }
+
+    let current = std::env::current_exe().ok().and_then(|p| p.canonicalize().ok());
+    for (path, command) in registrations {
+        let binary = settings::command_binary(&command);
+        checks.push(Check::pass(format!("hook registered in {}", path.display())));
+        let Ok(resolved) = Path::new(binary).canonicalize() else {
+            checks.push(Check::fail(
+                format!("hook binary '{}' does not exist", binary),
+                &format!("update the command in {}; {}", path.display(), PRINT_CONFIG_HINT),
+            ));
+            continue;
+        };
+        if current.as_ref() == Some(&resolved) {
+            checks.push(Check::pass(format!("hook runs this binary ({})", binary)));
+            continue;
+        }
+        match binary_version(binary) {
+            Some(v) if v == env!("CARGO_PKG_VERSION") => checks.push(Check::pass(format!(
+                "hook binary '{}' is the same version ({})",
+                binary, v
+            ))),
+            version => checks.push(Check::warn(
+                format!(
+                    "hook binary '{}' is version {}, this is {}",
+                    binary,
+                    version.as_deref().unwrap_or("unknown"),
+                    env!("CARGO_PKG_VERSION")
+                ),
+                "point the hook at the push-guard you are running, or upgrade it",
+            )),
+        }
+    }
+    checks
+}
+
+/// Returns the version `<binary> --version` reports.
+fn binary_version(binary: &str) -> Option<String> {
+    let out = std::process::Command::new(binary).arg("--version").output().ok()?;
+    let text = String::from_utf8_lossy(&out.stdout);
+    text.split_whitespace().last().map(str::to_string)
+}
@@ -0,0 +1,100 @@
+use crate::config;
+use crate::state::{self, State};
+use anyhow::{bail, Context, Result};
+use std::process::Command;
+
+/// How long a branch protection lookup is reused before asking GitHub again.
+fn protection_ttl() -> chrono::Duration {
+    chrono::Duration::minutes(15)
+}
+
+/// Returns (owner, repo) when `url` points at a GitHub repository, in either
+/// SSH ("git@github.com:owner/repo.git") or HTTPS form.
+pub fn parse_remote(url: &str) -> Option<(String, String)> {
+    let (host, path) = config::split_url(url)?;
+    if host != "github.com" {
+        return None;
+    }
+    let (owner, name) = path.trim_end_matches('/').split_once('/')?;
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    if owner.is_empty() || name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((owner.to_string(), name.to_string()))
+}
+
+/// Returns whether direct pushes to `branch` require a pull request on GitHub,
+/// or None when that cannot be determined (no `gh`, no auth, network errors).
+/// Known answers are cached in state.
+pub fn requires_pull_request(owner: &str, name: &str, branch: &str) -> Option<bool> {
+    let key = format!("{}/{}:{}", owner, name, branch);
+    if let Some(protected) = State::load()
+        .ok()
+        .and_then(|s| s.cached_protection(&key, protection_ttl()))
+    {
+        return Some(protected);
+    }
+    let protected = query_protection(owner, name, branch)?;
+    if state::writable() {
+        let _ = State::update(|s| s.cache_protection(&key, protected));
+    }
+    Some(protected)
+}
+
+fn query_protection(owner: &str, name: &str, branch: &str) -> Option<bool> {
+    let path = format!("repos/{}/{}/branches/{}/protection", owner, name, branch);
+    let output = Command::new("gh").args(["api", &path]).output().ok()?;
+    if output.status.success() {
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+        return Some(!json["required_pull_request_reviews"].is_null());
+    }
+    // Unprotected branches are answered with a 404 "Branch not protected"
+    String::from_utf8_lossy(&output.stderr)
+        .contains("Branch not protected")
+        .then_some(false)
+}
+
+/// Returns the head branch of pull request `number`, asking `gh pr view` in
+/// `dir`, of the GitHub repository `target` ("owner/repo") when given.
+pub fn pr_head_branch(dir: &str, number: u32, target: Option<&str>) -> Result<String> {
+    let number = number.to_string();
+    let mut cmd = Command::new("gh");
+    cmd.current_dir(dir).args(["pr", "view", &number, "--json", "headRefName"]);
+    if let Some(t) = target {
+        cmd.args(["-R", t]);
+    }
+    let output = cmd
+        .output()
+        .context("Failed to run `gh`; install the GitHub CLI to authorize by pull request")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("Could not look up pull request #{}: {}", number, stderr.trim());
+    }
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Unexpected `gh pr view {}` output", number))?;
+    match json["headRefName"].as_str() {
+        Some(branch) if !branch.is_empty() => Ok(branch.to_string()),
+        _ => bail!("`gh pr view {}` did not report a head branch", number),
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_remote_handles_ssh_and_https() {
+        let expected = Some(("org".to_string(), "tool".to_string()));
+        assert_eq!(parse_remote("git@github.com:org/tool.git"), expected);
+        assert_eq!(parse_remote("https://github.com/org/tool"), expected);
+        assert_eq!(parse_remote("ssh://git@github.com/org/tool.git/"), expected);
+        assert_eq!(parse_remote("https://gitlab.com/org/tool"), None);
+        assert_eq!(parse_remote("https://github.com/org"), None);
+        assert_eq!(parse_remote("https://GitHub.com/org/tool"), expected);
+        assert_eq!(parse_remote("https://notgithub.com/org/tool"), None);
+        assert_eq!(parse_remote("git@github.com.evil.example:org/tool"), None);
+        assert_eq!(parse_remote("https://evil.example/github.com/org/tool"), None);
+    }
+}
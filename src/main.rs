@@ -1,10 +1,30 @@
+mod audit;
+mod cleanup;
+mod config;
+mod doctor;
+mod git;
+mod github;
+mod pending;
+mod remote_head;
+mod selftest;
+mod settings;
 mod state;
+mod stats;
+mod summary;
+mod timefmt;
+mod webhook;
 
-use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
-use state::State;
-use std::io::{IsTerminal, Read};
-use std::process::Command;
+use anyhow::{bail, Context, Result};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
+use cleanup::{Reason, Removal};
+use config::{Config, MessageKind};
+use git::GitInfo;
+use state::{Export, Kind, State};
+use stats::Stats;
+use std::ffi::OsStr;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(
@@ -21,72 +41,778 @@ struct Cli {
 enum Commands {
     /// Entry point for Claude Code PreToolUse hook.
     /// Reads JSON from stdin, tracks branch creations, enforces push authorization.
-    Hook,
+    Hook {
+        /// Print the settings.json fragment that registers this hook instead.
+        #[arg(long)]
+        print_config: bool,
+        /// Settings file the fragment is meant for.
+        #[arg(long, value_enum, requires = "print_config")]
+        scope: Option<SettingsScope>,
+        /// Also register the PostToolUse pass (for `gh pr checkout <n>`).
+        #[arg(long, requires = "print_config")]
+        post: bool,
+    },
+
+    /// Diagnose setup problems: git, the state file, hook registration and the
+    /// repo's remote HEAD. Exits non-zero when any check fails.
+    Doctor {
+        /// Repo to check (defaults to the enclosing repository).
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+
+    /// Exercise the main scenarios against a throwaway repository and state
+    /// file, printing PASS or FAIL for each. Exits non-zero when any fails.
+    Selftest,
+
+    /// Describe this binary for wrapper tools: version, state schema, file
+    /// paths, rules and exit codes.
+    Meta {
+        /// Repo whose config file to report (defaults to the enclosing repository).
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Output as JSON.
+        #[arg(long)]
+        json: bool,
+        /// Exit with status 3 unless the state schema is at least this version.
+        #[arg(long, value_name = "MIN_SCHEMA")]
+        check_compat: Option<u32>,
+    },
+
+    /// Entry point for Claude Code SessionEnd/Stop hooks.
+    /// Stops tracking the branches created in the ending session.
+    SessionEnd {
+        /// Session to end; read from the hook JSON on stdin when omitted.
+        #[arg(long)]
+        session: Option<String>,
+    },
+
+    /// Entry point for Claude Code Stop hooks.
+    /// Prints what the session did: branches created, pushes allowed and
+    /// blocked, and authorizations used. Prints nothing without activity.
+    SessionSummary {
+        /// Session to summarize; read from the hook JSON on stdin when omitted.
+        #[arg(long)]
+        session: Option<String>,
+        /// Also print the summary as hook JSON output (`systemMessage`) on
+        /// stdout, for the user to see in Claude Code.
+        #[arg(long)]
+        json: bool,
+    },
 
     /// Check if a push to a branch is allowed.
     /// Exits 0 (allow) or 1 (blocked).
     Check {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Defaults to the current branch's upstream remote, else "origin";
+        /// with --remote-url, to that URL.
         #[arg(long)]
-        repo: String,
-        #[arg(long)]
-        remote: String,
-        #[arg(long)]
-        branch: String,
+        remote: Option<String>,
+        /// URL the push goes to, for a remote not configured in the repo:
+        /// URL policies and the default branch come from it.
+        #[arg(long, value_name = "URL")]
+        remote_url: Option<String>,
+        /// Defaults to the current branch.
+        #[arg(long, add = ArgValueCompleter::new(complete_branch))]
+        branch: Option<String>,
+        /// Local branch pushed to --branch, when named differently.
+        #[arg(long, requires = "branch", add = ArgValueCompleter::new(complete_branch))]
+        source: Option<String>,
         #[arg(long, default_value = "false")]
         force: bool,
+        /// Check a --force-with-lease push.
+        #[arg(long, conflicts_with = "force")]
+        force_with_lease: bool,
+        /// Check every push in a shell command, as the hook would.
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "remote", "remote_url", "branch", "source", "force", "force_with_lease"
+            ]
+        )]
+        command: Option<String>,
+        /// Track branches the command creates.
+        #[arg(long, requires = "command")]
+        track_creations: bool,
         /// Print decision without exiting non-zero.
         #[arg(long)]
         dry_run: bool,
+        /// Ask on the terminal whether to allow a blocked push.
+        #[arg(long, conflicts_with = "dry_run")]
+        interactive: bool,
+    },
+
+    /// Explain which rule decides a push, without recording it.
+    Why {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Defaults to the current branch's upstream remote, else "origin".
+        #[arg(long)]
+        remote: Option<String>,
+        /// Defaults to the current branch.
+        #[arg(long, add = ArgValueCompleter::new(complete_branch))]
+        branch: Option<String>,
+        /// Local branch pushed to --branch, when named differently.
+        #[arg(long, requires = "branch", add = ArgValueCompleter::new(complete_branch))]
+        source: Option<String>,
+        #[arg(long)]
+        force: bool,
+        /// Explain a --force-with-lease push.
+        #[arg(long, conflicts_with = "force")]
+        force_with_lease: bool,
+        /// Output as JSON.
+        #[arg(long)]
+        json: bool,
+        /// Show full timestamps instead of relative times like "3h ago".
+        #[arg(long)]
+        absolute: bool,
     },
 
     /// Mark a branch as created by Claude.
     Track {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Defaults to the current branch.
+        #[arg(
+            long,
+            conflicts_with_all = ["all_local", "stdin"],
+            add = ArgValueCompleter::new(complete_branch)
+        )]
+        branch: Option<String>,
+        /// Track every local branch except the default branch.
+        #[arg(long, conflicts_with = "stdin")]
+        all_local: bool,
+        /// With --all-local, only track branches matching this glob.
+        #[arg(long, requires = "all_local")]
+        pattern: Option<String>,
+        /// Read branch names from stdin, one per line.
+        #[arg(long, conflicts_with = "from_reflog")]
+        stdin: bool,
+        /// Track the branches the HEAD reflog shows were created by checking
+        /// them out, such as those created before push-guard was installed.
+        #[arg(long, conflicts_with_all = ["branch", "all_local"])]
+        from_reflog: bool,
+        /// With --from-reflog, only consider branches created this recently
+        /// (e.g. "2h", "3d").
+        #[arg(
+            long,
+            requires = "from_reflog",
+            value_name = "DURATION",
+            value_parser = timefmt::parse_duration
+        )]
+        since: Option<chrono::Duration>,
+        /// Track even the default branch or a protected branch, whose rules
+        /// take precedence over tracking. With --from-reflog, track what was
+        /// found without asking.
         #[arg(long)]
-        repo: String,
-        #[arg(long)]
-        branch: String,
+        yes: bool,
+        /// State file to write to. Defaults to where the branch already has
+        /// an entry, else global.
+        #[arg(long, value_enum)]
+        scope: Option<StateScope>,
     },
 
     /// Grant one-time authorization to push to a branch Claude did not create.
+    #[command(group = clap::ArgGroup::new("bulk").args(["all_tracked", "matching"]))]
     Authorize {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Defaults to the current branch.
+        #[arg(
+            long,
+            conflicts_with_all = ["stdin", "bulk"],
+            add = ArgValueCompleter::new(complete_branch)
+        )]
+        branch: Option<String>,
+        /// Authorize the head branch of this GitHub pull request, looked up
+        /// with `gh pr view`.
+        #[arg(long, conflicts_with_all = ["branch", "stdin", "bulk"])]
+        pr: Option<u32>,
+        /// With --pr, the remote whose GitHub repository the pull request is in.
+        /// Defaults to gh's choice for the repo.
+        #[arg(long, requires = "pr")]
+        remote: Option<String>,
+        /// Only allow pushing this exact commit.
+        /// Defaults to the branch's local tip when the repo is on disk.
+        #[arg(long, conflicts_with_all = ["stdin", "bulk"])]
+        sha: Option<String>,
+        /// Read branch names from stdin, one per line; each is bound to its
+        /// local tip.
+        #[arg(long, conflicts_with = "bulk")]
+        stdin: bool,
+        /// Authorize every tracked branch of the repo, each bound to its local
+        /// tip, except the default and protected branches.
+        #[arg(long, conflicts_with = "matching")]
+        all_tracked: bool,
+        /// Authorize the tracked branches matching this glob (e.g. "hotfix/*"),
+        /// except the default and protected branches. The glob is expanded
+        /// now; branches tracked later are not authorized.
+        #[arg(long, value_name = "GLOB")]
+        matching: Option<String>,
+        /// With --all-tracked or --matching, also authorize the default and
+        /// protected branches.
+        #[arg(long, requires = "bulk")]
+        include_protected: bool,
+        /// With --all-tracked or --matching, only print what would be
+        /// authorized.
+        #[arg(long, requires = "bulk")]
+        dry_run: bool,
+        /// Allow this many pushes, then require authorization again. Unless
+        /// --sha is given, the authorization follows the branch as it moves.
+        #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+        uses: Option<u32>,
+        /// Also allow the push during `restricted_hours`.
         #[arg(long)]
-        repo: String,
+        override_hours: bool,
+        /// Also allow pushes to the default branch, which the default-branch
+        /// rule blocks despite an authorization otherwise.
         #[arg(long)]
-        branch: String,
+        allow_default: bool,
+        /// Authorize the default branch without --allow-default anyway.
+        #[arg(long)]
+        yes: bool,
+        /// State file to write to. Defaults to where the branch already has
+        /// an entry, else global.
+        #[arg(long, value_enum)]
+        scope: Option<StateScope>,
     },
 
     /// Revoke a previously granted authorization.
+    #[command(group = clap::ArgGroup::new("bulk").args(["all", "matching"]))]
     Revoke {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        #[arg(
+            long,
+            required_unless_present = "bulk",
+            conflicts_with = "bulk",
+            add = ArgValueCompleter::new(complete_branch)
+        )]
+        branch: Option<String>,
+        /// Revoke every authorization of the repo, except those of the default
+        /// and protected branches.
+        #[arg(long, conflicts_with = "matching")]
+        all: bool,
+        /// Revoke the authorizations of branches matching this glob, except
+        /// those of the default and protected branches.
+        #[arg(long, value_name = "GLOB")]
+        matching: Option<String>,
+        /// With --all or --matching, also revoke the default and protected
+        /// branches.
+        #[arg(long, requires = "bulk")]
+        include_protected: bool,
+        /// With --all or --matching, only print what would be revoked.
+        #[arg(long, requires = "bulk")]
+        dry_run: bool,
+        /// Only remove the entry from this state file (default: both).
+        #[arg(long, value_enum)]
+        scope: Option<StateScope>,
+    },
+
+    /// Allow pushes to every branch of a remote, such as a throwaway backup.
+    /// Remotes whose URL matches `protected_remote_urls` are never allowed.
+    AllowRemote {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
         #[arg(long)]
-        repo: String,
+        remote: String,
+        /// Also allow force pushes and branch deletions.
+        #[arg(long)]
+        include_force: bool,
+    },
+
+    /// Remove a grant made with `allow-remote`.
+    DenyRemote {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
         #[arg(long)]
+        remote: String,
+    },
+
+    /// Update what is recorded about a repo's remotes after they were
+    /// renamed, removed or pointed elsewhere outside the hook.
+    Refresh {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+
+    /// Stop treating a branch as created by Claude.
+    Untrack {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        #[arg(long, add = ArgValueCompleter::new(complete_branch))]
         branch: String,
+        /// Only remove the entry from this state file (default: both).
+        #[arg(long, value_enum)]
+        scope: Option<StateScope>,
     },
 
     /// List all tracked and authorized branches.
     List {
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Output as JSON (same as --format json).
+        #[arg(long, conflicts_with = "format")]
+        json: bool,
+        /// Output format.
+        #[arg(long, value_enum, default_value_t = ListFormat::Plain)]
+        format: ListFormat,
+        /// Only show branches tracked as created by Claude.
+        #[arg(long, conflicts_with = "authorized_only")]
+        tracked_only: bool,
+        /// Only show authorized branches.
+        #[arg(long)]
+        authorized_only: bool,
+        /// With state_namespace = "hostname", list the entries of every host,
+        /// with repo paths prefixed by the host name.
+        #[arg(long, conflicts_with = "repo")]
+        all_hosts: bool,
+        /// Show full timestamps instead of relative times like "3h ago".
+        #[arg(long)]
+        absolute: bool,
+    },
+
+    /// Show how often pushes were allowed or blocked, per rule.
+    Stats {
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Output as JSON.
         #[arg(long)]
+        json: bool,
+        /// Show full timestamps instead of relative times like "3h ago".
+        #[arg(long)]
+        absolute: bool,
+    },
+
+    /// Show recorded push decisions from the audit log, newest first.
+    History {
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
         repo: Option<String>,
+        /// Only show blocked pushes.
+        #[arg(long)]
+        blocked_only: bool,
+        /// Maximum number of events to show.
+        #[arg(long, default_value_t = 50)]
+        limit: usize,
         /// Output as JSON.
         #[arg(long)]
         json: bool,
+        /// Show full timestamps instead of relative times like "3h ago".
+        #[arg(long)]
+        absolute: bool,
+    },
+
+    /// Re-evaluate a recorded push decision against current code and state.
+    /// Never modifies state.
+    Replay {
+        /// Event id from `history --json`.
+        #[arg(required_unless_present = "last", conflicts_with = "last")]
+        id: Option<String>,
+        /// Replay the most recent event.
+        #[arg(long)]
+        last: bool,
+        /// Re-run detection on the recorded hook command (default).
+        #[arg(long, conflicts_with = "as_recorded")]
+        against_current_state: bool,
+        /// Evaluate the recorded remote/branch/force without re-parsing the command.
+        #[arg(long)]
+        as_recorded: bool,
+    },
+
+    /// Require authorization for branches matching a pattern in a repo.
+    /// Written to the repo's .push-guard.toml.
+    Protect {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Branch glob, e.g. 'release/*'.
+        #[arg(long)]
+        pattern: String,
+    },
+
+    /// Remove a protected branch pattern from a repo.
+    Unprotect {
+        /// Defaults to the enclosing repository.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        #[arg(long)]
+        pattern: String,
+    },
+
+    /// Read or change settings in the user or per-repo config file.
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Export state as a versioned JSON document.
+    Export {
+        /// Only export entries for this repo path.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Write to a file instead of stdout.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Import a document written by `export`.
+    Import {
+        file: PathBuf,
+        /// Merge into existing state, keeping the newer entry on conflicts (default).
+        #[arg(long, conflicts_with = "replace")]
+        merge: bool,
+        /// Replace existing state entirely.
+        #[arg(long)]
+        replace: bool,
+    },
+
+    /// Move entries between the global state file and per-repo files,
+    /// and switch the storage setting accordingly.
+    Migrate {
+        #[arg(long, value_enum)]
+        to: Storage,
+        /// Only migrate this repo (written to its .push-guard.toml).
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
     },
 
     /// Remove state entries.
     Clean {
         /// Remove all entries for a specific repo path.
-        #[arg(long)]
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
         repo: Option<String>,
         /// Remove entries for repos no longer present on disk.
         #[arg(long)]
         stale: bool,
+        /// With --stale, also remove repos that are no longer a git repository
+        /// or whose path now lies inside another repository (runs git per repo).
+        #[arg(long, requires = "stale")]
+        verify: bool,
+        /// Remove tracked branches already merged into the remote default branch.
+        #[arg(long)]
+        merged: bool,
+        /// With --merged, also remove authorizations for merged branches.
+        #[arg(long, requires = "merged")]
+        include_authorized: bool,
+        /// Remove entries whose local branch no longer exists.
+        #[arg(long)]
+        gone: bool,
+        /// Print what would be removed without changing state.
+        #[arg(long)]
+        dry_run: bool,
+        /// Color the --dry-run listing.
+        #[arg(long, value_enum, default_value_t, value_name = "WHEN")]
+        color: ColorMode,
+    },
+
+    /// Run every cleanup in one pass: stale repos, exhausted grants, and
+    /// entries for branches deleted locally or merged into the default branch.
+    /// Never fails on a repo git cannot read; it is skipped instead.
+    Prune {
+        /// Only prune this repo.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+        /// Print what would be removed without changing state.
+        #[arg(long)]
+        dry_run: bool,
+        /// Color the --dry-run listing.
+        #[arg(long, value_enum, default_value_t, value_name = "WHEN")]
+        color: ColorMode,
+        /// Output as JSON.
+        #[arg(long)]
+        json: bool,
+        /// Also remove authorizations not used for this long, or created that
+        /// long ago and never used (e.g. "30d", "12h", "2w").
+        #[arg(long, value_name = "DURATION", value_parser = timefmt::parse_duration)]
+        unused_for: Option<chrono::Duration>,
+    },
+
+    /// Print a shell completion script to stdout.
+    Completions {
+        shell: clap_complete::Shell,
+        /// Print the registration script for dynamic completion, which also
+        /// completes --repo and --branch values from state.
+        #[arg(long)]
+        dynamic: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the effective value of a key.
+    Get {
+        key: String,
+        /// Include the config of this repo.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+    /// Print the effective config, with the block-message templates in use.
+    Show {
+        /// Include the config of this repo.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
     },
+    /// List the command rules that apply, with the push each stands for.
+    Rules {
+        /// Include the config of this repo.
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+    /// Set a key in the user config, or in a repo's .push-guard.toml with --repo.
+    Set {
+        key: String,
+        /// Booleans accept true/false; lists are comma-separated.
+        value: String,
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+    /// Remove a key from the user config, or from a repo's .push-guard.toml
+    /// with --repo, so the default (or the other layer) applies again.
+    Unset {
+        key: String,
+        #[arg(long, add = ArgValueCompleter::new(complete_repo))]
+        repo: Option<String>,
+    },
+    /// Make a `[profiles.<name>]` table of the user config the active profile.
+    UseProfile { name: String },
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum SettingsScope {
+    /// `~/.claude/settings.json`
+    User,
+    /// `.claude/settings.json` of the project
+    Project,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum StateScope {
+    /// `<gitdir>/push-guard.json` of the repository, consulted first.
+    Local,
+    /// The global state file.
+    Global,
+}
+
+impl From<StateScope> for state::Scope {
+    fn from(scope: StateScope) -> Self {
+        match scope {
+            StateScope::Local => state::Scope::Local,
+            StateScope::Global => state::Scope::Global,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Storage {
+    /// The global state file.
+    Global,
+    /// `<gitdir>/push-guard.json` of each repository.
+    Repo,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ListFormat {
+    Plain,
+    Table,
+    Json,
+    Csv,
+}
+
+/// When dry-run output is colored.
+#[derive(Clone, Copy, Default, PartialEq, ValueEnum)]
+enum ColorMode {
+    /// When stdout is a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => std::io::stdout().is_terminal(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+/// How a push overrides the remote's history.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Force {
+    #[default]
+    None,
+    /// `--force-with-lease`: only overwrites what was last fetched.
+    WithLease,
+    /// `--force` / `-f`: overwrites unconditionally.
+    Unconditional,
+}
+
+impl Force {
+    fn from_flags(force: bool, with_lease: bool) -> Self {
+        match (force, with_lease) {
+            (true, false) => Force::Unconditional,
+            (true, true) => Force::WithLease,
+            _ => Force::None,
+        }
+    }
+
+    fn is_force(self) -> bool {
+        self != Force::None
+    }
+
+    /// The flag that requested the force push, for messages.
+    fn flag(self) -> &'static str {
+        match self {
+            Force::None => "",
+            Force::WithLease => "--force-with-lease",
+            Force::Unconditional => "--force",
+        }
+    }
+}
+
+#[derive(Clone)]
 struct PushInfo {
     remote: String,
     branch: String,
-    force: bool,
+    force: Force,
+    /// Set when an earlier part of the same command rewrites history.
+    rewrite: Option<Rewrite>,
+    /// Claude session that issued the push, when it came from the hook.
+    session: Option<String>,
+    /// Subagent of that session that issued the push; None for the main agent.
+    agent: Option<state::Agent>,
+    /// Block message of the `command_rules` entry this push was synthesized
+    /// from, when that rule blocks outright.
+    rule_block: Option<String>,
+    /// Values of `-o`/`--push-option`.
+    push_options: Vec<String>,
+    /// Set when the branch was left implicit and could not be resolved, so
+    /// `branch` is empty.
+    unresolved: bool,
+    /// Set for pushes that update no branch: `--dry-run`, or only tags.
+    non_branch: bool,
+    /// Local branch a refspec pushes (`feature` in `feature:main`), with
+    /// `HEAD` resolved to the current branch.
+    source: Option<String>,
+    /// Set when the refspec deletes `branch` on the remote: an empty source
+    /// (`:branch`), or `--delete`.
+    deletion: bool,
+    /// `--recurse-submodules` value (else `push.recurseSubmodules`): "check",
+    /// "on-demand", "only" or "no".
+    recurse_submodules: Option<String>,
+    /// Destructive GitHub CLI operation this push stands for.
+    gh_operation: Option<GhOperation>,
+    /// What runs the push with a branch only known at run time (`xargs`,
+    /// `find -exec`, a loop); such pushes are unresolved.
+    runner: Option<&'static str>,
+    /// Set when an earlier part of the same command brings in remote commits
+    /// or moves branches (`git pull`, `merge`, `rebase`, `reset`), so how the
+    /// pushed branch compares to the remote is only known once it ran.
+    after_update: bool,
+    /// `--no-verify`: the repo's own pre-push hook is skipped.
+    no_verify: bool,
+    /// Set when the same command creates the pushed branch, which then counts
+    /// as tracked even if recording the creation failed.
+    just_created: bool,
+    /// URL the push goes to, given with `check --remote-url` for a remote that
+    /// has no name here; URL policies and the default branch then come from
+    /// it rather than from `remote`.
+    remote_url: Option<String>,
+    /// Where the refspecs come from when they are only known at run time: a
+    /// word such as `"$BRANCH"` or a command substitution, or `--stdin`. Such
+    /// pushes are unresolved.
+    dynamic: Option<String>,
+}
+
+impl PushInfo {
+    fn new(remote: &str, branch: &str, force: Force) -> Self {
+        Self {
+            remote: remote.to_string(),
+            branch: branch.to_string(),
+            force,
+            rewrite: None,
+            session: None,
+            agent: None,
+            rule_block: None,
+            push_options: Vec::new(),
+            unresolved: false,
+            non_branch: false,
+            source: None,
+            deletion: false,
+            recurse_submodules: None,
+            gh_operation: None,
+            runner: None,
+            after_update: false,
+            no_verify: false,
+            dynamic: None,
+            just_created: false,
+            remote_url: None,
+        }
+    }
+
+    /// The URL the push goes to: the one it was given, else the remote's.
+    fn url(&self, git: &GitInfo) -> Option<String> {
+        self.remote_url.clone().or_else(|| git.remote_url(&self.remote))
+    }
+
+    /// The URLs the push goes to: the one it was given, else the remote's
+    /// push URLs.
+    fn push_urls(&self, git: &GitInfo) -> Vec<String> {
+        match &self.remote_url {
+            Some(url) => vec![url.clone()],
+            None => git.push_urls(&self.remote),
+        }
+    }
+
+    /// The project the push goes to, e.g. "github.com/acme/api".
+    fn project(&self, git: &GitInfo) -> Option<String> {
+        self.url(git).as_deref().and_then(git::project_from_url)
+    }
+
+    /// The default branch of the repository the push goes to, asked of the
+    /// URL itself when the push was given one.
+    fn default_branch(&self, git: &GitInfo) -> Option<String> {
+        match &self.remote_url {
+            Some(url) => git.url_default_branch(url),
+            None => git.default_branch(&self.remote),
+        }
+    }
+
+    /// Returns true when the push also pushes submodules (`on-demand`, `only`);
+    /// `check` only verifies they were pushed already.
+    fn pushes_submodules(&self) -> bool {
+        matches!(self.recurse_submodules.as_deref(), Some("on-demand" | "only"))
+    }
+
+    /// Returns the local branch pushed when it differs from the destination.
+    fn renamed_from(&self) -> Option<&str> {
+        let strip = |b: &str| b.strip_prefix("refs/heads/").unwrap_or(b).to_string();
+        self.source
+            .as_deref()
+            .filter(|s| !self.branch.is_empty() && strip(s) != strip(&self.branch))
+    }
+}
+
+/// A history rewrite (`git commit --amend`, `git rebase`) preceding a push.
+#[derive(Clone, Debug, PartialEq)]
+struct Rewrite {
+    /// The command that rewrote history, for messages.
+    command: &'static str,
+    /// Commits reachable from the pushed branch but not from `base` are rewritten.
+    /// None means the whole history (`git rebase --root`).
+    base: Option<String>,
 }
 
 // ── Color helpers ─────────────────────────────────────────────────────────────
@@ -111,380 +837,5187 @@ fn red(s: &str) -> String {
     ansi(s, "31")
 }
 
-// ── Git command parsing ───────────────────────────────────────────────────────
+// ── Shell completion ──────────────────────────────────────────────────────────
 
-/// Returns all branch names created in the command (handles chained commands).
-fn detect_branch_creations(command: &str) -> Vec<String> {
-    let mut branches = Vec::new();
-    for segment in command.split(|c| c == ';' || c == '&') {
-        let tokens: Vec<&str> = segment.split_whitespace().collect();
-        let mut i = 0;
-        while i + 1 < tokens.len() {
-            if tokens[i] != "git" {
-                i += 1;
-                continue;
-            }
-            match tokens[i + 1] {
-                "checkout" | "switch" => {
-                    let rest = &tokens[i + 2..];
-                    let creates = rest.iter().any(|t| {
-                        matches!(*t, "-b" | "-B" | "-c" | "-C")
-                            || t.starts_with("-b")
-                            || t.starts_with("-B")
-                            || t.starts_with("-c")
-                            || t.starts_with("-C")
-                    });
-                    if creates {
-                        if let Some(b) = rest.iter().filter(|t| !t.starts_with('-')).last() {
-                            branches.push(b.to_string());
-                        }
-                    }
-                }
-                "branch" => {
-                    if let Some(b) =
-                        tokens[i + 2..].iter().find(|t| !t.starts_with('-'))
-                    {
-                        branches.push(b.to_string());
-                    }
-                }
-                _ => {}
+fn candidates(values: Vec<String>, current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+    values
+        .into_iter()
+        .filter(|v| v.starts_with(current.as_ref()))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Completes --repo from the repo paths recorded in state.
+fn complete_repo(current: &OsStr) -> Vec<CompletionCandidate> {
+    let repos = State::load().map(|s| s.repos()).unwrap_or_default();
+    candidates(repos, current)
+}
+
+/// Completes --branch from the entries of the repo given by --repo on the command
+/// line being completed, or from every repo when none is given yet.
+fn complete_branch(current: &OsStr) -> Vec<CompletionCandidate> {
+    let Ok(state) = State::load() else {
+        return Vec::new();
+    };
+    let args: Vec<String> = std::env::args().collect();
+    let repo = args.iter().enumerate().find_map(|(i, a)| match a.strip_prefix("--repo=") {
+        Some(r) => Some(r.to_string()),
+        None if a == "--repo" => args.get(i + 1).cloned(),
+        None => None,
+    });
+    let repos = match repo {
+        Some(r) => vec![r],
+        None => state.repos(),
+    };
+    let mut branches: Vec<String> = Vec::new();
+    for r in repos {
+        for b in state.branches(&r) {
+            if !branches.contains(&b) {
+                branches.push(b);
             }
-            i += 1;
         }
     }
-    branches
+    candidates(branches, current)
 }
 
-/// Returns all push operations found in the command (handles chained commands).
-fn detect_all_pushes(command: &str) -> Vec<PushInfo> {
-    let mut pushes = Vec::new();
-    for segment in command.split(|c| c == ';' || c == '&') {
-        let tokens: Vec<&str> = segment.split_whitespace().collect();
-        let mut i = 0;
-        while i + 1 < tokens.len() {
-            if tokens[i] == "git" && tokens[i + 1] == "push" {
-                pushes.push(parse_push_args(&tokens[i + 2..]));
-                break;
+// ── Git command parsing ───────────────────────────────────────────────────────
+
+/// Splits a shell command into the simple commands to scan: backslash-newline
+/// continuations are joined, newlines, `;` and `&` separate commands, and
+/// comment lines and heredoc bodies are dropped. The command lines wrappers
+/// such as `script -c "..."` and `eval` run are scanned too, and so are the
+/// commands of `(...)` subshells and `$(...)` and `<(...)` substitutions.
+fn command_segments(command: &str) -> Vec<String> {
+    let joined = command.replace("\\\r\n", " ").replace("\\\n", " ");
+    let joined = unwrap_groups(&unwrap_substitutions(&unwrap_payloads(&joined)));
+    let mut segments = Vec::new();
+    let mut heredocs: Vec<String> = Vec::new();
+    for line in joined.lines() {
+        if let Some(delimiter) = heredocs.first() {
+            if line.trim() == delimiter {
+                heredocs.remove(0);
             }
-            i += 1;
+            continue;
+        }
+        if line.trim_start().starts_with('#') {
+            continue;
         }
+        heredocs.extend(heredoc_delimiters(line));
+        segments.extend(split_commands(line));
     }
-    pushes
+    segments
 }
 
-fn parse_push_args(args: &[&str]) -> PushInfo {
-    let mut force = false;
-    let mut positional: Vec<&str> = vec![];
-
-    let mut i = 0;
-    while i < args.len() {
-        let arg = args[i];
-        match arg {
-            "--force" | "-f" | "--force-with-lease" | "--force-if-includes" => {
-                force = true;
-            }
-            "-o" | "--push-option" | "--receive-pack" | "--exec" => {
-                i += 1; // these flags consume the next token
-            }
-            a if a.starts_with('-') => {}
-            _ => positional.push(arg),
+/// Splits `line` at `;` and `&`, except for the `&` of redirections such as
+/// `2>&1` and `&>`.
+fn split_commands(line: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut start = 0;
+    let bytes = line.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        let redirection = b == b'&'
+            && (i.checked_sub(1).is_some_and(|j| matches!(bytes[j], b'>' | b'<'))
+                || bytes.get(i + 1) == Some(&b'>'));
+        if matches!(b, b';' | b'&') && !redirection {
+            commands.push(line[start..i].to_string());
+            start = i + 1;
         }
-        i += 1;
     }
+    commands.push(line[start..].to_string());
+    commands
+}
 
-    let (remote, branch) = if positional.is_empty() {
-        // No explicit remote or branch — look up the configured upstream
-        get_tracking_info()
-            .unwrap_or_else(|| ("origin".to_string(), get_current_branch().unwrap_or_default()))
-    } else {
-        let remote = positional[0].to_string();
-        let branch = positional
-            .get(1)
-            .map(|s| {
-                // Handle refspecs: HEAD:main, feature:upstream — take the destination side
-                if let Some(colon) = s.find(':') {
-                    s[colon + 1..].to_string()
-                } else {
-                    s.to_string()
-                }
-            })
-            .unwrap_or_else(|| get_current_branch().unwrap_or_default());
-        (remote, branch)
-    };
+/// Word standing in for a substitution whose command was moved out of a line.
+const SUBSTITUTION: &str = "$(…)";
 
-    PushInfo { remote, branch, force }
+/// Moves the commands of command substitutions (`$(...)`) and process
+/// substitutions (`<(...)`, `>(...)`) onto lines of their own, before the line
+/// they were on, so they are split and scanned like the rest of the command.
+/// The substitution itself becomes one word, keeping `$(...):refs/heads/main`
+/// a refspec. Nested ones are unwrapped too; an unclosed one is left alone, as
+/// the shell would not run it.
+fn unwrap_substitutions(command: &str) -> String {
+    let mut out = String::new();
+    let mut line_start = 0;
+    let mut rest = command;
+    let copy = |out: &mut String, line_start: &mut usize, text: &str| {
+        out.push_str(text);
+        if let Some(newline) = out.rfind('\n').filter(|n| *n >= *line_start) {
+            *line_start = newline + 1;
+        }
+    };
+    while let Some(at) = rest.find(['$', '<', '>']).filter(|_| rest.contains('(')) {
+        if !rest[at + 1..].starts_with('(') {
+            copy(&mut out, &mut line_start, &rest[..at + 1]);
+            rest = &rest[at + 1..];
+            continue;
+        }
+        let inner = &rest[at + 2..];
+        let Some(len) = closing_paren(inner) else {
+            break;
+        };
+        copy(&mut out, &mut line_start, &rest[..at]);
+        let commands = unwrap_substitutions(&inner[..len]) + "\n";
+        out.insert_str(line_start, &commands);
+        line_start += commands.len();
+        out.push_str(SUBSTITUTION);
+        rest = &inner[len + 1..];
+    }
+    out.push_str(rest);
+    out
 }
 
-// ── Git helpers ───────────────────────────────────────────────────────────────
+/// Replaces subshell groups, as in `(cd sub && git push)`, with the commands
+/// they run, on lines of their own. Only a `(` that starts a command opens a
+/// group; an unclosed one is left alone, as the shell would not run it.
+fn unwrap_groups(command: &str) -> String {
+    let mut out = String::new();
+    let mut rest = command;
+    while let Some(at) = group_start(rest) {
+        let inner = &rest[at + 1..];
+        let Some(len) = closing_paren(inner) else {
+            break;
+        };
+        out.push_str(&rest[..at]);
+        out.push('\n');
+        out.push_str(&unwrap_groups(&inner[..len]));
+        out.push('\n');
+        rest = &inner[len + 1..];
+    }
+    out.push_str(rest);
+    out
+}
 
-fn get_repo_root() -> Option<String> {
-    Command::new("git")
-        .args(["rev-parse", "--show-toplevel"])
-        .output()
-        .ok()
-        .filter(|o| o.status.success())
-        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+/// Returns the offset of the first `(` in `text` that starts a command: one
+/// at the start of a line, after a separator or pipe, or after `!`, `then`,
+/// `do` or `else`.
+fn group_start(text: &str) -> Option<usize> {
+    text.match_indices('(').map(|(i, _)| i).find(|&i| {
+        let before = text[..i].trim_end_matches([' ', '\t']);
+        let last_word = before.rsplit(char::is_whitespace).next().unwrap_or("");
+        before.is_empty()
+            || before.ends_with([';', '&', '|', '(', '\n', '!'])
+            || matches!(last_word, "then" | "do" | "else")
+    })
 }
 
-fn get_current_branch() -> Option<String> {
-    Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "HEAD"])
-        .output()
-        .ok()
-        .filter(|o| o.status.success())
-        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+/// Returns the offset of the `)` closing a parenthesis opened just before
+/// `text`, skipping nested pairs.
+fn closing_paren(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
 }
 
-/// Returns (remote, branch) from the current tracking upstream.
-/// `git rev-parse --abbrev-ref @{u}` → "origin/main" → ("origin", "main")
-fn get_tracking_info() -> Option<(String, String)> {
-    let output = Command::new("git")
-        .args(["rev-parse", "--abbrev-ref", "@{u}"])
-        .output()
-        .ok()
-        .filter(|o| o.status.success())?;
-    let s = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    let (remote, branch) = s.split_once('/')?;
-    Some((remote.to_string(), branch.to_string()))
-}
-
-/// Resolves the actual default branch of a remote — what the remote's HEAD points to.
-/// Does not rely on branch name conventions.
-///
-/// Strategy:
-///   1. `git symbolic-ref refs/remotes/<remote>/HEAD` — local, instant, works after fetch
-///   2. `git remote show <remote>` — makes a network call, always accurate
-///   3. None — caller treats as non-default
-fn get_default_branch(remote: &str) -> Option<String> {
-    let sym_ref = format!("refs/remotes/{}/HEAD", remote);
-    let output = Command::new("git")
-        .args(["symbolic-ref", &sym_ref, "--short"])
-        .output()
-        .ok()
-        .filter(|o| o.status.success())?;
+/// Programs whose `-c` argument is a command line they run (`expect -c` runs
+/// a script, whose `spawn git push ...` is found like any other push).
+const COMMAND_WRAPPERS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "script", "expect"];
 
-    let s = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if !s.is_empty() {
-        return s
-            .strip_prefix(&format!("{}/", remote))
-            .map(|b| b.to_string());
+/// Returns the words of `text` with their byte offsets.
+fn words_at(text: &str) -> Vec<(usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices() {
+        if c.is_whitespace() {
+            if let Some(s) = start.take() {
+                words.push((s, &text[s..i]));
+            }
+        } else if start.is_none() {
+            start = Some(i);
+        }
     }
+    words.extend(start.map(|s| (s, &text[s..])));
+    words
+}
 
-    let output = Command::new("git")
-        .args(["remote", "show", remote])
-        .output()
-        .ok()
-        .filter(|o| o.status.success())?;
-
-    String::from_utf8_lossy(&output.stdout)
-        .lines()
-        .find_map(|line| {
-            line.trim()
-                .strip_prefix("HEAD branch:")
-                .map(|b| b.trim().to_string())
+/// Returns the offset of the quoted command line passed with `-c` (or
+/// `--command`) to the first wrapper in `text`, or to `eval`, and its quote
+/// character. The flag is set for `eval`, which joins the words after the
+/// quoted one to the command line it runs.
+fn payload_start(text: &str) -> Option<(usize, char, bool)> {
+    let words = words_at(text);
+    for (i, (_, word)) in words.iter().enumerate() {
+        let opens = word.starts_with('(') || word.contains("$(");
+        let word = word.trim_start_matches(['$', '<', '>', '(']);
+        let program = word.rsplit('/').next().unwrap_or(word);
+        let starts_command = opens
+            || i.checked_sub(1).is_none_or(|j| {
+                let before = words[j].1;
+                before.ends_with([';', '&', '|']) || matches!(before, "then" | "do" | "else" | "!")
+            });
+        if program == "eval" && starts_command {
+            match words.get(i + 1) {
+                Some(&(at, payload)) if payload.starts_with(['\'', '"']) => {
+                    return payload.chars().next().map(|quote| (at, quote, true));
+                }
+                _ => continue,
+            }
+        }
+        if !COMMAND_WRAPPERS.contains(&program) {
+            continue;
+        }
+        for (j, (_, option)) in words.iter().enumerate().skip(i + 1) {
+            let is_command = *option == "--command"
+                || (option.starts_with('-') && !option.starts_with("--") && option.ends_with('c'));
+            if !option.starts_with('-') {
+                break;
+            }
+            if !is_command {
+                continue;
+            }
+            let &(at, payload) = words.get(j + 1)?;
+            match payload.chars().next() {
+                Some(quote @ ('\'' | '"')) => return Some((at, quote, false)),
+                _ => break,
+            }
+        }
+    }
+    None
+}
+
+/// Replaces the quoted payload of wrappers such as `script -c "..."`,
+/// `bash -c '...'`, `expect -c '...'` and `eval "..."` with the command line
+/// it holds, on lines of its own, so it is split and scanned like the rest of
+/// the command. The words `eval` is given after it stay on its line.
+fn unwrap_payloads(command: &str) -> String {
+    let mut out = String::new();
+    let mut rest = command;
+    while let Some((at, quote, joined)) = payload_start(rest) {
+        let Some(len) = rest[at + 1..].find(quote) else {
+            break;
+        };
+        out.push_str(&rest[..at]);
+        out.push('\n');
+        out.push_str(&unwrap_payloads(&rest[at + 1..at + 1 + len]));
+        out.push(if joined { ' ' } else { '\n' });
+        rest = &rest[at + 2 + len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns the delimiters of the heredocs (`<<EOF`, `<<-'EOF'`) opened on
+/// `line`, in order. Here-strings (`<<<`) are not heredocs.
+fn heredoc_delimiters(line: &str) -> Vec<String> {
+    let mut delimiters = Vec::new();
+    let mut rest = line;
+    while let Some(at) = rest.find("<<") {
+        rest = &rest[at + 2..];
+        if rest.starts_with('<') {
+            rest = rest.trim_start_matches('<');
+            continue;
+        }
+        let word = rest.strip_prefix('-').unwrap_or(rest).trim_start();
+        let end = word
+            .find(|c: char| c.is_whitespace() || matches!(c, ';' | '&' | '|' | ')'))
+            .unwrap_or(word.len());
+        let delimiter = word[..end].trim_matches(|c| c == '\'' || c == '"');
+        if !delimiter.is_empty() {
+            delimiters.push(delimiter.to_string());
+        }
+        rest = &word[end..];
+    }
+    delimiters
+}
+
+/// Returns the position of the `for`, `while` or `until` keyword opening a
+/// loop in `tokens`, at the start or after a pipe (`... | while read b`).
+fn loop_start(tokens: &[&str]) -> Option<usize> {
+    (0..tokens.len()).find(|&i| {
+        matches!(tokens[i], "for" | "while" | "until") && (i == 0 || tokens[i - 1].ends_with('|'))
+    })
+}
+
+/// Replaces references to the shell variable `name` in `text` with `value`,
+/// dropping the double quotes around a quoted reference.
+fn substitute_var(text: &str, name: &str, value: &str) -> String {
+    let text = text
+        .replace(&format!("\"${{{}}}\"", name), value)
+        .replace(&format!("\"${}\"", name), value);
+    let braced = format!("{{{}}}", name);
+    let mut out = String::new();
+    let mut rest = text.as_str();
+    while let Some(at) = rest.find('$') {
+        out.push_str(&rest[..at]);
+        let after = &rest[at + 1..];
+        let bare = after.strip_prefix(name).filter(|r| {
+            !r.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        });
+        let len = if after.starts_with(&braced) {
+            braced.len()
+        } else if bare.is_some() {
+            name.len()
+        } else {
+            0
+        };
+        if len == 0 {
+            out.push('$');
+        } else {
+            out.push_str(value);
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Returns true when `text` refers to the shell variable `name`.
+fn references_var(text: &str, name: &str) -> bool {
+    substitute_var(text, name, "") != text
+}
+
+/// Unrolls the shell loops in `segments` so pushes in their bodies are seen
+/// with concrete branches: a `for` loop over literal words repeats its body
+/// once per word, with the loop variable substituted. Body segments whose
+/// pushes depend on values only known at run time (a variable of a `while` or
+/// `until` loop, or of a `for` loop over a computed list) come with the loop
+/// as their runner.
+fn unroll_loops(segments: &[String]) -> Vec<(String, Option<&'static str>)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < segments.len() {
+        let header: Vec<&str> = segments[i].split_whitespace().collect();
+        i += 1;
+        let Some(at) = loop_start(&header) else {
+            out.push((segments[i - 1].clone(), None));
+            continue;
+        };
+        if at > 0 {
+            out.push((header[..at].join(" "), None));
+        }
+        let mut body: Vec<String> = Vec::new();
+        let mut depth = 1;
+        while i < segments.len() {
+            let segment = segments[i].trim_start();
+            i += 1;
+            let segment = match segment.strip_prefix("do") {
+                Some(rest) if rest.is_empty() || rest.starts_with(char::is_whitespace) => rest,
+                _ => segment,
+            };
+            let tokens: Vec<&str> = segment.split_whitespace().collect();
+            if tokens.first() == Some(&"done") {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            } else if loop_start(&tokens).is_some() {
+                depth += 1;
+            }
+            body.push(segment.to_string());
+        }
+        let literal = |w: &&str| !w.contains(['$', '`', '*', '?', '[', '{', '(']);
+        match &header[at..] {
+            ["for", name, "in", words @ ..] if !words.is_empty() && words.iter().all(literal) => {
+                for word in words {
+                    let word = word.trim_matches(|c| c == '\'' || c == '"');
+                    let unrolled: Vec<String> =
+                        body.iter().map(|s| substitute_var(s, name, word)).collect();
+                    out.extend(unroll_loops(&unrolled));
+                }
+            }
+            ["for", name, ..] => out.extend(unroll_loops(&body).into_iter().map(|(s, runner)| {
+                let runner = runner.or(references_var(&s, name).then_some("a `for` loop"));
+                (s, runner)
+            })),
+            _ => out.extend(unroll_loops(&body).into_iter().map(|(s, runner)| {
+                let runner = runner.or(s.contains('$').then_some("a `while` loop"));
+                (s, runner)
+            })),
+        }
+    }
+    out
+}
+
+/// Returns the command that runs a `git` invocation once per input when
+/// `before` precedes it in the same pipeline stage: `xargs` or `find -exec`.
+fn payload_runner(before: &[&str]) -> Option<&'static str> {
+    let stage = match before.iter().rposition(|t| t.ends_with('|')) {
+        Some(pipe) => &before[pipe + 1..],
+        None => before,
+    };
+    let exec = ["-exec", "-execdir", "-ok", "-okdir"];
+    if stage.iter().any(|t| *t == "xargs" || t.ends_with("/xargs")) {
+        Some("`xargs`")
+    } else if stage.contains(&"find") && stage.iter().any(|t| exec.contains(t)) {
+        Some("`find -exec`")
+    } else {
+        None
+    }
+}
+
+/// Subcommands detection understands; anything else may be an alias.
+const KNOWN_SUBCOMMANDS: &[&str] =
+    &["push", "checkout", "switch", "branch", "commit", "rebase", "fetch", "pull"];
+
+/// Rewrites `git <alias> args` into its expansion, as returned by `lookup`, so
+/// detection sees the real command. Shell aliases (`!...`) are replaced by their
+/// script text, which is then scanned like any other command.
+fn expand_aliases(command: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut lines = Vec::new();
+    for segment in command_segments(command) {
+        let mut segment = segment;
+        // Aliases may refer to other aliases; git itself rejects loops
+        for _ in 0..5 {
+            let tokens: Vec<&str> = segment.split_whitespace().collect();
+            let Some(i) = (0..tokens.len().saturating_sub(1)).find(|&i| {
+                tokens[i] == "git"
+                    && !tokens[i + 1].starts_with('-')
+                    && !KNOWN_SUBCOMMANDS.contains(&tokens[i + 1])
+            }) else {
+                break;
+            };
+            let Some(expansion) = lookup(tokens[i + 1]) else {
+                break;
+            };
+            let replacement = match expansion.strip_prefix('!') {
+                Some(script) => script.to_string(),
+                None => format!("git {}", expansion),
+            };
+            let mut words: Vec<String> = tokens[..i].iter().map(|t| t.to_string()).collect();
+            words.push(replacement);
+            words.extend(tokens[i + 2..].iter().map(|t| t.to_string()));
+            segment = words.join(" ");
+        }
+        lines.push(segment);
+    }
+    lines.join("\n")
+}
+
+/// Returns `command` with git aliases of `git`'s repository expanded, unless
+/// `resolve_git_aliases` is turned off for `repo`.
+fn expand_command(git: &GitInfo, repo: &str, command: &str) -> String {
+    let resolve = Config::load(Some(repo))
+        .map(|c| c.resolve_git_aliases.unwrap_or(true))
+        .unwrap_or(true);
+    if !resolve {
+        return command.to_string();
+    }
+    expand_aliases(command, |name| git.alias(name))
+}
+
+/// A branch a command creates.
+#[derive(Debug, PartialEq)]
+struct Creation {
+    branch: String,
+    /// Remote-tracking branch it is checked out from, for `--track` and
+    /// `git switch <name>` when only `<remote>/<name>` exists.
+    from_remote: Option<String>,
+    /// Only a candidate: a plain `git switch <name>` creates a branch just when
+    /// no local one exists and a single remote has it, which git has to confirm.
+    guessed: bool,
+    /// Only a creation while no local branch of that name exists: fetching
+    /// into an existing branch (`git fetch origin a:b`) just updates it.
+    only_if_new: bool,
+}
+
+impl Creation {
+    fn new(branch: &str) -> Self {
+        Self { branch: branch.to_string(), from_remote: None, guessed: false, only_if_new: false }
+    }
+}
+
+/// Returns all branches created in the command (handles chained commands).
+fn detect_branch_creations(command: &str) -> Vec<Creation> {
+    let mut branches = Vec::new();
+    for segment in command_segments(command) {
+        let tokens: Vec<&str> = segment.split_whitespace().collect();
+        let mut i = 0;
+        while i + 1 < tokens.len() {
+            if tokens[i] == "gh" {
+                if let Some(Some(b)) = gh_pr_checkout(&tokens[i + 1..]) {
+                    branches.push(Creation::new(&b));
+                }
+            }
+            if tokens[i] == "jj" {
+                let created = jj_bookmark_create(&tokens[i + 1..]);
+                branches.extend(created.iter().map(|b| Creation::new(b)));
+                break;
+            }
+            if tokens[i] != "git" {
+                i += 1;
+                continue;
+            }
+            match tokens[i + 1] {
+                "checkout" | "switch" => branches.extend(checkout_creation(&tokens[i + 2..])),
+                "fetch" | "pull" => branches.extend(fetch_creations(&tokens[i + 2..])),
+                "update-ref" => branches.extend(update_ref_creation(&tokens[i + 2..])),
+                // Renames and copies are handled by `branch_moves`
+                "branch" if branch_move(&tokens[i + 2..]).is_none() => {
+                    if let Some(b) =
+                        tokens[i + 2..].iter().find(|t| !t.starts_with('-'))
+                    {
+                        branches.push(Creation::new(b));
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+    branches
+}
+
+/// A `git branch -m` (rename) or `git branch -c` (copy) of a local branch.
+#[derive(Debug, PartialEq)]
+struct BranchMove {
+    from: String,
+    to: String,
+    copy: bool,
+}
+
+/// Recognizes `-m`/`-M`/`-c`/`-C` from the arguments after `git branch`.
+/// Returns (from, to, copy), where `from` is None in the one-argument form,
+/// which renames or copies the current branch.
+fn branch_move<'a>(args: &[&'a str]) -> Option<(Option<&'a str>, &'a str, bool)> {
+    let mut mode = None;
+    let mut names = Vec::new();
+    for arg in args {
+        match *arg {
+            "-m" | "-M" | "--move" => mode = Some(false),
+            "-c" | "-C" | "--copy" => mode = Some(true),
+            a if a.starts_with('-') => {}
+            a => names.push(a),
+        }
+    }
+    let copy = mode?;
+    match names.as_slice() {
+        [to] => Some((None, to, copy)),
+        [from, to] => Some((Some(from), to, copy)),
+        _ => None,
+    }
+}
+
+/// Returns the renames and copies of branches in the command. The current
+/// branch of the one-argument form is looked up where the command runs,
+/// following `cd <dir>` and `git -C <dir>` like `detect_all_pushes`.
+fn branch_moves(command: &str, git: &GitInfo) -> Vec<BranchMove> {
+    let mut moves = Vec::new();
+    let mut cwd: Option<PathBuf> = None;
+    for segment in command_segments(command) {
+        let tokens: Vec<&str> = segment.split_whitespace().collect();
+        if tokens.first() == Some(&"cd") {
+            cwd = tokens.get(1).map(|d| join_dir(cwd.as_deref(), d));
+            continue;
+        }
+        let Some(i) = tokens.iter().position(|t| *t == "git") else {
+            continue;
+        };
+        let (dir, subcommand) = git_invocation(&tokens[i + 1..]);
+        let Some((from, to, copy)) = subcommand
+            .split_first()
+            .filter(|(name, _)| **name == "branch")
+            .and_then(|(_, args)| branch_move(args))
+        else {
+            continue;
+        };
+        let from = match from {
+            Some(f) => Some(f.to_string()),
+            None => {
+                let dir = match (&cwd, dir) {
+                    (Some(cwd), d) => Some(d.map_or(cwd.clone(), |d| join_dir(Some(cwd), d))),
+                    (None, d) => d.map(|d| join_dir(None, d)),
+                };
+                let local = dir.map(|d| git.at(&d));
+                local.as_ref().unwrap_or(git).current_branch().filter(|b| b != "HEAD")
+            }
+        };
+        let valid = |b: &str| git::normalize_branch(b).ok();
+        match (from.as_deref().and_then(valid), valid(to)) {
+            (Some(from), Some(to)) => moves.push(BranchMove { from, to, copy }),
+            _ => debug_log(&format!("Not following branch rename/copy in: {}", segment.trim())),
+        }
+    }
+    moves
+}
+
+/// Applies `moves` to the state: a rename moves the tracked and authorized
+/// entries to the new name, a copy only tracks the copy when the original is
+/// tracked. Returns the moves that changed something.
+fn apply_branch_moves<'a>(
+    state: &mut State,
+    repo: &str,
+    moves: &'a [BranchMove],
+) -> Vec<&'a BranchMove> {
+    moves
+        .iter()
+        .filter(|m| {
+            if m.copy {
+                state.copy_tracking(repo, &m.from, &m.to)
+            } else {
+                state.rename_branch(repo, &m.from, &m.to)
+            }
+        })
+        .collect()
+}
+
+/// A change to a remote that what the state records about it must follow.
+#[derive(Debug, PartialEq)]
+enum RemoteChange {
+    /// `git remote rename <from> <to>`
+    Rename(String, String),
+    /// `git remote remove <name>`
+    Remove(String),
+    /// `git remote set-url <name> ...`
+    SetUrl(String),
+}
+
+/// Recognizes `rename`, `remove`/`rm` and `set-url` from the arguments after
+/// `git remote`.
+fn remote_change(args: &[&str]) -> Option<RemoteChange> {
+    let mut args = args.iter().copied().skip_while(|a| a.starts_with('-'));
+    let subcommand = args.next()?;
+    let names: Vec<&str> = args.filter(|a| !a.starts_with('-')).collect();
+    match (subcommand, names.as_slice()) {
+        ("rename", [from, to]) => Some(RemoteChange::Rename(from.to_string(), to.to_string())),
+        ("remove" | "rm", [name]) => Some(RemoteChange::Remove(name.to_string())),
+        ("set-url", [name, ..]) => Some(RemoteChange::SetUrl(name.to_string())),
+        _ => None,
+    }
+}
+
+/// Returns the remote renames, removals and URL changes in the command.
+fn remote_changes(command: &str) -> Vec<RemoteChange> {
+    let mut changes = Vec::new();
+    for segment in command_segments(command) {
+        let tokens: Vec<&str> = segment.split_whitespace().collect();
+        let Some(i) = tokens.iter().position(|t| *t == "git") else {
+            continue;
+        };
+        let (_, subcommand) = git_invocation(&tokens[i + 1..]);
+        if let Some(("remote", args)) = subcommand.split_first().map(|(n, a)| (*n, a)) {
+            changes.extend(remote_change(args));
+        }
+    }
+    changes
+}
+
+/// Applies `changes` to the state: a rename moves the remote's grant and
+/// recorded default branch to the new name, a removal drops both, and a new
+/// URL drops the default branch, which is then asked again. Grants already
+/// lapse when the URL changes.
+fn apply_remote_changes(state: &mut State, repo: &str, changes: &[RemoteChange]) {
+    for change in changes {
+        match change {
+            RemoteChange::Rename(from, to) => state.rename_remote(repo, from, to),
+            RemoteChange::Remove(remote) => state.forget_remote(repo, remote, true),
+            RemoteChange::SetUrl(remote) => state.forget_remote(repo, remote, false),
+        }
+    }
+}
+
+/// Brings what the state records about `repo`'s remotes in line with
+/// `remotes`, its current ones, after changes made outside the hook. A grant
+/// of a remote that is gone follows it when exactly one remote now pushes to
+/// its URLs, as after a rename, and is dropped otherwise; recorded default
+/// branches are forgotten so that they are asked again. Returns what changed.
+fn refresh_remotes(
+    state: &mut State,
+    repo: &str,
+    git: &GitInfo,
+    remotes: &[String],
+) -> Vec<String> {
+    let mut changes = Vec::new();
+    let grants: Vec<state::RemoteGrant> =
+        state.remote_grants.get(repo).cloned().unwrap_or_default();
+    for grant in grants.iter().filter(|g| !remotes.contains(&g.remote)) {
+        let renamed: Vec<&String> = remotes
+            .iter()
+            .filter(|r| state.remote_grant(repo, r).is_none())
+            .filter(|r| grant.applies_to(&git.push_urls(r)))
+            .collect();
+        match renamed.as_slice() {
+            [to] => {
+                state.rename_remote(repo, &grant.remote, to);
+                changes.push(format!("Moved the grant of '{}' to '{}'", grant.remote, to));
+            }
+            _ => {
+                state.forget_remote(repo, &grant.remote, true);
+                changes.push(format!("Dropped the grant of '{}', which is gone", grant.remote));
+            }
+        }
+    }
+    for remote in state.remotes_with_heads(repo) {
+        state.forget_remote(repo, &remote, false);
+        changes.push(format!("Forgot the recorded default branch of '{}'", remote));
+    }
+    changes
+}
+
+/// Returns the branch `git checkout`/`git switch` creates, from the arguments
+/// after the subcommand.
+fn checkout_creation(args: &[&str]) -> Option<Creation> {
+    let mut created = None;
+    let mut track = false;
+    let mut detach = false;
+    let mut paths = false;
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            "--" => {
+                paths = i + 1 < args.len();
+                break;
+            }
+            "-b" | "-B" | "-c" | "-C" | "--create" | "--force-create" => {
+                created = args.get(i + 1).copied();
+                i += 1;
+            }
+            "-t" | "--track" => track = true,
+            "-d" | "--detach" | "--no-guess" => detach = true,
+            a if a.starts_with("--track=") => track = true,
+            a if a.len() > 2 && ["-b", "-B", "-c", "-C"].contains(&&a[..2]) => {
+                created = Some(&a[2..]);
+            }
+            a if a.starts_with('-') => {}
+            a => positional.push(a),
+        }
+        i += 1;
+    }
+    let start = positional.first().copied();
+    if let Some(branch) = created {
+        return Some(Creation {
+            from_remote: start.filter(|_| track).map(str::to_string),
+            ..Creation::new(branch)
+        });
+    }
+    let start = start?;
+    if track {
+        // `--track origin/feature` names the local branch after the remote's
+        let (_, branch) = start.strip_prefix("refs/remotes/").unwrap_or(start).split_once('/')?;
+        return Some(Creation { from_remote: Some(start.to_string()), ..Creation::new(branch) });
+    }
+    // Restoring files (`git checkout <rev> -- <path>`) never creates a branch
+    if detach || paths || positional.len() != 1 {
+        return None;
+    }
+    Some(Creation { guessed: true, ..Creation::new(start) })
+}
+
+/// Options of `git fetch` and `git pull` that take the next word as their value.
+const FETCH_VALUE_OPTIONS: &[&str] = &[
+    "--depth", "--deepen", "--shallow-since", "--shallow-exclude", "-j", "--jobs",
+    "--upload-pack", "--refmap", "-o", "--server-option", "--negotiation-tip",
+    "-s", "--strategy", "-X", "--strategy-option",
+];
+
+/// Returns the local branches `git fetch`/`git pull` arguments fetch into:
+/// the destinations of `<src>:<dst>` refspecs naming a plain branch or
+/// `refs/heads/<dst>`. Forced (`+`) refspecs and other destinations, such as
+/// remote-tracking refs, are skipped.
+fn fetch_creations(args: &[&str]) -> Vec<Creation> {
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            a if FETCH_VALUE_OPTIONS.contains(&a) => i += 1,
+            a if a.starts_with('-') => {}
+            a => positional.push(a),
+        }
+        i += 1;
+    }
+    let Some((remote, refspecs)) = positional.split_first() else {
+        return Vec::new();
+    };
+    // A URL or path has no remote-tracking branches to name
+    let named_remote = !remote.contains([':', '/']);
+    refspecs
+        .iter()
+        .filter(|r| !r.starts_with('+'))
+        .filter_map(|refspec| {
+            let (src, dst) = refspec.split_once(':')?;
+            let dst = dst.strip_prefix("refs/heads/").unwrap_or(dst);
+            if src.is_empty() || dst.is_empty() || dst.starts_with("refs/") {
+                return None;
+            }
+            let src = src.strip_prefix("refs/heads/").unwrap_or(src);
+            let from_remote = named_remote.then(|| format!("{}/{}", remote, src));
+            Some(Creation { from_remote, only_if_new: true, ..Creation::new(dst) })
+        })
+        .collect()
+}
+
+/// Returns the branch `git update-ref refs/heads/<branch> <commit>` writes,
+/// unless it deletes the ref or reads its updates from stdin. Like a fetch, it
+/// only creates the branch when it does not exist yet.
+fn update_ref_creation(args: &[&str]) -> Option<Creation> {
+    let mut positional = Vec::new();
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            "-d" | "--stdin" => return None,
+            "-m" => i += 1,
+            a if a.starts_with('-') => {}
+            a => positional.push(a),
+        }
+        i += 1;
+    }
+    let [reference, _commit, ..] = positional.as_slice() else {
+        return None;
+    };
+    let branch = reference.strip_prefix("refs/heads/").filter(|b| !b.is_empty())?;
+    Some(Creation { only_if_new: true, ..Creation::new(branch) })
+}
+
+/// Returns the branches created in the command that are valid branch names.
+/// Anything else is a parsing accident and is logged instead. Guessed creations
+/// are kept only when `git` confirms them: the local branch does not exist yet
+/// and exactly one remote has a branch of that name. Fetches into a branch that
+/// already exists locally create nothing.
+fn valid_branch_creations(command: &str, git: &GitInfo) -> Vec<Creation> {
+    detect_branch_creations(command)
+        .into_iter()
+        .filter_map(|c| match git::normalize_branch(&c.branch) {
+            Ok(branch) => Some(Creation { branch, ..c }),
+            Err(e) => {
+                if !c.guessed {
+                    debug_log(&format!("Not tracking detected branch: {:#}", e));
+                }
+                None
+            }
+        })
+        .filter(|c| !(c.only_if_new && git.branch_tip(&c.branch).is_some()))
+        .filter_map(|c| {
+            if !c.guessed {
+                return Some(c);
+            }
+            if git.branch_tip(&c.branch).is_some() {
+                return None;
+            }
+            match git.remote_branches_named(&c.branch).as_slice() {
+                [remote_branch] => Some(Creation {
+                    from_remote: Some(remote_branch.clone()),
+                    guessed: false,
+                    ..c
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Returns the names created by `jj bookmark create` (or the older
+/// `jj branch create`) from the arguments after `jj`.
+fn jj_bookmark_create(args: &[&str]) -> Vec<String> {
+    if args.len() < 2 || !matches!(args[0], "bookmark" | "branch" | "b") || args[1] != "create" {
+        return Vec::new();
+    }
+    let mut names = Vec::new();
+    let mut i = 2;
+    while i < args.len() {
+        match args[i] {
+            "-r" | "--revision" | "--to" => i += 1,
+            a if a.starts_with('-') => {}
+            name => names.push(name.to_string()),
+        }
+        i += 1;
+    }
+    names
+}
+
+/// Recognizes `gh pr checkout <n>` from the arguments after `gh`. Returns
+/// Some(branch) when the local branch is named with `-b`/`--branch`, and
+/// Some(None) when gh picks the name itself.
+fn gh_pr_checkout(args: &[&str]) -> Option<Option<String>> {
+    if args.len() < 2 || args[0] != "pr" || args[1] != "checkout" {
+        return None;
+    }
+    let rest = &args[2..];
+    let named = rest.iter().enumerate().find_map(|(i, t)| match *t {
+        "-b" | "--branch" => rest.get(i + 1).map(|b| b.to_string()),
+        _ => t.strip_prefix("--branch=").map(str::to_string),
+    });
+    Some(named)
+}
+
+/// Returns true when the command runs `gh pr checkout`.
+fn has_gh_pr_checkout(command: &str) -> bool {
+    command_segments(command).iter().any(|segment| {
+        let tokens: Vec<&str> = segment.split_whitespace().collect();
+        (0..tokens.len()).any(|i| tokens[i] == "gh" && gh_pr_checkout(&tokens[i + 1..]).is_some())
+    })
+}
+
+/// Interprets `gh pr create` and `gh pr merge` from the arguments after `gh`.
+/// `gh pr create` pushes the current branch when it has no upstream yet (and
+/// no `--head` is given), so it is treated as a push of that branch.
+fn gh_pr_push(args: &[&str], git: &GitInfo) -> Option<PushInfo> {
+    if args.len() < 2 || args[0] != "pr" {
+        return None;
+    }
+    match args[1] {
+        "create" => {
+            let has_head = args.iter().any(|t| {
+                matches!(*t, "-H" | "--head") || t.starts_with("--head=")
+            });
+            if has_head || git.tracking_info().is_some() {
+                debug_log("gh pr create does not push here; not evaluated");
+                return None;
+            }
+            let branch = git.current_branch().filter(|b| b != "HEAD")?;
+            Some(PushInfo::new("origin", &branch, Force::None))
+        }
+        "merge" if args.iter().any(|t| matches!(*t, "-d" | "--delete-branch")) => {
+            debug_log("gh pr merge --delete-branch deletes a remote branch; not evaluated");
+            None
+        }
+        _ => None,
+    }
+}
+
+/// A GitHub CLI command that destroys remote history or data, checked like a
+/// push of the branch (or tag) it affects.
+#[derive(Clone, Debug, PartialEq)]
+enum GhOperation {
+    /// `gh repo sync --force`, of a fork given as OWNER/REPO or the local repo.
+    RepoSync { target: Option<String> },
+    /// `gh api -X DELETE .../git/refs/<ref>`.
+    DeleteRef { git_ref: String },
+    /// `gh release delete <tag>`, which also deletes the tag with `--cleanup-tag`.
+    ReleaseDelete { cleanup_tag: bool },
+}
+
+impl GhOperation {
+    fn message(&self, branch: &str) -> String {
+        let what = match self {
+            GhOperation::RepoSync { target } => format!(
+                "`gh repo sync --force` hard-resets '{}'{} to its source branch,\n\
+                 discarding any commits that exist only there.\n\
+                 Recommendation: sync without --force and resolve the divergence.",
+                branch,
+                target.as_ref().map(|t| format!(" of {}", t)).unwrap_or_default()
+            ),
+            GhOperation::DeleteRef { git_ref } => format!(
+                "`gh api -X DELETE` deletes the ref '{}' on GitHub.\n\
+                 Recommendation: delete branches by merging their pull request instead.",
+                git_ref
+            ),
+            GhOperation::ReleaseDelete { cleanup_tag } => format!(
+                "`gh release delete` deletes the GitHub release '{}' and its assets{}.",
+                branch,
+                if *cleanup_tag { ", and the tag itself" } else { "" }
+            ),
+        };
+        if branch.is_empty() {
+            return format!("{}\nName the branch with --branch to be able to authorize it.", what);
+        }
+        format!("{}\nTo allow it, say \"authorize push to {}\"", what, branch)
+    }
+}
+
+/// Recognizes destructive GitHub CLI operations from the arguments after `gh`:
+/// `gh repo sync --force`, `gh api -X DELETE` of a git ref, and
+/// `gh release delete`. Other gh commands are left alone.
+fn gh_destructive(args: &[&str], git: &GitInfo) -> Option<PushInfo> {
+    let value_of = |short: &str, long: &str| {
+        args.iter().enumerate().find_map(|(i, t)| match *t {
+            t if t == short || t == long => args.get(i + 1).map(|v| v.to_string()),
+            t => t.strip_prefix(long).and_then(|v| v.strip_prefix('=')).map(str::to_string),
         })
+    };
+    let positional = |from: usize, with_values: &[&str]| {
+        let mut i = from;
+        while i < args.len() {
+            match args[i] {
+                a if with_values.contains(&a) => i += 1,
+                a if a.starts_with('-') => {}
+                a => return Some(a.to_string()),
+            }
+            i += 1;
+        }
+        None
+    };
+    let repo_flag = value_of("-R", "--repo");
+    let (remote, branch, operation) = match args {
+        ["repo", "sync", ..] if args.contains(&"--force") => {
+            let target = positional(2, &["-b", "--branch", "-s", "--source"]);
+            let branch = value_of("-b", "--branch")
+                .or_else(|| target.is_none().then(|| git.default_branch("origin")).flatten());
+            let remote = target.clone().unwrap_or_else(|| "origin".to_string());
+            (remote, branch.unwrap_or_default(), GhOperation::RepoSync { target })
+        }
+        ["api", ..] => {
+            let method = value_of("-X", "--method")
+                .or_else(|| args.iter().find_map(|t| t.strip_prefix("-X").map(str::to_string)))
+                .filter(|m| !m.is_empty());
+            if !method.is_some_and(|m| m.eq_ignore_ascii_case("DELETE")) {
+                return None;
+            }
+            let endpoint = args.iter().find(|t| t.contains("git/refs/"))?;
+            let (path, git_ref) = endpoint.split_once("git/refs/")?;
+            // `repos/{owner}/{repo}/...` is filled in by gh from the local repo
+            let remote = path
+                .trim_matches('/')
+                .strip_prefix("repos/")
+                .filter(|r| !r.is_empty() && !r.contains('{'))
+                .unwrap_or("origin");
+            let branch = git_ref.strip_prefix("heads/").unwrap_or(git_ref).to_string();
+            (remote.to_string(), branch, GhOperation::DeleteRef { git_ref: git_ref.to_string() })
+        }
+        ["release", "delete", ..] => {
+            let tag = positional(2, &["-R", "--repo"]).unwrap_or_default();
+            let cleanup_tag = args.contains(&"--cleanup-tag");
+            let remote = repo_flag.unwrap_or_else(|| "origin".to_string());
+            (remote, tag, GhOperation::ReleaseDelete { cleanup_tag })
+        }
+        _ => return None,
+    };
+    let mut push = PushInfo::new(&remote, &branch, Force::None);
+    push.gh_operation = Some(operation);
+    Some(push)
+}
+
+/// Interprets `jj git push` from the arguments after `jj`, returning one push
+/// per bookmark. Bookmarks come from `--branch`/`-b`/`--bookmark` (repeatable);
+/// `--all` pushes every local branch; otherwise the bookmarks at the colocated
+/// git HEAD are assumed. `--change` only pushes new bookmarks jj
+/// creates itself, so it is not evaluated.
+fn jj_git_push(args: &[&str], git: &GitInfo) -> Option<Vec<PushInfo>> {
+    if args.len() < 2 || args[0] != "git" || args[1] != "push" {
+        return None;
+    }
+    let rest = &args[2..];
+    let mut remote = None;
+    let mut branches = Vec::new();
+    let (mut all, mut change) = (false, false);
+    let mut i = 0;
+    while i < rest.len() {
+        let value = rest.get(i + 1).map(|v| v.to_string());
+        match rest[i] {
+            "-b" | "--branch" | "--bookmark" => {
+                branches.extend(value);
+                i += 1;
+            }
+            "--remote" => {
+                remote = value;
+                i += 1;
+            }
+            "-c" | "--change" => {
+                change = true;
+                i += 1;
+            }
+            "-r" | "--revisions" => i += 1,
+            "--all" | "--tracked" => all = true,
+            a => {
+                if let Some(b) = a.strip_prefix("--branch=").or(a.strip_prefix("--bookmark=")) {
+                    branches.push(b.to_string());
+                } else if let Some(r) = a.strip_prefix("--remote=") {
+                    remote = Some(r.to_string());
+                } else if a.starts_with("--change=") {
+                    change = true;
+                }
+            }
+        }
+        i += 1;
+    }
+    if branches.is_empty() {
+        if all {
+            branches = git.local_branches().unwrap_or_default();
+        } else if change {
+            debug_log("jj git push --change creates its own bookmarks; not evaluated");
+        } else {
+            branches = git.branches_at("HEAD").unwrap_or_default();
+        }
+    }
+    let remote = remote.unwrap_or_else(jj_default_remote);
+    Some(branches.iter().map(|b| PushInfo::new(&remote, b, Force::None)).collect())
+}
+
+/// Returns the remote `jj git push` uses by default (`git.push`), else "origin".
+fn jj_default_remote() -> String {
+    std::process::Command::new("jj")
+        .args(["config", "get", "git.push"])
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .filter(|r| !r.is_empty())
+        .unwrap_or_else(|| "origin".to_string())
+}
+
+/// Returns the pushes stood for by `command_rules` entries matching parts of
+/// `command`. Rules without a branch push the current one.
+fn command_rule_pushes(config: &Config, command: &str, git: &GitInfo) -> Vec<PushInfo> {
+    let mut pushes = Vec::new();
+    for segment in command_segments(command) {
+        for rule in config.matching_command_rules(&segment) {
+            let remote = rule.remote.as_deref().unwrap_or("origin");
+            let branch = rule
+                .branch
+                .clone()
+                .or_else(|| git.current_branch().filter(|b| b != "HEAD"));
+            let force = if rule.force { Force::Unconditional } else { Force::None };
+            let mut push = PushInfo::new(remote, branch.as_deref().unwrap_or_default(), force);
+            push.unresolved = branch.is_none();
+            if rule.blocks() {
+                push.rule_block = Some(rule.message.clone().unwrap_or_else(|| {
+                    format!("'{}' is blocked by a command rule in the config.", segment.trim())
+                }));
+            }
+            pushes.push(push);
+        }
+    }
+    pushes
+}
+
+/// Returns all push operations found in the command (handles chained commands).
+/// Omitted remotes and branches are filled in from `git`.
+fn detect_all_pushes(command: &str, git: &GitInfo) -> Vec<PushInfo> {
+    let mut pushes = Vec::new();
+    let mut rewrite: Option<Rewrite> = None;
+    let mut updated = false;
+    // Directory an earlier `cd` in the command moved to
+    let mut cwd: Option<PathBuf> = None;
+    for (segment, runner) in unroll_loops(&command_segments(command)) {
+        let tokens: Vec<&str> = segment.split_whitespace().collect();
+        if tokens.first() == Some(&"cd") {
+            cwd = tokens.get(1).map(|d| join_dir(cwd.as_deref(), d));
+            continue;
+        }
+        let mut i = 0;
+        while i + 1 < tokens.len() {
+            if tokens[i] == "gh" {
+                let args = &tokens[i + 1..];
+                if let Some(push) = gh_pr_push(args, git).or_else(|| gh_destructive(args, git)) {
+                    pushes.push(push);
+                    break;
+                }
+            }
+            if tokens[i] == "jj" {
+                if let Some(jj_pushes) = jj_git_push(&tokens[i + 1..], git) {
+                    pushes.extend(jj_pushes);
+                }
+                break;
+            }
+            let (dir, subcommand) = git_invocation(&tokens[i + 1..]);
+            if tokens[i] == "git" && subcommand.first() == Some(&"push") {
+                // Implicit branches are resolved where the push actually runs
+                let dir = match (&cwd, dir) {
+                    (Some(cwd), Some(d)) => Some(join_dir(Some(cwd), d)),
+                    (Some(cwd), None) => Some(cwd.clone()),
+                    (None, d) => d.map(|d| join_dir(None, d)),
+                };
+                let local = dir.map(|d| git.at(&d));
+                let base = local.as_ref().unwrap_or(git);
+                // `-c push.default=current` and the like change what a bare push updates
+                let overrides = push_config_overrides(&tokens[i + 1..]);
+                let configured = (!overrides.is_empty()).then(|| base.with_config(&overrides));
+                let resolver = configured.as_ref().unwrap_or(base);
+                let mut parsed = parse_push_args(&subcommand[1..], resolver);
+                if overrides.iter().any(|s| is_url_override(s)) {
+                    parsed = parsed.into_iter().flat_map(|p| retarget(p, resolver)).collect();
+                }
+                let runner = runner.or_else(|| payload_runner(&tokens[..i]));
+                // Refspecs only known at run time make a single unresolved push
+                if runner.is_some() && parsed.iter().any(|p| !p.non_branch) {
+                    parsed.retain(|p| !p.non_branch);
+                    parsed.truncate(1);
+                    parsed[0].branch.clear();
+                    parsed[0].source = None;
+                    parsed[0].deletion = false;
+                    parsed[0].unresolved = true;
+                }
+                for mut push in parsed {
+                    if push.force.is_force() {
+                        push.rewrite = rewrite.clone();
+                    }
+                    push.runner = runner;
+                    push.after_update = updated;
+                    pushes.push(push);
+                }
+                break;
+            }
+            if tokens[i] == "git"
+                && matches!(subcommand.first(), Some(&("pull" | "merge" | "rebase" | "reset")))
+            {
+                updated = true;
+            }
+            if tokens[i] == "git" {
+                if let Some(r) = detect_rewrite(tokens[i + 1], &tokens[i + 2..]) {
+                    rewrite = Some(r);
+                    break;
+                }
+            }
+            i += 1;
+        }
+    }
+    pushes
+}
+
+/// Splits the arguments after `git` into the directory given with `-C`, if
+/// any, and the subcommand with its arguments.
+fn git_invocation<'a>(args: &'a [&'a str]) -> (Option<&'a str>, &'a [&'a str]) {
+    let mut dir = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i] {
+            "-C" => {
+                dir = args.get(i + 1).copied();
+                i += 2;
+            }
+            "-c" | "--git-dir" | "--work-tree" | "--namespace" => i += 2,
+            a if a.starts_with('-') => i += 1,
+            _ => break,
+        }
+    }
+    (dir, &args[i.min(args.len())..])
+}
+
+/// Returns the `key=value` settings given with `-c` before the subcommand in
+/// the arguments after `git` that change where a push without an explicit
+/// destination goes, or which URLs a push goes to. Other keys are ignored.
+fn push_config_overrides(args: &[&str]) -> Vec<String> {
+    let (_, subcommand) = git_invocation(args);
+    let global = &args[..args.len() - subcommand.len()];
+    global
+        .windows(2)
+        .filter(|pair| pair[0] == "-c")
+        .map(|pair| pair[1].trim_matches(['"', '\'']).to_string())
+        .filter(|setting| {
+            let Some((key, _)) = setting.split_once('=') else {
+                return false;
+            };
+            let key = key.to_ascii_lowercase();
+            let branch_key = key.strip_prefix("branch.").and_then(|k| k.rsplit_once('.'));
+            matches!(key.as_str(), "push.default" | "remote.pushdefault")
+                || branch_key.is_some_and(|(_, k)| matches!(k, "pushremote" | "remote" | "merge"))
+                || is_url_override(setting)
+        })
+        .collect()
+}
+
+/// Returns true when the `key=value` setting changes the URL of a remote
+/// (`remote.<name>.url`, `remote.<name>.pushurl`) or rewrites URLs
+/// (`url.<base>.insteadOf`, `url.<base>.pushInsteadOf`).
+fn is_url_override(setting: &str) -> bool {
+    let key = setting.split_once('=').map_or(setting, |(key, _)| key).to_ascii_lowercase();
+    let remote_key = key.strip_prefix("remote.").and_then(|k| k.rsplit_once('.'));
+    let url_key = key.strip_prefix("url.").and_then(|k| k.rsplit_once('.'));
+    remote_key.is_some_and(|(_, k)| matches!(k, "url" | "pushurl"))
+        || url_key.is_some_and(|(_, k)| matches!(k, "insteadof" | "pushinsteadof"))
+}
+
+/// Splits `push` into one push per URL it goes to under `-c` settings that
+/// change those URLs, so each is checked against the URL policies on its own
+/// rather than against the remote's configured URL.
+fn retarget(push: PushInfo, resolver: &GitInfo) -> Vec<PushInfo> {
+    let urls = resolver.push_urls(&push.remote);
+    if push.remote_url.is_some() || urls.is_empty() {
+        return vec![push];
+    }
+    urls.into_iter()
+        .map(|url| PushInfo { remote_url: Some(url), ..push.clone() })
+        .collect()
+}
+
+/// Resolves `dir` as given to `cd` or `git -C` against `base`, expanding `~`.
+fn join_dir(base: Option<&Path>, dir: &str) -> PathBuf {
+    let dir = match dir.strip_prefix('~') {
+        Some(rest) => dirs::home_dir()
+            .map(|h| h.join(rest.trim_start_matches('/')))
+            .unwrap_or_else(|| PathBuf::from(dir)),
+        None => PathBuf::from(dir),
+    };
+    match base {
+        Some(b) => b.join(dir),
+        None => dir,
+    }
+}
+
+/// Recognizes `git commit --amend` and `git rebase` as history rewrites.
+fn detect_rewrite(subcommand: &str, args: &[&str]) -> Option<Rewrite> {
+    match subcommand {
+        "commit" if args.contains(&"--amend") => Some(Rewrite {
+            command: "git commit --amend",
+            base: Some("HEAD~1".to_string()),
+        }),
+        "rebase" => {
+            if args
+                .iter()
+                .any(|a| matches!(*a, "--abort" | "--quit" | "--edit-todo" | "--show-current-patch"))
+            {
+                return None;
+            }
+            let base = if args.contains(&"--root") {
+                None
+            } else {
+                let mut positional = Vec::new();
+                let mut j = 0;
+                while j < args.len() {
+                    match args[j] {
+                        "--onto" | "-x" | "--exec" | "-s" | "--strategy" | "-X" => j += 1,
+                        a if a.starts_with('-') => {}
+                        a => positional.push(a),
+                    }
+                    j += 1;
+                }
+                Some(positional.first().map_or("@{u}", |b| *b).to_string())
+            };
+            Some(Rewrite { command: "git rebase", base })
+        }
+        _ => None,
+    }
+}
+
+/// Parses the arguments of `git push` into one push per refspec, each with
+/// its own destination branch and whether it deletes it.
+fn parse_push_args(args: &[&str], git: &GitInfo) -> Vec<PushInfo> {
+    let mut force = Force::None;
+    let mut positional: Vec<&str> = vec![];
+    let mut push_options = Vec::new();
+    let mut dry_run = false;
+    let mut tags = false;
+    let mut delete = false;
+    let mut recurse_submodules = None;
+    let mut no_verify = false;
+    let mut stdin = false;
+    let mut all = false;
+    let mut mirror = false;
+    let mut prune = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i];
+        match arg {
+            "--force" | "-f" => force = Force::Unconditional,
+            // --force-if-includes only refines --force-with-lease; alone it forces nothing
+            a if a == "--force-with-lease" || a.starts_with("--force-with-lease=") => {
+                if force == Force::None {
+                    force = Force::WithLease;
+                }
+            }
+            "-o" | "--push-option" => {
+                push_options.extend(args.get(i + 1).map(|o| o.to_string()));
+                i += 1;
+            }
+            "--receive-pack" | "--exec" => {
+                i += 1; // these flags consume the next token
+            }
+            "--dry-run" | "-n" => dry_run = true,
+            "--tags" => tags = true,
+            "--delete" | "-d" => delete = true,
+            "--no-verify" => no_verify = true,
+            "--verify" => no_verify = false,
+            "--stdin" => stdin = true,
+            "--all" | "--branches" => all = true,
+            "--mirror" => mirror = true,
+            "--prune" => prune = true,
+            "--recurse-submodules" => {
+                let value = args.get(i + 1).filter(|v| RECURSE_SUBMODULES.contains(v));
+                if let Some(v) = value {
+                    recurse_submodules = Some(v.to_string());
+                    i += 1;
+                }
+            }
+            "--no-recurse-submodules" => recurse_submodules = Some("no".to_string()),
+            a if a.starts_with("--recurse-submodules=") => {
+                recurse_submodules = Some(a["--recurse-submodules=".len()..].to_string());
+            }
+            a if a.starts_with("--push-option=") => {
+                push_options.push(a["--push-option=".len()..].to_string());
+            }
+            a if a.starts_with('-') => {}
+            // `2>&1`, `>log`, or `>` with the file as the next word
+            a if redirection(a).is_some() => i += usize::from(redirection(a) == Some(true)),
+            _ => positional.push(arg),
+        }
+        i += 1;
+    }
+
+    // `git push HEAD:feature` names no remote, so git picks the push remote
+    let push_remote = positional.first().filter(|w| is_refspec(w, git)).map(|_| git.push_remote());
+    if let Some(remote) = &push_remote {
+        positional.insert(0, remote);
+    }
+
+    let recurse_submodules =
+        recurse_submodules.or_else(|| git.run(&["config", "push.recurseSubmodules"]));
+    let current = || git.current_branch().filter(|b| b != "HEAD");
+    let new_push = |remote: &str, branch: Option<String>, force: Force| {
+        let mut push = PushInfo::new(remote, branch.as_deref().unwrap_or_default(), force);
+        push.push_options = push_options.clone();
+        push.unresolved = branch.is_none();
+        push.non_branch = dry_run;
+        push.recurse_submodules = recurse_submodules.clone();
+        push.no_verify = no_verify;
+        push
+    };
+    let tags_push = |remote: &str| {
+        let mut push = new_push(remote, Some(String::new()), force);
+        push.non_branch = true;
+        push
+    };
+    // One unresolved push stands for every refspec only known at run time
+    let dynamic_push = |remote: &str, source: &str, force: Force| {
+        let mut push = new_push(remote, None, force);
+        push.dynamic = Some(source.to_string());
+        push
+    };
+    // Deletions of the branches `remote` has, as of the last fetch, that no
+    // local branch matches: what `--prune` and `--mirror` remove
+    let stale = |remote: &str, local: &[String]| -> Vec<PushInfo> {
+        let branches = git.remote_branches(remote).into_iter().filter(|b| !local.contains(b));
+        let deletion = |branch| {
+            let mut push = new_push(remote, Some(branch), force);
+            push.deletion = true;
+            push
+        };
+        branches.map(deletion).collect()
+    };
+    // `--all` pushes every local branch; `--mirror` forces them all too, and
+    // deletes the rest of the remote's branches
+    if all || mirror {
+        let remote = positional.first().map_or_else(|| git.push_remote(), |r| r.to_string());
+        let force = if mirror { Force::Unconditional } else { force };
+        let Some(local) = git.local_branches() else {
+            return vec![new_push(&remote, None, force)];
+        };
+        let mut pushes: Vec<PushInfo> =
+            local.iter().map(|b| new_push(&remote, Some(b.clone()), force)).collect();
+        if mirror || prune {
+            pushes.extend(stale(&remote, &local));
+        }
+        return pushes;
+    }
+    // With --stdin and no refspecs, only the refs read from stdin are pushed
+    if stdin && positional.len() < 2 {
+        let remote = positional.first().map_or_else(|| git.push_remote(), |r| r.to_string());
+        return vec![dynamic_push(&remote, "--stdin", force)];
+    }
+
+    let Some((remote, refspecs)) = positional.split_first() else {
+        if tags && !force.is_force() {
+            return vec![tags_push("origin")];
+        }
+        // No explicit remote or branch — git picks both from its push config
+        let remote = git.push_remote();
+        let branches = implicit_branches(git, &remote, false);
+        return branches.into_iter().map(|b| new_push(&remote, b, force)).collect();
+    };
+    if refspecs.is_empty() {
+        if tags && !force.is_force() {
+            return vec![tags_push(remote)];
+        }
+        let branches = implicit_branches(git, remote, true);
+        return branches.into_iter().map(|b| new_push(remote, b, force)).collect();
+    }
+
+    let mut pushes = Vec::new();
+    let mut refspecs = refspecs.iter().peekable();
+    while let Some(&refspec) = refspecs.next() {
+        // `tag <name>` is short for refs/tags/<name>
+        let refspec = match (refspec, refspecs.next_if(|_| refspec == "tag")) {
+            (_, Some(name)) => format!("refs/tags/{}", name),
+            _ => refspec.to_string(),
+        };
+        // A leading + forces this refspec alone
+        let spec = refspec.trim_start_matches('+');
+        let force = if spec.len() < refspec.len() { Force::Unconditional } else { force };
+        // `:` pushes every branch the remote has too; each must be allowed
+        if spec == ":" {
+            let matching = matching_branches(git, remote);
+            pushes.extend(matching.into_iter().map(|b| new_push(remote, b, force)));
+            continue;
+        }
+        // Forced tag pushes still go through the force rule as branch pushes
+        if !force.is_force() && pushes_tag(spec, git) {
+            pushes.push(tags_push(remote));
+            continue;
+        }
+        // A computed source is fine as long as the destination is literal
+        if is_dynamic(spec.split_once(':').map_or(spec, |(_, dst)| dst)) {
+            if !pushes.iter().any(|p| p.dynamic.is_some()) {
+                pushes.push(dynamic_push(remote, spec, force));
+            }
+            continue;
+        }
+        // Take the destination side of HEAD:main, feature:refs/heads/upstream
+        let (src, dst) = match spec.split_once(':') {
+            Some((src, dst)) => (Some(src), dst),
+            None => (None, spec),
+        };
+        // A bare HEAD pushes the current branch to the branch of that name
+        let branch = match (src, dst) {
+            (None, "HEAD") => current(),
+            _ => Some(dst.strip_prefix("refs/heads/").unwrap_or(dst).to_string()),
+        };
+        let mut push = new_push(remote, branch, force);
+        // An empty source deletes the destination
+        push.deletion = delete || src == Some("");
+        push.source = src.filter(|src| !src.is_empty()).map(|src| match src {
+            "HEAD" => current().unwrap_or_else(|| src.to_string()),
+            _ => src.to_string(),
+        });
+        pushes.push(push);
+    }
+    if stdin && !pushes.iter().any(|p| p.dynamic.is_some()) {
+        pushes.push(dynamic_push(remote, "--stdin", force));
+    }
+    // Only wildcard refspecs prune
+    if prune && positional[1..].iter().any(|r| r.contains('*')) {
+        pushes.extend(stale(remote, &git.local_branches().unwrap_or_default()));
+    }
+    pushes
+}
+
+/// Says where the branches of a push with refspecs only known at run time
+/// come from: "the branches of this push to 'origin' come from `$BRANCH`".
+fn dynamic_source(push: &PushInfo) -> String {
+    let source = match push.dynamic.as_deref().unwrap_or_default() {
+        "--stdin" => "are read from stdin (--stdin)".to_string(),
+        word if word.contains('`') || word.contains(SUBSTITUTION) => {
+            "come from a command substitution".to_string()
+        }
+        word => format!("come from `{}`", word.trim_matches(['"', '\''])),
+    };
+    format!("the branches of this push to '{}' {}", push.remote, source)
+}
+
+/// Explains why a push with refspecs only known at run time can't be
+/// checked, asking for the literal branch names instead.
+fn dynamic_message(push: &PushInfo) -> String {
+    format!(
+        "Could not determine the push target: {}, which is only known when the command runs.\n\
+         Re-run the push with the literal branch names instead, e.g. \
+         `git push {} <branch> <branch>...`.\n\
+         Set dynamic_refspecs = \"warn\" in the config to allow such pushes with a warning.",
+        dynamic_source(push),
+        push.remote
+    )
+}
+
+/// Returns true when the shell computes `word` at run time: it uses a
+/// variable or a command substitution.
+fn is_dynamic(word: &str) -> bool {
+    word.contains(['$', '`'])
+}
+
+/// Returns true when `word`, the first positional of `git push`, is a refspec
+/// rather than a remote, as in `git push HEAD:feature`: one starting with `+`
+/// or `refs/`, or `src:dst`. URLs such as `user@host:repo` and
+/// `github.com:org/repo` (a dotted source that is not a revision) are remotes.
+fn is_refspec(word: &str, git: &GitInfo) -> bool {
+    if word.starts_with('+') || word.starts_with("refs/") {
+        return true;
+    }
+    let Some((src, _)) = word.split_once(':') else {
+        return false;
+    };
+    if word.contains("://") || src.contains('@') {
+        return false;
+    }
+    src.is_empty() || !src.contains('.') || git.resolve_commit(src).is_some()
+}
+
+/// The branches a push to `remote` without refspecs updates, by
+/// `push.default`: the current branch, or every branch the remote has too
+/// with "matching". With "upstream", or with "simple" (the default) unless
+/// the push `names_remote`, it is the current branch's upstream branch
+/// instead when that is on `remote`. None stands for a branch not known.
+fn implicit_branches(git: &GitInfo, remote: &str, names_remote: bool) -> Vec<Option<String>> {
+    let current = git.current_branch().filter(|b| b != "HEAD");
+    let mode = git.run(&["config", "push.default"]).unwrap_or_default().to_ascii_lowercase();
+    match mode.as_str() {
+        "current" => vec![current],
+        "matching" => matching_branches(git, remote),
+        mode => {
+            let to_upstream = matches!(mode, "upstream" | "tracking") || !names_remote;
+            let upstream = git.tracking_info().filter(|(r, _)| to_upstream && r == remote);
+            vec![upstream.map(|(_, branch)| branch).or(current)]
+        }
+    }
+}
+
+/// The local branches `remote` has too, as of the last fetch, which a
+/// matching push updates; a single None when there are none.
+fn matching_branches(git: &GitInfo, remote: &str) -> Vec<Option<String>> {
+    let matching: Vec<Option<String>> = git
+        .local_branches()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|b| git.has_remote_branch(remote, b))
+        .map(Some)
+        .collect();
+    if matching.is_empty() {
+        return vec![None];
+    }
+    matching
+}
+
+/// Returns whether `word` is a shell redirection (`2>&1`, `>>log`, `&>`, `<in`)
+/// and, if so, whether its target is the next word.
+fn redirection(word: &str) -> Option<bool> {
+    let op = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let op = op.strip_prefix('&').unwrap_or(op);
+    let target = op
+        .strip_prefix(">>")
+        .or_else(|| op.strip_prefix('>'))
+        .or_else(|| op.strip_prefix('<'))?;
+    Some(target.strip_prefix('&').unwrap_or(target).is_empty())
+}
+
+/// Values `git push --recurse-submodules` accepts.
+const RECURSE_SUBMODULES: &[&str] = &["check", "on-demand", "only", "no"];
+
+/// Returns true when `refspec` pushes a tag rather than a branch.
+fn pushes_tag(refspec: &str, git: &GitInfo) -> bool {
+    let refspec = refspec.trim_start_matches('+');
+    let (src, dst) = refspec.split_once(':').unwrap_or((refspec, refspec));
+    if dst.starts_with("refs/tags/") {
+        return true;
+    }
+    if dst.starts_with("refs/") {
+        return false;
+    }
+    // A bare destination has the type of the source
+    src.starts_with("refs/tags/") || (git.is_tag(src) && git.branch_tip(src).is_none())
+}
+
+// ── Git helpers ───────────────────────────────────────────────────────────────
+
+/// Returns `repo`, or the enclosing repository root when omitted.
+fn resolve_repo(repo: Option<String>) -> Result<String> {
+    match repo {
+        Some(r) => Ok(canonical_repo(&r)),
+        None => {
+            let root = GitInfo::cwd().repo_key().context(
+                "Not inside a git repository; pass --repo <path> or run from a checkout",
+            )?;
+            eprintln!("Using repo '{}'", root);
+            Ok(root)
+        }
+    }
+}
+
+/// Maps a `--repo` path such as `.` or a subdirectory to the key state uses
+/// for its repository: the enclosing repo root, else the canonical path. Paths
+/// that do not exist (a removed checkout) are returned unchanged.
+fn canonical_repo(path: &str) -> String {
+    let Ok(canonical) = std::fs::canonicalize(path) else {
+        return path.to_string();
+    };
+    let canonical = canonical.to_string_lossy().into_owned();
+    GitInfo::for_repo(&canonical).repo_key().unwrap_or(canonical)
+}
+
+/// Resolves a `--repo` path that selects stored entries. A path that neither
+/// exists nor matches a stored repo is an error naming the stored repos it
+/// was probably meant to be, rather than an empty result.
+fn stored_repo(state: &State, path: &str) -> Result<String> {
+    let repo = canonical_repo(path);
+    let mut stored = state.repos();
+    stored.extend(state.remote_grants.keys().cloned());
+    if stored.contains(&repo) || std::path::Path::new(&repo).exists() {
+        return Ok(repo);
+    }
+    let given = repo.trim_end_matches('/');
+    let basename = given.rsplit('/').next().unwrap_or(given);
+    let mut suggestions: Vec<&String> = stored
+        .iter()
+        .filter(|k| {
+            let name = k.rsplit('/').next().unwrap_or(k);
+            k.starts_with(given) || given.starts_with(k.as_str()) || name == basename
+        })
+        .collect();
+    suggestions.sort();
+    suggestions.dedup();
+    let hint = if suggestions.is_empty() {
+        "; `push-guard list` shows the stored repos".to_string()
+    } else {
+        let list: Vec<String> = suggestions.iter().map(|k| format!("  {}", k)).collect();
+        format!("; did you mean:\n{}", list.join("\n"))
+    };
+    bail!("No entries for repo '{}'{}", path, hint)
+}
+
+/// Returns `branch`, or the current branch of `repo` when omitted.
+fn resolve_branch(git: &GitInfo, branch: Option<String>) -> Result<String> {
+    match branch {
+        Some(b) => Ok(b),
+        None => {
+            let current = git
+                .current_branch()
+                .filter(|b| b != "HEAD")
+                .context("Could not determine the current branch; pass --branch <name>")?;
+            eprintln!("Using branch '{}'", current);
+            Ok(current)
+        }
+    }
+}
+
+/// Returns `remote`, or the current branch's upstream remote in `repo`, else "origin".
+fn resolve_remote(git: &GitInfo, remote: Option<String>) -> String {
+    remote.unwrap_or_else(|| {
+        let upstream = git
+            .tracking_info()
+            .map(|(r, _)| r)
+            .unwrap_or_else(|| "origin".to_string());
+        eprintln!("Using remote '{}'", upstream);
+        upstream
+    })
+}
+
+/// Reads branch names for a bulk import, one per line. Blank lines and
+/// repeats are skipped; any invalid name fails the whole import.
+fn read_branch_list(input: impl BufRead) -> Result<Vec<String>> {
+    let mut branches: Vec<String> = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.context("Failed to read branch names")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let branch =
+            git::normalize_branch(&line).with_context(|| format!("Line {}", i + 1))?;
+        if !branches.contains(&branch) {
+            branches.push(branch);
+        }
+    }
+    Ok(branches)
+}
+
+/// Fails when `pattern` is given but not a valid glob, which would otherwise
+/// silently match nothing.
+fn check_glob(pattern: Option<&str>) -> Result<()> {
+    if let Some(p) = pattern {
+        glob::Pattern::new(p).with_context(|| format!("Invalid pattern '{}'", p))?;
+    }
+    Ok(())
+}
+
+/// Returns the local branches of `git` matching `pattern` (all when None),
+/// except the remote's default branch.
+fn local_branches_matching(git: &GitInfo, pattern: Option<&str>) -> Result<Vec<String>> {
+    check_glob(pattern)?;
+    let default = git.default_branch(&resolve_remote(git, None));
+    let branches = git.local_branches().context("Failed to list local branches")?;
+    Ok(branches
+        .into_iter()
+        .filter(|b| Some(b) != default.as_ref())
+        // Names git printed with bytes that are not UTF-8 would never match
+        .filter(|b| git::normalize_branch(b).is_ok())
+        .filter(|b| pattern.is_none_or(|p| config::glob_matches(p, b)))
+        .collect())
+}
+
+/// Returns the branches that `checkout: moving from <x> to <branch>` entries
+/// of the HEAD `reflog` switched to as they were created, i.e. at the `birth`
+/// of the branch, with that time. Newest first, from `since` on.
+fn reflog_creations(
+    reflog: &[(chrono::DateTime<chrono::Utc>, String)],
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    birth: impl Fn(&str) -> Option<chrono::DateTime<chrono::Utc>>,
+) -> Vec<(String, chrono::DateTime<chrono::Utc>)> {
+    let mut created: Vec<(String, chrono::DateTime<chrono::Utc>)> = Vec::new();
+    for (time, message) in reflog.iter().filter(|(t, _)| since.is_none_or(|s| *t >= s)) {
+        let Some((_, to)) = message.strip_prefix("checkout: moving from ").and_then(|m| {
+            // Branch names cannot contain spaces, so the last " to " separates them
+            m.rsplit_once(" to ")
+        }) else {
+            continue;
+        };
+        // Both entries are written by the same command, within a second
+        let born_here = birth(to).is_some_and(|b| (b - *time).num_seconds().abs() <= 1);
+        if born_here && !created.iter().any(|(b, _)| b == to) {
+            created.push((to.to_string(), *time));
+        }
+    }
+    created
+}
+
+/// Tracks the branches `reflog_creations` finds in `repo`, except those
+/// already tracked, the default and protected branches, and branches a
+/// remote already has. Asks first on a terminal unless `yes`.
+fn track_from_reflog(
+    git: &GitInfo,
+    repo: &str,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    yes: bool,
+    scope: Option<StateScope>,
+) -> Result<()> {
+    let reflog = git.reflog("HEAD").context("No HEAD reflog to read")?;
+    let birth = |branch: &str| {
+        let first = git.reflog(&format!("refs/heads/{}", branch))?.pop()?;
+        first.1.starts_with("branch: Created from").then_some(first.0)
+    };
+    let state = State::load_for(repo)?;
+    let mut found = Vec::new();
+    let mut skipped = Vec::new();
+    for (branch, created_at) in reflog_creations(&reflog, since, birth) {
+        let upstream = git.remote_branches_named(&branch);
+        let reason = if state.is_tracked(repo, &branch) {
+            "already tracked".to_string()
+        } else if !matches!(precedence_conflict(repo, &branch, false), Ok(None)) {
+            "default or protected branch".to_string()
+        } else if let Some(remote_branch) = upstream.first() {
+            format!("already on {}", remote_branch)
+        } else {
+            found.push((branch, created_at));
+            continue;
+        };
+        skipped.push((branch, reason));
+    }
+    if found.is_empty() {
+        eprintln!("No untracked branch creations in the reflog of '{}'", repo);
+    } else {
+        let count = found.len();
+        eprintln!("Found {} branch(es) created in '{}' according to the reflog:", count, repo);
+    }
+    for (branch, created_at) in &found {
+        eprintln!("  {}  created {}", branch, timefmt::timestamp(*created_at, false));
+    }
+    for (branch, reason) in &skipped {
+        eprintln!("  {}  skipped: {}", branch, reason);
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    if !yes && !confirm("Track them?") {
+        bail!("Nothing changed; pass --yes to track them");
+    }
+    let added = State::update_for(repo, |state| {
+        state.write_scope = scope.map(Into::into);
+        let mut added = 0;
+        for (branch, _) in &found {
+            if !state.is_tracked(repo, branch) {
+                state.track(repo, branch);
+                added += 1;
+            }
+        }
+        added
+    })?;
+    eprintln!("Tracked {} new branch(es) in '{}'", added, repo);
+    Ok(())
+}
+
+// ── Authorization logic ───────────────────────────────────────────────────────
+
+/// The rule that produced a decision.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Rule {
+    EmptyBranch,
+    UnresolvedTarget,
+    NonBranch,
+    ForcePush,
+    DefaultBranch,
+    ProtectedBranch,
+    Tracked,
+    Authorized,
+    Untracked,
+    Disabled,
+    TrustedDir,
+    TrustedRemote,
+    GithubProtection,
+    ShaMismatch,
+    ForceWithLeaseTracked,
+    OtherSession,
+    EnvDisabled,
+    EnvAllow,
+    CommandPattern,
+    PushOption,
+    RenamedPush,
+    NewBranchPublish,
+    UnfinishedOperation,
+    SubmodulePush,
+    GhOperation,
+    AutoTrackedPrefix,
+    RemoteGrant,
+    RestrictedHours,
+    NoVerify,
+    LocalRemote,
+    UndecodableBranch,
+    SubagentPush,
+    DeniedRemote,
+}
+
+impl Rule {
+    /// Every rule, in declaration order.
+    const ALL: [Rule; 33] = [
+        Rule::EmptyBranch,
+        Rule::UnresolvedTarget,
+        Rule::NonBranch,
+        Rule::ForcePush,
+        Rule::DefaultBranch,
+        Rule::ProtectedBranch,
+        Rule::Tracked,
+        Rule::Authorized,
+        Rule::Untracked,
+        Rule::Disabled,
+        Rule::TrustedDir,
+        Rule::TrustedRemote,
+        Rule::GithubProtection,
+        Rule::ShaMismatch,
+        Rule::ForceWithLeaseTracked,
+        Rule::OtherSession,
+        Rule::EnvDisabled,
+        Rule::EnvAllow,
+        Rule::CommandPattern,
+        Rule::PushOption,
+        Rule::RenamedPush,
+        Rule::NewBranchPublish,
+        Rule::UnfinishedOperation,
+        Rule::SubmodulePush,
+        Rule::GhOperation,
+        Rule::AutoTrackedPrefix,
+        Rule::RemoteGrant,
+        Rule::RestrictedHours,
+        Rule::NoVerify,
+        Rule::LocalRemote,
+        Rule::UndecodableBranch,
+        Rule::SubagentPush,
+        Rule::DeniedRemote,
+    ];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Rule::EmptyBranch => "empty_branch",
+            Rule::UnresolvedTarget => "unresolved_target",
+            Rule::NonBranch => "non_branch",
+            Rule::ForcePush => "force_push",
+            Rule::DefaultBranch => "default_branch",
+            Rule::ProtectedBranch => "protected_branch",
+            Rule::Tracked => "tracked",
+            Rule::Authorized => "authorized",
+            Rule::Untracked => "untracked",
+            Rule::Disabled => "disabled",
+            Rule::TrustedDir => "trusted_dir",
+            Rule::TrustedRemote => "trusted_remote",
+            Rule::GithubProtection => "github_protection",
+            Rule::ShaMismatch => "sha_mismatch",
+            Rule::ForceWithLeaseTracked => "force_with_lease_tracked",
+            Rule::OtherSession => "other_session",
+            Rule::EnvDisabled => "env_disabled",
+            Rule::EnvAllow => "env_allow",
+            Rule::CommandPattern => "command_rule",
+            Rule::PushOption => "push_option",
+            Rule::RenamedPush => "renamed_push",
+            Rule::NewBranchPublish => "new_branch_publish",
+            Rule::UnfinishedOperation => "unfinished_operation",
+            Rule::SubmodulePush => "submodule_push",
+            Rule::GhOperation => "gh_operation",
+            Rule::AutoTrackedPrefix => "auto_tracked_prefix",
+            Rule::RemoteGrant => "remote_grant",
+            Rule::RestrictedHours => "restricted_hours",
+            Rule::NoVerify => "no_verify",
+            Rule::LocalRemote => "local_remote",
+            Rule::UndecodableBranch => "undecodable_branch",
+            Rule::SubagentPush => "subagent_push",
+            Rule::DeniedRemote => "denied_remote",
+        }
+    }
+
+    /// The flag an authorization needs to allow a push this rule blocks
+    /// (often none, hence ""), or None when no authorization allows it.
+    fn authorize_flag(self) -> Option<&'static str> {
+        match self {
+            Rule::DefaultBranch => Some(" --allow-default"),
+            Rule::RestrictedHours => Some(" --override-hours"),
+            Rule::ForcePush
+            | Rule::UnresolvedTarget
+            | Rule::UndecodableBranch
+            | Rule::DeniedRemote
+            | Rule::CommandPattern
+            | Rule::GithubProtection => None,
+            _ => Some(""),
+        }
+    }
+
+    /// Why a push this rule allows may go ahead, for allow notices.
+    fn allow_reason(self) -> &'static str {
+        match self {
+            Rule::Tracked => "branch created by Claude",
+            Rule::Authorized => "authorized",
+            Rule::AutoTrackedPrefix => "matched auto-tracked prefix",
+            Rule::ForceWithLeaseTracked => "--force-with-lease to a branch created by Claude",
+            Rule::NewBranchPublish => "publishes a branch the remote does not have",
+            Rule::TrustedRemote => "trusted remote URL",
+            Rule::RemoteGrant => "remote allowed with allow-remote",
+            Rule::LocalRemote => "remote is a local path",
+            Rule::EnvAllow => "PUSH_GUARD_ALLOW",
+            Rule::EmptyBranch => "empty_branch = \"allow\"",
+            Rule::UnresolvedTarget => "dynamic_refspecs = \"warn\"",
+            Rule::NonBranch => "updates no branch",
+            _ => "allowed",
+        }
+    }
+}
+
+enum Decision {
+    Allow(Rule),
+    Block(Rule, String),
+    /// Evaluation was skipped entirely (disabled repo or trusted directory).
+    Skip(Rule, String),
+}
+
+impl Decision {
+    fn rule(&self) -> Rule {
+        match self {
+            Decision::Allow(rule) | Decision::Block(rule, _) | Decision::Skip(rule, _) => *rule,
+        }
+    }
+
+    /// The decision as recorded in the audit log.
+    fn label(&self) -> &'static str {
+        match self {
+            Decision::Allow(_) => "allow",
+            Decision::Block(..) => "block",
+            Decision::Skip(..) => "skipped",
+        }
+    }
+
+    fn is_block(&self) -> bool {
+        matches!(self, Decision::Block(..))
+    }
+}
+
+fn evaluate(git: &GitInfo, repo: &str, push: &PushInfo) -> Result<Decision> {
+    let decision = evaluate_rules(git, repo, push)?;
+    let Decision::Block(rule, msg) = decision else {
+        return Ok(decision);
+    };
+    let mut context = String::new();
+    if push.deletion {
+        context += &format!("Deleting remote branch '{}'.\n", push.branch);
+    }
+    // A tracked source name must not hide which branch is actually updated
+    if let Some(source) = push.renamed_from() {
+        context += &format!("Pushing local '{}' to remote '{}'.\n", source, push.branch);
+    }
+    // With several remotes, the remote's name alone may not say which project it is
+    if let Some(project) = push.project(git) {
+        context += &format!("Destination: {}\n", remote_label(&push.remote, Some(&project)));
+    }
+    Ok(Decision::Block(rule, context + &msg))
+}
+
+/// Returns the first URL `push` goes to that `denied_remote_urls` denies,
+/// with the pattern denying it.
+fn denied_remote(git: &GitInfo, config: &Config, push: &PushInfo) -> Option<(String, String)> {
+    push.push_urls(git).into_iter().find_map(|url| {
+        let pattern = config.denied_remote(&url)?.to_string();
+        Some((url, pattern))
+    })
+}
+
+/// Renders a remote with the project it points at: "origin → github.com/acme/api".
+fn remote_label(remote: &str, project: Option<&str>) -> String {
+    match project {
+        Some(project) => format!("{} → {}", remote, project),
+        None => remote.to_string(),
+    }
+}
+
+fn evaluate_rules(git: &GitInfo, repo: &str, push: &PushInfo) -> Result<Decision> {
+    let (remote, branch, force) = (push.remote.as_str(), push.branch.as_str(), push.force);
+    if config::env_disabled() {
+        return Ok(Decision::Skip(
+            Rule::EnvDisabled,
+            "PUSH_GUARD_DISABLE=1 is set; pushes are not being checked".to_string(),
+        ));
+    }
+    let config = Config::load(Some(repo))?;
+    // Before anything that could skip or allow the push: only the config lifts this
+    if let Some((url, pattern)) = denied_remote(git, &config, push) {
+        return Ok(Decision::Block(Rule::DeniedRemote, format!(
+            "Pushing to {} is not allowed on this machine: it matches '{}' in \
+             denied_remote_urls.\n\
+             No authorization overrides this; do not push there.",
+            url, pattern
+        )));
+    }
+    if !config.is_enabled() {
+        return Ok(Decision::Skip(
+            Rule::Disabled,
+            format!("push-guard is disabled for '{}'", repo),
+        ));
+    }
+    if let Some(dir) = config.trusted_dir(repo) {
+        if !(force.is_force() && config.force_rule_in_trusted_dirs.unwrap_or(false)) {
+            return Ok(Decision::Skip(
+                Rule::TrustedDir,
+                format!("'{}' is inside trusted directory '{}'", repo, dir),
+            ));
+        }
+    }
+
+    if let Some(msg) = &push.rule_block {
+        return Ok(Decision::Block(Rule::CommandPattern, msg.clone()));
+    }
+
+    if let Some(operation) = &push.gh_operation {
+        if !branch.is_empty() {
+            let state = State::load_for(repo)?;
+            if let Some(decision) = authorization_decision(git, &state, repo, push) {
+                return Ok(decision);
+            }
+        }
+        return Ok(Decision::Block(Rule::GhOperation, operation.message(branch)));
+    }
+
+    if push.non_branch {
+        return Ok(Decision::Allow(Rule::NonBranch));
+    }
+
+    // Loops and xargs have their own hint below
+    if push.dynamic.is_some() && push.runner.is_none() {
+        if config.warns_on_dynamic_refspecs() {
+            return Ok(Decision::Allow(Rule::UnresolvedTarget));
+        }
+        return Ok(Decision::Block(Rule::UnresolvedTarget, dynamic_message(push)));
+    }
+
+    if branch.is_empty() {
+        if config.allows_empty_branch() {
+            return Ok(Decision::Allow(Rule::EmptyBranch));
+        }
+        let hint = if let Some(runner) = push.runner {
+            format!(
+                "\nThe branch is only known once {} runs the push; push each branch \
+                 explicitly instead, e.g. `git push -u {} <branch>`.",
+                runner, remote
+            )
+        } else if push.unresolved {
+            format!("\nName the branch explicitly, e.g. `git push -u {} <branch>`.", remote)
+        } else {
+            String::new()
+        };
+        return Ok(Decision::Block(
+            Rule::UnresolvedTarget,
+            format!(
+                "Could not determine the push target: no branch for this push to '{}'.{}\n\
+                 Set empty_branch = \"allow\" in the config to allow such pushes.",
+                remote, hint
+            ),
+        ));
+    }
+
+    // Git output is decoded lossily, so such a name matches no entry reliably
+    if branch.contains(char::REPLACEMENT_CHARACTER) {
+        return Ok(Decision::Block(
+            Rule::UndecodableBranch,
+            format!(
+                "Branch name '{}' contains bytes that are not valid UTF-8 (shown as '{}'), \
+                 so it cannot be matched against tracked or authorized branches.\n\
+                 Rename the branch with `git branch -m <new-name>` and push that instead.",
+                branch,
+                char::REPLACEMENT_CHARACTER
+            ),
+        ));
+    }
+
+    let ignore_case = config.case_insensitive_branches.unwrap_or(false);
+    let remote_url = push.url(git);
+    let is_default = |default: Option<String>| {
+        default.is_some_and(|d| state::same_branch(&d, branch, ignore_case))
+    };
+    let load_state = || {
+        State::load_for(repo).map(|mut state| {
+            state.ignore_case = ignore_case;
+            state
+        })
+    };
+
+    let session_scoped = config.session_scoped_tracking.unwrap_or(false);
+    // Such pushes never count as Claude's own, only authorizations allow them
+    let subagent = push
+        .agent
+        .as_ref()
+        .filter(|_| config.subagents_require_authorization.unwrap_or(false));
+    // Created by this command, or recently by one whose tracking write failed
+    let just_created = push.just_created || pending::is_pending(repo, branch, ignore_case);
+    let is_tracked = |state: &State| {
+        if subagent.is_some() {
+            false
+        } else if just_created {
+            true
+        } else if session_scoped {
+            state.is_tracked_in(repo, branch, push.session.as_deref())
+        } else {
+            state.is_tracked(repo, branch)
+        }
+    };
+
+    // Even a tracked branch may hold a half-rebased history at this point
+    let operation = git.operation_in_progress().filter(|_| config.blocks_during_rebase_or_merge());
+    if let Some(op) = operation {
+        if let Some(decision) = authorization_decision(git, &load_state()?, repo, push) {
+            return Ok(decision);
+        }
+        return Ok(Decision::Block(Rule::UnfinishedOperation, format!(
+            "'{}' has an unfinished {} in progress; the branch may be half-done.\n\
+             Recommendation: finish it with `git {} --continue` (or undo it with \
+             `git {} --abort`), then push.\n\
+             To push anyway, say \"authorize push to {}\"",
+            repo, op, op, op, branch
+        )));
+    }
+
+    if push.pushes_submodules() {
+        if let Some(decision) = authorization_decision(git, &load_state()?, repo, push) {
+            return Ok(decision);
+        }
+        let unpushed = git.submodules_with_unpushed_commits();
+        let listed = if unpushed.is_empty() {
+            String::new()
+        } else {
+            format!("Submodules with unpushed commits: {}\n", unpushed.join(", "))
+        };
+        return Ok(Decision::Block(Rule::SubmodulePush, format!(
+            "`--recurse-submodules={}` also pushes submodules to their own remotes,\n\
+             and those pushes can't be vetted individually.\n\
+             {}Recommendation: push each submodule from its own directory, then push \
+             without the flag.\n\
+             To push anyway, say \"authorize push to {}\"",
+            push.recurse_submodules.as_deref().unwrap_or_default(), listed, branch
+        )));
+    }
+
+    // An `allow-remote` grant, unless the remote's URL has changed or become
+    // protected since
+    let remote_protected =
+        remote_url.as_deref().is_some_and(|url| config.protected_remote(url).is_some());
+    let granted = !remote_protected
+        && load_state()?.remote_grant(repo, remote).is_some_and(|g| {
+            g.applies_to(&push.push_urls(git))
+                && (g.include_force || !(force.is_force() || push.deletion))
+        });
+
+    if force.is_force() && !granted {
+        if force == Force::WithLease
+            && config.allow_force_with_lease_on_tracked.unwrap_or(false)
+            && config.protected_pattern_on(branch, remote, remote_url.as_deref()).is_none()
+            && !is_default(push.default_branch(git))
+            && is_tracked(&load_state()?)
+        {
+            return Ok(Decision::Allow(Rule::ForceWithLeaseTracked));
+        }
+        // Only when the config says so does PUSH_GUARD_ALLOW lift the force rule
+        if !config.force_rule_ignores_env_allow.unwrap_or(true)
+            && config::env_allowed(repo, branch).is_some()
+        {
+            return Ok(Decision::Allow(Rule::EnvAllow));
+        }
+        let context = match &push.rewrite {
+            Some(r) => rewrite_context(git, r, remote, branch),
+            None => discarded_context(git, push, remote, branch),
+        };
+        let message = block_message(&config, MessageKind::Force, git, repo, push, &[
+            ("flag", force.flag()),
+            ("context", &context),
+        ]);
+        return Ok(Decision::Block(Rule::ForcePush, message));
+    }
+
+    let risky_option = push
+        .push_options
+        .iter()
+        .find_map(|o| config.risky_push_option(o).map(|p| (o, p)));
+    if let Some((option, pattern)) = risky_option {
+        if let Some(decision) = authorization_decision(git, &load_state()?, repo, push) {
+            return Ok(decision);
+        }
+        return Ok(Decision::Block(Rule::PushOption, format!(
+            "Push option '{}' (matches '{}') can land '{}' without review.\n\
+             Recommendation: push without it and merge through the web UI.\n\
+             To push with it, say \"authorize push to {}\"",
+            option, pattern, branch, branch
+        )));
+    }
+
+    if push.no_verify && config.block_no_verify.unwrap_or(false) {
+        if let Some(decision) = authorization_decision(git, &load_state()?, repo, push) {
+            return Ok(decision);
+        }
+        return Ok(Decision::Block(Rule::NoVerify, format!(
+            "`--no-verify` skips the pre-push hook of '{}', which may run checks of its own.\n\
+             Recommendation: push without it, and fix what the hook reports.\n\
+             To push with it, say \"authorize push to {}\"",
+            repo, branch
+        )));
+    }
+
+    // Before anything that allows pushes to default or protected branches
+    if config.restricted_hours.restricts(chrono::Utc::now())
+        && (config.protected_pattern_on(branch, remote, remote_url.as_deref()).is_some()
+            || is_default(push.default_branch(git)))
+        && !load_state()?.authorization(repo, branch).is_some_and(|e| e.override_hours)
+    {
+        return Ok(Decision::Block(Rule::RestrictedHours, format!(
+            "Pushes to '{}' are not allowed {}.\n\
+             Recommendation: push to a feature branch now, and to '{}' outside those hours.\n\
+             To push anyway, say \"authorize push to {} during restricted hours\"",
+            branch, config.restricted_hours.describe(), branch, branch
+        )));
+    }
+
+    if granted {
+        return Ok(Decision::Allow(Rule::RemoteGrant));
+    }
+    if remote_url.as_deref().is_some_and(|url| config.trusted_remote(url).is_some()) {
+        return Ok(Decision::Allow(Rule::TrustedRemote));
+    }
+    // Pushing to a directory on this machine (a test fixture) risks nothing
+    if !remote_protected
+        && !push.deletion
+        && config.allow_local_remotes.unwrap_or(true)
+        && remote_url.as_deref().is_some_and(git::is_local_url)
+    {
+        return Ok(Decision::Allow(Rule::LocalRemote));
+    }
+
+    if is_default(push.default_branch(git)) {
+        let state = load_state()?;
+        let authorization = state.authorization(repo, branch);
+        if authorization.is_some_and(|e| e.allow_default) {
+            if let Some(decision) = authorization_decision(git, &state, repo, push) {
+                return Ok(decision);
+            }
+        }
+        let suggested = suggest_branch(git, config.branch_prefix());
+        let mut message = block_message(&config, MessageKind::DefaultBranch, git, repo, push, &[
+            ("suggested", &suggested),
+        ]);
+        if authorization.is_some() {
+            message += "\nNote: authorization exists but the default-branch rule takes \
+                        precedence; authorize with --allow-default to override";
+        }
+        return Ok(Decision::Block(Rule::DefaultBranch, message));
+    }
+
+    if config.github_protection.unwrap_or(false) && !config.is_offline() {
+        let github = remote_url.as_deref().and_then(github::parse_remote);
+        if let Some((owner, name)) = github {
+            if github::requires_pull_request(&owner, &name, branch) == Some(true) {
+                return Ok(Decision::Block(Rule::GithubProtection, format!(
+                    "'{}' is protected on GitHub ({}/{}) and requires a pull request.\n\
+                     GitHub would reject a direct push.\n\
+                     Recommendation: push to a feature branch and open a pull request.",
+                    branch, owner, name
+                )));
+            }
+        }
+    }
+
+    let state = load_state()?;
+    if let Some(pattern) = config.protected_pattern_on(branch, remote, remote_url.as_deref()) {
+        if let Some(decision) = authorization_decision(git, &state, repo, push) {
+            return Ok(decision);
+        }
+        let message = block_message(&config, MessageKind::Denied, git, repo, push, &[
+            ("pattern", pattern),
+        ]);
+        return Ok(Decision::Block(Rule::ProtectedBranch, message));
+    }
+
+    if push.renamed_from().is_some() && config.renamed_push_requires_authorization.unwrap_or(false)
+    {
+        if let Some(decision) = authorization_decision(git, &state, repo, push) {
+            return Ok(decision);
+        }
+        return Ok(Decision::Block(Rule::RenamedPush, format!(
+            "Pushes to a branch of a different name require authorization in '{}',\n\
+             even when the local branch was created by me.\n\
+             To authorize: say \"authorize push to {}\"",
+            repo, branch
+        )));
+    }
+
+    if is_tracked(&state) {
+        return Ok(Decision::Allow(Rule::Tracked));
+    }
+    // Only updates: deleting a branch needs it tracked or authorized by name
+    if !push.deletion
+        && subagent.is_none()
+        && config.auto_tracked_prefix(branch, ignore_case).is_some()
+    {
+        return Ok(Decision::Allow(Rule::AutoTrackedPrefix));
+    }
+    if let Some(decision) = authorization_decision(git, &state, repo, push) {
+        return Ok(decision);
+    }
+    // Publishing a new branch can't overwrite anyone's work; updates can
+    if config.allow_new_branch_publish.unwrap_or(false) && !git.has_remote_branch(remote, branch)
+    {
+        return Ok(Decision::Allow(Rule::NewBranchPublish));
+    }
+
+    if let Some(agent) = subagent.filter(|_| state.is_tracked(repo, branch)) {
+        return Ok(Decision::Block(Rule::SubagentPush, format!(
+            "Branch '{}' was created by me, but this push comes from {},\n\
+             and pushes by subagents need authorization in '{}'.\n\
+             To authorize: say \"authorize push to {}\"",
+            branch, agent.describe(), repo, branch
+        )));
+    }
+
+    if session_scoped && state.is_tracked(repo, branch) {
+        return Ok(Decision::Block(Rule::OtherSession, format!(
+            "Branch '{}' was created by me in a different session.\n\
+             With session-scoped tracking it needs authorization now.\n\
+             To authorize: say \"authorize push to {}\"",
+            branch, branch
+        )));
+    }
+
+    let message = block_message(&config, MessageKind::Untracked, git, repo, push, &[]);
+    Ok(Decision::Block(Rule::Untracked, message))
+}
+
+/// Renders the configured (or built-in) template for `kind` with the common
+/// placeholders plus the rule-specific `extra` ones.
+fn block_message(
+    config: &Config,
+    kind: MessageKind,
+    git: &GitInfo,
+    repo: &str,
+    push: &PushInfo,
+    extra: &[(&str, &str)],
+) -> String {
+    let template = config.message.template(kind);
+    let (remote, branch) = (push.remote.as_str(), push.branch.as_str());
+    // Only look the default branch up when the template asks for it
+    let default_branch = if template.contains("{default_branch}") {
+        push.default_branch(git).unwrap_or_default()
+    } else {
+        String::new()
+    };
+    let authorize_cmd = format!("push-guard authorize --repo '{}' --branch '{}'", repo, branch);
+    let mut vars = vec![
+        ("branch", branch),
+        ("remote", remote),
+        ("repo", repo),
+        ("default_branch", default_branch.as_str()),
+        ("authorize_cmd", authorize_cmd.as_str()),
+    ];
+    vars.extend_from_slice(extra);
+    config::render(template, &vars)
+}
+
+/// Explains a history rewrite preceding a force push: which command rewrote
+/// history and which commits on the remote branch would be replaced.
+fn rewrite_context(git: &GitInfo, rewrite: &Rewrite, remote: &str, branch: &str) -> String {
+    let mut out = format!(
+        "History is rewritten by `{}` earlier in this command.\n",
+        rewrite.command
+    );
+    let target = format!("refs/remotes/{}/{}", remote, branch);
+    let range = match &rewrite.base {
+        Some(base) => format!("{}..{}", base, target),
+        None => target,
+    };
+    match git.log_oneline(&range) {
+        Some(commits) if !commits.is_empty() => {
+            out.push_str(&format!("Commits on '{}/{}' that would be replaced:\n", remote, branch));
+            for c in commits {
+                out.push_str(&format!("  {}\n", c));
+            }
+        }
+        Some(_) => out.push_str(&format!(
+            "No commits on '{}/{}' would be replaced as of the last fetch.\n",
+            remote, branch
+        )),
+        None => out.push_str(&format!(
+            "'{}/{}' is not known locally; fetch to see which commits would be replaced.\n",
+            remote, branch
+        )),
+    }
+    out
+}
+
+/// Commits of a force push's summary: at most this many are listed.
+const DISCARDED_LISTED: usize = 5;
+
+/// Summarizes the commits on `remote`/`branch` a force push of `push` would
+/// discard, from local refs only. Empty when the remote-tracking branch or
+/// the pushed commit is not known locally, or the pushed commit is only known
+/// once the command ran.
+fn discarded_context(git: &GitInfo, push: &PushInfo, remote: &str, branch: &str) -> String {
+    if push.deletion || push.after_update {
+        return String::new();
+    }
+    let source = push.source.as_deref().unwrap_or(branch);
+    let Some(commits) = git.discarded_commits(source, remote, branch) else {
+        return String::new();
+    };
+    if commits.is_empty() {
+        return format!(
+            "No commits on '{}/{}' would be discarded as of the last fetch.\n",
+            remote, branch
+        );
+    }
+    let mut out = format!(
+        "{} on '{}/{}' would be discarded (as of the last fetch):\n",
+        match commits.len() {
+            1 => "1 commit".to_string(),
+            n => format!("{} commits", n),
+        },
+        remote,
+        branch
+    );
+    for c in commits.iter().take(DISCARDED_LISTED) {
+        out.push_str(&format!("  {} {}\n", c.sha, c.subject));
+    }
+    if commits.len() > DISCARDED_LISTED {
+        out.push_str(&format!("  ... and {} more\n", commits.len() - DISCARDED_LISTED));
+    }
+    if let Some(me) = git.user_email() {
+        let mut others: Vec<&str> = Vec::new();
+        for c in commits.iter().filter(|c| !c.author_email.eq_ignore_ascii_case(&me)) {
+            if !others.contains(&c.author_email.as_str()) {
+                others.push(&c.author_email);
+            }
+        }
+        if !others.is_empty() {
+            out.push_str(&format!("Some are not authored by you: {}\n", others.join(", ")));
+        }
+    }
+    out
+}
+
+/// Returns the decision an authorization for `branch` leads to, or None when
+/// there is none. Authorizations bound to a commit only allow pushing while the
+/// local branch tip is still that commit. A `PUSH_GUARD_ALLOW` entry for the
+/// branch stands in for an authorization.
+fn authorization_decision(
+    git: &GitInfo,
+    state: &State,
+    repo: &str,
+    push: &PushInfo,
+) -> Option<Decision> {
+    let branch = push.branch.as_str();
+    if config::env_allowed(repo, branch).is_some() {
+        return Some(Decision::Allow(Rule::EnvAllow));
+    }
+    let entry = state.authorization(repo, branch)?;
+    let Some(sha) = &entry.sha else {
+        return Some(Decision::Allow(Rule::Authorized));
+    };
+    // The commit pushed, which a refspec (`other:feature`) can take from any ref
+    let source = push.source.as_deref().unwrap_or(branch);
+    let pushed = git.resolve_commit(source);
+    if pushed.as_deref() == Some(sha.as_str()) {
+        return Some(Decision::Allow(Rule::Authorized));
+    }
+    let short = &sha[..sha.len().min(12)];
+    let problem = match pushed {
+        None => format!(
+            "Could not resolve the commit pushed to '{}' ('{}') to check it against the \
+             authorization at {}.",
+            branch, source, short
+        ),
+        Some(_) if source != branch => format!(
+            "'{}' is not the commit '{}' was authorized at ({}).",
+            source, branch, short
+        ),
+        Some(_) => format!("'{}' has new commits since it was authorized at {}.", branch, short),
+    };
+    Some(Decision::Block(Rule::ShaMismatch, format!(
+        "{}\n\
+         Review what is pushed, then authorize again: \
+         push-guard authorize --repo '{}' --branch '{}'",
+        problem, repo, branch
+    )))
+}
+
+/// Explains why tracking (`authorizing` false) or authorizing `branch` would
+/// not allow pushing it: it is the default branch of one of `repo`'s remotes,
+/// whose rule is checked first, or for tracking, it matches a protected
+/// pattern. None when the entry would take effect, or `repo` is not on disk.
+fn precedence_conflict(repo: &str, branch: &str, authorizing: bool) -> Result<Option<String>> {
+    if !Path::new(repo).is_dir() {
+        return Ok(None);
+    }
+    let git = GitInfo::for_repo(repo);
+    let config = Config::load(Some(repo))?;
+    let ignore_case = config.case_insensitive_branches.unwrap_or(false);
+    let mut remotes: Vec<String> =
+        git.run(&["remote"]).unwrap_or_default().lines().map(str::to_string).collect();
+    if !remotes.iter().any(|r| r == "origin") {
+        remotes.push("origin".to_string());
+    }
+    for remote in &remotes {
+        // The recorded answer: tracking must not ask the remote
+        let default = git.recorded_default_branch(remote);
+        if default.is_some_and(|d| state::same_branch(&d, branch, ignore_case)) {
+            let fix = if authorizing {
+                "authorize with --allow-default to override it"
+            } else {
+                "authorize it with --allow-default instead"
+            };
+            return Ok(Some(format!(
+                "'{}' is the default branch of '{}'. The default-branch rule takes \
+                 precedence over {}, so pushes to it stay blocked; {}.",
+                branch,
+                remote,
+                if authorizing { "authorizations" } else { "tracking" },
+                fix
+            )));
+        }
+        let url = git.remote_url(remote);
+        let pattern = config.protected_pattern_on(branch, remote, url.as_deref());
+        if let Some(pattern) = pattern.filter(|_| !authorizing) {
+            return Ok(Some(format!(
+                "'{}' matches protected pattern '{}' on '{}'. Protected branches are \
+                 checked before tracking, so pushes to it stay blocked; authorize it instead.",
+                branch, pattern, remote
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// Warns about each of `branches` whose entry would not take effect (see
+/// `precedence_conflict`), and fails unless `yes` confirms it anyway.
+fn confirm_precedence(repo: &str, branches: &[String], authorizing: bool, yes: bool) -> Result<()> {
+    let mut conflicts = 0;
+    for branch in branches {
+        if let Some(warning) = precedence_conflict(repo, branch, authorizing)? {
+            eprintln!("Warning: {}", warning);
+            conflicts += 1;
+        }
+    }
+    if conflicts > 0 && !yes {
+        let verb = if authorizing { "authorize" } else { "track" };
+        bail!("Nothing changed; pass --yes to {} anyway", verb);
+    }
+    Ok(())
+}
+
+/// Splits `branches` into those a bulk `authorize` or `revoke` changes and
+/// the default or protected ones it keeps, unless `include_protected`. A
+/// branch whose rules cannot be checked is kept.
+fn split_guarded(
+    repo: &str,
+    branches: Vec<String>,
+    include_protected: bool,
+) -> (Vec<String>, Vec<String>) {
+    if include_protected {
+        return (branches, Vec::new());
+    }
+    branches.into_iter().partition(|b| matches!(precedence_conflict(repo, b, false), Ok(None)))
+}
+
+/// Notes the branches a bulk `verb` left alone by `split_guarded`.
+fn report_kept(kept: &[String], verb: &str) {
+    if !kept.is_empty() {
+        eprintln!(
+            "Skipped default or protected branch(es) {}; pass --include-protected to {} them too",
+            kept.join(", "),
+            verb
+        );
+    }
+}
+
+/// Suggests a feature branch named after the last commit, e.g.
+/// "claude/fix-login-redirect", that does not exist locally yet.
+fn suggest_branch(git: &GitInfo, prefix: &str) -> String {
+    let subject = git.head_subject().unwrap_or_default();
+    let existing = git.local_branches().unwrap_or_default();
+    unique_branch(&format!("{}{}", prefix, slugify(&subject)), &existing)
+}
+
+/// Lowercases `text` and joins its first few words with dashes.
+fn slugify(text: &str) -> String {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .take(6)
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if words.is_empty() {
+        return "changes".to_string();
+    }
+    words.join("-")
+}
+
+/// Returns `base`, or `base-2`, `base-3`, ... when it is already taken.
+fn unique_branch(base: &str, existing: &[String]) -> String {
+    let taken = |name: &str| existing.iter().any(|b| b == name);
+    if !taken(base) {
+        return base.to_string();
+    }
+    (2..)
+        .map(|n| format!("{}-{}", base, n))
+        .find(|name| !taken(name))
+        .unwrap_or_default()
+}
+
+/// Evaluates `push` and records the decision. Only skips are reported here;
+/// callers report allowed and blocked pushes through `check_pushes`.
+fn check(
+    git: &GitInfo,
+    repo: &str,
+    push: &PushInfo,
+    dry_run: bool,
+    command: Option<&str>,
+) -> Result<Decision> {
+    let (remote, branch, force) = (push.remote.as_str(), push.branch.as_str(), push.force);
+    let decision = evaluate(git, repo, push)?;
+    if !dry_run {
+        // Counting and logging are best-effort and must never change the outcome
+        let (rule, blocked) = (decision.rule(), decision.is_block());
+        let _ = Stats::record(repo, rule.as_str(), blocked);
+        let mut event = audit::Event::new(
+            repo,
+            remote,
+            branch,
+            force.is_force(),
+            decision.label(),
+            rule.as_str(),
+        );
+        event.force_with_lease = force == Force::WithLease;
+        event.deletion = push.deletion;
+        event.no_verify = push.no_verify;
+        event.destination = push.project(git);
+        event.remote_url = push.remote_url.clone();
+        event.session = push.session.clone();
+        event.agent = push.agent.clone();
+        if let Some(cmd) = command {
+            event.command = audit::capture_commands().then(|| cmd.to_string());
+            event.cwd = std::env::current_dir()
+                .ok()
+                .map(|d| d.to_string_lossy().into_owned());
+        }
+        let _ = audit::append(&event);
+    }
+    match &decision {
+        Decision::Skip(Rule::EnvDisabled, msg) => {
+            // Never silent, so the escape hatch can't be used unnoticed
+            eprintln!("{}: {}", red("WARNING"), msg);
+        }
+        Decision::Skip(_, msg) => {
+            if dry_run || Config::load(Some(repo)).is_ok_and(|c| c.is_verbose()) {
+                eprintln!("SKIPPED: {}", msg);
+            }
+        }
+        Decision::Allow(_) | Decision::Block(..) => {}
+    }
+    Ok(decision)
+}
+
+/// Who `check_pushes` reports to.
+#[derive(Clone, Copy, PartialEq)]
+enum Report {
+    /// The hook, whose block output starts with machine-readable lines.
+    Hook,
+    /// `check`, listing every allowed push with `show_allowed`.
+    Check { show_allowed: bool },
+}
+
+/// Checks all of `pushes` before reporting any of them, so one blocked push
+/// doesn't hide the outcome of the others: allowed pushes get a line each
+/// (when `show_allowed`, or when anything is blocked), blocked ones their full
+/// reason. Returns true when any push is still blocked after prompting.
+fn check_pushes(
+    git: &GitInfo,
+    repo: &str,
+    pushes: &[PushInfo],
+    dry_run: bool,
+    interactive: bool,
+    command: Option<&str>,
+    report: Report,
+) -> Result<bool> {
+    let decisions = pushes
+        .iter()
+        .map(|push| check(git, repo, push, dry_run, command))
+        .collect::<Result<Vec<_>>>()?;
+    let blocked = decisions.iter().filter(|d| d.is_block()).count();
+    let show_allowed = report == Report::Check { show_allowed: true };
+    let config = Config::load(Some(repo));
+    let warn_only = config.as_ref().is_ok_and(|c| c.warn_only.unwrap_or(false));
+    let label = if warn_only { "WARNING (warn_only)" } else { "BLOCKED" };
+    let reason_codes = config.as_ref().is_ok_and(|c| c.reason_codes.unwrap_or(true));
+    if report == Report::Hook && reason_codes && !warn_only {
+        for (push, decision) in pushes.iter().zip(&decisions) {
+            if let Decision::Block(rule, _) = decision {
+                eprintln!("{}", block_line(repo, push, *rule));
+            }
+        }
+    }
+    // A Claude session retrying the same blocked push is told to stop, tersely
+    let repeats = config.as_ref().ok().and_then(Config::repeat_block_limit);
+    let repeats = repeats.filter(|_| report == Report::Hook && !dry_run && !warn_only);
+    let events = if repeats.is_some() && blocked > 0 { audit::read_all() } else { Vec::new() };
+    let streak = |push: &PushInfo, rule: Rule| {
+        let (limit, window) = repeats?;
+        let (remote, branch) = (&push.remote, &push.branch);
+        let mut like = audit::Event::new(repo, remote, branch, false, "", rule.as_str());
+        like.session = Some(push.session.clone()?);
+        let since = chrono::Utc::now() - window;
+        Some(audit::block_streak(&events, &like, since)).filter(|n| *n >= limit)
+    };
+    for (push, decision) in pushes.iter().zip(&decisions) {
+        match decision {
+            Decision::Allow(Rule::UnresolvedTarget) => eprintln!(
+                "{}: {}, so it was not checked; allowed by dynamic_refspecs = \"warn\"",
+                red("WARNING"),
+                dynamic_source(push)
+            ),
+            Decision::Allow(_) if show_allowed || blocked > 0 => {
+                eprintln!("ALLOWED: {}", describe_push(push));
+            }
+            Decision::Block(rule, msg) => match streak(push, *rule) {
+                Some(n) => eprintln!("{}: {}", red(label), repeat_notice(repo, push, *rule, n)),
+                None => eprintln!("{}: {}", red(label), msg),
+            },
+            _ => {}
+        }
+    }
+    if blocked > 0 && pushes.len() > 1 {
+        // Deletions are counted apart, so a blocked one stands out among updates
+        let count = |deletion: bool| {
+            let of_kind = || pushes.iter().zip(&decisions).filter(|(p, _)| p.deletion == deletion);
+            (of_kind().filter(|(_, d)| d.is_block()).count(), of_kind().count())
+        };
+        let breakdown = match (count(true), count(false)) {
+            ((_, 0), _) => String::new(),
+            ((d, ds), (u, us)) => {
+                format!(" (deletions: {} of {}, updates: {} of {})", d, ds, u, us)
+            }
+        };
+        eprintln!(
+            "{} of {} pushes in this command are blocked{}, so the whole command is.",
+            blocked,
+            pushes.len(),
+            breakdown
+        );
+    }
+
+    let mut still_blocked = false;
+    for (push, decision) in pushes.iter().zip(&decisions) {
+        let Decision::Block(rule, _) = decision else {
+            continue;
+        };
+        if warn_only || interactive && override_block(git, repo, push, pushes.len() > 1)? {
+            continue;
+        }
+        still_blocked = true;
+        if !dry_run {
+            webhook::Notification {
+                event: "blocked",
+                repo,
+                branch: &push.branch,
+                remote: Some(&push.remote),
+                rule: Some(rule.as_str()),
+            }
+            .send();
+        }
+    }
+    if warn_only && blocked > 0 {
+        eprintln!("push-guard: warn_only is set, so the command is allowed anyway");
+    }
+    if !still_blocked {
+        let allowed = |(_, d): &(&PushInfo, &Decision)| matches!(d, Decision::Allow(_));
+        for (push, _) in pushes.iter().zip(&decisions).filter(allowed) {
+            if let Some(note) = divergence_note(git, push) {
+                eprintln!("push-guard: note: {}", note);
+            }
+        }
+    }
+    let announce = config.as_ref().is_ok_and(|c| c.announce_allows.unwrap_or(false));
+    if announce && !dry_run && !still_blocked && !show_allowed {
+        for (push, decision) in pushes.iter().zip(&decisions) {
+            if let Decision::Allow(rule) = decision {
+                let target = match push.branch.as_str() {
+                    "" => push.remote.clone(),
+                    branch => format!("'{}' on {}", branch, push.remote),
+                };
+                eprintln!(
+                    "push-guard: allowing push to {} ({}: {})",
+                    target,
+                    rule.as_str(),
+                    rule.allow_reason()
+                );
+            }
+        }
+    }
+    if !dry_run && !still_blocked {
+        let used: Vec<(Kind, &str)> = pushes
+            .iter()
+            .zip(&decisions)
+            .filter_map(|(p, d)| match d {
+                Decision::Allow(Rule::Tracked | Rule::ForceWithLeaseTracked) => {
+                    Some((Kind::Tracked, p.branch.as_str()))
+                }
+                Decision::Allow(Rule::Authorized) => Some((Kind::Authorized, p.branch.as_str())),
+                _ => None,
+            })
+            .collect();
+        if let Err(e) = record_uses(repo, &used) {
+            eprintln!("{}: could not record the use of an authorization: {:#}", red("BLOCKED"), e);
+            return Ok(true);
+        }
+    }
+    Ok(still_blocked)
+}
+
+/// Renders the line the hook prints first for a push blocked by `rule`, for
+/// instructions such as "on PUSH_GUARD_BLOCK, ask the user instead of
+/// retrying": `PUSH_GUARD_BLOCK rule=untracked branch=feature remote=origin
+/// authorize_hint="push-guard authorize ..."`. The hint is left out when no
+/// authorization would allow the push.
+fn block_line(repo: &str, push: &PushInfo, rule: Rule) -> String {
+    let mut fields = vec![
+        ("rule", rule.as_str().to_string()),
+        ("branch", push.branch.clone()),
+        ("remote", push.remote.clone()),
+    ];
+    if let Some(hint) = authorize_command(repo, push, rule) {
+        fields.push(("authorize_hint", hint));
+    }
+    let fields: Vec<String> =
+        fields.iter().map(|(key, value)| format!("{}={}", key, field_value(value))).collect();
+    format!("PUSH_GUARD_BLOCK {}", fields.join(" "))
+}
+
+/// The command that authorizes `push` despite `rule`, or None when no
+/// authorization would allow it.
+fn authorize_command(repo: &str, push: &PushInfo, rule: Rule) -> Option<String> {
+    let flag = rule.authorize_flag()?;
+    let (repo, branch) = (shell_quote(repo), shell_quote(&push.branch));
+    Some(format!("push-guard authorize --repo {} --branch {}{}", repo, branch, flag))
+}
+
+/// Replaces the block message once the same push was blocked `n` times in a
+/// row, so a retry loop ends in a short instruction rather than the full
+/// message again.
+fn repeat_notice(repo: &str, push: &PushInfo, rule: Rule, n: usize) -> String {
+    let ask = match authorize_command(repo, push, rule) {
+        Some(command) => format!("ask the user to run: {}", command),
+        None => "ask the user how to proceed".to_string(),
+    };
+    format!(
+        "this is the {} time in a row that the push to '{}' on {} was blocked ({}); \
+         stop retrying and {}",
+        ordinal(n),
+        push.branch,
+        push.remote,
+        rule.as_str(),
+        ask
+    )
+}
+
+/// "1st", "2nd", "3rd", "4th", ..., "11th", "21st".
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
+
+/// Returns true when `value` needs no quoting in a shell or a block line.
+fn is_plain_word(value: &str) -> bool {
+    !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || "._/@:+,-".contains(c))
+}
+
+/// Quotes `value` for a shell command line, leaving plain words as they are.
+fn shell_quote(value: &str) -> String {
+    if is_plain_word(value) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Renders a block line field value: plain words as they are, anything else
+/// double-quoted with `\`, `"`, `$` and backticks escaped, on one line.
+fn field_value(value: &str) -> String {
+    if is_plain_word(value) {
+        return value.to_string();
+    }
+    let mut quoted = String::from('"');
+    for c in value.chars() {
+        match c {
+            '\\' | '"' | '$' | '`' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => quoted.extend(c.escape_default()),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns a warning when `push`, a plain update, will be rejected as not a
+/// fast-forward: the remote's branch has commits the local one lacks, as of
+/// the last fetch. Only local refs are compared, so this never contacts the
+/// remote.
+fn divergence_note(git: &GitInfo, push: &PushInfo) -> Option<String> {
+    if push.force.is_force() || push.deletion || push.non_branch || push.after_update {
+        return None;
+    }
+    let branch = push.branch.strip_prefix("refs/heads/").unwrap_or(&push.branch);
+    let local = push.source.as_deref().unwrap_or(branch);
+    let local = local.strip_prefix("refs/heads/").unwrap_or(local);
+    if branch.is_empty() || local.is_empty() {
+        return None;
+    }
+    let (ahead, behind) = git.ahead_behind(local, &push.remote, branch)?;
+    if behind == 0 {
+        return None;
+    }
+    Some(format!(
+        "'{}' is {} commit(s) ahead of and {} behind {}/{} (as of the last fetch), so this \
+         push will likely be rejected as not a fast-forward.\n\
+         Recommendation: run `git pull --rebase {} {}` first, then push again; \
+         ask the user before forcing the push instead.",
+        local, ahead, behind, push.remote, branch, push.remote, branch
+    ))
+}
+
+/// Counts a push for each entry in `used` that allowed one, and uses up one
+/// push of each limited authorization among them, reporting what is left.
+/// Fails, recording nothing, when an authorization among them has fewer
+/// pushes left than `used` takes, having been used up or revoked since the
+/// push was checked, e.g. by a concurrent push: what is left is confirmed
+/// under the same lock that uses it up.
+fn record_uses(repo: &str, used: &[(Kind, &str)]) -> Result<()> {
+    if used.is_empty() {
+        return Ok(());
+    }
+    let ignore_case = Config::load(Some(repo))?.case_insensitive_branches.unwrap_or(false);
+    let mut state = State::load_for(repo)?;
+    state.ignore_case = ignore_case;
+    // One already gone may have been a limited one another push used up
+    let limited = used.iter().any(|(kind, b)| {
+        *kind == Kind::Authorized
+            && state.authorization(repo, b).is_none_or(|e| e.uses_left.is_some())
+    });
+    if !limited && !state::writable() {
+        return Ok(());
+    }
+    let updated = State::update_for(repo, |state| {
+        state.ignore_case = ignore_case;
+        let authorized: Vec<&str> =
+            used.iter().filter(|(kind, _)| *kind == Kind::Authorized).map(|(_, b)| *b).collect();
+        let mut short: Vec<&str> = authorized
+            .iter()
+            .copied()
+            .filter(|b| {
+                let needed = authorized.iter().filter(|o| *o == b).count();
+                state
+                    .authorization(repo, b)
+                    .is_none_or(|e| e.uses_left.is_some_and(|n| (n as usize) < needed))
+            })
+            .collect();
+        short.dedup();
+        if !short.is_empty() {
+            return Err(short);
+        }
+        let mut left = Vec::new();
+        for (kind, branch) in used {
+            state.record_use(*kind, repo, branch);
+            if *kind == Kind::Authorized {
+                left.extend(state.use_authorization(repo, branch).map(|n| (*branch, n)));
+            }
+        }
+        Ok(left)
+    });
+    let left = match updated {
+        Ok(Ok(left)) => left,
+        Ok(Err(short)) => bail!(
+            "the authorization for '{}' has fewer pushes left than this command makes; \
+             it was used up or revoked since the push was checked",
+            short.join("', '")
+        ),
+        // An unrecorded use would make a limited authorization unlimited
+        Err(e) if limited => return Err(e),
+        Err(e) => {
+            debug_log(&format!("could not record the use of an entry: {:#}", e));
+            return Ok(());
+        }
+    };
+    for (branch, n) in left {
+        match n {
+            0 => eprintln!("Authorization for '{}' is used up; the next push needs another", branch),
+            n => eprintln!("Authorization for '{}' allows {} more push(es)", branch, n),
+        }
+    }
+    Ok(())
+}
+
+/// "push to 'main'", or which local branch goes where when they differ.
+fn describe_push(push: &PushInfo) -> String {
+    if push.deletion {
+        return format!("deletion of '{}'", push.branch);
+    }
+    match push.renamed_from() {
+        Some(source) => format!("pushing local '{}' to remote '{}'", source, push.branch),
+        None => format!("push to '{}'", push.branch),
+    }
+}
+
+/// Asks whether to let a blocked push through, authorizing its branch when
+/// the answer is "always". Returns true when the push is allowed.
+fn override_block(git: &GitInfo, repo: &str, push: &PushInfo, name_push: bool) -> Result<bool> {
+    let branch = push.branch.as_str();
+    if name_push {
+        eprintln!("{}:", describe_push(push));
+    }
+    match prompt_override() {
+        Some(Answer::Yes) => Ok(true),
+        Some(Answer::Always) => {
+            let sha = git.branch_tip(branch);
+            State::update_for(repo, |state| state.authorize(repo, branch, sha.as_deref()))?;
+            eprintln!("Authorized push to '{}' in '{}'", branch, repo);
+            webhook::Notification {
+                event: "authorized",
+                repo,
+                branch,
+                remote: None,
+                rule: None,
+            }
+            .send();
+            Ok(true)
+        }
+        Some(Answer::No) | None => Ok(false),
+    }
+}
+
+/// Checks every push in a shell command, reporting each one, and returns true
+/// when any of them is blocked.
+fn check_command(
+    git: &GitInfo,
+    repo: &str,
+    command: &str,
+    track_creations: bool,
+    dry_run: bool,
+    interactive: bool,
+) -> Result<bool> {
+    let expanded = expand_command(git, repo, command);
+    if track_creations && !dry_run {
+        let creations = valid_branch_creations(&expanded, git);
+        if !creations.is_empty() {
+            State::update_for(repo, |state| {
+                for c in &creations {
+                    state.track_from(repo, &c.branch, None, None, c.from_remote.as_deref());
+                }
+            })?;
+            for c in &creations {
+                let from = c.from_remote.as_ref().map(|r| format!(" (from {})", r));
+                eprintln!("Tracking '{}' in '{}'{}", c.branch, repo, from.unwrap_or_default());
+            }
+        }
+        let moves = branch_moves(&expanded, git);
+        if !moves.is_empty() {
+            let applied = State::update_for(repo, |state| {
+                let applied = apply_branch_moves(state, repo, &moves);
+                applied.iter().map(|m| (m.from.clone(), m.to.clone(), m.copy)).collect::<Vec<_>>()
+            })?;
+            for (from, to, copy) in applied {
+                let verb = if copy { "Tracking copy" } else { "Moving entries of" };
+                eprintln!("{} '{}' as '{}' in '{}'", verb, from, to, repo);
+            }
+        }
+        let remote_changes = remote_changes(&expanded);
+        if !remote_changes.is_empty() {
+            State::update(|state| apply_remote_changes(state, repo, &remote_changes))?;
+        }
+    }
+    let mut pushes = detect_all_pushes(&expanded, git);
+    pushes.extend(command_rule_pushes(&Config::load(Some(repo))?, command, git));
+    if pushes.is_empty() {
+        eprintln!("No git push found in command");
+    }
+    let report = Report::Check { show_allowed: true };
+    check_pushes(git, repo, &pushes, dry_run, interactive, Some(command), report)
+}
+
+/// Answer to the interactive "Allow this push?" prompt.
+#[derive(Debug, PartialEq)]
+enum Answer {
+    Yes,
+    No,
+    /// Allow, and authorize the branch for future pushes.
+    Always,
+}
+
+fn parse_answer(line: &str) -> Answer {
+    match line.trim().to_ascii_lowercase().as_str() {
+        "y" | "yes" => Answer::Yes,
+        "a" | "always" => Answer::Always,
+        _ => Answer::No,
+    }
+}
+
+/// Asks whether to allow a blocked push, or returns None when there is no
+/// terminal to ask. Reads /dev/tty rather than stdin, which carries the ref
+/// list when running as git's pre-push hook.
+fn prompt_override() -> Option<Answer> {
+    if !std::io::stderr().is_terminal() {
+        return None;
+    }
+    let tty = std::fs::File::open("/dev/tty").ok()?;
+    eprint!("Allow this push? [y/N/always] ");
+    let mut line = String::new();
+    std::io::BufReader::new(tty).read_line(&mut line).ok()?;
+    Some(parse_answer(&line))
+}
+
+/// Asks a yes/no `question` on the terminal. False when there is none to ask.
+fn confirm(question: &str) -> bool {
+    if !std::io::stderr().is_terminal() {
+        return false;
+    }
+    let Ok(tty) = std::fs::File::open("/dev/tty") else {
+        return false;
+    };
+    eprint!("{} [y/N] ", question);
+    let mut line = String::new();
+    std::io::BufReader::new(tty).read_line(&mut line).is_ok() && parse_answer(&line) == Answer::Yes
+}
+
+// ── Why ───────────────────────────────────────────────────────────────────────
+
+fn why(git: &GitInfo, repo: &str, push: &PushInfo, json: bool, absolute: bool) -> Result<()> {
+    let (remote, branch, force) = (push.remote.as_str(), push.branch.as_str(), push.force);
+    let decision = evaluate(git, repo, push)?;
+    let remote_url = push.url(git);
+    let project = remote_url.as_deref().and_then(git::project_from_url);
+    let state = State::load_for(repo)?;
+    let tracked_from = state.tracked_entry(repo, branch).and_then(|e| e.from_remote.as_deref());
+    let denied = denied_remote(git, &Config::load(Some(repo))?, push);
+    let target = (!branch.is_empty()).then(|| {
+        if git.has_remote_branch(remote, branch) {
+            "existing remote branch"
+        } else {
+            "new remote branch"
+        }
+    });
+    let (message, reason) = match &decision {
+        Decision::Allow(rule) => (None, Some(rule.allow_reason())),
+        Decision::Block(_, msg) | Decision::Skip(_, msg) => (Some(msg.as_str()), None),
+    };
+    if json {
+        let output = serde_json::json!({
+            "repo": repo,
+            "remote": remote,
+            "remote_url": remote_url,
+            "destination": project,
+            "branch": branch,
+            "source": push.source.as_deref().unwrap_or(branch),
+            "force": force.is_force(),
+            "force_with_lease": force == Force::WithLease,
+            "decision": decision.label(),
+            "rule": decision.rule().as_str(),
+            "message": message,
+            "reason": reason,
+            "tracked_from": tracked_from,
+            "target": target,
+            "denied_pattern": denied.as_ref().map(|(_, pattern)| pattern),
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+    println!("{}  {}", decision_label(decision.label()), decision.rule().as_str());
+    println!("  repo:   {}", repo);
+    let label = remote_label(remote, project.as_deref());
+    match remote_url {
+        Some(url) => println!("  remote: {} ({})", label, url),
+        None => println!("  remote: {}", label),
+    }
+    match force {
+        Force::None => println!("  branch: {}", branch),
+        _ => println!("  branch: {}  ({})", branch, force.flag()),
+    }
+    if let Some(source) = push.renamed_from() {
+        println!("  source: {}  (pushing local '{}' to remote '{}')", source, source, branch);
+    }
+    if let Some(target) = target {
+        println!("  target: {}", target);
+    }
+    if let Some(from) = &tracked_from {
+        println!("  from:   {} (tracked as an existing remote branch)", from);
+    }
+    if let Some((url, pattern)) = &denied {
+        println!("  denied: {} (matches denied_remote_urls pattern '{}')", url, pattern);
+    }
+    let entries = [
+        ("tracked", state.tracked_entry(repo, branch)),
+        ("authorized", state.authorization(repo, branch)),
+    ];
+    for (kind, entry) in entries.iter().filter_map(|(k, e)| Some((k, (*e)?))) {
+        let mut line = kind.to_string();
+        if let Some(t) = entry.created_at {
+            line += &format!(" {}", timefmt::timestamp(t, absolute));
+        }
+        if let Some(t) = entry.last_used_at {
+            line += &format!(", last used {}", timefmt::timestamp(t, absolute));
+        }
+        println!("  entry:  {}", line);
+    }
+    if let Some(reason) = reason {
+        println!("  reason: {}", reason);
+    }
+    for line in message.into_iter().flat_map(str::lines) {
+        println!("  {}", line);
+    }
+    Ok(())
+}
+
+// ── Replay ────────────────────────────────────────────────────────────────────
+
+/// Renders an audit-log decision ("allow", "block", "skipped") for display.
+fn decision_label(decision: &str) -> String {
+    match decision {
+        "block" => ansi_stdout("BLOCKED", "31"),
+        "skipped" => ansi_stdout("SKIPPED", "36"),
+        _ => ansi_stdout("ALLOWED", "32"),
+    }
+}
+
+fn replay(event: &audit::Event, as_recorded: bool) -> Result<()> {
+    // Detection helpers resolve upstreams relative to the working directory
+    if let Some(cwd) = event.cwd.as_deref().filter(|d| std::path::Path::new(d).is_dir()) {
+        std::env::set_current_dir(cwd)?;
+    }
+    let git = GitInfo::cwd();
+    let pushes = match (&event.command, as_recorded) {
+        (Some(cmd), false) => detect_all_pushes(&expand_command(&git, &event.repo, cmd), &git),
+        _ => vec![PushInfo::new(
+            &event.remote,
+            &event.branch,
+            Force::from_flags(event.force, event.force_with_lease),
+        )],
+    };
+
+    println!(
+        "Event {}  {}  {}",
+        event.id,
+        event.timestamp.format("%Y-%m-%d %H:%M:%S"),
+        event.repo
+    );
+    if let Some(cmd) = &event.command {
+        println!("  command:  {}", cmd);
+    }
+    println!(
+        "  recorded: {}  {:<14}  {}/{}",
+        decision_label(&event.decision),
+        event.rule,
+        event.remote,
+        event.branch
+    );
+    let mut changed = false;
+    for push in &pushes {
+        let decision = evaluate(&git, &event.repo, push)?;
+        let rule = decision.rule().as_str();
+        if push.branch == event.branch && push.remote == event.remote {
+            changed |= decision.label() != event.decision || rule != event.rule;
+        }
+        println!(
+            "  replayed: {}  {:<14}  {}/{}",
+            decision_label(decision.label()),
+            rule,
+            push.remote,
+            push.branch
+        );
+    }
+    println!("{}", if changed { "Decision changed." } else { "Decision unchanged." });
+    Ok(())
+}
+
+// ── List rendering ────────────────────────────────────────────────────────────
+
+struct ListRow<'a> {
+    kind: &'static str,
+    repo: &'a str,
+    entry: &'a state::Entry,
+}
+
+/// Returns why `grant` in `repo` is inert, if it is: the remote's URL
+/// changed since, or matches a `protected_remote_urls` pattern.
+fn inert_grant(repo: &str, grant: &state::RemoteGrant) -> Option<String> {
+    let git = GitInfo::for_repo(repo);
+    if !grant.applies_to(&git.push_urls(&grant.remote)) {
+        return Some("URL changed since granted".to_string());
+    }
+    let url = git.remote_url(&grant.remote)?;
+    let config = Config::load(Some(repo)).ok()?;
+    config.protected_remote(&url).map(|pattern| format!("URL matches '{}'", pattern))
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+/// Abbreviates a path under the home directory with `~`.
+fn shorten_home(path: &str) -> String {
+    let home = dirs::home_dir().map(|h| h.to_string_lossy().into_owned());
+    match home.as_deref().and_then(|h| path.strip_prefix(h)) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("~{}", rest),
+        _ => path.to_string(),
+    }
+}
+
+fn print_list(
+    state: &State,
+    repo: Option<&str>,
+    format: ListFormat,
+    tracked: bool,
+    authorized: bool,
+    absolute: bool,
+) -> Result<()> {
+    let mut rows: Vec<ListRow> = Vec::new();
+    for (kind, map, show) in [
+        ("claude", &state.tracked, tracked),
+        ("authorized", &state.authorized, authorized),
+    ] {
+        if !show {
+            continue;
+        }
+        for (r, entries) in map {
+            if repo.is_some_and(|only| only != r) {
+                continue;
+            }
+            rows.extend(entries.iter().map(|entry| ListRow { kind, repo: r, entry }));
+        }
+    }
+    rows.sort_by(|a, b| {
+        (a.repo, &a.entry.branch, a.kind).cmp(&(b.repo, &b.entry.branch, b.kind))
+    });
+    // Remote grants are authorizations too
+    let grants: Vec<(&str, &state::RemoteGrant, Option<String>)> = state
+        .remote_grants
+        .iter()
+        .filter(|(r, _)| authorized && repo.is_none_or(|only| only == r.as_str()))
+        .flat_map(|(r, grants)| grants.iter().map(move |g| (r.as_str(), g)))
+        .map(|(r, g)| (r, g, inert_grant(r, g)))
+        .collect();
+
+    if format == ListFormat::Json {
+        let is_local = |row: &ListRow| row.entry.scope == state::Scope::Local;
+        let sections = |only_local: bool| {
+            let mut out = serde_json::Map::new();
+            let kinds = [("claude", "tracked", tracked), ("authorized", "authorized", authorized)];
+            for (kind, key, show) in kinds {
+                if !show {
+                    continue;
+                }
+                let rows: Vec<&ListRow> = rows
+                    .iter()
+                    .filter(|row| row.kind == kind && (!only_local || is_local(row)))
+                    .collect();
+                let names = |r: &str| -> Vec<&str> {
+                    rows.iter()
+                        .filter(|row| row.repo == r)
+                        .map(|row| row.entry.branch.as_str())
+                        .collect()
+                };
+                let value = match repo {
+                    Some(r) => serde_json::json!(names(r)),
+                    None => {
+                        let mut per_repo = serde_json::Map::new();
+                        for row in &rows {
+                            per_repo
+                                .entry(row.repo.to_string())
+                                .or_insert_with(|| serde_json::json!(names(row.repo)));
+                        }
+                        serde_json::Value::Object(per_repo)
+                    }
+                };
+                out.insert(key.to_string(), value);
+            }
+            out
+        };
+        let mut out = sections(false);
+        // Only present when some entry comes from a repo-local state file
+        if rows.iter().any(is_local) {
+            out.insert("local".to_string(), serde_json::Value::Object(sections(true)));
+        }
+        // Only present when some tracked branch carries a Claude session id
+        let mut sessions = serde_json::Map::new();
+        for row in rows.iter().filter(|row| row.kind == "claude") {
+            let Some(id) = &row.entry.session_id else {
+                continue;
+            };
+            let per_branch = match repo {
+                Some(_) => &mut sessions,
+                None => sessions
+                    .entry(row.repo.to_string())
+                    .or_insert_with(|| serde_json::json!({}))
+                    .as_object_mut()
+                    .unwrap(),
+            };
+            per_branch.insert(row.entry.branch.clone(), serde_json::json!(id));
+        }
+        if !sessions.is_empty() {
+            out.insert("sessions".to_string(), serde_json::Value::Object(sessions));
+        }
+        // Only present when some tracked branch was created by a subagent
+        let mut agents = serde_json::Map::new();
+        for row in rows.iter().filter(|row| row.kind == "claude") {
+            let Some(agent) = &row.entry.agent else {
+                continue;
+            };
+            let per_branch = match repo {
+                Some(_) => &mut agents,
+                None => agents
+                    .entry(row.repo.to_string())
+                    .or_insert_with(|| serde_json::json!({}))
+                    .as_object_mut()
+                    .unwrap(),
+            };
+            per_branch.insert(row.entry.branch.clone(), serde_json::json!(agent));
+        }
+        if !agents.is_empty() {
+            out.insert("agents".to_string(), serde_json::Value::Object(agents));
+        }
+        // Only present when some authorization is limited to a number of pushes
+        let mut uses = serde_json::Map::new();
+        for row in rows.iter().filter(|row| row.kind == "authorized") {
+            let Some(n) = row.entry.uses_left else {
+                continue;
+            };
+            let per_branch = match repo {
+                Some(_) => &mut uses,
+                None => uses
+                    .entry(row.repo.to_string())
+                    .or_insert_with(|| serde_json::json!({}))
+                    .as_object_mut()
+                    .unwrap(),
+            };
+            per_branch.insert(row.entry.branch.clone(), serde_json::json!(n));
+        }
+        if !uses.is_empty() {
+            out.insert("uses_left".to_string(), serde_json::Value::Object(uses));
+        }
+        // Only present when some entry has allowed a push
+        let mut usage = serde_json::Map::new();
+        for (kind, key) in [("claude", "tracked"), ("authorized", "authorized")] {
+            let mut per_kind = serde_json::Map::new();
+            for row in rows.iter().filter(|row| row.kind == kind && row.entry.use_count > 0) {
+                let per_branch = match repo {
+                    Some(_) => &mut per_kind,
+                    None => per_kind
+                        .entry(row.repo.to_string())
+                        .or_insert_with(|| serde_json::json!({}))
+                        .as_object_mut()
+                        .unwrap(),
+                };
+                per_branch.insert(row.entry.branch.clone(), serde_json::json!({
+                    "use_count": row.entry.use_count,
+                    "last_used_at": row.entry.last_used_at.map(|t| t.to_rfc3339()),
+                }));
+            }
+            if !per_kind.is_empty() {
+                usage.insert(key.to_string(), serde_json::Value::Object(per_kind));
+            }
+        }
+        if !usage.is_empty() {
+            out.insert("usage".to_string(), serde_json::Value::Object(usage));
+        }
+        // Only present when some remote has an `allow-remote` grant
+        let mut remotes = serde_json::Map::new();
+        for (r, grant, inert) in &grants {
+            let grant = serde_json::json!({
+                "remote": grant.remote,
+                "include_force": grant.include_force,
+                "inert": inert.is_some(),
+            });
+            let list = remotes.entry(r.to_string()).or_insert_with(|| serde_json::json!([]));
+            list.as_array_mut().unwrap().push(grant);
+        }
+        if let Some(r) = repo.filter(|_| !remotes.is_empty()) {
+            out.insert("remotes".to_string(), remotes.remove(r).unwrap_or_default());
+        } else if !remotes.is_empty() {
+            out.insert("remotes".to_string(), serde_json::Value::Object(remotes));
+        }
+        println!("{}", serde_json::to_string_pretty(&out)?);
+        return Ok(());
+    }
+
+    if rows.is_empty() && grants.is_empty() {
+        eprintln!("No entries.");
+        return Ok(());
+    }
+
+    let when = |t: Option<chrono::DateTime<chrono::Utc>>| -> String {
+        t.map(|t| timefmt::timestamp(t, absolute)).unwrap_or_default()
+    };
+    let created = |row: &ListRow| when(row.entry.created_at);
+
+    match format {
+        ListFormat::Plain => {
+            let tag_claude = ansi_stdout("[claude]    ", "32");
+            let tag_auth = ansi_stdout("[authorized]", "33");
+            let repos: Vec<String> = rows.iter().map(|row| shorten_home(row.repo)).collect();
+            let repo_width = repos.iter().map(|r| r.chars().count()).max().unwrap_or(0);
+            let branch_width = rows.iter().map(|r| r.entry.branch.chars().count()).max();
+            for (row, repo_cell) in rows.iter().zip(&repos) {
+                let tag = if row.kind == "claude" { &tag_claude } else { &tag_auth };
+                let mut meta = Vec::new();
+                if row.entry.scope == state::Scope::Local {
+                    meta.push("(local)".to_string());
+                }
+                if let Some(from) = &row.entry.from_remote {
+                    meta.push(format!("from {}", from));
+                }
+                if let Some(n) = row.entry.uses_left {
+                    meta.push(format!("{} use(s) left", n));
+                }
+                if row.entry.override_hours {
+                    meta.push("overrides restricted hours".to_string());
+                }
+                if row.entry.allow_default {
+                    meta.push("allows the default branch".to_string());
+                }
+                meta.push(created(row));
+                meta.retain(|m| !m.is_empty());
+                let mut line = tag.clone();
+                if repo.is_none() {
+                    line.push_str(&format!("  {:<w$}", repo_cell, w = repo_width));
+                }
+                line.push_str(&format!(
+                    "  {:<w$}  {}",
+                    row.entry.branch,
+                    meta.join("  "),
+                    w = branch_width.unwrap_or(0)
+                ));
+                println!("{}", line.trim_end());
+            }
+            let tag_remote = ansi_stdout("[remote]    ", "36");
+            for (r, grant, inert) in &grants {
+                let mut meta = vec![if grant.include_force {
+                    "all branches, force pushes too".to_string()
+                } else {
+                    "all branches".to_string()
+                }];
+                if let Some(reason) = inert {
+                    meta.push(format!("(inert: {})", reason));
+                }
+                meta.push(when(grant.created_at));
+                meta.retain(|m| !m.is_empty());
+                let mut line = tag_remote.clone();
+                if repo.is_none() {
+                    line.push_str(&format!("  {}", shorten_home(r)));
+                }
+                line.push_str(&format!("  {}  {}", grant.remote, meta.join("  ")));
+                println!("{}", line.trim_end());
+            }
+        }
+        ListFormat::Table => {
+            let header = ["TAG", "REPO", "BRANCH", "SCOPE", "CREATED", "USES", "LAST USED"];
+            let cells: Vec<[String; 7]> = rows
+                .iter()
+                .map(|row| {
+                    [
+                        row.kind.to_string(),
+                        row.repo.to_string(),
+                        row.entry.branch.clone(),
+                        row.entry.scope.as_str().to_string(),
+                        created(row),
+                        row.entry.use_count.to_string(),
+                        when(row.entry.last_used_at),
+                    ]
+                })
+                .collect();
+            let mut widths = header.map(str::len);
+            for c in &cells {
+                for (w, v) in widths.iter_mut().zip(c) {
+                    *w = (*w).max(v.len());
+                }
+            }
+            let line = |c: [&str; 7]| {
+                let padded: Vec<String> =
+                    c.iter().zip(widths).map(|(v, w)| format!("{:<w$}", v, w = w)).collect();
+                padded.join("  ").trim_end().to_string()
+            };
+            println!("{}", line(header));
+            for c in &cells {
+                println!("{}", line(c.each_ref().map(String::as_str)));
+            }
+        }
+        ListFormat::Csv => {
+            println!("kind,repo,branch,created_at,scope");
+            for row in &rows {
+                println!(
+                    "{},{},{},{},{}",
+                    row.kind,
+                    csv_field(row.repo),
+                    csv_field(&row.entry.branch),
+                    row.entry.created_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+                    row.entry.scope.as_str()
+                );
+            }
+        }
+        ListFormat::Json => unreachable!(),
+    }
+    Ok(())
 }
 
-// ── Authorization logic ───────────────────────────────────────────────────────
+// ── Prune ─────────────────────────────────────────────────────────────────────
 
-enum Decision {
-    Allow,
-    Block(String),
+/// What one `prune` pass removed.
+#[derive(Default)]
+struct Pruned {
+    removals: Vec<Removal>,
+    /// Whether unused authorizations were looked for (`--unused-for`).
+    unused_checked: bool,
+    /// Repos a category was skipped for, with the reason.
+    skipped: Vec<(String, String)>,
 }
 
-fn evaluate(repo: &str, remote: &str, branch: &str, force: bool) -> Result<Decision> {
-    if branch.is_empty() {
-        return Ok(Decision::Allow);
+impl Pruned {
+    fn is_empty(&self) -> bool {
+        self.removals.is_empty()
     }
 
-    if force {
-        return Ok(Decision::Block(format!(
-            "Force push to '{}' requires explicit user authorization.\n\
-             Say \"I authorize\" to proceed.",
-            branch
-        )));
+    /// The (repo, branch) pairs removed for `reason`, each once even when
+    /// both of its entries were.
+    fn pairs(&self, reason: Reason) -> Vec<(&str, &str)> {
+        let mut pairs: Vec<(&str, &str)> = Vec::new();
+        for r in self.removals.iter().filter(|r| r.reason == reason) {
+            let pair = (r.repo.as_str(), r.branch.as_deref().unwrap_or_default());
+            if !pairs.contains(&pair) {
+                pairs.push(pair);
+            }
+        }
+        pairs
     }
 
-    let default_branch = get_default_branch(remote);
-    if default_branch.as_deref() == Some(branch) {
-        return Ok(Decision::Block(format!(
-            "'{}' is the default branch of '{}'.\n\
-             Recommendation: push to a feature branch instead.\n\
-             To push to '{}' directly, say \"I authorize\".",
-            branch, remote, branch
-        )));
+    fn to_json(&self, dry_run: bool) -> serde_json::Value {
+        let entries = |reason: Reason| -> Vec<serde_json::Value> {
+            let pairs = self.pairs(reason).into_iter();
+            pairs.map(|(r, b)| serde_json::json!({ "repo": r, "branch": b })).collect()
+        };
+        let stale: Vec<&str> = self.pairs(Reason::Stale).into_iter().map(|(r, _)| r).collect();
+        let skipped: Vec<serde_json::Value> = self
+            .skipped
+            .iter()
+            .map(|(r, reason)| serde_json::json!({ "repo": r, "reason": reason }))
+            .collect();
+        let removals: Vec<serde_json::Value> = self.removals.iter().map(Removal::to_json).collect();
+        let mut out = serde_json::json!({
+            "dry_run": dry_run,
+            "stale": stale,
+            "exhausted": entries(Reason::Exhausted),
+            "gone": entries(Reason::Gone),
+            "merged": entries(Reason::Merged),
+            "skipped": skipped,
+            "removals": removals,
+        });
+        if self.unused_checked {
+            out["unused"] = serde_json::json!(entries(Reason::Unused));
+        }
+        out
+    }
+}
+
+/// Applies every cleanup to `state`, for `repo` or for all repos, including
+/// authorizations unused since `unused_cutoff` when given. Git errors only
+/// skip the repo's git-based categories.
+fn prune(
+    state: &mut State,
+    repo: Option<&str>,
+    unused_cutoff: Option<chrono::DateTime<chrono::Utc>>,
+) -> Pruned {
+    let mut pruned = Pruned { unused_checked: unused_cutoff.is_some(), ..Pruned::default() };
+    let repos = match repo {
+        Some(r) if !Path::new(r).exists() => {
+            if !state.branches(r).is_empty() {
+                state.clean_repo(r);
+                pruned.removals.push(Removal::repo(r, Reason::Stale, "no longer exists"));
+            }
+            Vec::new()
+        }
+        Some(r) => vec![r.to_string()],
+        None => {
+            for (r, reason) in state.clean_stale(false) {
+                pruned.removals.push(Removal::repo(&r, Reason::Stale, &reason));
+            }
+            // Entries kept only in the current repo's local file are listed nowhere else
+            let mut repos = state.repos();
+            if let Some(cwd) = GitInfo::cwd().repo_key().filter(|r| !repos.contains(r)) {
+                repos.push(cwd);
+            }
+            repos
+        }
+    };
+    // Local-scope entries of repos without `storage = "repo"` are not loaded by default
+    for r in &repos {
+        state.layer_local(r);
+    }
+    let authorized = |r: &str, branch: String, reason: Reason, detail: String| Removal {
+        repo: r.to_string(),
+        kind: Some(Kind::Authorized),
+        branch: Some(branch),
+        reason,
+        detail,
+    };
+    for r in &repos {
+        for b in state.remove_exhausted(r) {
+            let detail = "no uses left".to_string();
+            pruned.removals.push(authorized(r, b, Reason::Exhausted, detail));
+        }
+        if let Some(cutoff) = unused_cutoff {
+            let details: Vec<(String, String)> = state
+                .authorized
+                .get(r)
+                .into_iter()
+                .flatten()
+                .map(|e| (e.branch.clone(), cleanup::unused_detail(e)))
+                .collect();
+            for b in state.remove_unused(r, cutoff) {
+                let detail = details.iter().find(|(branch, _)| *branch == b);
+                let detail = detail.map(|(_, d)| d.clone()).unwrap_or_default();
+                pruned.removals.push(authorized(r, b, Reason::Unused, detail));
+            }
+        }
+        let git = GitInfo::for_repo(r);
+        let Some(local) = git.local_branches() else {
+            pruned.skipped.push((r.clone(), "could not list local branches".to_string()));
+            continue;
+        };
+        let gone: Vec<String> = state
+            .branches(r)
+            .into_iter()
+            .filter(|b| !b.contains(['*', '?', '[']) && !local.contains(b))
+            .collect();
+        pruned.removals.extend(removals(state, r, &gone, true, Reason::Gone));
+        state.remove_branches(r, &gone, true);
+        let remote = git.push_remote();
+        match git.merged_branches(&remote) {
+            Some(merged) => {
+                pruned.removals.extend(removals(state, r, &merged, false, Reason::Merged));
+                state.remove_branches(r, &merged, false);
+            }
+            None => pruned
+                .skipped
+                .push((r.clone(), format!("could not resolve {}'s default branch", remote))),
+        }
     }
+    pruned
+}
+
+/// The removals `State::remove_branches` is about to make for `reason`.
+fn removals(
+    state: &State,
+    repo: &str,
+    branches: &[String],
+    include_authorized: bool,
+    reason: Reason,
+) -> Vec<Removal> {
+    let kinds: &[Kind] = if include_authorized {
+        &[Kind::Tracked, Kind::Authorized]
+    } else {
+        &[Kind::Tracked]
+    };
+    let detail = match reason {
+        Reason::Merged => "merged into the remote's default branch",
+        _ => "local branch deleted",
+    };
+    let entries = |b: &String| Removal::entries(state, repo, b, kinds, reason, detail);
+    branches.iter().flat_map(entries).collect()
+}
 
-    let state = State::load()?;
-    if state.is_tracked(repo, branch) || state.is_authorized(repo, branch) {
-        return Ok(Decision::Allow);
+/// Prints what a real `prune` pass removed; dry runs use `cleanup::render`.
+fn print_pruned(pruned: &Pruned) {
+    for r in pruned.pairs(Reason::Stale) {
+        println!("Removed stale repo: {}", r.0);
+    }
+    let categories = [
+        ("exhausted grant", Reason::Exhausted),
+        ("unused grant", Reason::Unused),
+        ("gone branch", Reason::Gone),
+        ("merged branch", Reason::Merged),
+    ];
+    for (label, reason) in categories {
+        for (r, b) in pruned.pairs(reason) {
+            println!("Removed {} '{}' from '{}'", label, b, r);
+        }
     }
+    let unused = match pruned.unused_checked {
+        true => format!(", {} unused grants", pruned.pairs(Reason::Unused).len()),
+        false => String::new(),
+    };
+    println!(
+        "Pruned: {} stale repos, {} exhausted grants, {} gone branches, {} merged branches{}",
+        pruned.pairs(Reason::Stale).len(),
+        pruned.pairs(Reason::Exhausted).len(),
+        pruned.pairs(Reason::Gone).len(),
+        pruned.pairs(Reason::Merged).len(),
+        unused
+    );
+}
 
-    Ok(Decision::Block(format!(
-        "Branch '{}' was not created by me and has no authorization.\n\
-         To authorize: say \"authorize push to {}\"\n\
-         To revoke later: push-guard revoke --repo '{}' --branch '{}'",
-        branch, branch, repo, branch
-    )))
+// ── Hook entry point ──────────────────────────────────────────────────────────
+
+/// Appends a timestamped line to the file named by PUSH_GUARD_DEBUG_LOG, if set.
+fn debug_log(msg: &str) {
+    let Some(path) = std::env::var_os("PUSH_GUARD_DEBUG_LOG") else {
+        return;
+    };
+    let line = format!("{} {}\n", chrono::Utc::now().to_rfc3339(), msg);
+    let _ = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut f| f.write_all(line.as_bytes()));
 }
 
-fn check(repo: &str, remote: &str, branch: &str, force: bool, dry_run: bool) -> Result<()> {
-    match evaluate(repo, remote, branch, force)? {
-        Decision::Allow => {
-            if dry_run {
-                eprintln!("ALLOWED: push to '{}'", branch);
+/// This machine's host name: $HOSTNAME, else /etc/hostname, else `hostname`.
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok())
+        .or_else(|| {
+            let out = std::process::Command::new("hostname").output().ok()?;
+            Some(String::from_utf8_lossy(&out.stdout).into_owned())
+        })
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+}
+
+/// Extracts the commands of `tool_input`: `command` as a string or an array
+/// of strings, and every entry of a `commands` array. An array of single words
+/// is one command in argv form (`["git", "push"]`), joined with spaces; any
+/// other array holds one command per element. `commands` entries may be argv
+/// arrays too. Other shapes and fields are ignored.
+fn hook_commands(json: &serde_json::Value) -> Vec<String> {
+    use serde_json::Value;
+    let Some(input) = json.get("tool_input") else {
+        return Vec::new();
+    };
+    let strings = |items: &[Value]| -> Option<Vec<String>> {
+        items.iter().map(|v| v.as_str().map(str::to_string)).collect()
+    };
+    let mut commands = Vec::new();
+    match input.get("command") {
+        Some(Value::String(s)) => commands.push(s.clone()),
+        Some(Value::Array(items)) => match strings(items) {
+            Some(words) if words.iter().all(|w| !w.contains(char::is_whitespace)) => {
+                commands.push(words.join(" "))
+            }
+            Some(each) => commands.extend(each),
+            None => {}
+        },
+        _ => {}
+    }
+    for entry in input.get("commands").and_then(Value::as_array).into_iter().flatten() {
+        match entry {
+            Value::String(s) => commands.push(s.clone()),
+            Value::Array(argv) => commands.extend(strings(argv).map(|w| w.join(" "))),
+            _ => {}
+        }
+    }
+    commands.retain(|c| !c.trim().is_empty());
+    commands
+}
+
+/// Repo key of hook calls made outside any repository. It is not a path, so no
+/// tracked or authorized entry ever matches it.
+const NO_REPO: &str = "(no repository)";
+
+/// Parses the JSON object the hook is given, skipping a byte order mark or
+/// stray control characters before it and anything after it.
+fn parse_hook_input(input: &[u8]) -> serde_json::Result<serde_json::Value> {
+    let input = input.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(input);
+    let start = input.iter().position(|b| !b.is_ascii_control() && *b != b' ');
+    let input = &input[start.unwrap_or(input.len())..];
+    match serde_json::Deserializer::from_slice(input).into_iter().next() {
+        Some(value) => value,
+        None => serde_json::from_slice(input),
+    }
+}
+
+fn run_hook() -> Result<()> {
+    // Malformed input is never this hook's business: log it and let the tool run
+    let mut input = Vec::new();
+    if let Err(e) = std::io::stdin().read_to_end(&mut input) {
+        debug_log(&format!("Failed to read hook stdin: {}", e));
+        return Ok(());
+    }
+    let json = match parse_hook_input(&input) {
+        Ok(json) => json,
+        Err(e) => {
+            debug_log(&format!("Ignoring hook input that is not JSON: {}", e));
+            return Ok(());
+        }
+    };
+    if hook(&json)? {
+        Exit::Failure.exit();
+    }
+    Ok(())
+}
+
+/// Handles one parsed hook payload: tracks the branches its commands create
+/// and checks their pushes. Returns true when a push is blocked.
+fn hook(json: &serde_json::Value) -> Result<bool> {
+    let commands = hook_commands(json);
+    if commands.is_empty() {
+        debug_log("Ignoring hook input without a usable tool_input.command or .commands");
+        return Ok(false);
+    }
+
+    let session = json.get("session_id").and_then(|v| v.as_str());
+    let agent = state::Agent::from_hook(json);
+
+    let git = GitInfo::cwd();
+    let in_repo = git.repo_key();
+    if in_repo.is_none() && Config::load(None).is_ok_and(|c| c.is_verbose()) {
+        eprintln!(
+            "push-guard: not inside a git repository; branch creations are not tracked \
+             and no authorizations apply"
+        );
+    }
+    let repo = in_repo.clone().unwrap_or_else(|| NO_REPO.to_string());
+
+    // After the command ran: pick up branches whose names were only known then
+    if json.get("hook_event_name").and_then(|v| v.as_str()) == Some("PostToolUse") {
+        let checkout = commands.iter().any(|c| has_gh_pr_checkout(c));
+        if checkout && in_repo.is_some() && state::writable() {
+            if let Some(branch) = git.current_branch().filter(|b| b != "HEAD") {
+                let _ = State::update_for(&repo, |state| {
+                    state.track_from(&repo, &branch, session, agent.as_ref(), None)
+                });
             }
         }
-        Decision::Block(msg) => {
-            eprintln!("{}: {}", red("BLOCKED"), msg);
-            if !dry_run {
-                std::process::exit(1);
+        return Ok(false);
+    }
+
+    let config = Config::load(Some(&repo));
+    if let Err(e) = &config {
+        debug_log(&format!("Not applying command rules: {:#}", e));
+    }
+    let mut pushes = Vec::new();
+    let mut created = Vec::new();
+    for command in &commands {
+        let expanded = expand_command(&git, &repo, command);
+
+        // Track all branch creations first
+        let creations = valid_branch_creations(&expanded, &git);
+        if !creations.is_empty() && in_repo.is_some() {
+            let tracked = state::writable()
+                && State::update_for(&repo, |state| {
+                    for c in &creations {
+                        let from_remote = c.from_remote.as_deref();
+                        state.track_from(&repo, &c.branch, session, agent.as_ref(), from_remote);
+                    }
+                })
+                .is_ok();
+            // A follow-up push in a later tool call must not be blocked for it
+            let branches: Vec<String> = creations.into_iter().map(|c| c.branch).collect();
+            if !tracked {
+                if let Err(e) = pending::note(&repo, &branches) {
+                    debug_log(&format!("Failed to note untracked branch creations: {:#}", e));
+                }
             }
+            created.extend(branches);
+        }
+        // Renamed branches keep their entries, so the push of the new name is allowed
+        let moves = branch_moves(&expanded, &git);
+        if !moves.is_empty() && in_repo.is_some() && state::writable() {
+            let _ = State::update_for(&repo, |state| {
+                apply_branch_moves(state, &repo, &moves);
+            });
         }
+        // Grants and recorded default branches follow the remote's new name
+        let remote_changes = remote_changes(&expanded);
+        if !remote_changes.is_empty() && in_repo.is_some() && state::writable() {
+            let _ = State::update(|state| apply_remote_changes(state, &repo, &remote_changes));
+        }
+
+        pushes.extend(detect_all_pushes(&expanded, &git));
+        if let Ok(config) = &config {
+            pushes.extend(command_rule_pushes(config, command, &git));
+        }
+    }
+
+    // Check every push across the commands — if any would block, block
+    for push in &mut pushes {
+        push.session = session.map(str::to_string);
+        push.agent = agent.clone();
+        push.just_created = created.contains(&push.branch);
+    }
+    let command = commands.join("\n");
+    check_pushes(&git, &repo, &pushes, false, false, Some(&command), Report::Hook)
+}
+
+/// Forgets branches tracked in a Claude session. Like the hook, bad input is
+/// logged and ignored rather than reported as a failure.
+fn run_session_end(session: Option<String>) -> Result<()> {
+    let explicit = session.is_some();
+    let session = match session {
+        Some(s) => s,
+        None => {
+            let mut input = Vec::new();
+            let _ = std::io::stdin().read_to_end(&mut input);
+            let json: Option<serde_json::Value> = serde_json::from_slice(&input).ok();
+            let Some(id) = json.as_ref().and_then(|j| j.get("session_id")?.as_str()) else {
+                debug_log("Ignoring session-end input without a session_id");
+                return Ok(());
+            };
+            id.to_string()
+        }
+    };
+    if !explicit && !state::writable() {
+        return Ok(());
+    }
+    let removed = State::update(|state| state.prune_session(&session))?;
+    for (repo, branch) in &removed {
+        debug_log(&format!("Session {} ended; untracked '{}' in '{}'", session, branch, repo));
+    }
+    if explicit {
+        eprintln!("Stopped tracking {} branches from session {}", removed.len(), session);
     }
     Ok(())
 }
 
-// ── Hook entry point ──────────────────────────────────────────────────────────
+/// Prints the summary of a session's activity. Like the hook, bad input is
+/// logged and ignored rather than reported as a failure.
+fn run_session_summary(session: Option<String>, json: bool) -> Result<()> {
+    let session = match session {
+        Some(s) => s,
+        None => {
+            let mut input = Vec::new();
+            let _ = std::io::stdin().read_to_end(&mut input);
+            let json: Option<serde_json::Value> = serde_json::from_slice(&input).ok();
+            let Some(id) = json.as_ref().and_then(|j| j.get("session_id")?.as_str()) else {
+                debug_log("Ignoring session-summary input without a session_id");
+                return Ok(());
+            };
+            id.to_string()
+        }
+    };
+    let events = audit::read_all();
+    let mut state = State::load()?;
+    // Branches kept in a repo's own file are only found by reading that file
+    for event in events.iter().filter(|e| e.session.as_deref() == Some(session.as_str())) {
+        state.layer_local(&event.repo);
+    }
+    let created: Vec<(String, String)> = state
+        .tracked
+        .iter()
+        .flat_map(|(repo, entries)| {
+            entries
+                .iter()
+                .filter(|e| e.session_id.as_deref() == Some(session.as_str()))
+                .map(move |e| (repo.clone(), e.branch.clone()))
+        })
+        .collect();
+    let Some(summary) = summary::summarize(&session, &events, &created) else {
+        return Ok(());
+    };
+    eprintln!("{}", summary);
+    if json {
+        println!("{}", serde_json::json!({ "systemMessage": summary }));
+    }
+    Ok(())
+}
 
-fn run_hook() -> Result<()> {
-    let mut input = String::new();
-    std::io::stdin()
-        .read_to_string(&mut input)
-        .context("Failed to read hook stdin")?;
+// ── Meta ──────────────────────────────────────────────────────────────────────
+
+/// Exit statuses of push-guard commands.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exit {
+    Success = 0,
+    Failure = 1,
+    /// Set by clap for command lines it can't parse.
+    Usage = 2,
+    Incompatible = 3,
+}
+
+impl Exit {
+    const ALL: [Exit; 4] = [Exit::Success, Exit::Failure, Exit::Usage, Exit::Incompatible];
+
+    fn meaning(self) -> &'static str {
+        match self {
+            Exit::Success => "success; every checked push is allowed",
+            Exit::Failure => "a push was blocked, a doctor check failed, or the command failed",
+            Exit::Usage => "invalid command line",
+            Exit::Incompatible => "`meta --check-compat` found an older state schema",
+        }
+    }
+
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+/// Describes this binary for wrapper tools. Rules and exit codes come from
+/// `Rule::ALL` and `Exit::ALL`, so they can't drift from what commands do.
+fn meta(repo: Option<&str>) -> serde_json::Value {
+    let rules: Vec<&str> = Rule::ALL.iter().map(|r| r.as_str()).collect();
+    let exit_codes: Vec<serde_json::Value> = Exit::ALL
+        .iter()
+        .map(|e| serde_json::json!({ "code": *e as i32, "meaning": e.meaning() }))
+        .collect();
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "state_schema_version": state::STATE_VERSION,
+        "state_file": state::state_path(),
+        "config_files": {
+            "user": config::user_config_path(),
+            "repo": repo.map(config::repo_config_path),
+        },
+        "rules": rules,
+        "exit_codes": exit_codes,
+    })
+}
+
+fn print_meta(meta: &serde_json::Value) {
+    let text = |v: &serde_json::Value| v.as_str().map_or_else(|| v.to_string(), str::to_string);
+    println!("version:       {}", text(&meta["version"]));
+    println!("state schema:  {}", meta["state_schema_version"]);
+    println!("state file:    {}", text(&meta["state_file"]));
+    println!("user config:   {}", text(&meta["config_files"]["user"]));
+    if let Some(repo) = meta["config_files"]["repo"].as_str() {
+        println!("repo config:   {}", repo);
+    }
+    let rules: Vec<String> = meta["rules"].as_array().into_iter().flatten().map(text).collect();
+    println!("rules:         {}", rules.join(", "));
+    println!("exit codes:");
+    for e in meta["exit_codes"].as_array().into_iter().flatten() {
+        println!("  {}  {}", e["code"], text(&e["meaning"]));
+    }
+}
+
+// ── CLI dispatch ──────────────────────────────────────────────────────────────
+
+fn main() -> Result<()> {
+    clap_complete::CompleteEnv::with_factory(Cli::command).complete();
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Hook { print_config: true, scope, post } => {
+            let file = match scope.unwrap_or(SettingsScope::User) {
+                SettingsScope::User => "~/.claude/settings.json",
+                SettingsScope::Project => ".claude/settings.json",
+            };
+            eprintln!("Merge into {}:", file);
+            println!("{}", serde_json::to_string_pretty(&settings::hook_snippet(post)?)?);
+        }
+
+        Commands::Hook { .. } => {
+            // A push-guard bug must never abort an unrelated tool call
+            std::panic::set_hook(Box::new(|info| {
+                debug_log(&format!("Hook panicked: {}", info));
+                Exit::Success.exit();
+            }));
+            if let Err(e) = run_hook() {
+                debug_log(&format!("Hook error: {:#}", e));
+                eprintln!("push-guard hook error: {}", e);
+            }
+        }
+
+        Commands::Doctor { repo } => {
+            if doctor::report(&doctor::run(repo.as_deref())) {
+                Exit::Failure.exit();
+            }
+        }
+
+        Commands::Selftest => {
+            if selftest::report(&selftest::run()?) {
+                Exit::Failure.exit();
+            }
+        }
+
+        Commands::Meta { repo, json, check_compat } => {
+            let repo = repo.or_else(|| GitInfo::cwd().repo_key());
+            let meta = meta(repo.as_deref());
+            if json {
+                println!("{}", serde_json::to_string_pretty(&meta)?);
+            } else {
+                print_meta(&meta);
+            }
+            if let Some(min) = check_compat.filter(|min| *min > state::STATE_VERSION) {
+                eprintln!(
+                    "State schema version {} is older than the required {}; upgrade push-guard",
+                    state::STATE_VERSION,
+                    min
+                );
+                Exit::Incompatible.exit();
+            }
+        }
 
-    let json: serde_json::Value =
-        serde_json::from_str(&input).context("Failed to parse hook JSON")?;
+        Commands::SessionEnd { session } => {
+            if let Err(e) = run_session_end(session) {
+                debug_log(&format!("Session end error: {:#}", e));
+                eprintln!("push-guard session-end error: {}", e);
+            }
+        }
+
+        Commands::SessionSummary { session, json } => {
+            if let Err(e) = run_session_summary(session, json) {
+                debug_log(&format!("Session summary error: {:#}", e));
+                eprintln!("push-guard session-summary error: {}", e);
+            }
+        }
+
+        Commands::Check {
+            repo,
+            remote,
+            remote_url,
+            branch,
+            source,
+            force,
+            force_with_lease,
+            command,
+            track_creations,
+            dry_run,
+            interactive,
+        } => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            let interactive = !dry_run
+                && (interactive || Config::load(Some(&repo)).is_ok_and(|c| c.is_interactive()));
+            let blocked = match command {
+                Some(command) => {
+                    check_command(&git, &repo, &command, track_creations, dry_run, interactive)?
+                }
+                None => {
+                    let remote = match (&remote_url, remote) {
+                        (Some(url), None) => url.clone(),
+                        (_, remote) => resolve_remote(&git, remote),
+                    };
+                    let branch = resolve_branch(&git, branch)?;
+                    let force = Force::from_flags(force || force_with_lease, force_with_lease);
+                    let mut push = PushInfo::new(&remote, &branch, force);
+                    push.source = source;
+                    push.remote_url = remote_url;
+                    let report = Report::Check { show_allowed: dry_run };
+                    check_pushes(&git, &repo, &[push], dry_run, interactive, None, report)?
+                }
+            };
+            if blocked && !dry_run {
+                Exit::Failure.exit();
+            }
+        }
+
+        Commands::Why {
+            repo,
+            remote,
+            branch,
+            source,
+            force,
+            force_with_lease,
+            json,
+            absolute,
+        } => {
+            let repo = match repo {
+                Some(r) => stored_repo(&State::load()?, &r)?,
+                None => resolve_repo(None)?,
+            };
+            let git = GitInfo::for_repo(&repo);
+            let remote = resolve_remote(&git, remote);
+            let branch = resolve_branch(&git, branch)?;
+            let force = Force::from_flags(force || force_with_lease, force_with_lease);
+            let mut push = PushInfo::new(&remote, &branch, force);
+            push.source = source;
+            why(&git, &repo, &push, json, absolute)?;
+        }
+
+        Commands::Track {
+            repo,
+            branch,
+            all_local,
+            pattern,
+            stdin,
+            from_reflog,
+            since,
+            yes,
+            scope,
+        } => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            if from_reflog {
+                let since = since.map(|age| chrono::Utc::now() - age);
+                track_from_reflog(&git, &repo, since, yes, scope)?;
+            } else if all_local || stdin {
+                let branches = if all_local {
+                    local_branches_matching(&git, pattern.as_deref())?
+                } else {
+                    read_branch_list(std::io::stdin().lock())?
+                };
+                confirm_precedence(&repo, &branches, false, yes)?;
+                // One locked update, so a bulk import is all or nothing
+                let added = State::update_for(&repo, |state| {
+                    state.write_scope = scope.map(Into::into);
+                    let mut added = 0;
+                    for branch in &branches {
+                        if !state.is_tracked(&repo, branch) {
+                            state.track(&repo, branch);
+                            added += 1;
+                        }
+                    }
+                    added
+                })?;
+                eprintln!(
+                    "Tracked {} new branch(es) in '{}'; {} already tracked",
+                    added,
+                    repo,
+                    branches.len() - added
+                );
+            } else {
+                let branch = git::normalize_branch(&resolve_branch(&git, branch)?)?;
+                confirm_precedence(&repo, std::slice::from_ref(&branch), false, yes)?;
+                State::update_for(&repo, |state| {
+                    state.write_scope = scope.map(Into::into);
+                    state.track(&repo, &branch)
+                })?;
+                eprintln!("Tracking '{}' in '{}'", branch, repo);
+            }
+        }
+
+        Commands::Untrack { repo, branch, scope } => {
+            let repo = resolve_repo(repo)?;
+            State::update_for(&repo, |state| {
+                state.write_scope = scope.map(Into::into);
+                state.untrack(&repo, &branch)
+            })?;
+            eprintln!("Stopped tracking '{}' in '{}'", branch, repo);
+        }
+
+        Commands::Authorize {
+            repo,
+            stdin,
+            all_tracked,
+            matching,
+            include_protected,
+            dry_run,
+            uses,
+            override_hours,
+            allow_default,
+            yes,
+            scope,
+            ..
+        } if stdin || all_tracked || matching.is_some() => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            let branches = if stdin {
+                read_branch_list(std::io::stdin().lock())?
+            } else {
+                check_glob(matching.as_deref())?;
+                let state = State::load_for(&repo)?;
+                let tracked = state.tracked.get(&repo).into_iter().flatten();
+                let tracked: Vec<String> = tracked
+                    .map(|e| e.branch.clone())
+                    .filter(|b| matching.as_deref().is_none_or(|p| config::glob_matches(p, b)))
+                    .collect();
+                let (branches, kept) = split_guarded(&repo, tracked, include_protected);
+                let verb = if dry_run { "Would authorize" } else { "Authorizing" };
+                match branches.as_slice() {
+                    [] => eprintln!("No tracked branches to authorize in '{}'", repo),
+                    _ => eprintln!(
+                        "{} {} tracked branch(es) in '{}': {}",
+                        verb,
+                        branches.len(),
+                        repo,
+                        branches.join(", ")
+                    ),
+                }
+                report_kept(&kept, "authorize");
+                if dry_run || branches.is_empty() {
+                    return Ok(());
+                }
+                branches
+            };
+            if !allow_default {
+                confirm_precedence(&repo, &branches, true, yes || include_protected)?;
+            }
+            let on_disk = std::path::Path::new(&repo).is_dir();
+            let tips: Vec<Option<String>> = branches
+                .iter()
+                .map(|b| if on_disk && uses.is_none() { git.branch_tip(b) } else { None })
+                .collect();
+            let added = State::update_for(&repo, |state| {
+                state.write_scope = scope.map(Into::into);
+                let mut added = 0;
+                for (branch, sha) in branches.iter().zip(&tips) {
+                    added += usize::from(state.authorization(&repo, branch).is_none());
+                    state.authorize_uses(&repo, branch, sha.as_deref(), uses);
+                    if override_hours {
+                        state.override_hours(&repo, branch);
+                    }
+                    if allow_default {
+                        state.allow_default(&repo, branch);
+                    }
+                }
+                added
+            })?;
+            eprintln!(
+                "Authorized {} new branch(es) in '{}'; {} already authorized, now at their tip",
+                added,
+                repo,
+                branches.len() - added
+            );
+            for branch in &branches {
+                webhook::Notification {
+                    event: "authorized",
+                    repo: &repo,
+                    branch,
+                    remote: None,
+                    rule: None,
+                }
+                .send();
+            }
+        }
+
+        Commands::Authorize {
+            repo,
+            branch,
+            pr,
+            remote,
+            sha,
+            uses,
+            override_hours,
+            allow_default,
+            yes,
+            scope,
+            ..
+        } => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            let branch = match pr {
+                Some(number) => {
+                    let target = match &remote {
+                        Some(r) => {
+                            let url = git
+                                .remote_url(r)
+                                .with_context(|| format!("No remote '{}' in '{}'", r, repo))?;
+                            let (owner, name) = github::parse_remote(&url).with_context(|| {
+                                format!("Remote '{}' ({}) is not a GitHub repository", r, url)
+                            })?;
+                            Some(format!("{}/{}", owner, name))
+                        }
+                        None => None,
+                    };
+                    let dir = if Path::new(&repo).is_dir() { repo.as_str() } else { "." };
+                    let head = github::pr_head_branch(dir, number, target.as_deref())?;
+                    eprintln!("Pull request #{} is branch '{}'", number, head);
+                    Some(head)
+                }
+                None => branch,
+            };
+            let branch = git::normalize_branch(&resolve_branch(&git, branch)?)?;
+            if !allow_default {
+                confirm_precedence(&repo, std::slice::from_ref(&branch), true, yes)?;
+            }
+            let on_disk = std::path::Path::new(&repo).is_dir();
+            let sha = match sha {
+                Some(rev) if on_disk => Some(
+                    git.resolve_commit(&rev)
+                        .with_context(|| format!("Unknown commit '{}' in '{}'", rev, repo))?,
+                ),
+                Some(rev) => Some(rev),
+                // A limited authorization is meant for iterating, i.e. new commits
+                None if on_disk && uses.is_none() => git.branch_tip(&branch),
+                None => None,
+            };
+            State::update_for(&repo, |state| {
+                state.write_scope = scope.map(Into::into);
+                state.authorize_uses(&repo, &branch, sha.as_deref(), uses);
+                if override_hours {
+                    state.override_hours(&repo, &branch);
+                }
+                if allow_default {
+                    state.allow_default(&repo, &branch);
+                }
+            })?;
+            let mut limit = uses.map(|n| format!(" for {} push(es)", n)).unwrap_or_default();
+            if override_hours {
+                limit += ", also during restricted hours";
+            }
+            if allow_default {
+                limit += ", overriding the default-branch rule";
+            }
+            match &sha {
+                Some(sha) => eprintln!(
+                    "Authorized push to '{}' in '{}' at {}{}",
+                    branch,
+                    repo,
+                    &sha[..sha.len().min(12)],
+                    limit
+                ),
+                None => eprintln!("Authorized push to '{}' in '{}'{}", branch, repo, limit),
+            }
+            webhook::Notification {
+                event: "authorized",
+                repo: &repo,
+                branch: &branch,
+                remote: None,
+                rule: None,
+            }
+            .send();
+        }
+
+        Commands::Revoke { repo, all, matching, include_protected, dry_run, scope, .. }
+            if all || matching.is_some() =>
+        {
+            let repo = resolve_repo(repo)?;
+            check_glob(matching.as_deref())?;
+            let select = |state: &State| {
+                let authorized = state.authorized.get(&repo).into_iter().flatten();
+                let scope = state.write_scope;
+                let pattern = matching.as_deref();
+                let matches = |b: &str| pattern.is_none_or(|p| config::glob_matches(p, b));
+                let in_scope = authorized
+                    .filter(|e| scope.is_none_or(|s| e.scope == s) && matches(&e.branch));
+                let branches: Vec<String> = in_scope.map(|e| e.branch.clone()).collect();
+                split_guarded(&repo, branches, include_protected)
+            };
+            let (revoked, kept) = if dry_run {
+                let mut state = State::load_for(&repo)?;
+                state.write_scope = scope.map(Into::into);
+                select(&state)
+            } else {
+                // One locked update, so a concurrent authorization is either kept or revoked
+                State::update_for(&repo, |state| {
+                    state.write_scope = scope.map(Into::into);
+                    let (revoked, kept) = select(state);
+                    for branch in &revoked {
+                        state.revoke(&repo, branch);
+                    }
+                    (revoked, kept)
+                })?
+            };
+            let verb = if dry_run { "Would revoke" } else { "Revoked" };
+            match revoked.as_slice() {
+                [] => eprintln!("No authorizations to revoke in '{}'", repo),
+                _ => eprintln!(
+                    "{} {} authorization(s) in '{}': {}",
+                    verb,
+                    revoked.len(),
+                    repo,
+                    revoked.join(", ")
+                ),
+            }
+            report_kept(&kept, "revoke");
+            for branch in revoked.iter().filter(|_| !dry_run) {
+                webhook::Notification {
+                    event: "revoked",
+                    repo: &repo,
+                    branch,
+                    remote: None,
+                    rule: None,
+                }
+                .send();
+            }
+        }
+
+        Commands::Revoke { repo, branch, scope, .. } => {
+            let repo = resolve_repo(repo)?;
+            let branch = branch.context("--branch, --all or --matching is required")?;
+            State::update_for(&repo, |state| {
+                state.write_scope = scope.map(Into::into);
+                state.revoke(&repo, &branch)
+            })?;
+            eprintln!("Revoked authorization for '{}' in '{}'", branch, repo);
+            webhook::Notification {
+                event: "revoked",
+                repo: &repo,
+                branch: &branch,
+                remote: None,
+                rule: None,
+            }
+            .send();
+        }
+
+        Commands::AllowRemote { repo, remote, include_force } => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            let url = git
+                .remote_url(&remote)
+                .with_context(|| format!("No remote '{}' in '{}'", remote, repo))?;
+            let push_urls = git.push_urls(&remote);
+            State::update(|state| state.allow_remote(&repo, &remote, push_urls, include_force))?;
+            let what = if include_force { "pushes, including force pushes," } else { "pushes" };
+            eprintln!("Allowed {} to any branch of '{}' in '{}'", what, remote, repo);
+            let config = Config::load(Some(&repo))?;
+            if let Some(pattern) = config.protected_remote(&url) {
+                eprintln!(
+                    "warning: '{}' matches protected_remote_urls '{}'; the grant has no \
+                     effect while it does",
+                    remote, pattern
+                );
+            }
+        }
+
+        Commands::Refresh { repo } => {
+            let repo = resolve_repo(repo)?;
+            let git = GitInfo::for_repo(&repo);
+            let remotes: Vec<String> =
+                git.run(&["remote"]).unwrap_or_default().lines().map(str::to_string).collect();
+            let changes = State::update(|state| refresh_remotes(state, &repo, &git, &remotes))?;
+            if changes.is_empty() {
+                eprintln!("Nothing is recorded about the remotes of '{}'", repo);
+            }
+            for change in changes {
+                eprintln!("{}", change);
+            }
+        }
 
-    let command = json["tool_input"]["command"]
-        .as_str()
-        .unwrap_or("")
-        .to_string();
+        Commands::DenyRemote { repo, remote } => {
+            let repo = resolve_repo(repo)?;
+            if State::update(|state| state.deny_remote(&repo, &remote))? {
+                eprintln!("Removed the grant for remote '{}' in '{}'", remote, repo);
+            } else {
+                eprintln!("Remote '{}' in '{}' had no grant", remote, repo);
+            }
+        }
 
-    if command.is_empty() {
-        return Ok(());
-    }
+        Commands::List {
+            repo,
+            json,
+            format,
+            tracked_only,
+            authorized_only,
+            all_hosts,
+            absolute,
+        } => {
+            // Local entries of the given (else the enclosing) repo are listed too
+            let repo = match repo {
+                Some(r) => Some(stored_repo(&State::load()?, &r)?),
+                None => None,
+            };
+            let mut state = match repo.clone().or_else(|| GitInfo::cwd().repo_key()) {
+                Some(r) => State::load_for(&r)?,
+                None => State::load()?,
+            };
+            if all_hosts {
+                state = state.all_hosts();
+            }
+            let format = if json { ListFormat::Json } else { format };
+            let (tracked, authorized) = (!authorized_only, !tracked_only);
+            print_list(&state, repo.as_deref(), format, tracked, authorized, absolute)?;
+        }
 
-    let repo = get_repo_root().unwrap_or_else(|| "unknown".to_string());
+        Commands::Stats { repo, json, absolute } => {
+            let stats = Stats::load()?;
+            let total = stats.total(repo.as_deref());
+            if json {
+                let repos: Vec<_> = stats
+                    .repos
+                    .iter()
+                    .filter(|(r, _)| repo.as_deref().is_none_or(|only| only == r.as_str()))
+                    .collect();
+                let output = serde_json::json!({
+                    "allowed": total.allowed,
+                    "blocked": total.blocked,
+                    "rules": total.rules,
+                    "last_block": total.last_block,
+                    "repos": repos.into_iter().collect::<std::collections::BTreeMap<_, _>>(),
+                });
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            } else {
+                println!(
+                    "{} checks: {} allowed, {} blocked",
+                    total.allowed + total.blocked,
+                    total.allowed,
+                    total.blocked
+                );
+                if let Some(t) = total.last_block {
+                    println!("Last block: {}", timefmt::timestamp(t, absolute));
+                }
+                let width = total.rules.keys().map(String::len).max().unwrap_or(0);
+                for (rule, n) in &total.rules {
+                    println!("  {:<width$}  {}", rule, n, width = width);
+                }
+            }
+        }
 
-    // Track all branch creations first
-    let creations = detect_branch_creations(&command);
-    if !creations.is_empty() {
-        if let Ok(mut state) = State::load() {
-            for branch in &creations {
-                state.track(&repo, branch);
+        Commands::History { repo, blocked_only, limit, json, absolute } => {
+            let events: Vec<audit::Event> = audit::read_all()
+                .into_iter()
+                .rev()
+                .filter(|e| repo.as_deref().is_none_or(|r| r == e.repo))
+                .filter(|e| !blocked_only || e.blocked())
+                .take(limit)
+                .collect();
+            if json {
+                println!("{}", serde_json::to_string_pretty(&events)?);
+            } else if events.is_empty() {
+                eprintln!("No events.");
+            } else {
+                let times: Vec<String> =
+                    events.iter().map(|e| timefmt::timestamp(e.timestamp, absolute)).collect();
+                let width = times.iter().map(String::len).max().unwrap_or(0);
+                for (e, time) in events.iter().zip(&times) {
+                    let decision = decision_label(&e.decision);
+                    println!(
+                        "{:<width$}  {}  {:<14}  {}  {}/{}{}{}{}",
+                        time,
+                        decision,
+                        e.rule,
+                        e.repo,
+                        e.remote,
+                        e.branch,
+                        match (e.force, e.force_with_lease) {
+                            (true, true) => "  (force-with-lease)",
+                            (true, false) => "  (force)",
+                            _ => "",
+                        },
+                        if e.no_verify { "  (no-verify)" } else { "" },
+                        match &e.agent {
+                            Some(agent) => format!("  (by {})", agent.describe()),
+                            None => String::new(),
+                        }
+                    );
+                }
             }
-            let _ = state.save();
         }
-    }
 
-    // Check every push in the command — if any would block, block
-    for push in detect_all_pushes(&command) {
-        check(&repo, &push.remote, &push.branch, push.force, false)?;
-    }
+        Commands::Replay { id, last, against_current_state: _, as_recorded } => {
+            let events = audit::read_all();
+            let event = if last {
+                events.last()
+            } else {
+                events.iter().rev().find(|e| Some(&e.id) == id.as_ref())
+            }
+            .context("No matching event in the audit log")?;
+            replay(event, as_recorded)?;
+        }
 
-    Ok(())
-}
+        Commands::Protect { repo, pattern } => {
+            let repo = resolve_repo(repo)?;
+            glob::Pattern::new(&pattern)
+                .with_context(|| format!("Invalid pattern '{}'", pattern))?;
+            let path = config::repo_config_path(&repo);
+            let mut config = Config::load_file(&path)?;
+            if !config.protected_branches.contains(&pattern) {
+                config.protected_branches.push(pattern.clone());
+            }
+            config.save_file(&path)?;
+            eprintln!("Protected '{}' in '{}'", pattern, repo);
+        }
 
-// ── CLI dispatch ──────────────────────────────────────────────────────────────
+        Commands::Unprotect { repo, pattern } => {
+            let repo = resolve_repo(repo)?;
+            let path = config::repo_config_path(&repo);
+            let mut config = Config::load_file(&path)?;
+            config.protected_branches.retain(|p| *p != pattern);
+            config.save_file(&path)?;
+            eprintln!("Unprotected '{}' in '{}'", pattern, repo);
+        }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+        Commands::Config { action } => match action {
+            ConfigAction::Get { key, repo } => {
+                match Config::load(repo.as_deref())?.get(&key)? {
+                    Some(v) => println!("{}", v),
+                    None => eprintln!("'{}' is not set", key),
+                }
+            }
+            ConfigAction::Show { repo } => {
+                let mut config = Config::load(repo.as_deref())?;
+                config.message = config.message.effective();
+                let profiles = std::mem::take(&mut config.profiles);
+                print!("{}", toml::to_string_pretty(&config)?);
+                // As comments, so the output stays valid TOML
+                if let Some(name) = &config.active_profile {
+                    let from = match config::env_profile() {
+                        Some(_) => "PUSH_GUARD_PROFILE",
+                        None => "active_profile",
+                    };
+                    let keys = profiles.get(name).map(Config::keys).transpose()?;
+                    println!("\n# Active profile: {} (from {})", name, from);
+                    println!("# Set by the profile: {}", keys.unwrap_or_default().join(", "));
+                } else if !profiles.is_empty() {
+                    let names: Vec<&str> = profiles.keys().map(String::as_str).collect();
+                    println!("\n# No active profile; defined profiles: {}", names.join(", "));
+                }
+                if let Some(path) = config::policy_path() {
+                    println!("\n# Org policy: {} (from PUSH_GUARD_POLICY_FILE)", path.display());
+                }
+                let sources = Config::sources(repo.as_deref())?;
+                println!("\n# Where each value comes from (the last layer wins; lists combine):");
+                for key in config.keys()?.iter().filter(|k| *k != "active_profile") {
+                    let layers = sources.get(key).map(|l| l.join(", "));
+                    println!("#   {}: {}", key, layers.as_deref().unwrap_or("built-in default"));
+                }
+                if let Some(r) = &repo {
+                    let git = GitInfo::for_repo(r);
+                    let remotes = git.run(&["remote"]).unwrap_or_default();
+                    if !remotes.is_empty() {
+                        println!("\n# Protected branches per remote of {}:", r);
+                    }
+                    for remote in remotes.lines() {
+                        let url = git.remote_url(remote);
+                        let protected = config.protected_on(remote, url.as_deref());
+                        println!("#   {} = {:?}", remote, protected);
+                    }
+                }
+            }
+            ConfigAction::Rules { repo } => {
+                let config = Config::load(repo.as_deref())?;
+                if config.command_rules.is_empty() {
+                    eprintln!("No command rules configured");
+                }
+                for rule in &config.command_rules {
+                    let target = if rule.blocks() {
+                        format!("block: {}", rule.message.as_deref().unwrap_or("(default message)"))
+                    } else {
+                        format!(
+                            "push{} to {}/{}",
+                            if rule.force { " --force" } else { "" },
+                            rule.remote.as_deref().unwrap_or("origin"),
+                            rule.branch.as_deref().unwrap_or("<current branch>")
+                        )
+                    };
+                    println!("{}  →  {}", rule.pattern, target);
+                }
+            }
+            ConfigAction::Set { key, value, repo: Some(r) } if key == "enabled" => {
+                // Kept in the user config, where the repo can't switch itself off
+                let enabled = config::parse_bool(&key, &value)?;
+                config::set_repo_enabled(&r, enabled)?;
+                let done = if enabled { "Enabled" } else { "Disabled" };
+                let path = config::user_config_path();
+                eprintln!("{} push-guard for {} in {}", done, r, path.display());
+            }
+            ConfigAction::Set { key, value, repo } => {
+                let path = match &repo {
+                    Some(r) => {
+                        config::check_repo_key(&key, &value)?;
+                        config::repo_config_path(r)
+                    }
+                    None => config::user_config_path(),
+                };
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                config::set_key(&path, &key, &value)?;
+                eprintln!("Set {} = {} in {}", key, value, path.display());
+            }
+            ConfigAction::Unset { key, repo } => {
+                let path = match &repo {
+                    Some(r) => config::repo_config_path(r),
+                    None => config::user_config_path(),
+                };
+                let reenabled = match repo.as_deref().filter(|_| key == "enabled") {
+                    Some(r) if Config::load(Some(r))?.disables(r) => {
+                        config::set_repo_enabled(r, true)?;
+                        let path = config::user_config_path();
+                        eprintln!("Enabled push-guard for {} in {}", r, path.display());
+                        true
+                    }
+                    _ => false,
+                };
+                if config::unset_key(&path, &key)? {
+                    eprintln!("Unset {} in {}", key, path.display());
+                } else if !reenabled {
+                    eprintln!("'{}' is not set in {}", key, path.display());
+                }
+            }
+            ConfigAction::UseProfile { name } => {
+                let path = config::user_config_path();
+                let keys = Config::load_file(&path)?.profile(&name)?.keys()?;
+                config::set_key(&path, "active_profile", &name)?;
+                eprintln!("Active profile is now '{}', setting {}", name, keys.join(", "));
+                if let Some(other) = config::env_profile().filter(|p| *p != name) {
+                    eprintln!("Note: PUSH_GUARD_PROFILE={} overrides it in this shell", other);
+                }
+            }
+        },
 
-    match cli.command {
-        Commands::Hook => {
-            if let Err(e) = run_hook() {
-                eprintln!("push-guard hook error: {}", e);
+        Commands::Export { repo, output } => {
+            let export = State::load()?.export(repo.as_deref());
+            let contents = serde_json::to_string_pretty(&export)?;
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, contents)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    eprintln!("Exported state to {}", path.display());
+                }
+                None => println!("{}", contents),
             }
         }
 
-        Commands::Check { repo, remote, branch, force, dry_run } => {
-            check(&repo, &remote, &branch, force, dry_run)?;
+        Commands::Import { file, merge: _, replace } => {
+            let contents = std::fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            let imported = Export::parse(&contents)?.into_state();
+            let tracked: usize = imported.tracked.values().map(Vec::len).sum();
+            let authorized: usize = imported.authorized.values().map(Vec::len).sum();
+            State::update(|state| {
+                if replace {
+                    *state = imported;
+                } else {
+                    state.merge(imported);
+                }
+            })?;
+            eprintln!(
+                "Imported {} tracked and {} authorized entries ({})",
+                tracked,
+                authorized,
+                if replace { "replaced" } else { "merged" }
+            );
         }
 
-        Commands::Track { repo, branch } => {
-            let mut state = State::load()?;
-            state.track(&repo, &branch);
-            state.save()?;
-            eprintln!("Tracking '{}' in '{}'", branch, repo);
+        Commands::Migrate { to, repo } => {
+            let value = match to {
+                Storage::Global => "global",
+                Storage::Repo => "repo",
+            };
+            let path = match &repo {
+                Some(r) => config::repo_config_path(r),
+                None => config::user_config_path(),
+            };
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            config::set_key(&path, "storage", value)?;
+            // Rewriting routes every repo's entries to its configured backend
+            State::update(|_| ())?;
+            let local = State::load()?.local_repos.len();
+            eprintln!("Set storage = {} in {}", value, path.display());
+            eprintln!("{} repos now use repo-local storage", local);
         }
 
-        Commands::Authorize { repo, branch } => {
-            let mut state = State::load()?;
-            state.authorize(&repo, &branch);
-            state.save()?;
-            eprintln!("Authorized push to '{}' in '{}'", branch, repo);
+        Commands::Completions { shell, dynamic } => {
+            let mut stdout = std::io::stdout();
+            if dynamic {
+                let bin = std::env::current_exe()?.to_string_lossy().into_owned();
+                let shells = clap_complete::env::Shells::builtins();
+                let completer = shells
+                    .completer(&shell.to_string())
+                    .context("Dynamic completion is not supported for this shell")?;
+                completer.write_registration("COMPLETE", "push-guard", &bin, &bin, &mut stdout)?;
+            } else {
+                clap_complete::generate(shell, &mut Cli::command(), "push-guard", &mut stdout);
+            }
         }
 
-        Commands::Revoke { repo, branch } => {
+        Commands::Prune { repo, dry_run, color, json, unused_for } => {
+            // save() merges these removals into the file as it is at that point
             let mut state = State::load()?;
-            state.revoke(&repo, &branch);
-            state.save()?;
-            eprintln!("Revoked authorization for '{}' in '{}'", branch, repo);
-        }
-
-        Commands::List { repo, json } => {
-            let state = State::load()?;
+            let repo = match repo {
+                Some(r) => Some(stored_repo(&state, &r)?),
+                None => None,
+            };
+            let cutoff = unused_for.map(|age| chrono::Utc::now() - age);
+            let pruned = prune(&mut state, repo.as_deref(), cutoff);
             if json {
-                let output = match &repo {
-                    Some(r) => serde_json::json!({
-                        "tracked": state.tracked.get(r).cloned().unwrap_or_default(),
-                        "authorized": state.authorized.get(r).cloned().unwrap_or_default(),
-                    }),
-                    None => serde_json::json!({
-                        "tracked": state.tracked,
-                        "authorized": state.authorized,
-                    }),
-                };
-                println!("{}", serde_json::to_string_pretty(&output)?);
+                println!("{}", serde_json::to_string_pretty(&pruned.to_json(dry_run))?);
             } else {
-                let tag_claude = ansi_stdout("[claude]    ", "32");
-                let tag_auth = ansi_stdout("[authorized]", "33");
-                match &repo {
-                    Some(r) => {
-                        for b in state.tracked.get(r).into_iter().flatten() {
-                            println!("{}  {}", tag_claude, b);
-                        }
-                        for b in state.authorized.get(r).into_iter().flatten() {
-                            println!("{}  {}", tag_auth, b);
-                        }
-                    }
-                    None => {
-                        for (r, branches) in &state.tracked {
-                            for b in branches {
-                                println!("{}  {}  ::  {}", tag_claude, r, b);
-                            }
-                        }
-                        for (r, branches) in &state.authorized {
-                            for b in branches {
-                                println!("{}  {}  ::  {}", tag_auth, r, b);
-                            }
-                        }
-                    }
+                if dry_run {
+                    print!("{}", cleanup::render(&pruned.removals, color.enabled()));
+                } else {
+                    print_pruned(&pruned);
                 }
+                for (r, reason) in &pruned.skipped {
+                    eprintln!("Skipped some checks for '{}': {}", r, reason);
+                }
+            }
+            if !pruned.is_empty() && !dry_run {
+                state.save()?;
             }
         }
 
-        Commands::Clean { repo, stale } => {
+        Commands::Clean {
+            repo,
+            stale,
+            verify,
+            merged,
+            include_authorized,
+            gone,
+            dry_run,
+            color,
+        } => {
+            // save() merges these removals into the file as it is at that point
             let mut state = State::load()?;
             let mut changed = false;
-            if let Some(r) = repo {
-                state.clean_repo(&r);
-                eprintln!("Removed all entries for '{}'", r);
-                changed = true;
+            // Collected for the dry-run listing; real runs report as they go
+            let mut removed_all: Vec<Removal> = Vec::new();
+            let repo = match repo {
+                Some(r) => Some(stored_repo(&state, &r)?),
+                None => None,
+            };
+            let repos = match &repo {
+                Some(r) => vec![r.clone()],
+                None => state.repos(),
+            };
+            if merged {
+                for r in &repos {
+                    if !std::path::Path::new(r).exists() {
+                        continue;
+                    }
+                    let git = GitInfo::for_repo(r);
+                    let remote = git.push_remote();
+                    let Some(branches) = git.merged_branches(&remote) else {
+                        eprintln!(
+                            "Skipping '{}': could not resolve {}'s default branch",
+                            r, remote
+                        );
+                        continue;
+                    };
+                    let reason = Reason::Merged;
+                    removed_all.extend(removals(&state, r, &branches, include_authorized, reason));
+                    let removed = state.remove_branches(r, &branches, include_authorized);
+                    for b in removed.iter().filter(|_| !dry_run) {
+                        eprintln!("Removed merged branch '{}' from '{}'", b, r);
+                    }
+                    changed |= !removed.is_empty();
+                }
+            }
+            if gone {
+                for r in &repos {
+                    if !std::path::Path::new(r).exists() {
+                        continue;
+                    }
+                    let Some(local) = GitInfo::for_repo(r).local_branches() else {
+                        eprintln!("Skipping '{}': could not list local branches", r);
+                        continue;
+                    };
+                    let mut missing: Vec<String> = Vec::new();
+                    for b in state.branches(r) {
+                        if b.contains(['*', '?', '[']) {
+                            eprintln!("Skipping pattern entry '{}' in '{}'", b, r);
+                        } else if !local.contains(&b) {
+                            missing.push(b);
+                        }
+                    }
+                    removed_all.extend(removals(&state, r, &missing, true, Reason::Gone));
+                    let removed = state.remove_branches(r, &missing, true);
+                    for b in removed.iter().filter(|_| !dry_run) {
+                        eprintln!("Removed gone branch '{}' from '{}'", b, r);
+                    }
+                    changed |= !removed.is_empty();
+                }
+            }
+            if !merged && !gone {
+                if let Some(r) = repo {
+                    state.clean_repo(&r);
+                    if dry_run {
+                        removed_all.push(Removal::repo(&r, Reason::Requested, "--repo given"));
+                    } else {
+                        eprintln!("Removed all entries for '{}'", r);
+                    }
+                    changed = true;
+                }
             }
             if stale {
-                let removed = state.clean_stale();
+                let removed = state.clean_stale(verify);
                 if removed.is_empty() {
                     eprintln!("No stale entries found.");
-                } else {
-                    for r in &removed {
-                        eprintln!("Removed stale repo: {}", r);
+                }
+                for (r, reason) in &removed {
+                    if dry_run {
+                        removed_all.push(Removal::repo(r, Reason::Stale, reason));
+                    } else {
+                        eprintln!("Removed stale repo: {} ({})", r, reason);
                     }
                     changed = true;
                 }
             }
-            if changed {
+            if dry_run {
+                print!("{}", cleanup::render(&removed_all, color.enabled()));
+            } else if changed {
                 state.save()?;
             }
         }
@@ -504,80 +6037,574 @@ mod tests {
     #[test]
     fn parse_push_simple() {
         let args = ["origin", "main"];
-        let p = parse_push_args(&args);
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
         assert_eq!(p.remote, "origin");
         assert_eq!(p.branch, "main");
-        assert!(!p.force);
+        assert_eq!(p.force, Force::None);
     }
 
     #[test]
     fn parse_push_refspec_colon() {
         let args = ["origin", "HEAD:main"];
-        let p = parse_push_args(&args);
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
         assert_eq!(p.remote, "origin");
         assert_eq!(p.branch, "main");
     }
 
+    #[test]
+    fn read_branch_list_skips_blanks_and_repeats() {
+        let input = "feature\n\n  refs/heads/fix  \nfeature\n\t\nfix\n";
+        assert_eq!(read_branch_list(input.as_bytes()).unwrap(), vec!["feature", "fix"]);
+        let err = read_branch_list("ok\nbad name\n".as_bytes()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("Line 2"), "{:#}", err);
+    }
+
+    #[test]
+    fn parse_push_refspec_source() {
+        let p = parse_push_args(&["origin", "+feature:main"], &GitInfo::cwd()).remove(0);
+        assert_eq!(p.source.as_deref(), Some("feature"));
+        assert_eq!(p.renamed_from(), Some("feature"));
+        let args = ["origin", "feature:refs/heads/feature"];
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
+        assert_eq!(p.renamed_from(), None);
+        let p = parse_push_args(&["origin", "feature"], &GitInfo::cwd()).remove(0);
+        assert_eq!(p.source, None);
+    }
+
+    #[test]
+    fn parse_push_one_push_per_refspec() {
+        let parse = |args: &[&str]| -> Vec<(String, bool, Option<String>, Force)> {
+            let pushes = parse_push_args(args, &GitInfo::cwd());
+            pushes.into_iter().map(|p| (p.branch, p.deletion, p.source, p.force)).collect()
+        };
+        let push = |branch: &str, deletion, source: Option<&str>| {
+            (branch.to_string(), deletion, source.map(str::to_string), Force::None)
+        };
+        assert_eq!(parse(&["origin", ":old-feature", "new-feature", "dev:staging"]), vec![
+            push("old-feature", true, None),
+            push("new-feature", false, None),
+            push("staging", false, Some("dev")),
+        ]);
+        // Full ref paths name the same branches
+        assert_eq!(parse(&["origin", ":refs/heads/old", "refs/heads/dev:refs/heads/main"]), vec![
+            push("old", true, None),
+            push("main", false, Some("refs/heads/dev")),
+        ]);
+        assert_eq!(parse(&["origin", "--delete", "a", "b"]), vec![
+            push("a", true, None),
+            push("b", true, None),
+        ]);
+        // A leading + forces only its own refspec
+        let forced = parse(&["origin", "+main", "feature"]);
+        assert_eq!((forced[0].3, forced[1].3), (Force::Unconditional, Force::None));
+    }
+
+    #[test]
+    fn parse_push_refspec_without_a_remote() {
+        let git = GitInfo::cwd();
+        let remote = git.push_remote();
+        let p = parse_push_args(&["HEAD:feature"], &git).remove(0);
+        assert_eq!((p.remote.as_str(), p.branch.as_str()), (remote.as_str(), "feature"));
+        let pushes = parse_push_args(&["-f", "refs/heads/a", "b:c"], &git);
+        let branches: Vec<&str> = pushes.iter().map(|p| p.branch.as_str()).collect();
+        assert_eq!(branches, ["a", "c"]);
+        assert!(pushes.iter().all(|p| p.remote == remote && p.force == Force::Unconditional));
+        // URLs stay remotes
+        for url in ["git@github.com:org/repo.git", "github.com:org/repo", "https://h/r.git"] {
+            let p = parse_push_args(&[url, "main"], &git).remove(0);
+            assert_eq!((p.remote.as_str(), p.branch.as_str()), (url, "main"));
+        }
+        assert!(is_refspec(":gone", &git) && is_refspec("+main", &git) && is_refspec(":", &git));
+        assert!(!is_refspec("origin", &git) && !is_refspec("user@host:repo", &git));
+    }
+
+    #[test]
+    fn parse_push_dynamic_refspecs() {
+        let git = GitInfo::cwd();
+        let parse = |args: &[&str]| -> Vec<(String, Option<String>)> {
+            let pushes = parse_push_args(args, &git);
+            pushes.into_iter().map(|p| (p.branch, p.dynamic)).collect()
+        };
+        let dynamic = |source: &str| (String::new(), Some(source.to_string()));
+        assert_eq!(parse(&["origin", "\"$BRANCH\""]), vec![dynamic("\"$BRANCH\"")]);
+        assert_eq!(parse(&["origin", "-d", SUBSTITUTION]), vec![dynamic(SUBSTITUTION)]);
+        // Several computed refspecs make one push; literal ones keep their own
+        assert_eq!(parse(&["origin", "main", "`cat", "b`", "$x"]), vec![
+            ("main".to_string(), None),
+            dynamic("`cat"),
+        ]);
+        assert_eq!(parse(&["--stdin", "origin"]), vec![dynamic("--stdin")]);
+        assert_eq!(parse(&["--stdin", "origin", "main"]), vec![
+            ("main".to_string(), None),
+            dynamic("--stdin"),
+        ]);
+        let p = parse_push_args(&["origin", "$(…):refs/heads/main"], &git).remove(0);
+        assert_eq!((p.branch.as_str(), p.dynamic), ("main", None));
+    }
+
+    #[test]
+    fn parse_push_recurse_submodules() {
+        let value = |args: &[&str]| {
+            let p = parse_push_args(args, &GitInfo::cwd()).remove(0);
+            (p.recurse_submodules.clone(), p.pushes_submodules())
+        };
+        let on_demand = Some("on-demand".to_string());
+        assert_eq!(value(&["--recurse-submodules=on-demand", "origin", "x"]), (on_demand, true));
+        assert!(value(&["--recurse-submodules=only", "origin", "x"]).1);
+        assert!(!value(&["--recurse-submodules=check", "origin", "x"]).1);
+        assert!(!value(&["--recurse-submodules=no", "origin", "x"]).1);
+        // The value may be a separate word, which is then not the remote
+        let args = ["--recurse-submodules", "on-demand", "up", "x"];
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
+        assert!(p.pushes_submodules());
+        assert_eq!((p.remote.as_str(), p.branch.as_str()), ("up", "x"));
+        let args = ["--recurse-submodules=on-demand", "--no-recurse-submodules", "origin", "x"];
+        assert!(!parse_push_args(&args, &GitInfo::cwd()).remove(0).pushes_submodules());
+    }
+
     #[test]
     fn parse_push_force_flag() {
         let args = ["--force", "origin", "feature"];
-        let p = parse_push_args(&args);
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
         assert_eq!(p.remote, "origin");
         assert_eq!(p.branch, "feature");
-        assert!(p.force);
+        assert_eq!(p.force, Force::Unconditional);
     }
 
     #[test]
     fn parse_push_force_with_lease() {
         let args = ["origin", "feature", "--force-with-lease"];
-        let p = parse_push_args(&args);
-        assert!(p.force);
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
+        assert_eq!(p.force, Force::WithLease);
+        let args = ["--force-with-lease=feature:abc", "--force-if-includes", "origin", "feature"];
+        assert_eq!(parse_push_args(&args, &GitInfo::cwd()).remove(0).force, Force::WithLease);
+    }
+
+    #[test]
+    fn parse_push_force_wins_over_lease() {
+        let args = ["--force-with-lease", "-f", "origin", "feature"];
+        assert_eq!(parse_push_args(&args, &GitInfo::cwd()).remove(0).force, Force::Unconditional);
+    }
+
+    #[test]
+    fn parse_push_force_if_includes_alone_is_not_force() {
+        let args = ["--force-if-includes", "origin", "feature"];
+        assert_eq!(parse_push_args(&args, &GitInfo::cwd()).remove(0).force, Force::None);
     }
 
     #[test]
     fn parse_push_short_force() {
         let args = ["-f", "origin", "feature"];
-        let p = parse_push_args(&args);
-        assert!(p.force);
+        let p = parse_push_args(&args, &GitInfo::cwd()).remove(0);
+        assert_eq!(p.force, Force::Unconditional);
     }
 
     // detect_branch_creations
 
+    /// Names of the branches the command certainly creates.
+    fn created(command: &str) -> Vec<String> {
+        detect_branch_creations(command)
+            .into_iter()
+            .filter(|c| !c.guessed)
+            .map(|c| c.branch)
+            .collect()
+    }
+
     #[test]
     fn detect_checkout_b() {
-        let branches = detect_branch_creations("git checkout -b feature");
+        let branches = created("git checkout -b feature");
         assert_eq!(branches, vec!["feature"]);
     }
 
     #[test]
     fn detect_switch_c() {
-        let branches = detect_branch_creations("git switch -c new-feature");
+        let branches = created("git switch -c new-feature");
         assert_eq!(branches, vec!["new-feature"]);
     }
 
+    #[test]
+    fn detect_checkout_track() {
+        let creations = detect_branch_creations("git checkout --track origin/feature-x");
+        assert_eq!(creations[0].branch, "feature-x");
+        assert_eq!(creations[0].from_remote.as_deref(), Some("origin/feature-x"));
+        assert!(!creations[0].guessed);
+        let creations = detect_branch_creations("git switch -t upstream/fix/login");
+        assert_eq!(creations[0].branch, "fix/login");
+
+        // An explicit local name wins over the remote's
+        let creations = detect_branch_creations("git checkout -b mine --track origin/feature-x");
+        assert_eq!(creations[0].branch, "mine");
+        assert_eq!(creations[0].from_remote.as_deref(), Some("origin/feature-x"));
+        let creations = detect_branch_creations("git switch -c mine origin/feature-x");
+        assert_eq!(creations, vec![Creation::new("mine")]);
+    }
+
+    #[test]
+    fn detect_switch_dwim_is_only_a_guess() {
+        let creations = detect_branch_creations("git switch feature-x");
+        assert_eq!(creations, vec![Creation { guessed: true, ..Creation::new("feature-x") }]);
+        assert!(detect_branch_creations("git switch --detach feature-x").is_empty());
+        assert!(detect_branch_creations("git checkout main -- src/lib.rs").is_empty());
+        assert!(detect_branch_creations("git checkout v1 src/lib.rs").is_empty());
+    }
+
+    #[test]
+    fn detect_fetch_into_local_branch() {
+        let creations = detect_branch_creations("git fetch origin feature-x:feature-x");
+        assert_eq!(creations, vec![Creation {
+            from_remote: Some("origin/feature-x".into()),
+            only_if_new: true,
+            ..Creation::new("feature-x")
+        }]);
+        let fetched = |command: &str| -> Vec<String> {
+            detect_branch_creations(command).into_iter().map(|c| c.branch).collect()
+        };
+        assert_eq!(fetched("git fetch --depth 1 origin refs/heads/a:refs/heads/mine"), ["mine"]);
+        assert_eq!(fetched("git pull origin fix:fix-copy"), ["fix-copy"]);
+        assert_eq!(fetched("git fetch origin a:a +b:b c:refs/remotes/origin/c"), ["a"]);
+        let url = detect_branch_creations("git fetch https://host/x.git a:b");
+        assert_eq!(url[0].from_remote, None);
+
+        for command in [
+            "git fetch origin",
+            "git fetch",
+            "git pull --rebase",
+            "git pull --rebase origin main",
+            "git fetch origin main",
+            "git fetch --all --prune",
+            "git fetch origin +feature:feature",
+            "git fetch origin feature:refs/remotes/origin/feature",
+            "git fetch origin :feature",
+        ] {
+            assert!(fetched(command).is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn reflog_creations_need_a_checkout_at_the_branch_birth() {
+        let now = chrono::Utc::now();
+        let ago = |minutes: i64| now - chrono::Duration::minutes(minutes);
+        let reflog = [
+            (ago(1), "checkout: moving from b to main".to_string()),
+            (ago(2), "checkout: moving from a to b".to_string()),
+            (ago(3), "commit: work".to_string()),
+            (ago(4), "checkout: moving from main to a".to_string()),
+            (ago(5), "checkout: moving from old to a".to_string()),
+            (ago(6), "checkout: moving from main to old".to_string()),
+        ];
+        // `old` was deleted; `main` existed before it was checked out
+        let birth = |branch: &str| match branch {
+            "a" => Some(ago(4)),
+            "b" => Some(ago(2)),
+            "main" => Some(ago(60)),
+            _ => None,
+        };
+        let created = reflog_creations(&reflog, None, birth);
+        assert_eq!(created, [("b".to_string(), ago(2)), ("a".to_string(), ago(4))]);
+        let recent = reflog_creations(&reflog, Some(ago(3)), birth);
+        assert_eq!(recent, [("b".to_string(), ago(2))]);
+    }
+
+    #[test]
+    fn detect_update_ref_creation() {
+        let creations = detect_branch_creations("git update-ref refs/heads/feature HEAD");
+        assert_eq!(creations, vec![Creation { only_if_new: true, ..Creation::new("feature") }]);
+        let written = |command: &str| -> Vec<String> {
+            detect_branch_creations(command).into_iter().map(|c| c.branch).collect()
+        };
+        assert_eq!(written("git update-ref -m 'why' refs/heads/a 0a1b2c3 0000000"), ["a"]);
+        for command in [
+            "git update-ref -d refs/heads/feature",
+            "git update-ref refs/tags/v1 HEAD",
+            "git update-ref refs/remotes/origin/x HEAD",
+            "git update-ref refs/heads/feature",
+            "git update-ref --stdin",
+        ] {
+            assert!(written(command).is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn ordinals() {
+        let all: Vec<String> = [1, 2, 3, 4, 11, 12, 13, 21, 22, 101, 111].map(ordinal).into();
+        let expected = ["1st", "2nd", "3rd", "4th", "11th", "12th", "13th", "21st", "22nd"];
+        assert_eq!(all[..9], expected);
+        assert_eq!((all[9].as_str(), all[10].as_str()), ("101st", "111th"));
+    }
+
+    #[test]
+    fn block_line_values_are_quoted_for_the_shell() {
+        assert_eq!(shell_quote("/tmp/repo"), "/tmp/repo");
+        assert_eq!(shell_quote("/tmp/my repo"), "'/tmp/my repo'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(field_value("feature/x-1.0"), "feature/x-1.0");
+        assert_eq!(field_value(""), "\"\"");
+        assert_eq!(field_value("a \"b\" $c `d` \\"), r#""a \"b\" \$c \`d\` \\""#);
+        assert_eq!(field_value("line\nbreak"), "\"line\\nbreak\"");
+
+        let mut push = PushInfo::new("origin", "it's", Force::None);
+        push.deletion = true;
+        assert_eq!(
+            block_line("/tmp/my repo", &push, Rule::Untracked),
+            "PUSH_GUARD_BLOCK rule=untracked branch=\"it's\" remote=origin \
+             authorize_hint=\"push-guard authorize --repo '/tmp/my repo' --branch 'it'\\\\''s'\""
+        );
+        assert_eq!(
+            block_line("/r", &push, Rule::ForcePush),
+            "PUSH_GUARD_BLOCK rule=force_push branch=\"it's\" remote=origin"
+        );
+    }
+
+    #[test]
+    fn hook_input_tolerates_a_bom_and_trailing_bytes() {
+        let payload = br#"{"tool_input":{"command":"git push"}}"#;
+        let expected = serde_json::json!({ "tool_input": { "command": "git push" } });
+        for input in [
+            [b"\xEF\xBB\xBF".as_slice(), payload].concat(),
+            [b"\0\r\n ".as_slice(), payload, b"\n"].concat(),
+            [payload.as_slice(), b"\x1b[0m trailing"].concat(),
+            [payload.as_slice(), payload].concat(),
+        ] {
+            assert_eq!(parse_hook_input(&input).unwrap(), expected, "{:?}", input);
+        }
+        assert!(parse_hook_input(b"").is_err());
+        assert!(parse_hook_input(b"\xEF\xBB\xBF").is_err());
+        assert!(parse_hook_input(b"not json").is_err());
+    }
+
     #[test]
     fn detect_branch_create() {
-        let branches = detect_branch_creations("git branch my-branch");
+        let branches = created("git branch my-branch");
         assert_eq!(branches, vec!["my-branch"]);
     }
 
+    #[test]
+    fn detect_branch_renames_and_copies() {
+        assert_eq!(branch_move(&["-m", "old", "new"]), Some((Some("old"), "new", false)));
+        assert_eq!(branch_move(&["-M", "new"]), Some((None, "new", false)));
+        assert_eq!(branch_move(&["--copy", "-f", "a", "b"]), Some((Some("a"), "b", true)));
+        assert_eq!(branch_move(&["-C", "b"]), Some((None, "b", true)));
+        assert_eq!(branch_move(&["new-branch"]), None);
+        // Neither name counts as a creation
+        assert!(created("git branch -m old new && git branch -c a b").is_empty());
+
+        let command = "git -C . branch -m old new; git branch --copy x y";
+        let moves = branch_moves(command, &GitInfo::cwd());
+        let moves: Vec<_> = moves.iter().map(|m| (&*m.from, &*m.to, m.copy)).collect();
+        assert_eq!(moves, [("old", "new", false), ("x", "y", true)]);
+    }
+
+    #[test]
+    fn detect_remote_renames_removals_and_url_changes() {
+        let command = "git remote -v rename origin upstream && git -C . remote rm backup; \
+                       git remote set-url --push fork git@example.com:me/x.git; git remote add a b";
+        assert_eq!(remote_changes(command), [
+            RemoteChange::Rename("origin".into(), "upstream".into()),
+            RemoteChange::Remove("backup".into()),
+            RemoteChange::SetUrl("fork".into()),
+        ]);
+        assert_eq!(remote_change(&["rename", "origin"]), None);
+        assert!(created("git remote rename origin upstream").is_empty());
+    }
+
     #[test]
     fn detect_chained_multiple_creations() {
-        let branches = detect_branch_creations("git branch a; git checkout -b b");
+        let branches = created("git branch a; git checkout -b b");
         assert_eq!(branches, vec!["a", "b"]);
     }
 
+    #[test]
+    fn detect_gh_pr_checkout() {
+        let branches = created("gh pr checkout 123 -b review-123");
+        assert_eq!(branches, vec!["review-123"]);
+        let branches = created("env GH_REPO=o/r gh pr checkout 7 --branch=pr-7");
+        assert_eq!(branches, vec!["pr-7"]);
+        // Numeric form: the name is only known after checkout
+        assert!(created("gh pr checkout 123").is_empty());
+        assert!(has_gh_pr_checkout("cd repo && gh pr checkout 123"));
+        assert!(!has_gh_pr_checkout("gh pr view 123"));
+    }
+
+    #[test]
+    fn detect_destructive_gh_operations() {
+        let git = GitInfo::cwd();
+        let detect = |command: &str| {
+            let pushes = detect_all_pushes(command, &git);
+            assert_eq!(pushes.len(), 1, "{}", command);
+            let p = &pushes[0];
+            (p.remote.clone(), p.branch.clone(), p.gh_operation.clone().unwrap())
+        };
+        let sync = detect("gh repo sync acme/fork --force -b develop");
+        let target = Some("acme/fork".to_string());
+        assert_eq!(sync, ("acme/fork".into(), "develop".into(), GhOperation::RepoSync { target }));
+
+        let deleted = GhOperation::DeleteRef { git_ref: "heads/feature".to_string() };
+        let api = "gh api -X DELETE repos/acme/api/git/refs/heads/feature";
+        assert_eq!(detect(api), ("acme/api".into(), "feature".into(), deleted.clone()));
+        let api = "gh api --method=delete /repos/{owner}/{repo}/git/refs/heads/feature";
+        assert_eq!(detect(api), ("origin".into(), "feature".into(), deleted));
+        let (_, tag, _) = detect("gh api -XDELETE repos/acme/api/git/refs/tags/v1");
+        assert_eq!(tag, "tags/v1");
+
+        let release = detect("env GH_TOKEN=x gh release delete -R acme/api v1.2 --cleanup-tag -y");
+        let cleanup = GhOperation::ReleaseDelete { cleanup_tag: true };
+        assert_eq!(release, ("acme/api".into(), "v1.2".into(), cleanup));
+
+        // Reads and non-destructive variants are not pushes
+        for command in [
+            "gh pr view 12",
+            "gh repo sync",
+            "gh api repos/acme/api/git/refs/heads/feature",
+            "gh api -X DELETE repos/acme/api/issues/1/labels/bug",
+            "gh release view v1.2",
+        ] {
+            assert!(detect_all_pushes(command, &git).is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn detect_jj_pushes_and_bookmarks() {
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("jj git push --remote fork -b a --branch=b --bookmark c", &git);
+        let branches: Vec<_> = pushes.iter().map(|p| p.branch.as_str()).collect();
+        assert_eq!(branches, vec!["a", "b", "c"]);
+        assert!(pushes.iter().all(|p| p.remote == "fork" && !p.force.is_force()));
+        assert!(detect_all_pushes("jj git push --change @ --remote=o", &git).is_empty());
+
+        // Mixed with git, and not mistaken for `git push`
+        let command = "jj bookmark create feat -r @- && jj git push --remote o -b feat; git push o x";
+        assert_eq!(created(command), vec!["feat"]);
+        let pushes = detect_all_pushes(command, &git);
+        let branches: Vec<_> = pushes.iter().map(|p| p.branch.as_str()).collect();
+        assert_eq!(branches, vec!["feat", "x"]);
+        assert_eq!(created("jj branch create a b"), vec!["a", "b"]);
+        assert!(created("jj git push -b a").is_empty());
+    }
+
+    #[test]
+    fn jj_push_all_expands_to_local_branches() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().to_str().unwrap();
+        let run = |args: &[&str]| {
+            std::process::Command::new("git").arg("-C").arg(path).args(args).output().unwrap();
+        };
+        run(&["init", "-q", "-b", "main"]);
+        run(&["-c", "user.name=t", "-c", "user.email=t@t", "commit", "--allow-empty", "-qm", "."]);
+        run(&["branch", "feat"]);
+        let git = GitInfo::for_repo(path);
+        let pushes = detect_all_pushes("jj git push --all --remote origin", &git);
+        let branches: Vec<_> = pushes.iter().map(|p| p.branch.as_str()).collect();
+        assert_eq!(branches, vec!["feat", "main"]);
+        // Without flags, the bookmarks at the colocated HEAD
+        let pushes = detect_all_pushes("jj git push --remote origin", &git);
+        assert_eq!(pushes.len(), 2);
+    }
+
+    #[test]
+    fn multi_line_commands_are_split() {
+        let git = GitInfo::cwd();
+        let command = r#"# start a branch for the fix
+git checkout -b fix \
+  --no-track
+cat > notes.md <<'EOF'
+git push origin main
+EOF
+cat <<-END | wc -l
+	git branch ignored
+	END
+grep x <<< "$y"
+git commit -qam 'fix' &&
+  git push -u origin fix"#;
+        assert_eq!(created(command), vec!["fix"]);
+        let pushes = detect_all_pushes(command, &git);
+        assert_eq!(pushes.len(), 1);
+        assert_eq!((pushes[0].remote.as_str(), pushes[0].branch.as_str()), ("origin", "fix"));
+        assert_eq!(heredoc_delimiters(r#"a <<EOF; b <<-"X""#), vec!["EOF", "X"]);
+    }
+
+    #[test]
+    fn aliases_are_expanded_before_detection() {
+        let lookup = |name: &str| match name {
+            "pushf" => Some("push --force-with-lease".to_string()),
+            "nb" => Some("checkout -b".to_string()),
+            "ship" => Some("!git fetch && git push origin HEAD:main".to_string()),
+            "up" => Some("pushf".to_string()),
+            _ => None,
+        };
+        let expanded = expand_aliases("git nb feat && git pushf origin feat", lookup);
+        assert_eq!(created(&expanded), vec!["feat"]);
+        let pushes = detect_all_pushes(&expanded, &GitInfo::cwd());
+        assert_eq!(pushes[0].branch, "feat");
+        assert_eq!(pushes[0].force, Force::WithLease);
+
+        // Shell aliases are scanned as commands
+        let pushes = detect_all_pushes(&expand_aliases("git ship", lookup), &GitInfo::cwd());
+        assert_eq!(pushes[0].branch, "main");
+        // Aliases of aliases, and unknown subcommands left alone
+        assert_eq!(expand_aliases("git up o b", lookup), "git push --force-with-lease o b");
+        assert_eq!(expand_aliases("git status", lookup), "git status");
+    }
+
+    #[test]
+    fn push_options_are_captured() {
+        let git = GitInfo::cwd();
+        let push = parse_push_args(
+            &["-o", "ci.skip", "--push-option", "a=1", "--push-option=b", "origin", "feat"],
+            &git,
+        )
+        .remove(0);
+        assert_eq!(push.push_options, vec!["ci.skip", "a=1", "b"]);
+        assert_eq!((push.remote.as_str(), push.branch.as_str()), ("origin", "feat"));
+    }
+
+    #[test]
+    fn no_verify_is_captured() {
+        let git = GitInfo::cwd();
+        let no_verify = |args: &[&str]| parse_push_args(args, &git).remove(0).no_verify;
+        assert!(no_verify(&["origin", "HEAD", "--no-verify"]));
+        assert!(no_verify(&["--no-verify", "origin", "feat"]));
+        assert!(!no_verify(&["--no-verify", "--verify", "origin", "feat"]));
+        assert!(!no_verify(&["origin", "feat"]));
+    }
+
     #[test]
     fn detect_no_creation() {
-        let branches = detect_branch_creations("git push origin main");
+        let branches = created("git push origin main");
         assert!(branches.is_empty());
     }
 
+    // hook_commands
+
+    #[test]
+    fn hook_commands_coerces_shapes() {
+        use serde_json::json;
+        let cmd = |command: serde_json::Value| {
+            hook_commands(&json!({"tool_input": {"command": command}}))
+        };
+        let expected = vec!["git push origin x".to_string()];
+        assert_eq!(cmd(json!("git push origin x")), expected);
+        assert_eq!(cmd(json!(["git", "push", "origin", "x"])), expected);
+        let each = ["git add .", "git push origin x"];
+        assert_eq!(cmd(json!(each)), each);
+        assert!(cmd(json!(["git", 1])).is_empty());
+        assert!(cmd(json!(42)).is_empty());
+        assert!(cmd(json!("  ")).is_empty());
+        assert!(hook_commands(&json!({"tool_input": ["git push"]})).is_empty());
+        assert!(hook_commands(&json!({})).is_empty());
+
+        let input = json!({"tool_input": {
+            "command": "git fetch",
+            "commands": ["git add .", ["git", "push", "origin", "x"], 7, ""],
+            "timeout": 30,
+        }});
+        assert_eq!(hook_commands(&input), ["git fetch", "git add .", "git push origin x"]);
+    }
+
     // detect_all_pushes
 
     #[test]
     fn detect_single_push() {
-        let pushes = detect_all_pushes("git push origin feature");
+        let pushes = detect_all_pushes("git push origin feature", &GitInfo::cwd());
         assert_eq!(pushes.len(), 1);
         assert_eq!(pushes[0].remote, "origin");
         assert_eq!(pushes[0].branch, "feature");
@@ -585,7 +6612,7 @@ mod tests {
 
     #[test]
     fn detect_chained_pushes() {
-        let pushes = detect_all_pushes("git push origin a; git push upstream b");
+        let pushes = detect_all_pushes("git push origin a; git push upstream b", &GitInfo::cwd());
         assert_eq!(pushes.len(), 2);
         assert_eq!(pushes[0].remote, "origin");
         assert_eq!(pushes[0].branch, "a");
@@ -593,13 +6620,298 @@ mod tests {
         assert_eq!(pushes[1].branch, "b");
     }
 
+    #[test]
+    fn detect_pushes_run_by_xargs_find_and_loops() {
+        let git = GitInfo::cwd();
+        let runners = |command: &str| -> Vec<(String, Option<&str>)> {
+            let pushes = detect_all_pushes(command, &git);
+            pushes.into_iter().map(|p| (p.branch, p.runner)).collect()
+        };
+        let unknown = |runner| vec![(String::new(), Some(runner))];
+        let xargs = "git branch | grep feat | xargs -I{} git push origin {}";
+        assert_eq!(runners(xargs), unknown("`xargs`"));
+        assert_eq!(runners("echo a b | xargs -n1 git push -u origin"), unknown("`xargs`"));
+        let find = "find . -name '*.git' -execdir git push origin main \\;";
+        assert_eq!(runners(find), unknown("`find -exec`"));
+        // xargs feeding a later pipeline stage does not run the push
+        assert_eq!(runners("xargs echo | git push origin main")[0].1, None);
+
+        let named = |branches: &[&str]| -> Vec<(String, Option<&str>)> {
+            branches.iter().map(|b| (b.to_string(), None)).collect()
+        };
+        let for_loop = "for b in a 'b' c; do git push origin \"$b\"; done";
+        assert_eq!(runners(for_loop), named(&["a", "b", "c"]));
+        let multiline = "for b in x y\ndo\n  git push origin ${b}\n  git push fork $b\ndone";
+        let pushes = detect_all_pushes(multiline, &git);
+        let targets: Vec<_> = pushes.iter().map(|p| format!("{}/{}", p.remote, p.branch)).collect();
+        assert_eq!(targets, ["origin/x", "fork/x", "origin/y", "fork/y"]);
+        let nested = "for r in o u; do for b in a b; do git push $r $b; done; done";
+        assert_eq!(detect_all_pushes(nested, &git).len(), 4);
+        // Computed lists and while loops leave the branch to run time
+        let computed = "for b in $(git branch); do git push origin $b; done";
+        assert_eq!(runners(computed), unknown("a `for` loop"));
+        let piped = "git branch | while read b; do git push origin $b; done";
+        assert_eq!(runners(piped), unknown("a `while` loop"));
+        let fixed = "for i in $(seq 3); do git push origin main; done";
+        assert_eq!(runners(fixed), named(&["main"]));
+    }
+
+    #[test]
+    fn meta_lists_every_rule_and_exit_code() {
+        // A new variant fails to compile here until it gets the next index,
+        // and then this test until it is in Rule::ALL
+        let index = |rule: Rule| match rule {
+            Rule::EmptyBranch => 0,
+            Rule::UnresolvedTarget => 1,
+            Rule::NonBranch => 2,
+            Rule::ForcePush => 3,
+            Rule::DefaultBranch => 4,
+            Rule::ProtectedBranch => 5,
+            Rule::Tracked => 6,
+            Rule::Authorized => 7,
+            Rule::Untracked => 8,
+            Rule::Disabled => 9,
+            Rule::TrustedDir => 10,
+            Rule::TrustedRemote => 11,
+            Rule::GithubProtection => 12,
+            Rule::ShaMismatch => 13,
+            Rule::ForceWithLeaseTracked => 14,
+            Rule::OtherSession => 15,
+            Rule::EnvDisabled => 16,
+            Rule::EnvAllow => 17,
+            Rule::CommandPattern => 18,
+            Rule::PushOption => 19,
+            Rule::RenamedPush => 20,
+            Rule::NewBranchPublish => 21,
+            Rule::UnfinishedOperation => 22,
+            Rule::SubmodulePush => 23,
+            Rule::GhOperation => 24,
+            Rule::AutoTrackedPrefix => 25,
+            Rule::RemoteGrant => 26,
+            Rule::RestrictedHours => 27,
+            Rule::NoVerify => 28,
+            Rule::LocalRemote => 29,
+            Rule::UndecodableBranch => 30,
+            Rule::SubagentPush => 31,
+            Rule::DeniedRemote => 32,
+        };
+        let meta = meta(None);
+        let rules = meta["rules"].as_array().unwrap();
+        for (i, rule) in Rule::ALL.iter().enumerate() {
+            assert_eq!(index(*rule), i, "{:?} is out of place in Rule::ALL", rule);
+            assert!(rules.contains(&serde_json::json!(rule.as_str())), "{:?}", rule);
+        }
+        assert_eq!(rules.len(), 33);
+
+        let code = |exit: Exit| match exit {
+            Exit::Success => 0,
+            Exit::Failure => 1,
+            Exit::Usage => 2,
+            Exit::Incompatible => 3,
+        };
+        let exit_codes = meta["exit_codes"].as_array().unwrap();
+        let codes: Vec<i64> = exit_codes.iter().map(|e| e["code"].as_i64().unwrap()).collect();
+        assert_eq!(codes, Exit::ALL.map(code));
+        assert_eq!(meta["state_schema_version"], state::STATE_VERSION);
+        assert!(meta["config_files"]["repo"].is_null());
+    }
+
+    #[test]
+    fn detect_pushes_behind_wrappers_and_pipelines() {
+        let git = GitInfo::cwd();
+        for command in [
+            "yes | git push origin main",
+            "echo y | git push origin main",
+            "unbuffer git push origin main",
+            "script -c \"git push origin main\" /dev/null",
+            "script -qc 'git fetch; git push origin main' /dev/null",
+            "/usr/bin/script --command 'git push origin main' /dev/null",
+            "expect -c 'spawn git push origin main; expect eof'",
+            "bash -lc \"cd . && git push origin main\"",
+            "sh -c \"script -q -c 'git push origin main' /dev/null\"",
+            "(git push origin main)",
+            "true && (cd . && git push origin main) || exit 1",
+            "if true; then (git push origin main); fi",
+            "eval \"git push origin main\"",
+            "eval 'git push' origin main",
+            "(eval 'git push origin main')",
+            "bash -c '(git push origin main)'",
+        ] {
+            let pushes = detect_all_pushes(command, &git);
+            assert_eq!(pushes.len(), 1, "{}", command);
+            assert_eq!((pushes[0].remote.as_str(), pushes[0].branch.as_str()), ("origin", "main"));
+        }
+        for command in [
+            "git log | grep push",
+            "echo \"git push origin main\"",
+            "bash deploy.sh -c 'git push origin main'",
+            "script -c \"git push origin main",
+            "git commit -m \"push (git push origin main)\"",
+            "echo eval 'git push origin main'",
+            "(git push origin main",
+        ] {
+            assert!(detect_all_pushes(command, &git).is_empty(), "{}", command);
+        }
+    }
+
+    #[test]
+    fn detect_pushes_inside_substitutions() {
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("echo \"pushed: $(git push origin main 2>&1)\"", &git);
+        assert_eq!(pushes.len(), 1);
+        assert_eq!((pushes[0].remote.as_str(), pushes[0].branch.as_str()), ("origin", "main"));
+        assert!(!pushes[0].non_branch);
+
+        let command = "diff <(git push --dry-run origin main 2>&1) expected.txt";
+        let pushes = detect_all_pushes(command, &git);
+        assert_eq!(pushes.len(), 1);
+        assert!(pushes[0].non_branch);
+
+        let nested = "echo $(cat $(git push origin feat > log))";
+        assert_eq!(detect_all_pushes(nested, &git)[0].branch, "feat");
+        // Unclosed substitutions are not run, and must not trip the parser
+        for command in ["echo $(git push origin main", "diff <(", "echo $(", "a $( ( b"] {
+            assert!(detect_all_pushes(command, &git).is_empty(), "{}", command);
+        }
+        assert_eq!(unwrap_substitutions("a $((1+2)) b"), "(1+2)\na $(…) b");
+        assert_eq!(unwrap_substitutions("x\na <(b $(c)) d"), "x\nc\nb $(…)\na $(…) d");
+    }
+
+    #[test]
+    fn redirections_are_not_refspecs() {
+        let commands = split_commands("git push origin main 2>&1 &> log; b & c");
+        assert_eq!(commands, ["git push origin main 2>&1 &> log", " b ", " c"]);
+        let words = [("2>&1", false), (">log", false), (">", true), ("2>>", true)];
+        for (word, target_follows) in words {
+            assert_eq!(redirection(word), Some(target_follows), "{}", word);
+        }
+        assert_eq!(redirection("main"), None);
+        assert_eq!(redirection("2"), None);
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("git push origin feat 2> err.log >&2", &git);
+        let branches: Vec<_> = pushes.iter().map(|p| p.branch.as_str()).collect();
+        assert_eq!(branches, ["feat"]);
+    }
+
+    #[test]
+    fn git_invocation_skips_global_options() {
+        let (dir, sub) = git_invocation(&["-C", "../other", "-c", "a=b", "--no-pager", "push"]);
+        assert_eq!((dir, sub), (Some("../other"), &["push"][..]));
+        assert_eq!(git_invocation(&["push", "-u"]), (None, &["push", "-u"][..]));
+        assert_eq!(join_dir(Some(Path::new("/a")), "b"), PathBuf::from("/a/b"));
+        assert_eq!(join_dir(Some(Path::new("/a")), "/c"), PathBuf::from("/c"));
+    }
+
+    #[test]
+    fn push_config_overrides_keep_push_target_settings() {
+        let args = [
+            "-c",
+            "push.default=current",
+            "-c",
+            "'remote.pushDefault=upstream'",
+            "-c",
+            "branch.Feature.pushRemote=fork",
+            "-c",
+            "color.ui=always",
+            "-c",
+            "branch.main.description=x",
+            "-c",
+            "push.default",
+            "push",
+            "-c",
+            "branch.main.remote=origin",
+        ];
+        assert_eq!(
+            push_config_overrides(&args),
+            [
+                "push.default=current",
+                "remote.pushDefault=upstream",
+                "branch.Feature.pushRemote=fork"
+            ]
+        );
+        assert!(push_config_overrides(&["push", "origin"]).is_empty());
+    }
+
+    #[test]
+    fn push_to_remote_only_resolves_in_command_directory() {
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("cd /nonexistent && git push -u origin", &git);
+        assert_eq!(pushes[0].remote, "origin");
+        assert!(pushes[0].unresolved && pushes[0].branch.is_empty());
+        let pushes = detect_all_pushes("git -C /nonexistent push --set-upstream origin", &git);
+        assert!(pushes[0].unresolved);
+        let pushes = detect_all_pushes("git -C /nonexistent push -u origin HEAD:feat", &git);
+        assert_eq!(pushes[0].branch, "feat");
+        assert!(!pushes[0].unresolved);
+    }
+
+    #[test]
+    fn tag_and_dry_run_pushes_update_no_branch() {
+        let git = GitInfo::cwd();
+        for command in [
+            "git push --tags",
+            "git push origin --tags",
+            "git push origin tag v1.0",
+            "git push origin refs/tags/v1.0",
+            "git push origin v1.0:refs/tags/v1.0",
+            "git push -n origin main",
+        ] {
+            assert!(detect_all_pushes(command, &git)[0].non_branch, "{}", command);
+        }
+        assert!(!detect_all_pushes("git push -f origin refs/tags/v1.0", &git)[0].non_branch);
+        assert!(!detect_all_pushes("git push origin main", &git)[0].non_branch);
+    }
+
     #[test]
     fn detect_push_with_creation() {
         // Both a branch creation and a push in same chained command
-        let creations = detect_branch_creations("git checkout -b feat && git push origin feat");
+        let creations = created("git checkout -b feat && git push origin feat");
         assert_eq!(creations, vec!["feat"]);
-        let pushes = detect_all_pushes("git checkout -b feat && git push origin feat");
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("git checkout -b feat && git push origin feat", &git);
         assert_eq!(pushes.len(), 1);
         assert_eq!(pushes[0].branch, "feat");
     }
+
+    #[test]
+    fn detect_rewrite_before_force_push() {
+        let git = GitInfo::cwd();
+        let pushes = detect_all_pushes("git commit --amend --no-edit && git push -f origin f", &git);
+        let rewrite = pushes[0].rewrite.as_ref().unwrap();
+        assert_eq!(rewrite.command, "git commit --amend");
+        assert_eq!(rewrite.base.as_deref(), Some("HEAD~1"));
+
+        let pushes = detect_all_pushes("git rebase -i --onto x origin/main; git push -f o f", &git);
+        assert_eq!(pushes[0].rewrite.as_ref().unwrap().base.as_deref(), Some("origin/main"));
+
+        // Only force pushes carry the rewrite, and only when it came first
+        assert!(detect_all_pushes("git commit --amend && git push o f", &git)[0].rewrite.is_none());
+        assert!(detect_all_pushes("git push -f o f; git rebase main", &git)[0].rewrite.is_none());
+        assert!(detect_all_pushes("git rebase --abort; git push -f o f", &git)[0].rewrite.is_none());
+    }
+
+    #[test]
+    fn slugify_keeps_first_words() {
+        assert_eq!(slugify("Fix login redirect (#42)"), "fix-login-redirect-42");
+        assert_eq!(slugify("feat: add OAuth2 to the CLI tool now"), "feat-add-oauth2-to-the-cli");
+        assert_eq!(slugify("  --- "), "changes");
+        assert_eq!(slugify("Überarbeitung"), "berarbeitung");
+    }
+
+    #[test]
+    fn unique_branch_avoids_existing_names() {
+        let existing = vec!["claude/fix".to_string(), "claude/fix-2".to_string()];
+        assert_eq!(unique_branch("claude/fix", &existing), "claude/fix-3");
+        assert_eq!(unique_branch("claude/other", &existing), "claude/other");
+    }
+
+    #[test]
+    fn parse_answer_defaults_to_no() {
+        assert_eq!(parse_answer("y\n"), Answer::Yes);
+        assert_eq!(parse_answer(" YES "), Answer::Yes);
+        assert_eq!(parse_answer("always\n"), Answer::Always);
+        assert_eq!(parse_answer("a"), Answer::Always);
+        assert_eq!(parse_answer("\n"), Answer::No);
+        assert_eq!(parse_answer("sure"), Answer::No);
+    }
 }
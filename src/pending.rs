@@ -0,0 +1,118 @@
+use crate::state::{same_branch, state_path};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How long a noted creation lets pushes of its branch through.
+const TTL_MINUTES: i64 = 5;
+
+/// A branch creation the hook saw but could not record in the state.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Creation {
+    pub repo: String,
+    pub branch: String,
+    pub at: DateTime<Utc>,
+}
+
+/// The scratch file the notes are kept in. It lives in the user's runtime
+/// directory (`$XDG_RUNTIME_DIR`, which only they can use) when there is one,
+/// since the state file's may be the one that is read-only, and is named
+/// after the state file so that each state has its own. Without one, it is
+/// kept next to the state file.
+pub fn pending_path() -> PathBuf {
+    let runtime = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+    let Some(dir) = runtime.filter(|dir| dir.is_absolute()) else {
+        return state_path().with_extension("pending.json");
+    };
+    let mut hasher = DefaultHasher::new();
+    state_path().hash(&mut hasher);
+    dir.join(format!("push-guard-pending-{:016x}.json", hasher.finish()))
+}
+
+fn load() -> Vec<Creation> {
+    fs::read_to_string(pending_path())
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Notes that `branches` were just created in `repo`, for when tracking them
+/// failed. Expired notes are dropped on the way.
+pub fn note(repo: &str, branches: &[String]) -> Result<()> {
+    let now = Utc::now();
+    let mut notes: Vec<Creation> = load().into_iter().filter(|n| live(n, now)).collect();
+    notes.retain(|n| !(n.repo == repo && branches.contains(&n.branch)));
+    notes.extend(branches.iter().map(|branch| Creation {
+        repo: repo.to_string(),
+        branch: branch.clone(),
+        at: now,
+    }));
+    let path = pending_path();
+    replace(&path, &serde_json::to_string_pretty(&notes)?)
+        .with_context(|| format!("Failed to write pending creations to {}", path.display()))
+}
+
+/// Writes `contents` to a file created for it next to `path`, then renames
+/// that over `path`, so a link planted at either name is never followed.
+fn replace(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+    let tmp = PathBuf::from(tmp);
+    let written = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .and_then(|()| fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Returns true when a creation of `branch` in `repo` was noted less than
+/// the TTL ago.
+pub fn is_pending(repo: &str, branch: &str, ignore_case: bool) -> bool {
+    noted(&load(), repo, branch, ignore_case, Utc::now())
+}
+
+fn noted(
+    notes: &[Creation],
+    repo: &str,
+    branch: &str,
+    ignore_case: bool,
+    now: DateTime<Utc>,
+) -> bool {
+    notes
+        .iter()
+        .any(|n| n.repo == repo && same_branch(&n.branch, branch, ignore_case) && live(n, now))
+}
+
+fn live(note: &Creation, now: DateTime<Utc>) -> bool {
+    now - note.at < Duration::minutes(TTL_MINUTES)
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_expire_after_the_ttl() {
+        let created = Utc::now();
+        let notes = [Creation { repo: "/repo".into(), branch: "Feature".into(), at: created }];
+        let at = |seconds| created + Duration::seconds(seconds);
+        assert!(noted(&notes, "/repo", "Feature", false, at(0)));
+        assert!(noted(&notes, "/repo", "Feature", false, at(30)));
+        assert!(!noted(&notes, "/repo", "Feature", false, at(TTL_MINUTES * 60)));
+        assert!(!noted(&notes, "/repo", "Feature", false, at(10 * 60)));
+        assert!(!noted(&notes, "/other", "Feature", false, at(30)));
+        assert!(!noted(&notes, "/repo", "feature", false, at(30)));
+        assert!(noted(&notes, "/repo", "feature", true, at(30)));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::Config;
+use crate::git::GitInfo;
+use crate::state::{self, CachedHead, State};
+use chrono::{DateTime, Duration, Utc};
+
+/// How long asking a remote for its HEAD may take before the recorded answer
+/// is used as-is.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Returns the default branch of `remote`, given the one `recorded` in
+/// `refs/remotes/<remote>/HEAD`. Once the last check is older than
+/// `remote_head_max_age_hours`, the remote is asked with `git ls-remote`; its
+/// answer is cached in state, so the hook pays for this at most once per
+/// period. Offline, for remotes without a URL, and when the check is turned
+/// off, the recorded answer is returned unchanged.
+pub fn checked(git: &GitInfo, remote: &str, recorded: Option<String>) -> Option<String> {
+    let Some(repo) = git.repo_key() else {
+        return recorded;
+    };
+    let config = Config::load(Some(&repo)).unwrap_or_default();
+    let Some(max_age) = config.remote_head_max_age() else {
+        return recorded;
+    };
+    if config.is_offline() || git.remote_url(remote).is_none() {
+        return recorded;
+    }
+    let key = state::remote_head_key(&repo, remote);
+    let state = State::load().ok();
+    let cached = state.as_ref().and_then(|s| s.remote_heads.get(&key));
+    let ls_remote = || git.ls_remote_head(remote, TIMEOUT);
+    let check = resolve(recorded.as_deref(), cached, Utc::now(), max_age, ls_remote);
+    if let Some(answer) = check.asked.as_ref().filter(|_| state::writable()) {
+        let _ = State::update(|s| s.cache_remote_head(&key, answer.as_deref()));
+        let moved = answer.as_ref().filter(|a| recorded.as_ref() != Some(*a));
+        if let Some(branch) = moved.filter(|_| config.update_remote_head.unwrap_or(false)) {
+            git.run(&["remote", "set-head", remote, branch]);
+        }
+    }
+    check.branch
+}
+
+/// Returns the default branch of the repository at `url`, asking it with
+/// `git ls-remote`. None offline, or when it can't be reached in time.
+pub fn asked(git: &GitInfo, url: &str) -> Option<String> {
+    let config = Config::load(git.repo_key().as_deref()).unwrap_or_default();
+    if config.is_offline() {
+        return None;
+    }
+    git.ls_remote_head(url, TIMEOUT).as_deref().and_then(parse_symref)
+}
+
+/// Outcome of checking a recorded default branch.
+#[derive(Debug, PartialEq)]
+struct Check {
+    branch: Option<String>,
+    /// The remote's answer when it was asked, to be cached; None inside the
+    /// inner option when it could not be reached.
+    asked: Option<Option<String>>,
+}
+
+/// Decides the default branch from the `recorded` one and the `cached` check,
+/// calling `ls_remote` only when the cache is older than `max_age` at `now`.
+fn resolve(
+    recorded: Option<&str>,
+    cached: Option<&CachedHead>,
+    now: DateTime<Utc>,
+    max_age: Duration,
+    ls_remote: impl FnOnce() -> Option<String>,
+) -> Check {
+    if let Some(c) = cached.filter(|c| now - c.checked_at < max_age) {
+        let branch = c.branch.as_deref().or(recorded).map(str::to_string);
+        return Check { branch, asked: None };
+    }
+    let answer = ls_remote().as_deref().and_then(parse_symref);
+    let branch = answer.clone().or_else(|| recorded.map(str::to_string));
+    Check { branch, asked: Some(answer) }
+}
+
+/// Returns the branch in the `ref: refs/heads/<branch>\tHEAD` line of
+/// `git ls-remote --symref` output.
+fn parse_symref(output: &str) -> Option<String> {
+    output.lines().find_map(|line| {
+        let (target, name) = line.strip_prefix("ref:")?.trim().split_once('\t')?;
+        let branch = target.trim().strip_prefix("refs/heads/")?;
+        (name.trim() == "HEAD").then(|| branch.to_string())
+    })
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LS_REMOTE: &str = "ref: refs/heads/main\tHEAD\n0a1b2c3d\tHEAD\n";
+
+    fn cached(branch: Option<&str>, age: Duration, now: DateTime<Utc>) -> CachedHead {
+        CachedHead { branch: branch.map(str::to_string), checked_at: now - age }
+    }
+
+    #[test]
+    fn parse_symref_reads_the_head_line() {
+        assert_eq!(parse_symref(LS_REMOTE).as_deref(), Some("main"));
+        assert_eq!(parse_symref("0a1b2c3d\tHEAD\n"), None);
+        assert_eq!(parse_symref(""), None);
+    }
+
+    #[test]
+    fn fresh_cache_skips_the_remote() {
+        let now = Utc::now();
+        let day = Duration::hours(24);
+        let unreachable = || -> Option<String> { panic!("remote asked despite a fresh cache") };
+        let fresh = cached(Some("main"), Duration::hours(1), now);
+        let check = resolve(Some("master"), Some(&fresh), now, day, unreachable);
+        assert_eq!(check, Check { branch: Some("main".into()), asked: None });
+        // A failed check still counts, and leaves the recorded answer in place
+        let failed = cached(None, Duration::hours(1), now);
+        let check = resolve(Some("master"), Some(&failed), now, day, unreachable);
+        assert_eq!(check.branch.as_deref(), Some("master"));
+    }
+
+    #[test]
+    fn stale_cache_asks_the_remote_and_records_its_answer() {
+        let now = Utc::now();
+        let day = Duration::hours(24);
+        let stale = cached(Some("master"), Duration::hours(25), now);
+        let check = resolve(Some("master"), Some(&stale), now, day, || Some(LS_REMOTE.into()));
+        let main = Some("main".to_string());
+        assert_eq!(check, Check { branch: main.clone(), asked: Some(main) });
+        let check = resolve(Some("master"), None, now, day, || Some(LS_REMOTE.into()));
+        assert_eq!(check.branch.as_deref(), Some("main"));
+        // An unreachable remote is recorded as such, keeping the local answer
+        let check = resolve(Some("master"), None, now, day, || None);
+        assert_eq!(check, Check { branch: Some("master".into()), asked: Some(None) });
+    }
+}
@@ -0,0 +1,243 @@
+use crate::git::GitInfo;
+use crate::state::State;
+use crate::{detect_all_pushes, evaluate, expand_command, Decision, Rule};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Environment variables that would let the user's setup leak into the
+/// scenarios, or the scenarios leak into the user's setup.
+const CLEARED_ENV: [&str; 12] = [
+    "PUSH_GUARD_ALLOW",
+    "PUSH_GUARD_DISABLE",
+    "PUSH_GUARD_PROFILE",
+    "PUSH_GUARD_POLICY_FILE",
+    "PUSH_GUARD_STATE_READONLY",
+    "PUSH_GUARD_WEBHOOK",
+    "PUSH_GUARD_DEBUG_LOG",
+    "PUSH_GUARD_AUDIT_COMMANDS",
+    "PUSH_GUARD_VERBOSE",
+    "GIT_DIR",
+    "GIT_WORK_TREE",
+    "GIT_INDEX_FILE",
+];
+
+/// The scenarios' user config: the built-in defaults, minus the exemptions
+/// that would let every push to the local "origin" through.
+const CONFIG: &str = "allow_local_remotes = false\noffline = true\n";
+
+const FEATURE: &str = "selftest/feature";
+const OTHER: &str = "selftest/other";
+
+/// Result of one scenario; `failure` says what went wrong.
+pub struct Outcome {
+    pub name: &'static str,
+    pub failure: Option<String>,
+}
+
+/// Runs every scenario in a throwaway repository with its own state file,
+/// user config and git config, restoring the working directory afterwards.
+pub fn run() -> Result<Vec<Outcome>> {
+    let tmp = TempDir::create()?;
+    isolate(&tmp.0)?;
+    let dir = setup(&tmp.0)?;
+    let cwd = std::env::current_dir().ok();
+    std::env::set_current_dir(&dir)
+        .with_context(|| format!("Failed to enter {}", dir.display()))?;
+    let outcomes = match GitInfo::cwd().repo_key() {
+        Some(repo) => scenarios(&repo),
+        None => bail!("{} is not a git repository", dir.display()),
+    };
+    if let Some(cwd) = cwd {
+        let _ = std::env::set_current_dir(cwd);
+    }
+    Ok(outcomes)
+}
+
+/// Prints one `PASS`/`FAIL` line per scenario and a count. Returns true when
+/// any scenario failed.
+pub fn report(outcomes: &[Outcome]) -> bool {
+    for outcome in outcomes {
+        match &outcome.failure {
+            None => println!("PASS  {}", outcome.name),
+            Some(failure) => {
+                println!("FAIL  {}", outcome.name);
+                println!("      → {}", failure);
+            }
+        }
+    }
+    let passed = outcomes.iter().filter(|o| o.failure.is_none()).count();
+    println!("{} of {} scenarios passed", passed, outcomes.len());
+    passed < outcomes.len()
+}
+
+// ── Scenarios ─────────────────────────────────────────────────────────────────
+
+/// A scenario, run against the repo key of the temporary repository.
+type Scenario = fn(&str) -> Result<()>;
+
+/// The scenarios in the order they run; each builds on the branches of the last.
+const SCENARIOS: [(&str, Scenario); 5] = [
+    ("branch creation is tracked from a hook payload", tracks_creation),
+    ("push to the tracked branch is allowed", allows_tracked),
+    ("push to the default branch is blocked", blocks_default),
+    ("force push is blocked", blocks_force),
+    ("push is allowed once authorized", allows_authorized),
+];
+
+fn scenarios(repo: &str) -> Vec<Outcome> {
+    SCENARIOS
+        .into_iter()
+        .map(|(name, scenario)| {
+            let failure = scenario(repo).err().map(|e| format!("{:#}", e));
+            Outcome { name, failure }
+        })
+        .collect()
+}
+
+fn tracks_creation(repo: &str) -> Result<()> {
+    let command = format!("git checkout -b {}", FEATURE);
+    let payload = serde_json::json!({ "tool_name": "Bash", "tool_input": { "command": command } });
+    if crate::hook(&payload)? {
+        bail!("the hook blocked '{}'", command);
+    }
+    if !State::load_for(repo)?.is_tracked(repo, FEATURE) {
+        bail!("'{}' was not tracked after '{}'", FEATURE, command);
+    }
+    git(Path::new(repo), &["checkout", "-q", "-b", FEATURE])?;
+    git(Path::new(repo), &["commit", "-q", "--allow-empty", "-m", "Feature work"])
+}
+
+fn allows_tracked(repo: &str) -> Result<()> {
+    let command = format!("git push -u origin {}", FEATURE);
+    expect(repo, &command, Expected::Allow(Rule::Tracked))?;
+    git(Path::new(repo), &["push", "-q", "-u", "origin", FEATURE])
+}
+
+fn blocks_default(repo: &str) -> Result<()> {
+    expect(repo, "git push origin main", Expected::Block(Rule::DefaultBranch))
+}
+
+fn blocks_force(repo: &str) -> Result<()> {
+    let command = format!("git push --force origin {}", FEATURE);
+    expect(repo, &command, Expected::Block(Rule::ForcePush))
+}
+
+fn allows_authorized(repo: &str) -> Result<()> {
+    git(Path::new(repo), &["checkout", "-q", "-b", OTHER])?;
+    let command = format!("git push origin {}", OTHER);
+    expect(repo, &command, Expected::Block(Rule::Untracked))?;
+    State::update_for(repo, |state| state.authorize(repo, OTHER, None))?;
+    expect(repo, &command, Expected::Allow(Rule::Authorized))?;
+    git(Path::new(repo), &["push", "-q", "origin", OTHER])
+}
+
+enum Expected {
+    Allow(Rule),
+    Block(Rule),
+}
+
+/// Checks the single push in `command` the way the hook would, without
+/// running it, and fails unless the decision is the `expected` one.
+fn expect(repo: &str, command: &str, expected: Expected) -> Result<()> {
+    let git = GitInfo::cwd();
+    let pushes = detect_all_pushes(&expand_command(&git, repo, command), &git);
+    let [push] = pushes.as_slice() else {
+        bail!("expected one push in '{}', found {}", command, pushes.len());
+    };
+    let decision = evaluate(&git, repo, push)?;
+    match (&expected, &decision) {
+        (Expected::Allow(rule), Decision::Allow(r)) if r == rule => Ok(()),
+        (Expected::Block(rule), Decision::Block(r, _)) if r == rule => Ok(()),
+        _ => {
+            let (verb, rule) = match expected {
+                Expected::Allow(rule) => ("allowed", rule),
+                Expected::Block(rule) => ("blocked", rule),
+            };
+            let got = match decision {
+                Decision::Allow(r) => format!("allowed by {}", r.as_str()),
+                Decision::Block(r, _) => format!("blocked by {}", r.as_str()),
+                Decision::Skip(r, _) => format!("skipped by {}", r.as_str()),
+            };
+            bail!("'{}' should be {} by {}, but was {}", command, verb, rule.as_str(), got)
+        }
+    }
+}
+
+// ── Sandbox ───────────────────────────────────────────────────────────────────
+
+/// A temporary directory, removed with everything in it when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn create() -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("push-guard-selftest-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Points this process, and the git commands it runs, at files in `tmp`
+/// instead of the user's state, config and git settings.
+fn isolate(tmp: &Path) -> Result<()> {
+    for var in CLEARED_ENV {
+        std::env::remove_var(var);
+    }
+    let config = tmp.join("config.toml");
+    fs::write(&config, CONFIG).with_context(|| format!("Failed to write {}", config.display()))?;
+    let gitconfig = tmp.join("gitconfig");
+    fs::write(&gitconfig, "")
+        .with_context(|| format!("Failed to write {}", gitconfig.display()))?;
+    std::env::set_var("PUSH_GUARD_STATE_FILE", tmp.join("state.json"));
+    std::env::set_var("PUSH_GUARD_CONFIG_FILE", config);
+    std::env::set_var("GIT_CONFIG_GLOBAL", gitconfig);
+    std::env::set_var("GIT_CONFIG_NOSYSTEM", "1");
+    for (var, value) in [
+        ("GIT_AUTHOR_NAME", "push-guard selftest"),
+        ("GIT_AUTHOR_EMAIL", "selftest@push-guard.invalid"),
+        ("GIT_COMMITTER_NAME", "push-guard selftest"),
+        ("GIT_COMMITTER_EMAIL", "selftest@push-guard.invalid"),
+    ] {
+        std::env::set_var(var, value);
+    }
+    Ok(())
+}
+
+/// Creates a bare "origin" and a clone-alike of it with one commit on "main",
+/// its default branch. Returns the repository's path.
+fn setup(tmp: &Path) -> Result<PathBuf> {
+    let origin = tmp.join("origin.git");
+    let repo = tmp.join("repo");
+    git(tmp, &["init", "-q", "--bare", "-b", "main", "origin.git"])?;
+    git(tmp, &["init", "-q", "-b", "main", "repo"])?;
+    let origin = origin.to_str().context("temporary directory path is not UTF-8")?;
+    git(&repo, &["commit", "-q", "--allow-empty", "-m", "Initial commit"])?;
+    git(&repo, &["remote", "add", "origin", origin])?;
+    git(&repo, &["push", "-q", "origin", "main"])?;
+    git(&repo, &["remote", "set-head", "origin", "main"])?;
+    Ok(repo)
+}
+
+/// Runs git in `dir`, failing with its stderr.
+fn git(dir: &Path, args: &[&str]) -> Result<()> {
+    let out = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        bail!("git {} failed: {}", args.join(" "), stderr.trim());
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Command line Claude Code should run for the hook: this binary's absolute
+/// path followed by `hook`.
+pub fn hook_command() -> Result<String> {
+    let exe = std::env::current_exe().context("Failed to resolve the push-guard binary")?;
+    let exe = exe.canonicalize().unwrap_or(exe);
+    let path = exe.to_string_lossy();
+    if path.contains(char::is_whitespace) {
+        Ok(format!("\"{}\" hook", path))
+    } else {
+        Ok(format!("{} hook", path))
+    }
+}
+
+/// The `hooks` fragment of settings.json registering push-guard for Bash tool
+/// calls, plus the PostToolUse pass when `post` is set.
+pub fn hook_snippet(post: bool) -> Result<serde_json::Value> {
+    let entry = serde_json::json!([{
+        "matcher": "Bash",
+        "hooks": [{ "type": "command", "command": hook_command()? }],
+    }]);
+    let mut hooks = serde_json::Map::new();
+    hooks.insert("PreToolUse".to_string(), entry.clone());
+    if post {
+        hooks.insert("PostToolUse".to_string(), entry);
+    }
+    Ok(serde_json::json!({ "hooks": hooks }))
+}
+
+/// Settings files Claude Code reads: the user's, then the project's shared and
+/// local ones when `repo` is given.
+pub fn settings_files(repo: Option<&str>) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = dirs::home_dir()
+        .map(|h| h.join(".claude").join("settings.json"))
+        .into_iter()
+        .collect();
+    if let Some(r) = repo {
+        let dir = Path::new(r).join(".claude");
+        files.push(dir.join("settings.json"));
+        files.push(dir.join("settings.local.json"));
+    }
+    files
+}
+
+/// Returns the PreToolUse hook commands in `settings` that run `push-guard hook`.
+pub fn registered_commands(settings: &serde_json::Value) -> Vec<String> {
+    let Some(entries) = settings["hooks"]["PreToolUse"].as_array() else {
+        return Vec::new();
+    };
+    entries
+        .iter()
+        .filter_map(|e| e["hooks"].as_array())
+        .flatten()
+        .filter_map(|h| h["command"].as_str())
+        .filter(|c| c.contains("push-guard") && c.trim_end().ends_with(" hook"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the program a hook command runs, without surrounding quotes.
+pub fn command_binary(command: &str) -> &str {
+    let command = command.trim();
+    match command.strip_prefix('"') {
+        Some(rest) => rest.split('"').next().unwrap_or(rest),
+        None => command.split_whitespace().next().unwrap_or(command),
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_commands_find_push_guard_hooks() {
+        let settings = serde_json::json!({ "hooks": { "PreToolUse": [
+            { "matcher": "Bash", "hooks": [
+                { "type": "command", "command": "\"/opt/my tools/push-guard\" hook" },
+                { "type": "command", "command": "/usr/bin/other-hook" },
+            ]},
+            { "matcher": "Edit", "hooks": [{ "type": "command", "command": "push-guard list" }] },
+        ]}});
+        let commands = registered_commands(&settings);
+        assert_eq!(commands, vec!["\"/opt/my tools/push-guard\" hook"]);
+        assert_eq!(command_binary(&commands[0]), "/opt/my tools/push-guard");
+        assert_eq!(command_binary("/bin/push-guard hook"), "/bin/push-guard");
+        assert!(registered_commands(&serde_json::json!({})).is_empty());
+    }
+}
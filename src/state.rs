@@ -1,15 +1,350 @@
-use anyhow::{Context, Result};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::config::Config;
+use crate::git::GitInfo;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Once, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Current state file schema. Files without a version are version 0.
+pub const STATE_VERSION: u32 = 1;
 
 #[derive(Serialize, Deserialize, Default, Debug)]
 pub struct State {
+    #[serde(default)]
+    pub version: u32,
     /// Branches created by Claude, keyed by canonical repo path
-    pub tracked: HashMap<String, Vec<String>>,
+    pub tracked: BTreeMap<String, Vec<Entry>>,
     /// One-time authorized branches, keyed by canonical repo path
-    pub authorized: HashMap<String, Vec<String>>,
+    pub authorized: BTreeMap<String, Vec<Entry>>,
+    /// GitHub branch protection lookups, keyed by "owner/repo:branch"
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub github_protection: BTreeMap<String, CachedProtection>,
+    /// Remote default branches checked with `git ls-remote`, keyed by
+    /// "<repo>:<remote>"
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote_heads: BTreeMap<String, CachedHead>,
+    /// Remotes every branch of which may be pushed to, keyed by canonical repo
+    /// path
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remote_grants: BTreeMap<String, Vec<RemoteGrant>>,
+    /// Repos whose entries are stored in their own `<gitdir>/push-guard.json`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub local_repos: BTreeSet<String>,
+    /// Entries of other hosts, with `state_namespace = "hostname"`, keyed by
+    /// host name. The current host's namespace is moved into the maps above
+    /// on load and back on save.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Namespace>,
+    /// Fields written by newer versions, preserved through load and save.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+    /// Mutations made since load, replayed onto the file's current contents by
+    /// `save` so that concurrent writers don't clobber each other.
+    #[serde(skip)]
+    pending: Vec<Change>,
+    /// Set when the file is newer than this binary understands; saving is refused.
+    #[serde(skip)]
+    read_only: bool,
+    /// Compare branch names case-insensitively in lookups (stored names keep
+    /// their case).
+    #[serde(skip)]
+    pub ignore_case: bool,
+    /// Scope new entries are written to and removals apply to. None writes
+    /// where an entry for the branch already exists, else globally, and
+    /// removes from both scopes.
+    #[serde(skip)]
+    pub write_scope: Option<Scope>,
+    /// Repos whose repo-local file was layered over the global entries.
+    #[serde(skip)]
+    layered: BTreeSet<String>,
+    /// Host whose namespace the maps hold, when namespacing by host.
+    #[serde(skip)]
+    namespace: Option<String>,
+}
+
+/// One host's entries in a state file namespaced by host.
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct Namespace {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tracked: BTreeMap<String, Vec<Entry>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub authorized: BTreeMap<String, Vec<Entry>>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub local_repos: BTreeSet<String>,
+}
+
+impl Namespace {
+    fn is_empty(&self) -> bool {
+        self.tracked.is_empty() && self.authorized.is_empty() && self.local_repos.is_empty()
+    }
+}
+
+/// Where an entry is stored: the global state file, or the repo-local
+/// `<gitdir>/push-guard.json`, which is consulted first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Scope {
+    #[default]
+    Global,
+    Local,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Local => "local",
+        }
+    }
+}
+
+/// Key of the default branch of `remote` in `repo` in `remote_heads`.
+pub fn remote_head_key(repo: &str, remote: &str) -> String {
+    format!("{}:{}", repo, remote)
+}
+
+/// Returns true when `a` and `b` name the same branch, ignoring case if asked.
+pub fn same_branch(a: &str, b: &str, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.to_lowercase() == b.to_lowercase())
+}
+
+/// Which of the two entry lists a change applies to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Tracked,
+    Authorized,
+}
+
+/// A single recorded mutation of the state.
+#[derive(Clone, Debug)]
+enum Change {
+    /// Adds an entry unless the branch is already present in its scope.
+    Add(Kind, String, Entry),
+    /// Adds an entry, replacing any entry for the same branch in its scope.
+    Put(Kind, String, Entry),
+    /// Removes a branch from one scope, or from both when None.
+    Remove(Kind, String, String, Option<Scope>),
+    RemoveRepo(String),
+    /// Uses up one push of a limited authorization in the given scope.
+    UseAuthorization(String, String, Scope),
+    /// Counts a push an entry in the given scope allowed, at the given time.
+    RecordUse(Kind, String, String, Scope, DateTime<Utc>),
+    CacheProtection(String, CachedProtection),
+    CacheRemoteHead(String, CachedHead),
+    /// Adds a remote grant, replacing any for the same remote.
+    GrantRemote(String, RemoteGrant),
+    /// Removes the grant of a remote.
+    DenyRemote(String, String),
+    /// Moves a remote's grant and recorded default branch to its new name.
+    RenameRemote(String, String, String),
+    /// Drops a remote's recorded default branch, and its grant when set.
+    ForgetRemote(String, String, bool),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedProtection {
+    pub protected: bool,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// What a remote's HEAD pointed to when last asked; `branch` is None when
+/// the remote could not be asked, so the local answer is used until then.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CachedHead {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    pub checked_at: DateTime<Utc>,
+}
+
+/// A grant allowing pushes to any branch of one remote, made with
+/// `push-guard allow-remote`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RemoteGrant {
+    pub remote: String,
+    /// Where the remote pushed to when granted. The grant lapses once that
+    /// changes, so it never follows the name to another repository.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub push_urls: Vec<String>,
+    /// Also allow force pushes and deletions.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_force: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+}
+
+impl RemoteGrant {
+    /// Returns true when the remote still pushes to `push_urls`, the URLs it
+    /// was granted for. Grants recorded without them never apply.
+    pub fn applies_to(&self, push_urls: &[String]) -> bool {
+        !self.push_urls.is_empty() && self.push_urls == push_urls
+    }
+}
+
+/// A single tracked or authorized branch.
+///
+/// Entries without metadata are stored as a bare branch-name string, which is
+/// also the format written by older versions.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(remote = "Self")]
+pub struct Entry {
+    pub branch: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<DateTime<Utc>>,
+    /// Commit an authorization is bound to; pushes are only allowed while the
+    /// branch tip is exactly this commit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha: Option<String>,
+    /// Claude session that created a tracked branch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    /// File the entry was loaded from or is written to; not stored itself.
+    #[serde(skip)]
+    pub scope: Scope,
+    /// Subagent whose command created a tracked branch; None for the main
+    /// agent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<Agent>,
+    /// Remote-tracking branch a tracked branch was checked out from (e.g.
+    /// "origin/feature-x"), when it already existed upstream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_remote: Option<String>,
+    /// Pushes an authorization still allows; the entry is removed when the
+    /// last one is used. None means unlimited.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uses_left: Option<u32>,
+    /// Pushes this entry has allowed, and when it last did.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub use_count: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<DateTime<Utc>>,
+    /// An authorization that also applies during `restricted_hours`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub override_hours: bool,
+    /// An authorization that also overrides the default-branch rule.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_default: bool,
+    /// Fields written by newer versions, preserved through load and save.
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+/// A Claude subagent (e.g. one started with the Task tool), as identified by
+/// the `agent_id` and `agent_type` of its hook calls.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Agent {
+    pub id: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+impl Agent {
+    /// The subagent a hook call came from, or None for the main agent.
+    pub fn from_hook(json: &serde_json::Value) -> Option<Self> {
+        let field = |key: &str| {
+            json.get(key).and_then(|v| v.as_str()).filter(|v| !v.is_empty()).map(str::to_string)
+        };
+        Some(Self { id: field("agent_id")?, kind: field("agent_type") })
+    }
+
+    /// "subagent <type> (<id>)", for messages.
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            Some(kind) => format!("subagent {} ({})", kind, self.id),
+            None => format!("subagent {}", self.id),
+        }
+    }
+}
+
+impl Entry {
+    pub fn new(branch: &str) -> Self {
+        Self {
+            branch: branch.to_string(),
+            created_at: Some(Utc::now()),
+            sha: None,
+            session_id: None,
+            scope: Scope::Global,
+            agent: None,
+            from_remote: None,
+            uses_left: None,
+            use_count: 0,
+            last_used_at: None,
+            override_hours: false,
+            allow_default: false,
+            extra: serde_json::Map::new(),
+        }
+    }
+
+    fn bare(branch: String) -> Self {
+        Self { created_at: None, ..Self::new(&branch) }
+    }
+
+    fn is_bare(&self) -> bool {
+        self.created_at.is_none()
+            && self.sha.is_none()
+            && self.session_id.is_none()
+            && self.agent.is_none()
+            && self.from_remote.is_none()
+            && self.uses_left.is_none()
+            && self.use_count == 0
+            && self.last_used_at.is_none()
+            && !self.override_hours
+            && !self.allow_default
+            && self.extra.is_empty()
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+impl Serialize for Entry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.is_bare() {
+            serializer.serialize_str(&self.branch)
+        } else {
+            Entry::serialize(self, serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Entry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Bare(String),
+            #[serde(with = "Entry")]
+            Full(Entry),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Bare(branch) => Entry::bare(branch),
+            Repr::Full(entry) => entry,
+        })
+    }
+}
+
+/// Per-repo state file, inside the repository's git directory.
+pub const REPO_STATE_FILE: &str = "push-guard.json";
+
+/// Entries of a single repo, as stored in its `<gitdir>/push-guard.json`.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct RepoFile {
+    #[serde(default)]
+    tracked: Vec<Entry>,
+    #[serde(default)]
+    authorized: Vec<Entry>,
+}
+
+/// Returns the repo-local state file of `repo`, when it is a git checkout on disk.
+pub fn repo_state_path(repo: &str) -> Option<PathBuf> {
+    if !Path::new(repo).is_dir() {
+        return None;
+    }
+    let git_dir = GitInfo::for_repo(repo).git_dir()?;
+    Some(Path::new(&git_dir).join(REPO_STATE_FILE))
 }
 
 pub fn state_path() -> PathBuf {
@@ -23,90 +358,1044 @@ pub fn state_path() -> PathBuf {
         .join("state.json")
 }
 
+// ── Schema migrations ─────────────────────────────────────────────────────────
+
+/// Upgrades a state document from `version` to STATE_VERSION, one step at a time.
+fn migrate(value: &mut serde_json::Value, version: u32) {
+    for from in version..STATE_VERSION {
+        match from {
+            // v0 had no version marker and could omit either map
+            0 => {
+                for key in ["tracked", "authorized"] {
+                    if value.get(key).is_none_or(serde_json::Value::is_null) {
+                        value[key] = serde_json::json!({});
+                    }
+                }
+            }
+            _ => unreachable!("no migration from state version {}", from),
+        }
+    }
+    value["version"] = STATE_VERSION.into();
+}
+
+// ── Export format ─────────────────────────────────────────────────────────────
+
+pub const EXPORT_FORMAT: &str = "push-guard-export";
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Self-describing document written by `push-guard export`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Export {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    #[serde(default)]
+    pub tracked: BTreeMap<String, Vec<Entry>>,
+    #[serde(default)]
+    pub authorized: BTreeMap<String, Vec<Entry>>,
+}
+
+impl Export {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let export: Export =
+            serde_json::from_str(contents).context("Failed to parse export file")?;
+        if export.format != EXPORT_FORMAT {
+            bail!("Not a push-guard export (format '{}')", export.format);
+        }
+        if export.version > EXPORT_VERSION {
+            bail!(
+                "Export version {} is newer than supported version {}",
+                export.version,
+                EXPORT_VERSION
+            );
+        }
+        Ok(export)
+    }
+
+    /// Converts the document into a State, canonicalizing repo paths that exist
+    /// on this machine.
+    pub fn into_state(self) -> State {
+        let canon = |map: BTreeMap<String, Vec<Entry>>| -> BTreeMap<String, Vec<Entry>> {
+            let mut out: BTreeMap<String, Vec<Entry>> = BTreeMap::new();
+            for (repo, entries) in map {
+                let key = fs::canonicalize(&repo)
+                    .map(|p| p.to_string_lossy().into_owned())
+                    .unwrap_or(repo);
+                out.entry(key).or_default().extend(entries);
+            }
+            out
+        };
+        State {
+            tracked: canon(self.tracked),
+            authorized: canon(self.authorized),
+            ..Default::default()
+        }
+    }
+}
+
+// ── Locking ───────────────────────────────────────────────────────────────────
+
+/// Locks older than this are assumed to belong to a crashed process.
+const STALE_LOCK: Duration = Duration::from_secs(10);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Exclusive lock on the state file, held via a sibling `.lock` file.
+/// Released when dropped.
+pub struct StateLock {
+    path: PathBuf,
+}
+
+impl StateLock {
+    pub fn acquire() -> Result<Self> {
+        if let Some(reason) = read_only_reason() {
+            bail!("State is read-only: {}", reason);
+        }
+        let mut path = state_path().into_os_string();
+        path.push(".lock");
+        let path = PathBuf::from(path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create dir {}", parent.display()))?;
+        }
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if lock_is_stale(&path) {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if start.elapsed() > LOCK_TIMEOUT {
+                        bail!("Timed out waiting for state lock {}", path.display());
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to create lock {}", path.display()))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn lock_is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_some_and(|age| age > STALE_LOCK)
+}
+
+// ── Read-only state ───────────────────────────────────────────────────────────
+
+/// Why the state directory cannot be written to, or None when it can.
+/// Probed once per process; PUSH_GUARD_STATE_READONLY=1 forces read-only.
+pub fn read_only_reason() -> Option<&'static str> {
+    static REASON: OnceLock<Option<String>> = OnceLock::new();
+    REASON.get_or_init(probe_read_only).as_deref()
+}
+
+fn probe_read_only() -> Option<String> {
+    if matches!(std::env::var("PUSH_GUARD_STATE_READONLY").as_deref(), Ok("1") | Ok("true")) {
+        return Some("PUSH_GUARD_STATE_READONLY is set".to_string());
+    }
+    let path = state_path();
+    // The directory is created on first save, so probe where it would be
+    let dir = path
+        .ancestors()
+        .skip(1)
+        .map(|d| if d.as_os_str().is_empty() { Path::new(".") } else { d })
+        .find(|d| d.is_dir())?;
+    let probe = dir.join(format!(".push-guard-probe-{}", std::process::id()));
+    match fs::OpenOptions::new().write(true).create_new(true).open(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            None
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => None,
+        Err(e) => Some(format!("{} is not writable ({})", dir.display(), e)),
+    }
+}
+
+/// Returns whether a non-essential write (branch tracking from the hook,
+/// caches, stats, the audit log) should go ahead. When the state is
+/// read-only, warns once per process and returns false; pushes are still
+/// checked against what the state already holds.
+pub fn writable() -> bool {
+    let Some(reason) = read_only_reason() else {
+        return true;
+    };
+    static WARN: Once = Once::new();
+    WARN.call_once(|| {
+        eprintln!(
+            "push-guard: warning: state is read-only ({}); pushes are checked against it, \
+             but branch creations, caches, stats and the audit log are not recorded",
+            reason
+        )
+    });
+    false
+}
+
 impl State {
     pub fn load() -> Result<Self> {
         let path = state_path();
-        if !path.exists() {
-            return Ok(Self::default());
+        let contents = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read state from {}", path.display()))?
+        } else {
+            String::new()
+        };
+        let mut state = if contents.trim().is_empty() {
+            Self::default()
+        } else {
+            Self::parse(&contents)?
+        };
+        if state.read_only {
+            static WARN: Once = Once::new();
+            WARN.call_once(|| {
+                eprintln!(
+                    "warning: {} was written by a newer push-guard (version {}, supported {}); \
+                     running read-only",
+                    path.display(),
+                    state.version,
+                    STATE_VERSION
+                )
+            });
+        }
+        if Config::load(None).is_ok_and(|c| c.namespaces_by_host()) {
+            let Some(host) = crate::hostname() else {
+                bail!("state_namespace is \"hostname\", but the host name could not be determined");
+            };
+            state.enter_namespace(host);
+        }
+        state.load_local_repos();
+        Ok(state)
+    }
+
+    /// Moves `host`'s namespace into the top-level maps. Entries stored flat
+    /// (before namespacing was turned on) are kept too, and so end up in
+    /// `host`'s namespace on the next save.
+    fn enter_namespace(&mut self, host: String) {
+        let ns = self.hosts.remove(&host).unwrap_or_default();
+        for (kind, theirs) in [(Kind::Tracked, ns.tracked), (Kind::Authorized, ns.authorized)] {
+            for (repo, entries) in theirs {
+                let existing = self.map_mut(kind).entry(repo).or_default();
+                let flat = std::mem::replace(existing, entries);
+                for entry in flat {
+                    if !existing.iter().any(|e| e.branch == entry.branch) {
+                        existing.push(entry);
+                    }
+                }
+            }
+        }
+        self.local_repos.extend(ns.local_repos);
+        self.namespace = Some(host);
+    }
+
+    /// Parses a state document of any known version, upgrading it in memory.
+    /// Documents newer than STATE_VERSION are read as-is and marked read-only.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(contents).context("Failed to parse state file")?;
+        if !value.is_object() {
+            bail!("Failed to parse state file: expected a JSON object");
+        }
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0) as u32;
+        let newer = version > STATE_VERSION;
+        if !newer {
+            migrate(&mut value, version);
+        }
+        let mut state: State =
+            serde_json::from_value(value).context("Failed to parse state file")?;
+        state.read_only = newer;
+        Ok(state)
+    }
+
+    /// Reads the entries of repos using repo-local storage. Repos whose file is
+    /// unreachable (e.g. on an unmounted drive) simply contribute no entries.
+    fn load_local_repos(&mut self) {
+        for repo in self.local_repos.clone() {
+            self.layer_local(&repo);
         }
-        let contents = fs::read_to_string(&path)
-            .with_context(|| format!("Failed to read state from {}", path.display()))?;
-        if contents.trim().is_empty() {
-            return Ok(Self::default());
+    }
+
+    /// Loads the state with `repo`'s repo-local entries layered over the
+    /// global ones, even when the repo does not use repo-local storage.
+    pub fn load_for(repo: &str) -> Result<Self> {
+        let mut state = Self::load()?;
+        state.layer_local(repo);
+        Ok(state)
+    }
+
+    /// Puts the entries of `repo`'s repo-local file in front of its global
+    /// entries, so that lookups consult them first.
+    pub fn layer_local(&mut self, repo: &str) {
+        if !self.layered.insert(repo.to_string()) {
+            return;
+        }
+        let Some(file) = repo_state_path(repo)
+            .and_then(|p| fs::read_to_string(p).ok())
+            .and_then(|c| serde_json::from_str::<RepoFile>(&c).ok())
+        else {
+            return;
+        };
+        for (kind, local) in [(Kind::Tracked, file.tracked), (Kind::Authorized, file.authorized)] {
+            let entries = self.map_mut(kind).entry(repo.to_string()).or_default();
+            let global = std::mem::take(entries);
+            *entries = local
+                .into_iter()
+                .map(|e| Entry { scope: Scope::Local, ..e })
+                .chain(global)
+                .collect();
         }
-        serde_json::from_str(&contents).context("Failed to parse state file")
     }
 
+    /// Saves the changes made since load, merged into the file's current
+    /// contents under the state lock. Entries added by other processes in the
+    /// meantime survive; removals only apply to what this value removed.
     pub fn save(&self) -> Result<()> {
+        let _lock = StateLock::acquire()?;
+        let mut current = Self::load()?;
+        for repo in &self.layered {
+            current.layer_local(repo);
+        }
+        for change in &self.pending {
+            current.apply_in_place(change);
+        }
+        current.write()
+    }
+
+    /// Writes this state as-is, after layering in the repo-local files of its
+    /// repos that were not read yet. The caller must hold the state lock.
+    /// Refuses to overwrite a file written by a newer version.
+    ///
+    /// Entries of repos configured with `storage = "repo"`, and local-scope
+    /// entries of any repo, go to their `<gitdir>/push-guard.json`; everything
+    /// else, including entries whose local file can't be written, goes to the
+    /// global state file. An unchanged global file is not rewritten.
+    fn write(&mut self) -> Result<()> {
+        if self.read_only {
+            bail!(
+                "State file version {} is newer than supported version {}; refusing to overwrite it",
+                self.version,
+                STATE_VERSION
+            );
+        }
+        // A local file that was never read would be rewritten without its entries
+        let repos: Vec<String> =
+            self.tracked.keys().chain(self.authorized.keys()).cloned().collect();
+        for repo in repos {
+            self.layer_local(&repo);
+        }
+        let mut global = State {
+            version: STATE_VERSION,
+            github_protection: self.github_protection.clone(),
+            remote_heads: self.remote_heads.clone(),
+            remote_grants: self.remote_grants.clone(),
+            extra: self.extra.clone(),
+            ..Default::default()
+        };
+        let repos: BTreeSet<&String> = self
+            .tracked
+            .keys()
+            .chain(self.authorized.keys())
+            .chain(&self.local_repos)
+            .chain(&self.layered)
+            .collect();
+        for repo in repos {
+            let all_local = Config::load(Some(repo)).is_ok_and(|c| c.stores_in_repo());
+            // A repo that stopped using repo-local storage moves everything back
+            let leaving = !all_local && self.local_repos.contains(repo);
+            let split = |map: &BTreeMap<String, Vec<Entry>>| -> (Vec<Entry>, Vec<Entry>) {
+                map.get(repo)
+                    .into_iter()
+                    .flatten()
+                    .cloned()
+                    .partition(|e| all_local || (e.scope == Scope::Local && !leaving))
+            };
+            let (mut local_tracked, mut tracked) = split(&self.tracked);
+            let (mut local_authorized, mut authorized) = split(&self.authorized);
+            // Sorted, so saving the same entries always writes the same file
+            let all = [&mut local_tracked, &mut tracked, &mut local_authorized, &mut authorized];
+            for entries in all {
+                entries.sort_by(|a, b| a.branch.cmp(&b.branch));
+            }
+            let has_local = !local_tracked.is_empty() || !local_authorized.is_empty();
+            // Rewrite an existing local file too, so removals reach it
+            let local = repo_state_path(repo).filter(|p| all_local || has_local || p.exists());
+            let file = RepoFile { tracked: local_tracked, authorized: local_authorized };
+            let written = match local {
+                Some(path) => fs::write(&path, serde_json::to_string_pretty(&file)?).is_ok(),
+                None => false,
+            };
+            if written && all_local {
+                global.local_repos.insert(repo.clone());
+            }
+            if !written {
+                tracked.extend(file.tracked);
+                authorized.extend(file.authorized);
+                tracked.sort_by(|a, b| a.branch.cmp(&b.branch));
+                authorized.sort_by(|a, b| a.branch.cmp(&b.branch));
+            }
+            if !tracked.is_empty() {
+                global.tracked.insert(repo.clone(), tracked);
+            }
+            if !authorized.is_empty() {
+                global.authorized.insert(repo.clone(), authorized);
+            }
+        }
+
+        global.hosts = self.hosts.clone();
+        if let Some(host) = &self.namespace {
+            let ns = Namespace {
+                tracked: std::mem::take(&mut global.tracked),
+                authorized: std::mem::take(&mut global.authorized),
+                local_repos: std::mem::take(&mut global.local_repos),
+            };
+            if !ns.is_empty() {
+                global.hosts.insert(host.clone(), ns);
+            }
+        }
+
         let path = state_path();
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)
                 .with_context(|| format!("Failed to create dir {}", parent.display()))?;
         }
-        let contents = serde_json::to_string_pretty(self)?;
+        let on_disk = fs::read_to_string(&path)
+            .ok()
+            .and_then(|c| serde_json::from_str::<serde_json::Value>(&c).ok());
+        if on_disk.is_some_and(|v| v == serde_json::to_value(&global).unwrap_or_default()) {
+            return Ok(());
+        }
+        let contents = serde_json::to_string_pretty(&global)?;
         fs::write(&path, contents)
             .with_context(|| format!("Failed to write state to {}", path.display()))
     }
 
+    /// Like `update`, with `repo`'s repo-local entries layered in (see `load_for`).
+    pub fn update_for<T>(repo: &str, f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        Self::update(|state| {
+            state.layer_local(repo);
+            f(state)
+        })
+    }
+
+    /// Loads, mutates, and saves the state while holding the state lock.
+    pub fn update<T>(f: impl FnOnce(&mut State) -> T) -> Result<T> {
+        let _lock = StateLock::acquire()?;
+        let mut state = Self::load()?;
+        let out = f(&mut state);
+        // The lock was held since load, so there is nothing to merge with
+        state.write()?;
+        Ok(out)
+    }
+
+    fn map(&self, kind: Kind) -> &BTreeMap<String, Vec<Entry>> {
+        match kind {
+            Kind::Tracked => &self.tracked,
+            Kind::Authorized => &self.authorized,
+        }
+    }
+
+    fn map_mut(&mut self, kind: Kind) -> &mut BTreeMap<String, Vec<Entry>> {
+        match kind {
+            Kind::Tracked => &mut self.tracked,
+            Kind::Authorized => &mut self.authorized,
+        }
+    }
+
+    /// Applies `change` and records it for `save`.
+    fn apply(&mut self, change: Change) {
+        self.apply_in_place(&change);
+        self.pending.push(change);
+    }
+
+    fn apply_in_place(&mut self, change: &Change) {
+        match change {
+            Change::Add(kind, repo, entry) => {
+                let entries = self.map_mut(*kind).entry(repo.clone()).or_default();
+                if !entries.iter().any(|e| e.branch == entry.branch && e.scope == entry.scope) {
+                    entries.push(entry.clone());
+                    entries.sort_by_key(|e| e.scope != Scope::Local);
+                }
+            }
+            Change::Put(kind, repo, entry) => {
+                let entries = self.map_mut(*kind).entry(repo.clone()).or_default();
+                entries.retain(|e| e.branch != entry.branch || e.scope != entry.scope);
+                entries.push(entry.clone());
+                entries.sort_by_key(|e| e.scope != Scope::Local);
+            }
+            Change::Remove(kind, repo, branch, scope) => {
+                if let Some(entries) = self.map_mut(*kind).get_mut(repo) {
+                    entries.retain(|e| {
+                        e.branch != *branch || scope.is_some_and(|s| e.scope != s)
+                    });
+                }
+            }
+            Change::RemoveRepo(repo) => {
+                self.tracked.remove(repo);
+                self.authorized.remove(repo);
+                self.remote_grants.remove(repo);
+            }
+            Change::UseAuthorization(repo, branch, scope) => {
+                if let Some(entries) = self.authorized.get_mut(repo) {
+                    let used = |e: &&mut Entry| e.branch == *branch && e.scope == *scope;
+                    for e in entries.iter_mut().filter(used) {
+                        e.uses_left = e.uses_left.map(|n| n.saturating_sub(1));
+                    }
+                    entries.retain(|e| e.uses_left != Some(0));
+                }
+            }
+            Change::RecordUse(kind, repo, branch, scope, at) => {
+                if let Some(entries) = self.map_mut(*kind).get_mut(repo) {
+                    let used = |e: &&mut Entry| e.branch == *branch && e.scope == *scope;
+                    for e in entries.iter_mut().filter(used) {
+                        e.use_count = e.use_count.saturating_add(1);
+                        e.last_used_at = Some(*at);
+                    }
+                }
+            }
+            Change::CacheProtection(key, cached) => {
+                self.github_protection.insert(key.clone(), cached.clone());
+            }
+            Change::CacheRemoteHead(key, cached) => {
+                self.remote_heads.insert(key.clone(), cached.clone());
+            }
+            Change::GrantRemote(repo, grant) => {
+                let grants = self.remote_grants.entry(repo.clone()).or_default();
+                grants.retain(|g| g.remote != grant.remote);
+                grants.push(grant.clone());
+                grants.sort_by(|a, b| a.remote.cmp(&b.remote));
+            }
+            Change::DenyRemote(repo, remote) | Change::ForgetRemote(repo, remote, true) => {
+                if let Some(grants) = self.remote_grants.get_mut(repo) {
+                    grants.retain(|g| g.remote != *remote);
+                    if grants.is_empty() {
+                        self.remote_grants.remove(repo);
+                    }
+                }
+                if matches!(change, Change::ForgetRemote(..)) {
+                    self.remote_heads.remove(&remote_head_key(repo, remote));
+                }
+            }
+            Change::ForgetRemote(repo, remote, false) => {
+                self.remote_heads.remove(&remote_head_key(repo, remote));
+            }
+            Change::RenameRemote(repo, from, to) => {
+                let grants = self.remote_grants.get_mut(repo);
+                if let Some(grants) = grants.filter(|g| g.iter().any(|g| g.remote == *from)) {
+                    grants.retain(|g| g.remote != *to);
+                    for grant in grants.iter_mut().filter(|g| g.remote == *from) {
+                        grant.remote = to.clone();
+                    }
+                    grants.sort_by(|a, b| a.remote.cmp(&b.remote));
+                }
+                if let Some(head) = self.remote_heads.remove(&remote_head_key(repo, from)) {
+                    self.remote_heads.insert(remote_head_key(repo, to), head);
+                }
+            }
+        }
+    }
+
     pub fn is_tracked(&self, repo: &str, branch: &str) -> bool {
+        self.tracked_entry(repo, branch).is_some()
+    }
+
+    /// Returns the tracked entry for `branch`.
+    pub fn tracked_entry(&self, repo: &str, branch: &str) -> Option<&Entry> {
         self.tracked
-            .get(repo)
-            .map(|branches| branches.iter().any(|b| b == branch))
-            .unwrap_or(false)
+            .get(repo)?
+            .iter()
+            .find(|e| same_branch(&e.branch, branch, self.ignore_case))
     }
 
-    pub fn is_authorized(&self, repo: &str, branch: &str) -> bool {
-        self.authorized
-            .get(repo)
-            .map(|branches| branches.iter().any(|b| b == branch))
-            .unwrap_or(false)
+    /// Returns true when `branch` was tracked outside any session or in `session`.
+    pub fn is_tracked_in(&self, repo: &str, branch: &str, session: Option<&str>) -> bool {
+        self.tracked.get(repo).is_some_and(|entries| {
+            entries.iter().any(|e| {
+                same_branch(&e.branch, branch, self.ignore_case)
+                    && e.session_id.as_deref().is_none_or(|id| Some(id) == session)
+            })
+        })
     }
 
     pub fn track(&mut self, repo: &str, branch: &str) {
-        let branches = self.tracked.entry(repo.to_string()).or_default();
-        if !branches.iter().any(|b| b == branch) {
-            branches.push(branch.to_string());
+        self.track_session(repo, branch, None);
+    }
+
+    /// Tracks `branch` as created in Claude session `session`.
+    pub fn track_session(&mut self, repo: &str, branch: &str, session: Option<&str>) {
+        self.track_from(repo, branch, session, None, None);
+    }
+
+    /// Tracks `branch` as created in `session`, by `agent` when a subagent
+    /// created it, from the remote-tracking branch `from_remote` when it
+    /// continues work that already exists upstream.
+    pub fn track_from(
+        &mut self,
+        repo: &str,
+        branch: &str,
+        session: Option<&str>,
+        agent: Option<&Agent>,
+        from_remote: Option<&str>,
+    ) {
+        let entry = Entry {
+            session_id: session.map(str::to_string),
+            agent: agent.cloned(),
+            scope: self.scope_for(Kind::Tracked, repo, branch),
+            from_remote: from_remote.map(str::to_string),
+            ..Entry::new(branch)
+        };
+        self.apply(Change::Add(Kind::Tracked, repo.to_string(), entry));
+    }
+
+    /// Scope a new entry for `branch` goes to: `write_scope`, else the scope of
+    /// the entry already there, else global.
+    fn scope_for(&self, kind: Kind, repo: &str, branch: &str) -> Scope {
+        self.write_scope.unwrap_or_else(|| {
+            self.map(kind)
+                .get(repo)
+                .and_then(|entries| entries.iter().find(|e| e.branch == branch))
+                .map_or(Scope::Global, |e| e.scope)
+        })
+    }
+
+    /// Stops tracking every branch created in `session`.
+    /// Returns the removed (repo, branch) pairs, sorted.
+    pub fn prune_session(&mut self, session: &str) -> Vec<(String, String)> {
+        let mut removed: Vec<(String, String)> = self
+            .tracked
+            .iter()
+            .flat_map(|(repo, entries)| {
+                entries
+                    .iter()
+                    .filter(|e| e.session_id.as_deref() == Some(session))
+                    .map(move |e| (repo.clone(), e.branch.clone()))
+            })
+            .collect();
+        removed.sort();
+        for (repo, branch) in &removed {
+            self.apply(Change::Remove(Kind::Tracked, repo.clone(), branch.clone(), None));
         }
+        removed
+    }
+
+    /// Authorizes `branch`, bound to `sha` when given. Re-authorizing replaces
+    /// the existing entry.
+    pub fn authorize(&mut self, repo: &str, branch: &str, sha: Option<&str>) {
+        self.authorize_uses(repo, branch, sha, None);
+    }
+
+    /// Authorizes `branch` for at most `uses` pushes (unlimited when None).
+    pub fn authorize_uses(
+        &mut self,
+        repo: &str,
+        branch: &str,
+        sha: Option<&str>,
+        uses: Option<u32>,
+    ) {
+        let entry = Entry {
+            sha: sha.map(str::to_string),
+            scope: self.scope_for(Kind::Authorized, repo, branch),
+            uses_left: uses,
+            ..Entry::new(branch)
+        };
+        self.apply(Change::Put(Kind::Authorized, repo.to_string(), entry));
+    }
+
+    /// Lets the authorization of `branch` apply during restricted hours too.
+    pub fn override_hours(&mut self, repo: &str, branch: &str) {
+        let Some(entry) = self.authorization(repo, branch).cloned() else {
+            return;
+        };
+        let entry = Entry { override_hours: true, ..entry };
+        self.apply(Change::Put(Kind::Authorized, repo.to_string(), entry));
+    }
+
+    /// Lets the authorization of `branch` allow pushes to the default branch.
+    pub fn allow_default(&mut self, repo: &str, branch: &str) {
+        let Some(entry) = self.authorization(repo, branch).cloned() else {
+            return;
+        };
+        let entry = Entry { allow_default: true, ..entry };
+        self.apply(Change::Put(Kind::Authorized, repo.to_string(), entry));
+    }
+
+    /// Uses up one push of `branch`'s authorization when it is limited, and
+    /// returns the pushes left.
+    pub fn use_authorization(&mut self, repo: &str, branch: &str) -> Option<u32> {
+        let entry = self.authorization(repo, branch)?;
+        let left = entry.uses_left?.saturating_sub(1);
+        let change = Change::UseAuthorization(repo.into(), entry.branch.clone(), entry.scope);
+        self.apply(change);
+        Some(left)
+    }
+
+    /// Counts a push the `kind` entry for `branch` allowed. Returns false when
+    /// there is no such entry.
+    pub fn record_use(&mut self, kind: Kind, repo: &str, branch: &str) -> bool {
+        let entry = match kind {
+            Kind::Tracked => self.tracked_entry(repo, branch),
+            Kind::Authorized => self.authorization(repo, branch),
+        };
+        let Some(entry) = entry else {
+            return false;
+        };
+        let (branch, scope) = (entry.branch.clone(), entry.scope);
+        self.apply(Change::RecordUse(kind, repo.into(), branch, scope, Utc::now()));
+        true
+    }
+
+    /// Returns the authorization entry for `branch`, if any.
+    pub fn authorization(&self, repo: &str, branch: &str) -> Option<&Entry> {
+        self.authorized
+            .get(repo)?
+            .iter()
+            .find(|e| same_branch(&e.branch, branch, self.ignore_case))
     }
 
-    pub fn authorize(&mut self, repo: &str, branch: &str) {
-        let branches = self.authorized.entry(repo.to_string()).or_default();
-        if !branches.iter().any(|b| b == branch) {
-            branches.push(branch.to_string());
+    /// Moves the tracked and authorized entries of `from` to `to`, keeping
+    /// their metadata, as `git branch -m` does. Returns true when any moved.
+    pub fn rename_branch(&mut self, repo: &str, from: &str, to: &str) -> bool {
+        let mut moved = false;
+        for kind in [Kind::Tracked, Kind::Authorized] {
+            let entries: Vec<Entry> = self
+                .map(kind)
+                .get(repo)
+                .into_iter()
+                .flatten()
+                .filter(|e| same_branch(&e.branch, from, self.ignore_case))
+                .cloned()
+                .collect();
+            for entry in entries {
+                let (repo, scope) = (repo.to_string(), Some(entry.scope));
+                self.apply(Change::Remove(kind, repo.clone(), entry.branch.clone(), scope));
+                self.apply(Change::Put(kind, repo, Entry { branch: to.to_string(), ..entry }));
+                moved = true;
+            }
         }
+        moved
+    }
+
+    /// Tracks `to` like `from` when `from` is tracked, as `git branch -c`
+    /// copies it. Authorizations are not copied. Returns true when tracked.
+    pub fn copy_tracking(&mut self, repo: &str, from: &str, to: &str) -> bool {
+        let Some(entry) = self.tracked_entry(repo, from).cloned() else {
+            return false;
+        };
+        let copy = Entry { branch: to.to_string(), created_at: Some(Utc::now()), ..entry };
+        self.apply(Change::Add(Kind::Tracked, repo.to_string(), copy));
+        true
     }
 
+    /// Stops tracking `branch` in `write_scope`, or in both scopes.
+    pub fn untrack(&mut self, repo: &str, branch: &str) {
+        let change = Change::Remove(Kind::Tracked, repo.into(), branch.into(), self.write_scope);
+        self.apply(change);
+    }
+
+    /// Revokes the authorization of `branch` in `write_scope`, or in both scopes.
     pub fn revoke(&mut self, repo: &str, branch: &str) {
-        if let Some(branches) = self.authorized.get_mut(repo) {
-            branches.retain(|b| b != branch);
+        let change = Change::Remove(Kind::Authorized, repo.into(), branch.into(), self.write_scope);
+        self.apply(change);
+    }
+
+    /// Allows pushes to any branch of `remote` in `repo`, and with
+    /// `include_force` also force pushes and deletions.
+    pub fn allow_remote(
+        &mut self,
+        repo: &str,
+        remote: &str,
+        push_urls: Vec<String>,
+        include_force: bool,
+    ) {
+        let grant = RemoteGrant {
+            remote: remote.to_string(),
+            push_urls,
+            include_force,
+            created_at: Some(Utc::now()),
+        };
+        self.apply(Change::GrantRemote(repo.to_string(), grant));
+    }
+
+    /// Removes the grant of `remote` in `repo`. Returns false when it had none.
+    pub fn deny_remote(&mut self, repo: &str, remote: &str) -> bool {
+        if self.remote_grant(repo, remote).is_none() {
+            return false;
         }
+        self.apply(Change::DenyRemote(repo.to_string(), remote.to_string()));
+        true
     }
 
-    pub fn clean_repo(&mut self, repo: &str) {
-        self.tracked.remove(repo);
-        self.authorized.remove(repo);
+    /// Moves the grant and the recorded default branch of remote `from` in
+    /// `repo` to `to`, as `git remote rename` does.
+    pub fn rename_remote(&mut self, repo: &str, from: &str, to: &str) {
+        self.apply(Change::RenameRemote(repo.into(), from.into(), to.into()));
     }
 
-    /// Removes entries for repo paths that no longer exist on disk.
-    /// Returns the list of removed repo paths.
-    pub fn clean_stale(&mut self) -> Vec<String> {
-        let mut removed: Vec<String> = Vec::new();
-        let stale: Vec<String> = self
+    /// Forgets the recorded default branch of `remote` in `repo`, so that it is
+    /// asked again, and with `grant` also the remote's `allow-remote` grant.
+    pub fn forget_remote(&mut self, repo: &str, remote: &str, grant: bool) {
+        self.apply(Change::ForgetRemote(repo.into(), remote.into(), grant));
+    }
+
+    /// Returns the remotes of `repo` whose default branch is recorded.
+    pub fn remotes_with_heads(&self, repo: &str) -> Vec<String> {
+        let prefix = remote_head_key(repo, "");
+        self.remote_heads.keys().filter_map(|k| k.strip_prefix(&prefix)).map(String::from).collect()
+    }
+
+    /// Returns the grant of `remote` in `repo`, if any.
+    pub fn remote_grant(&self, repo: &str, remote: &str) -> Option<&RemoteGrant> {
+        self.remote_grants.get(repo)?.iter().find(|g| g.remote == remote)
+    }
+
+    /// Returns a cached protection lookup younger than `ttl`.
+    pub fn cached_protection(&self, key: &str, ttl: chrono::Duration) -> Option<bool> {
+        self.github_protection
+            .get(key)
+            .filter(|c| Utc::now() - c.checked_at < ttl)
+            .map(|c| c.protected)
+    }
+
+    pub fn cache_protection(&mut self, key: &str, protected: bool) {
+        self.apply(Change::CacheProtection(
+            key.to_string(),
+            CachedProtection { protected, checked_at: Utc::now() },
+        ));
+    }
+
+    pub fn cache_remote_head(&mut self, key: &str, branch: Option<&str>) {
+        self.apply(Change::CacheRemoteHead(
+            key.to_string(),
+            CachedHead { branch: branch.map(str::to_string), checked_at: Utc::now() },
+        ));
+    }
+
+    /// Returns every repo path that has tracked or authorized entries, sorted.
+    pub fn repos(&self) -> Vec<String> {
+        let mut repos: Vec<String> = self
             .tracked
             .keys()
             .chain(self.authorized.keys())
-            .filter(|r| !std::path::Path::new(r.as_str()).exists())
             .cloned()
             .collect::<std::collections::HashSet<_>>()
             .into_iter()
             .collect();
-        for repo in stale {
-            self.tracked.remove(&repo);
-            self.authorized.remove(&repo);
-            removed.push(repo);
+        repos.sort();
+        repos
+    }
+
+    /// Returns every branch recorded for `repo`, tracked first, without duplicates.
+    pub fn branches(&self, repo: &str) -> Vec<String> {
+        let mut branches: Vec<String> = Vec::new();
+        for e in self
+            .tracked
+            .get(repo)
+            .into_iter()
+            .chain(self.authorized.get(repo))
+            .flatten()
+        {
+            if !branches.contains(&e.branch) {
+                branches.push(e.branch.clone());
+            }
+        }
+        branches
+    }
+
+    /// Removes the given branches from the tracked entries of `repo` (and from the
+    /// authorized entries when `include_authorized` is set).
+    /// Returns the branches that were actually removed, deduplicated.
+    pub fn remove_branches(
+        &mut self,
+        repo: &str,
+        branches: &[String],
+        include_authorized: bool,
+    ) -> Vec<String> {
+        let mut removed: Vec<String> = Vec::new();
+        let mut kinds = vec![Kind::Tracked];
+        if include_authorized {
+            kinds.push(Kind::Authorized);
+        }
+        for kind in kinds {
+            let hits: Vec<String> = self
+                .map_mut(kind)
+                .get(repo)
+                .into_iter()
+                .flatten()
+                .filter(|e| branches.contains(&e.branch))
+                .map(|e| e.branch.clone())
+                .collect();
+            for branch in hits {
+                if !removed.contains(&branch) {
+                    removed.push(branch.clone());
+                }
+                self.apply(Change::Remove(kind, repo.to_string(), branch, None));
+            }
         }
         removed
     }
+
+    /// Removes authorizations of `repo` with no uses left. Using the last one
+    /// removes the entry already, so these only come from hand edits or imports.
+    /// Returns the branches removed.
+    pub fn remove_exhausted(&mut self, repo: &str) -> Vec<String> {
+        let exhausted: Vec<(String, Scope)> = self
+            .authorized
+            .get(repo)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.uses_left == Some(0))
+            .map(|e| (e.branch.clone(), e.scope))
+            .collect();
+        for (branch, scope) in &exhausted {
+            self.apply(Change::Remove(Kind::Authorized, repo.into(), branch.clone(), Some(*scope)));
+        }
+        exhausted.into_iter().map(|(b, _)| b).collect()
+    }
+
+    /// Removes authorizations of `repo` neither used nor created since
+    /// `cutoff`. Entries without either timestamp are kept, since their age is
+    /// unknown. Returns the branches removed.
+    pub fn remove_unused(&mut self, repo: &str, cutoff: DateTime<Utc>) -> Vec<String> {
+        let unused: Vec<(String, Scope)> = self
+            .authorized
+            .get(repo)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.last_used_at.or(e.created_at).is_some_and(|t| t < cutoff))
+            .map(|e| (e.branch.clone(), e.scope))
+            .collect();
+        for (branch, scope) in &unused {
+            self.apply(Change::Remove(Kind::Authorized, repo.into(), branch.clone(), Some(*scope)));
+        }
+        unused.into_iter().map(|(b, _)| b).collect()
+    }
+
+    /// Builds an export document, optionally restricted to a single repo.
+    pub fn export(&self, repo: Option<&str>) -> Export {
+        let pick = |map: &BTreeMap<String, Vec<Entry>>| -> BTreeMap<String, Vec<Entry>> {
+            map.iter()
+                .filter(|(r, _)| repo.is_none_or(|only| only == r.as_str()))
+                .map(|(r, e)| (r.clone(), e.clone()))
+                .collect()
+        };
+        Export {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_VERSION,
+            exported_at: Utc::now(),
+            tracked: pick(&self.tracked),
+            authorized: pick(&self.authorized),
+        }
+    }
+
+    /// Merges `other` into this state. When both sides hold the same repo/branch,
+    /// the entry with the newer `created_at` wins.
+    pub fn merge(&mut self, other: State) {
+        for (kind, theirs) in [
+            (Kind::Tracked, other.tracked),
+            (Kind::Authorized, other.authorized),
+        ] {
+            for (repo, entries) in theirs {
+                for entry in entries {
+                    let newer = match self.map_mut(kind).get(&repo) {
+                        Some(existing) => existing
+                            .iter()
+                            .find(|e| e.branch == entry.branch)
+                            .is_none_or(|e| entry.created_at > e.created_at),
+                        None => true,
+                    };
+                    if newer {
+                        self.apply(Change::Put(kind, repo.clone(), entry));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns a view of every host's entries for listing, with repo paths
+    /// prefixed by `<host>:`. This host's entries are unprefixed when the
+    /// state is not namespaced by host.
+    pub fn all_hosts(&self) -> State {
+        fn add(view: &mut State, prefix: Option<&str>, ns: Namespace) {
+            let Namespace { tracked, authorized, .. } = ns;
+            for (map, from) in [(&mut view.tracked, tracked), (&mut view.authorized, authorized)] {
+                for (repo, entries) in from {
+                    let key = prefix.map_or(repo.clone(), |p| format!("{}:{}", p, repo));
+                    map.entry(key).or_default().extend(entries);
+                }
+            }
+        }
+        let mut view = State::default();
+        let own = Namespace {
+            tracked: self.tracked.clone(),
+            authorized: self.authorized.clone(),
+            local_repos: BTreeSet::new(),
+        };
+        add(&mut view, self.namespace.as_deref(), own);
+        for (host, ns) in &self.hosts {
+            add(&mut view, Some(host), ns.clone());
+        }
+        view
+    }
+
+    pub fn clean_repo(&mut self, repo: &str) {
+        self.apply(Change::RemoveRepo(repo.to_string()));
+    }
+
+    /// Removes entries for repo paths that are stale (see `stale_reason`).
+    /// Returns the removed repo paths with the reason for each, sorted.
+    pub fn clean_stale(&mut self, verify: bool) -> Vec<(String, String)> {
+        let repos: BTreeSet<&String> = self
+            .tracked
+            .keys()
+            .chain(self.authorized.keys())
+            .chain(self.remote_grants.keys())
+            .collect();
+        let stale: Vec<(String, String)> = repos
+            .into_iter()
+            .filter_map(|r| stale_reason(r, verify).map(|reason| (r.clone(), reason)))
+            .collect();
+        for (repo, _) in &stale {
+            self.apply(Change::RemoveRepo(repo.clone()));
+        }
+        stale
+    }
+}
+
+/// Returns why the repo key `repo` is stale, or None while it still names
+/// that repository. Only existence is checked unless `verify` is set; then the
+/// path must also be a git repository whose top level is `repo` itself, so a
+/// path reused by a plain directory or another checkout doesn't keep grants.
+pub fn stale_reason(repo: &str, verify: bool) -> Option<String> {
+    let path = Path::new(repo);
+    if !path.exists() {
+        return Some("no longer exists".to_string());
+    }
+    if !verify {
+        return None;
+    }
+    let key = path.is_dir().then(|| GitInfo::for_repo(repo).repo_key()).flatten();
+    let Some(key) = key else {
+        return Some("is not a git repository".to_string());
+    };
+    let canonical = |p: &str| fs::canonicalize(p).unwrap_or_else(|_| PathBuf::from(p));
+    if canonical(&key) != canonical(repo) {
+        return Some(format!("is now inside the repository at '{}'", key));
+    }
+    None
 }
 
 // ── Tests ─────────────────────────────────────────────────────────────────────
@@ -119,6 +1408,19 @@ mod tests {
         State::default()
     }
 
+    #[test]
+    fn agent_is_read_from_subagent_hook_calls() {
+        let json = serde_json::json!({ "agent_id": "a1", "agent_type": "Explore" });
+        let agent = Agent::from_hook(&json).unwrap();
+        assert_eq!(agent, Agent { id: "a1".into(), kind: Some("Explore".into()) });
+        assert_eq!(agent.describe(), "subagent Explore (a1)");
+        let untyped = Agent::from_hook(&serde_json::json!({ "agent_id": "a2" })).unwrap();
+        assert_eq!(untyped.describe(), "subagent a2");
+        // The main agent's calls carry no agent id
+        assert_eq!(Agent::from_hook(&serde_json::json!({ "session_id": "s1" })), None);
+        assert_eq!(Agent::from_hook(&serde_json::json!({ "agent_id": "" })), None);
+    }
+
     #[test]
     fn fresh_state_not_tracked() {
         let s = empty();
@@ -128,7 +1430,7 @@ mod tests {
     #[test]
     fn fresh_state_not_authorized() {
         let s = empty();
-        assert!(!s.is_authorized("/repo", "main"));
+        assert!(s.authorization("/repo", "main").is_none());
     }
 
     #[test]
@@ -142,22 +1444,131 @@ mod tests {
     fn track_does_not_affect_authorized() {
         let mut s = empty();
         s.track("/repo", "feature");
-        assert!(!s.is_authorized("/repo", "feature"));
+        assert!(s.authorization("/repo", "feature").is_none());
     }
 
     #[test]
     fn authorize_then_is_authorized() {
         let mut s = empty();
-        s.authorize("/repo", "main");
-        assert!(s.is_authorized("/repo", "main"));
+        s.authorize("/repo", "main", None);
+        assert!(s.authorization("/repo", "main").is_some());
+    }
+
+    #[test]
+    fn ignore_case_matches_without_rewriting_names() {
+        let mut state = State::default();
+        state.track("/r", "Feature/Login");
+        state.authorize("/r", "Release", None);
+        assert!(!state.is_tracked("/r", "feature/login"));
+        assert!(state.authorization("/r", "release").is_none());
+
+        state.ignore_case = true;
+        assert!(state.is_tracked("/r", "feature/login"));
+        assert!(state.is_tracked_in("/r", "FEATURE/LOGIN", None));
+        assert!(state.authorization("/r", "release").is_some());
+        assert_eq!(state.tracked["/r"][0].branch, "Feature/Login");
+    }
+
+    #[test]
+    fn authorize_replaces_existing_binding() {
+        let mut s = empty();
+        s.authorize("/repo", "feature", Some("aaa"));
+        s.authorize("/repo", "feature", Some("bbb"));
+        assert_eq!(s.authorized["/repo"].len(), 1);
+        assert_eq!(s.authorization("/repo", "feature").unwrap().sha.as_deref(), Some("bbb"));
+        assert!(s.authorization("/repo", "other").is_none());
     }
 
     #[test]
     fn revoke_removes_authorization() {
         let mut s = empty();
-        s.authorize("/repo", "main");
+        s.authorize("/repo", "main", None);
         s.revoke("/repo", "main");
-        assert!(!s.is_authorized("/repo", "main"));
+        assert!(s.authorization("/repo", "main").is_none());
+    }
+
+    #[test]
+    fn exhausted_authorizations_are_removed() {
+        let mut s = empty();
+        s.authorize_uses("/repo", "spent", None, Some(0));
+        s.authorize_uses("/repo", "live", None, Some(1));
+        assert_eq!(s.remove_exhausted("/repo"), vec!["spent"]);
+        assert!(s.authorization("/repo", "spent").is_none());
+        assert!(s.authorization("/repo", "live").is_some());
+    }
+
+    #[test]
+    fn uses_are_counted_and_old_unused_grants_removed() {
+        let mut s = empty();
+        s.track("/repo", "feature");
+        s.authorize("/repo", "hotfix", None);
+        assert!(s.record_use(Kind::Tracked, "/repo", "feature"));
+        assert!(s.record_use(Kind::Tracked, "/repo", "feature"));
+        assert!(!s.record_use(Kind::Authorized, "/repo", "feature"));
+        let entry = s.tracked_entry("/repo", "feature").unwrap();
+        assert_eq!(entry.use_count, 2);
+        assert!(entry.last_used_at.is_some());
+        assert_eq!(s.authorization("/repo", "hotfix").unwrap().use_count, 0);
+
+        s.authorize("/repo", "used", None);
+        s.record_use(Kind::Authorized, "/repo", "used");
+        s.authorized.get_mut("/repo").unwrap().push(Entry::bare("undated".into()));
+        let cutoff = Utc::now();
+        s.authorize("/repo", "fresh", None);
+        s.record_use(Kind::Authorized, "/repo", "used");
+        assert_eq!(s.remove_unused("/repo", cutoff), vec!["hotfix"]);
+        assert!(s.authorization("/repo", "undated").is_some());
+    }
+
+    #[test]
+    fn limited_authorization_is_used_up() {
+        let mut s = empty();
+        s.authorize_uses("/repo", "feature", None, Some(2));
+        s.authorize("/repo", "main", None);
+        assert_eq!(s.use_authorization("/repo", "feature"), Some(1));
+        assert_eq!(s.authorization("/repo", "feature").unwrap().uses_left, Some(1));
+        assert_eq!(s.use_authorization("/repo", "feature"), Some(0));
+        assert!(s.authorization("/repo", "feature").is_none());
+        // Unlimited authorizations are left alone
+        assert_eq!(s.use_authorization("/repo", "main"), None);
+        assert!(s.authorization("/repo", "main").is_some());
+        let json = serde_json::to_value(Entry { uses_left: Some(3), ..Entry::new("x") }).unwrap();
+        assert_eq!(json["uses_left"], 3);
+    }
+
+    #[test]
+    fn entering_a_host_namespace_keeps_flat_entries() {
+        let mut s = State::parse(
+            r#"{"tracked": {"/repo": ["flat", "both"]}, "authorized": {},
+                "hosts": {"a": {"tracked": {"/repo": ["both", "mine"]}},
+                          "b": {"authorized": {"/other": ["theirs"]}}}}"#,
+        )
+        .unwrap();
+        s.enter_namespace("a".to_string());
+        assert_eq!(s.branches("/repo"), ["both", "mine", "flat"]);
+        assert!(!s.hosts.contains_key("a"));
+        let all = s.all_hosts();
+        assert_eq!(all.repos(), ["a:/repo", "b:/other"]);
+        assert!(all.authorized["b:/other"].iter().any(|e| e.branch == "theirs"));
+    }
+
+    #[test]
+    fn renames_move_entries_and_copies_only_track() {
+        let mut s = empty();
+        s.track_session("/repo", "old", Some("s1"));
+        s.authorize("/repo", "old", Some("abc"));
+        assert!(s.rename_branch("/repo", "old", "new"));
+        assert!(!s.is_tracked("/repo", "old") && s.authorization("/repo", "old").is_none());
+        assert_eq!(s.tracked_entry("/repo", "new").unwrap().session_id.as_deref(), Some("s1"));
+        assert_eq!(s.authorization("/repo", "new").unwrap().sha.as_deref(), Some("abc"));
+
+        assert!(s.copy_tracking("/repo", "new", "copy"));
+        assert!(s.is_tracked("/repo", "copy") && s.is_tracked("/repo", "new"));
+        assert!(s.authorization("/repo", "copy").is_none());
+        // Untracked branches gain nothing from being renamed or copied
+        assert!(!s.rename_branch("/repo", "main", "mine"));
+        assert!(!s.copy_tracking("/repo", "main", "mine"));
+        assert!(!s.is_tracked("/repo", "mine"));
     }
 
     #[test]
@@ -168,6 +1579,54 @@ mod tests {
         assert!(s.is_tracked("/repo", "feature"));
     }
 
+    #[test]
+    fn local_scope_entries_are_consulted_first_and_removed_separately() {
+        let mut s = empty();
+        s.authorize("/repo", "feature", Some("aaa"));
+        s.write_scope = Some(Scope::Local);
+        s.authorize("/repo", "feature", Some("bbb"));
+        assert_eq!(s.authorized["/repo"].len(), 2);
+        assert_eq!(s.authorization("/repo", "feature").unwrap().sha.as_deref(), Some("bbb"));
+
+        s.revoke("/repo", "feature");
+        let left = s.authorization("/repo", "feature").unwrap();
+        assert_eq!((left.scope, left.sha.as_deref()), (Scope::Global, Some("aaa")));
+
+        // Without a scope, writes go where the entry is and removals hit both
+        s.authorize("/repo", "feature", Some("bbb"));
+        s.write_scope = None;
+        s.authorize("/repo", "feature", Some("ccc"));
+        assert_eq!(s.authorization("/repo", "feature").unwrap().scope, Scope::Local);
+        assert_eq!(s.authorized["/repo"].len(), 2);
+        s.revoke("/repo", "feature");
+        assert!(s.authorization("/repo", "feature").is_none());
+    }
+
+    #[test]
+    fn untrack_removes_tracking_only() {
+        let mut s = empty();
+        s.track("/repo", "feature");
+        s.authorize("/repo", "feature", None);
+        s.untrack("/repo", "feature");
+        assert!(!s.is_tracked("/repo", "feature"));
+        assert!(s.authorization("/repo", "feature").is_some());
+    }
+
+    #[test]
+    fn session_scoped_tracking_and_pruning() {
+        let mut s = empty();
+        s.track_session("/repo", "a-branch", Some("A"));
+        s.track("/repo", "manual");
+        assert!(s.is_tracked_in("/repo", "a-branch", Some("A")));
+        assert!(!s.is_tracked_in("/repo", "a-branch", Some("B")));
+        assert!(!s.is_tracked_in("/repo", "a-branch", None));
+        assert!(s.is_tracked_in("/repo", "manual", Some("B")));
+
+        assert_eq!(s.prune_session("A"), vec![("/repo".to_string(), "a-branch".to_string())]);
+        assert!(!s.is_tracked("/repo", "a-branch"));
+        assert!(s.is_tracked("/repo", "manual"));
+    }
+
     #[test]
     fn track_deduplication() {
         let mut s = empty();
@@ -179,8 +1638,8 @@ mod tests {
     #[test]
     fn authorize_deduplication() {
         let mut s = empty();
-        s.authorize("/repo", "main");
-        s.authorize("/repo", "main");
+        s.authorize("/repo", "main", None);
+        s.authorize("/repo", "main", None);
         assert_eq!(s.authorized["/repo"].len(), 1);
     }
 
@@ -207,10 +1666,10 @@ mod tests {
     fn clean_repo_removes_tracked_and_authorized() {
         let mut s = empty();
         s.track("/repo", "a");
-        s.authorize("/repo", "b");
+        s.authorize("/repo", "b", None);
         s.clean_repo("/repo");
         assert!(!s.is_tracked("/repo", "a"));
-        assert!(!s.is_authorized("/repo", "b"));
+        assert!(s.authorization("/repo", "b").is_none());
     }
 
     #[test]
@@ -223,12 +1682,224 @@ mod tests {
         assert!(s.is_tracked("/repo-b", "feature"));
     }
 
+    #[test]
+    fn bare_string_entries_still_load() {
+        let s: State =
+            serde_json::from_str(r#"{"tracked":{"/repo":["feature"]},"authorized":{}}"#).unwrap();
+        assert!(s.is_tracked("/repo", "feature"));
+        assert_eq!(s.tracked["/repo"][0].created_at, None);
+    }
+
+    #[test]
+    fn entry_without_metadata_serializes_as_string() {
+        let json = serde_json::to_value(Entry::bare("feature".into()));
+        assert_eq!(json.unwrap(), serde_json::json!("feature"));
+    }
+
+    #[test]
+    fn entry_with_metadata_round_trips() {
+        let entry = Entry::new("feature");
+        let json = serde_json::to_string(&entry).unwrap();
+        let back: Entry = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, entry);
+    }
+
+    #[test]
+    fn merge_prefers_newer_entry() {
+        let old = Utc::now() - chrono::Duration::hours(1);
+        let mut a = empty();
+        a.tracked.insert(
+            "/repo".into(),
+            vec![Entry { created_at: Some(old), ..Entry::new("f") }],
+        );
+        let mut b = empty();
+        b.track("/repo", "f");
+        b.track("/repo", "g");
+        let newer = b.tracked["/repo"][0].created_at;
+        a.merge(b);
+        assert_eq!(a.tracked["/repo"].len(), 2);
+        assert_eq!(a.tracked["/repo"][0].created_at, newer);
+    }
+
+    #[test]
+    fn merge_keeps_newer_existing_entry() {
+        let mut a = empty();
+        a.track("/repo", "f");
+        let mine = a.tracked["/repo"][0].created_at;
+        let mut b = empty();
+        b.tracked.insert(
+            "/repo".into(),
+            vec![Entry::bare("f".into())],
+        );
+        a.merge(b);
+        assert_eq!(a.tracked["/repo"][0].created_at, mine);
+    }
+
+    #[test]
+    fn remove_branches_leaves_authorized_by_default() {
+        let mut s = empty();
+        s.track("/repo", "done");
+        s.track("/repo", "wip");
+        s.authorize("/repo", "done", None);
+        let removed = s.remove_branches("/repo", &["done".to_string()], false);
+        assert_eq!(removed, vec!["done"]);
+        assert!(!s.is_tracked("/repo", "done"));
+        assert!(s.is_tracked("/repo", "wip"));
+        assert!(s.authorization("/repo", "done").is_some());
+    }
+
+    #[test]
+    fn remove_branches_include_authorized() {
+        let mut s = empty();
+        s.track("/repo", "done");
+        s.authorize("/repo", "done", None);
+        let removed = s.remove_branches("/repo", &["done".to_string()], true);
+        assert_eq!(removed, vec!["done"]);
+        assert!(s.authorization("/repo", "done").is_none());
+    }
+
+    #[test]
+    fn cached_protection_expires_after_ttl() {
+        let mut s = empty();
+        s.cache_protection("o/r:main", true);
+        assert_eq!(s.cached_protection("o/r:main", chrono::Duration::minutes(5)), Some(true));
+        assert_eq!(s.cached_protection("o/r:other", chrono::Duration::minutes(5)), None);
+        s.github_protection.get_mut("o/r:main").unwrap().checked_at -= chrono::Duration::hours(1);
+        assert_eq!(s.cached_protection("o/r:main", chrono::Duration::minutes(5)), None);
+    }
+
+    #[test]
+    fn remote_grants_replace_and_deny() {
+        let mut s = empty();
+        let urls = vec!["ssh://nas/backup.git".to_string()];
+        s.allow_remote("/repo", "backup", urls.clone(), false);
+        s.allow_remote("/repo", "backup", urls.clone(), true);
+        assert_eq!(s.remote_grants["/repo"].len(), 1);
+        let grant = s.remote_grant("/repo", "backup").unwrap();
+        assert!(grant.include_force);
+        assert!(grant.applies_to(&urls));
+        assert!(!grant.applies_to(&["ssh://nas/other.git".to_string()]));
+        assert!(!RemoteGrant { push_urls: vec![], ..grant.clone() }.applies_to(&[]));
+        assert!(s.remote_grant("/repo", "origin").is_none());
+        assert!(!s.deny_remote("/repo", "origin"));
+        assert!(s.deny_remote("/repo", "backup"));
+        assert!(s.remote_grants.is_empty());
+    }
+
+    #[test]
+    fn remote_renames_move_grants_and_recorded_heads() {
+        let mut s = empty();
+        s.allow_remote("/repo", "backup", vec!["ssh://nas/backup.git".into()], false);
+        s.cache_remote_head(&remote_head_key("/repo", "backup"), Some("main"));
+        s.cache_remote_head(&remote_head_key("/repo", "origin"), Some("trunk"));
+        s.rename_remote("/repo", "backup", "nas");
+        assert!(s.remote_grant("/repo", "backup").is_none());
+        assert!(s.remote_grant("/repo", "nas").is_some());
+        assert_eq!(s.remotes_with_heads("/repo"), ["nas", "origin"]);
+        assert!(s.remotes_with_heads("/other").is_empty());
+
+        s.forget_remote("/repo", "origin", false);
+        assert_eq!(s.remotes_with_heads("/repo"), ["nas"]);
+        s.forget_remote("/repo", "nas", true);
+        assert!(s.remote_grants.is_empty());
+        assert!(s.remote_heads.is_empty());
+    }
+
+    #[test]
+    fn concurrent_saves_merge_instead_of_clobbering() {
+        // The only unit test that touches the state file
+        let dir = tempfile::TempDir::new().unwrap();
+        std::env::set_var("PUSH_GUARD_STATE_FILE", dir.path().join("state.json"));
+        State::update(|s| {
+            s.track("/repo", "old");
+            s.track("/repo", "gone");
+        })
+        .unwrap();
+
+        let mut a = State::load().unwrap();
+        let mut b = State::load().unwrap();
+        a.track("/repo", "from-a");
+        a.untrack("/repo", "gone");
+        b.track("/repo", "from-b");
+        b.authorize("/repo", "auth-b", None);
+        a.save().unwrap();
+        b.save().unwrap();
+
+        let merged = State::load().unwrap();
+        let mut tracked = merged.branches("/repo");
+        tracked.sort();
+        assert_eq!(tracked, vec!["auth-b", "from-a", "from-b", "old"]);
+        assert!(!merged.is_tracked("/repo", "gone"));
+    }
+
+    #[test]
+    fn repo_state_path_is_inside_git_dir() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::process::Command::new("git")
+            .arg("-C")
+            .arg(dir.path())
+            .args(["init", "-q"])
+            .status()
+            .unwrap();
+        let path = repo_state_path(dir.path().to_str().unwrap()).unwrap();
+        assert!(path.ends_with(".git/push-guard.json"));
+        assert_eq!(repo_state_path("/nonexistent/push-guard-repo"), None);
+    }
+
+    // Schema versions
+
+    const FIXTURES: [(&str, &str); 4] = [
+        ("v0-bare", include_str!("../tests/fixtures/state/v0-bare.json")),
+        ("v0-entries", include_str!("../tests/fixtures/state/v0-entries.json")),
+        ("v1", include_str!("../tests/fixtures/state/v1.json")),
+        ("future", include_str!("../tests/fixtures/state/future.json")),
+    ];
+
+    #[test]
+    fn every_fixture_loads_and_round_trips() {
+        for (name, contents) in FIXTURES {
+            let state = State::parse(contents).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(state.is_tracked("/home/me/project", "feature"), "{}", name);
+            let written = serde_json::to_string(&state).unwrap();
+            let again = State::parse(&written).unwrap();
+            assert_eq!(
+                serde_json::to_value(&again).unwrap(),
+                serde_json::to_value(&state).unwrap(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn old_versions_are_upgraded_in_memory() {
+        let state = State::parse(FIXTURES[0].1).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert!(!state.read_only);
+        let state = State::parse(FIXTURES[1].1).unwrap();
+        let release = state.authorization("/home/me/project", "release").unwrap();
+        assert!(release.sha.is_some());
+        assert!(State::parse(r#"{"tracked": {"/r": ["a"]}}"#).unwrap().authorized.is_empty());
+    }
+
+    #[test]
+    fn newer_versions_are_read_only_and_keep_unknown_fields() {
+        let mut state = State::parse(FIXTURES[3].1).unwrap();
+        assert!(state.read_only);
+        assert!(state.write().is_err());
+        let json = serde_json::to_value(&state).unwrap();
+        assert_eq!(json["version"], 99);
+        assert_eq!(json["denied"]["/home/me/project"][0], "main");
+        assert_eq!(json["tracked"]["/home/me/project"][0]["session_id"], "abc");
+    }
+
     #[test]
     fn clean_stale_removes_nonexistent_repos() {
         let mut s = empty();
         s.track("/definitely/does/not/exist/on/disk/repo", "feature");
-        let removed = s.clean_stale();
+        let removed = s.clean_stale(false);
         assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].1, "no longer exists");
         assert!(s.tracked.is_empty());
     }
 
@@ -236,7 +1907,7 @@ mod tests {
     fn clean_stale_keeps_existing_repos() {
         let mut s = empty();
         s.track("/tmp", "feature"); // /tmp always exists
-        let removed = s.clean_stale();
+        let removed = s.clean_stale(false);
         assert!(removed.is_empty());
         assert!(s.is_tracked("/tmp", "feature"));
     }
@@ -0,0 +1,124 @@
+use crate::state::{self, state_path, StateLock};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Decision counters, stored next to the state file.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Stats {
+    /// Counters keyed by repo path
+    pub repos: BTreeMap<String, RepoStats>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+pub struct RepoStats {
+    pub allowed: u64,
+    pub blocked: u64,
+    /// Number of decisions made by each rule
+    pub rules: BTreeMap<String, u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_block: Option<DateTime<Utc>>,
+}
+
+impl RepoStats {
+    fn add(&mut self, other: &RepoStats) {
+        self.allowed += other.allowed;
+        self.blocked += other.blocked;
+        for (rule, n) in &other.rules {
+            *self.rules.entry(rule.clone()).or_default() += n;
+        }
+        self.last_block = self.last_block.max(other.last_block);
+    }
+}
+
+pub fn stats_path() -> PathBuf {
+    state_path().with_extension("stats.json")
+}
+
+impl Stats {
+    pub fn load() -> Result<Self> {
+        let path = stats_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read stats from {}", path.display()))?;
+        if contents.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(&contents).context("Failed to parse stats file")
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = stats_path();
+        let contents = serde_json::to_string_pretty(self)?;
+        fs::write(&path, contents)
+            .with_context(|| format!("Failed to write stats to {}", path.display()))
+    }
+
+    /// Records one decision under the state lock.
+    pub fn record(repo: &str, rule: &str, blocked: bool) -> Result<()> {
+        if !state::writable() {
+            return Ok(());
+        }
+        let _lock = StateLock::acquire()?;
+        let mut stats = Self::load()?;
+        let entry = stats.repos.entry(repo.to_string()).or_default();
+        if blocked {
+            entry.blocked += 1;
+            entry.last_block = Some(Utc::now());
+        } else {
+            entry.allowed += 1;
+        }
+        *entry.rules.entry(rule.to_string()).or_default() += 1;
+        stats.save()
+    }
+
+    /// Sums the counters of every repo, or of just `repo` when given.
+    pub fn total(&self, repo: Option<&str>) -> RepoStats {
+        let mut total = RepoStats::default();
+        for (r, s) in &self.repos {
+            if repo.is_none_or(|only| only == r) {
+                total.add(s);
+            }
+        }
+        total
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_sums_across_repos() {
+        let mut s = Stats::default();
+        s.repos.insert(
+            "/a".into(),
+            RepoStats {
+                allowed: 2,
+                blocked: 1,
+                rules: BTreeMap::from([("tracked".into(), 2), ("untracked".into(), 1)]),
+                last_block: None,
+            },
+        );
+        s.repos.insert(
+            "/b".into(),
+            RepoStats {
+                allowed: 0,
+                blocked: 3,
+                rules: BTreeMap::from([("untracked".into(), 3)]),
+                last_block: None,
+            },
+        );
+        let total = s.total(None);
+        assert_eq!((total.allowed, total.blocked), (2, 4));
+        assert_eq!(total.rules["untracked"], 4);
+        assert_eq!(s.total(Some("/b")).allowed, 0);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::audit::Event;
+use crate::shorten_home;
+use std::collections::BTreeMap;
+
+/// What one Claude session did in one repo.
+#[derive(Default)]
+struct Activity {
+    created: Vec<String>,
+    /// Push descriptions with how often each happened, in first-seen order.
+    allowed: Vec<(String, usize)>,
+    blocked: Vec<(String, usize)>,
+    authorizations_used: Vec<String>,
+}
+
+fn count(pushes: &mut Vec<(String, usize)>, push: String) {
+    match pushes.iter_mut().find(|(p, _)| *p == push) {
+        Some((_, n)) => *n += 1,
+        None => pushes.push((push, 1)),
+    }
+}
+
+fn join_counted(pushes: &[(String, usize)]) -> String {
+    let counted = pushes.iter().map(|(push, n)| match n {
+        1 => push.clone(),
+        n => format!("{} ×{}", push, n),
+    });
+    counted.collect::<Vec<_>>().join(", ")
+}
+
+/// Summarizes the pushes of `session` among the audit `events` and the
+/// branches it `created` (repo, branch), one block per repo. Returns None
+/// when the session did nothing push-guard saw.
+pub fn summarize(
+    session: &str,
+    events: &[Event],
+    created: &[(String, String)],
+) -> Option<String> {
+    let mut repos: BTreeMap<&str, Activity> = BTreeMap::new();
+    for (repo, branch) in created {
+        repos.entry(repo).or_default().created.push(branch.clone());
+    }
+    for event in events.iter().filter(|e| e.session.as_deref() == Some(session)) {
+        let activity = repos.entry(&event.repo).or_default();
+        let target = if event.deletion {
+            format!("delete {} on {}", event.branch, event.remote)
+        } else {
+            format!("{} → {}", event.branch, event.remote)
+        };
+        let force = if event.force { ", force" } else { "" };
+        let push = format!("{} ({}{})", target, event.rule, force);
+        match event.decision.as_str() {
+            "block" => count(&mut activity.blocked, push),
+            "allow" => {
+                count(&mut activity.allowed, push);
+                let used = &mut activity.authorizations_used;
+                if event.rule == "authorized" && !used.contains(&event.branch) {
+                    used.push(event.branch.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    if repos.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec![format!("push-guard: summary of session {}", session)];
+    for (repo, activity) in &repos {
+        lines.push(format!("  {}", shorten_home(repo)));
+        if !activity.created.is_empty() {
+            lines.push(format!("    created: {}", activity.created.join(", ")));
+        }
+        if !activity.allowed.is_empty() {
+            lines.push(format!("    allowed: {}", join_counted(&activity.allowed)));
+        }
+        if !activity.blocked.is_empty() {
+            lines.push(format!("    blocked: {}", join_counted(&activity.blocked)));
+        }
+        if !activity.authorizations_used.is_empty() {
+            let used = activity.authorizations_used.join(", ");
+            lines.push(format!("    authorizations used: {}", used));
+        }
+    }
+    Some(lines.join("\n"))
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(session: &str, branch: &str, decision: &str, rule: &str) -> Event {
+        Event {
+            session: Some(session.to_string()),
+            ..Event::new("/repo", "origin", branch, false, decision, rule)
+        }
+    }
+
+    #[test]
+    fn summary_covers_only_the_session() {
+        let events = [
+            event("s1", "feature", "allow", "tracked"),
+            event("s1", "feature", "allow", "tracked"),
+            event("s1", "release", "allow", "authorized"),
+            event("s1", "main", "block", "default_branch"),
+            event("s2", "other", "block", "untracked"),
+            Event::new("/repo", "origin", "manual", false, "allow", "tracked"),
+        ];
+        let created = [("/repo".to_string(), "feature".to_string())];
+        let summary = summarize("s1", &events, &created).unwrap();
+        assert_eq!(
+            summary,
+            "push-guard: summary of session s1\n  \
+             /repo\n    \
+             created: feature\n    \
+             allowed: feature → origin (tracked) ×2, release → origin (authorized)\n    \
+             blocked: main → origin (default_branch)\n    \
+             authorizations used: release"
+        );
+        assert!(!summary.contains("other") && !summary.contains("manual"));
+        assert_eq!(summarize("s3", &events, &[]), None);
+    }
+}
@@ -0,0 +1,110 @@
+use chrono::{DateTime, Duration, Utc};
+
+/// Units of user-facing durations, largest first, with their length in seconds.
+const UNITS: [(&str, i64); 4] = [("w", 7 * 86_400), ("d", 86_400), ("h", 3_600), ("m", 60)];
+
+/// Parses a duration such as "30d": a whole number followed by m (minutes),
+/// h (hours), d (days) or w (weeks). Used for every duration flag.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let unit_at = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(unit_at);
+    let n: i64 = number
+        .parse()
+        .map_err(|_| format!("'{}' does not start with a number, e.g. \"30d\"", value))?;
+    let Some((_, seconds)) = UNITS.iter().find(|(u, _)| *u == unit) else {
+        return Err(format!("unknown unit in '{}'; use m, h, d or w", value));
+    };
+    n.checked_mul(*seconds)
+        .and_then(Duration::try_seconds)
+        .ok_or_else(|| format!("'{}' is too long", value))
+}
+
+/// Renders a duration in the units `parse_duration` reads, rounded down to
+/// the largest unit it spans at least twice: "90m", "36h", "13d", "2w".
+pub fn span(duration: Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let (unit, length) = UNITS
+        .iter()
+        .find(|(_, length)| seconds >= 2 * length)
+        .unwrap_or(&UNITS[UNITS.len() - 1]);
+    format!("{}{}", seconds / length, unit)
+}
+
+/// Renders `time` relative to `now`: "3h ago", "in 2d", or "just now" when
+/// it is less than a minute away either way.
+pub fn relative(time: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let offset = time - now;
+    if offset.num_seconds().abs() < 60 {
+        "just now".to_string()
+    } else if offset > Duration::zero() {
+        format!("in {}", span(offset))
+    } else {
+        format!("{} ago", span(-offset))
+    }
+}
+
+/// Renders `time` for text output: relative to the current time, or with
+/// `absolute` as its UTC date and time. JSON output uses RFC 3339 instead.
+pub fn timestamp(time: DateTime<Utc>, absolute: bool) -> String {
+    if absolute {
+        time.format("%Y-%m-%d %H:%M").to_string()
+    } else {
+        relative(time, Utc::now())
+    }
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_reads_a_number_and_unit() {
+        assert_eq!(parse_duration("90m"), Ok(Duration::minutes(90)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::hours(12)));
+        assert_eq!(parse_duration("30d"), Ok(Duration::days(30)));
+        assert_eq!(parse_duration("2w"), Ok(Duration::weeks(2)));
+        assert_eq!(parse_duration("0m"), Ok(Duration::zero()));
+        let unit = "unknown unit in '30'; use m, h, d or w";
+        assert_eq!(parse_duration("30"), Err(unit.to_string()));
+        assert!(parse_duration("d").unwrap_err().contains("does not start with a number"));
+        assert!(parse_duration("3 days").is_err());
+        assert!(parse_duration("-3d").is_err());
+        assert_eq!(parse_duration("99999999999999w"), Err("'99999999999999w' is too long".into()));
+    }
+
+    #[test]
+    fn span_uses_the_largest_unit_spanned_twice() {
+        assert_eq!(span(Duration::seconds(59)), "0m");
+        assert_eq!(span(Duration::minutes(90)), "90m");
+        assert_eq!(span(Duration::hours(2)), "2h");
+        assert_eq!(span(Duration::hours(47)), "47h");
+        assert_eq!(span(Duration::days(13)), "13d");
+        assert_eq!(span(Duration::days(14)), "2w");
+        // Rendered spans parse back to at most the original
+        for d in [Duration::minutes(5), Duration::hours(30), Duration::days(20)] {
+            assert!(parse_duration(&span(d)).unwrap() <= d);
+        }
+    }
+
+    #[test]
+    fn relative_times_around_now() {
+        let now = Utc::now();
+        assert_eq!(relative(now, now), "just now");
+        // Just expired, or about to: still "just now" within the minute
+        assert_eq!(relative(now - Duration::seconds(1), now), "just now");
+        assert_eq!(relative(now + Duration::seconds(59), now), "just now");
+        assert_eq!(relative(now - Duration::seconds(60), now), "1m ago");
+        assert_eq!(relative(now + Duration::seconds(60), now), "in 1m");
+        assert_eq!(relative(now - Duration::hours(3), now), "3h ago");
+        assert_eq!(relative(now + Duration::days(2), now), "in 2d");
+    }
+
+    #[test]
+    fn timestamp_is_absolute_on_request() {
+        let time = DateTime::parse_from_rfc3339("2024-05-02T09:30:45Z").unwrap().to_utc();
+        assert_eq!(timestamp(time, true), "2024-05-02 09:30");
+        assert!(timestamp(time, false).ends_with(" ago"));
+    }
+}
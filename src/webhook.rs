@@ -0,0 +1,93 @@
+use crate::config::Config;
+use crate::{debug_log, hostname};
+
+/// How long a webhook delivery may take before it is abandoned.
+#[cfg(feature = "webhook")]
+const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(1500);
+
+/// Returns false when `PUSH_GUARD_WEBHOOK=0` turns deliveries off.
+pub fn enabled() -> bool {
+    std::env::var("PUSH_GUARD_WEBHOOK").map_or(true, |v| v != "0")
+}
+
+/// A block, authorization or revocation, as posted to `webhook_url`.
+pub struct Notification<'a> {
+    /// "blocked", "authorized" or "revoked".
+    pub event: &'a str,
+    pub repo: &'a str,
+    pub branch: &'a str,
+    pub remote: Option<&'a str>,
+    pub rule: Option<&'a str>,
+}
+
+impl Notification<'_> {
+    fn payload(&self) -> serde_json::Value {
+        serde_json::json!({
+            "event": self.event,
+            "repo": self.repo,
+            "branch": self.branch,
+            "remote": self.remote,
+            "rule": self.rule,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "hostname": hostname(),
+        })
+    }
+
+    /// Posts this notification to the configured `webhook_url`, if any.
+    /// Delivery is best-effort: failures are written to the debug log and never
+    /// reported to the caller.
+    pub fn send(&self) {
+        if !enabled() {
+            return;
+        }
+        let Some(url) = Config::load(Some(self.repo)).ok().and_then(|c| c.webhook_url) else {
+            return;
+        };
+        if let Err(e) = post(&url, &self.payload()) {
+            debug_log(&format!("webhook: {} event not delivered to {}: {}", self.event, url, e));
+        }
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn post(url: &str, payload: &serde_json::Value) -> Result<(), String> {
+    ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .post(url)
+        .set("Content-Type", "application/json")
+        .send_string(&payload.to_string())
+        .map(drop)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post(_url: &str, _payload: &serde_json::Value) -> Result<(), String> {
+    Err("built without the `webhook` feature".to_string())
+}
+
+// ── Tests ─────────────────────────────────────────────────────────────────────
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_has_all_fields() {
+        let n = Notification {
+            event: "blocked",
+            repo: "/r",
+            branch: "main",
+            remote: Some("origin"),
+            rule: Some("default_branch"),
+        };
+        let payload = n.payload();
+        assert_eq!(payload["event"], "blocked");
+        assert_eq!(payload["repo"], "/r");
+        assert_eq!(payload["branch"], "main");
+        assert_eq!(payload["remote"], "origin");
+        assert_eq!(payload["rule"], "default_branch");
+        assert!(payload["timestamp"].is_string());
+        assert!(payload.get("hostname").is_some());
+    }
+}
@@ -1,20 +1,52 @@
 use assert_cmd::Command;
-use tempfile::NamedTempFile;
+use predicates::prelude::PredicateBooleanExt;
+use std::path::Path;
+use tempfile::{NamedTempFile, TempDir};
 
 fn cmd() -> Command {
-    Command::cargo_bin("push-guard").unwrap()
+    assert_cmd::cargo::cargo_bin_cmd!("push-guard")
+}
+
+fn git(dir: &Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("GIT_AUTHOR_NAME", "test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?} failed", args);
+}
+
+/// Creates a temp repo on `main` with one commit and an `origin/HEAD` pointing at
+/// `origin/main`, without any network access.
+fn temp_repo() -> TempDir {
+    let dir = TempDir::new().unwrap();
+    git(dir.path(), &["init", "-q", "-b", "main"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "init"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/main", "HEAD"]);
+    git(dir.path(), &["symbolic-ref", "refs/remotes/origin/HEAD", "refs/remotes/origin/main"]);
+    dir
+}
+
+fn repo_str(dir: &TempDir) -> String {
+    dir.path().to_str().unwrap().to_string()
 }
 
 fn with_state() -> (Command, NamedTempFile) {
     let f = NamedTempFile::new().unwrap();
-    let mut c = cmd();
-    c.env("PUSH_GUARD_STATE_FILE", f.path());
+    let c = state_cmd(&f);
     (c, f)
 }
 
+/// Points both the state file and the user config at per-test paths.
 fn state_cmd(f: &NamedTempFile) -> Command {
     let mut c = cmd();
     c.env("PUSH_GUARD_STATE_FILE", f.path());
+    c.env("PUSH_GUARD_CONFIG_FILE", f.path().with_extension("config.toml"));
     c
 }
 
@@ -30,6 +62,75 @@ fn track_succeeds() {
         .success();
 }
 
+#[test]
+fn track_and_authorize_normalize_branch_names() {
+    let f = NamedTempFile::new().unwrap();
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", " feature "])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["authorize", "--repo", REPO, "--branch", "refs/heads/release"])
+        .assert()
+        .success();
+    for bad in ["feature/", "a..b", "bad\u{7}name", "  "] {
+        state_cmd(&f)
+            .args(["track", "--repo", REPO, "--branch", bad])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("Invalid branch name"));
+    }
+    let json = list_json(&f);
+    assert_eq!(json["tracked"][REPO], serde_json::json!(["feature"]));
+    assert_eq!(json["authorized"][REPO], serde_json::json!(["release"]));
+}
+
+#[test]
+fn case_insensitive_branches_match_mixed_case_entries() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--branch", "Feature/Login"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "Hotfix"])
+        .assert()
+        .success();
+
+    check("feature/login").assert().failure();
+    check("MAIN").assert().failure().stderr(predicates::str::contains("not created by me"));
+
+    // Matching more branches loosens the policy, so the repo config can't do it
+    state_cmd(&f)
+        .args(["config", "set", "case_insensitive_branches", "true", "--repo", &repo])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("would loosen the policy"));
+    // ...and says so when the file is edited by hand
+    let repo_config = dir.path().join(".push-guard.toml");
+    std::fs::write(&repo_config, "case_insensitive_branches = true\n").unwrap();
+    check("feature/login")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("ignoring case_insensitive_branches in the repo config"));
+    std::fs::remove_file(&repo_config).unwrap();
+    state_cmd(&f)
+        .args(["config", "set", "case_insensitive_branches", "true"])
+        .assert()
+        .success();
+    check("feature/login").assert().success();
+    check("HOTFIX").assert().success();
+    check("MAIN").assert().failure().stderr(predicates::str::contains("default branch"));
+    assert_eq!(list_json(&f)["tracked"][&repo], serde_json::json!(["Feature/Login"]));
+}
+
 // ── Check: tracked branch is allowed ─────────────────────────────────────────
 
 #[test]
@@ -135,6 +236,676 @@ fn check_dry_run_does_not_block() {
         .success();
 }
 
+#[test]
+fn allowed_pushes_are_announced_only_when_configured() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature-x"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git push origin feature-x").assert().success().stderr("");
+
+    state_cmd(&f).args(["config", "set", "announce_allows", "true"]).assert().success();
+    hook("git push origin feature-x").assert().success().stderr(
+        "push-guard: allowing push to 'feature-x' on origin (tracked: branch created by Claude)\n",
+    );
+    // Blocked commands stay blocked, without allow notices
+    hook("git push origin feature-x && git push origin main")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("push-guard: allowing").not());
+}
+
+#[test]
+fn push_set_upstream_to_remote_resolves_current_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    let hook = |cwd: &Path, command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(cwd).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook(dir.path(), "git push -u origin")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'feature'"));
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    hook(dir.path(), "git push -u origin").assert().success();
+    hook(dir.path(), "git push --set-upstream origin").assert().success();
+
+    // Outside any repository there is no current branch to push
+    let outside = TempDir::new().unwrap();
+    hook(outside.path(), "git push -u origin")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("git push -u origin <branch>"));
+    // ...unless the command itself moves into one
+    hook(outside.path(), &format!("cd {} && git push -u origin", repo))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'feature'"));
+}
+
+#[test]
+fn inline_push_default_remote_is_honored() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    // upstream's default branch is "trunk", which origin does not protect
+    git(dir.path(), &["update-ref", "refs/remotes/upstream/trunk", "HEAD"]);
+    let head = "refs/remotes/upstream/HEAD";
+    git(dir.path(), &["symbolic-ref", head, "refs/remotes/upstream/trunk"]);
+    git(dir.path(), &["checkout", "-q", "-b", "trunk"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "trunk"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git push").assert().success();
+    hook("git -c remote.pushDefault=upstream push")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("default branch"))
+        .stderr(predicates::str::contains("upstream"));
+    // Settings that do not pick the destination change nothing
+    hook("git -c color.ui=always -c core.pager=cat push").assert().success();
+}
+
+#[test]
+fn inline_push_default_picks_the_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    // No upstream configured: the current branch, to origin
+    hook("git -c push.default=current push").assert().success();
+    hook("git -c push.default=current push --force")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'feature'"));
+
+    // The inline setting wins over the one on disk, which targets the upstream
+    // An origin with a fetch refspec but no URL, so @{u} resolves
+    git(dir.path(), &["config", "remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*"]);
+    state_cmd(&f).args(["config", "set", "allow_local_remotes", "false"]).assert().success();
+    git(dir.path(), &["config", "branch.feature.remote", "origin"]);
+    git(dir.path(), &["config", "branch.feature.merge", "refs/heads/main"]);
+    git(dir.path(), &["config", "push.default", "upstream"]);
+    hook("git push").assert().failure().stderr(predicates::str::contains("'main'"));
+    hook("git -c push.default=current push").assert().success();
+    hook("git -c 'push.default=current' push origin").assert().success();
+}
+
+#[test]
+fn all_mirror_and_prune_pushes_cover_every_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    state_cmd(&f).args(["config", "set", "allow_local_remotes", "false"]).assert().success();
+    state_cmd(&f).args(["config", "set", "offline", "true"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+
+    // Every local branch is pushed, whatever push.default says
+    for flag in ["--all", "--branches"] {
+        check(&format!("git -c push.default=current push {} origin", flag))
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("'main'"));
+    }
+    git(dir.path(), &["branch", "-q", "-D", "main"]);
+    check("git push --all origin").assert().success();
+    // The remote's main has no local counterpart left, so these delete it
+    check("git push --all --prune origin")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Deleting remote branch 'main'"));
+    check("git push --mirror origin")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push"));
+    // Without a wildcard refspec, --prune deletes nothing
+    check("git push --prune origin feature").assert().success();
+}
+
+#[test]
+fn just_created_branches_are_pushable_when_tracking_fails() {
+    let f = NamedTempFile::new().unwrap();
+    let tmp = TempDir::new().unwrap();
+    let dir = temp_repo();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        // Tracking writes fail; the pending note goes to a runtime dir of our own
+        c.env("PUSH_GUARD_STATE_READONLY", "1").env("XDG_RUNTIME_DIR", tmp.path());
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git checkout -b feature && git push -u origin feature").assert().success();
+    assert_eq!(std::fs::read_to_string(f.path()).unwrap(), "");
+    // A later tool call within the grace period
+    hook("git push origin feature").assert().success();
+    hook("git push origin other").assert().failure();
+    hook("git push origin main").assert().failure();
+
+    // Once the note is older than the grace period, tracking is needed again
+    let notes: Vec<_> = std::fs::read_dir(tmp.path()).unwrap().map(|e| e.unwrap().path()).collect();
+    assert_eq!(notes.len(), 1);
+    let mut note: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&notes[0]).unwrap()).unwrap();
+    let expired = chrono::Utc::now() - chrono::Duration::minutes(10);
+    note[0]["at"] = expired.to_rfc3339().into();
+    std::fs::write(&notes[0], note.to_string()).unwrap();
+    hook("git push origin feature").assert().failure();
+}
+
+#[cfg(unix)]
+#[test]
+fn pending_notes_never_follow_planted_links() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let victim = NamedTempFile::new().unwrap();
+    // Without a runtime directory, the note is kept next to the state file
+    let note = f.path().with_extension("pending.json");
+    std::os::unix::fs::symlink(victim.path(), &note).unwrap();
+    hook_cmd(&f, dir.path(), "git checkout -b feature")
+        .env("PUSH_GUARD_STATE_READONLY", "1")
+        .env_remove("XDG_RUNTIME_DIR")
+        .assert()
+        .success();
+    assert_eq!(std::fs::read_to_string(victim.path()).unwrap(), "");
+    assert!(!std::fs::symlink_metadata(&note).unwrap().file_type().is_symlink());
+    assert!(std::fs::read_to_string(&note).unwrap().contains("feature"));
+    std::fs::remove_file(&note).unwrap();
+}
+
+#[test]
+fn every_push_in_a_command_is_reported_before_blocking() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "safe"]).assert().success();
+    let command = "git push origin safe && git push origin main && git push origin other";
+
+    let out = state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload(command))
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let allowed = stderr.find("ALLOWED: push to 'safe'").expect(&stderr);
+    let main = stderr.find("'main' is the default branch").expect(&stderr);
+    let other = stderr.find("Branch 'other' was not created by me").expect(&stderr);
+    assert!(allowed < main && main < other, "{}", stderr);
+    assert!(stderr.contains("2 of 3 pushes in this command are blocked"), "{}", stderr);
+
+    // Nothing is printed when every push is allowed
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push origin safe"))
+        .assert()
+        .success()
+        .stderr("");
+
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--command", command])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("ALLOWED: push to 'safe'"))
+        .stderr(predicates::str::contains("'main' is the default branch"))
+        .stderr(predicates::str::contains("Branch 'other' was not created by me"));
+}
+
+#[test]
+fn mixed_deletions_and_updates_are_decided_per_refspec() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let command = "git push origin :old-feature new-feature dev:staging";
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    let authorize = |branch: &str| {
+        state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", branch]).assert().success();
+    };
+    authorize("new-feature");
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "staging"]).assert().success();
+
+    // Authorizing new-feature does not cover deleting old-feature
+    let out = check().output().unwrap();
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("ALLOWED: push to 'new-feature'"), "{}", stderr);
+    assert!(stderr.contains("ALLOWED: pushing local 'dev' to remote 'staging'"), "{}", stderr);
+    assert!(stderr.contains("Deleting remote branch 'old-feature'."), "{}", stderr);
+    assert!(stderr.contains("Branch 'old-feature' was not created by me"), "{}", stderr);
+    assert!(
+        stderr.contains(
+            "1 of 3 pushes in this command are blocked (deletions: 1 of 1, updates: 0 of 2)"
+        ),
+        "{}",
+        stderr
+    );
+
+    authorize("old-feature");
+    check().assert().success();
+    // The default branch can't be deleted alongside either
+    let command = "git push origin new-feature :refs/heads/main";
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--command", command])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Deleting remote branch 'main'."))
+        .stderr(predicates::str::contains("(deletions: 1 of 1, updates: 0 of 1)"));
+}
+
+#[test]
+fn auto_tracked_prefixes_count_as_created_by_claude() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    check("git push origin claude/foo").assert().failure();
+    state_cmd(&f)
+        .args(["config", "set", "auto_tracked_prefixes", "claude/, ai/"])
+        .assert()
+        .success();
+
+    // Allowed with empty state, and the trace names the prefix rule
+    check("git push origin claude/foo").assert().success();
+    check("git push origin ai/bar").assert().success();
+    let out = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--branch", "claude/foo", "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(json["rule"], "auto_tracked_prefix");
+    assert_eq!(json["reason"], "matched auto-tracked prefix");
+
+    check("git push origin main")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'main' is the default branch"));
+    check("git push origin feature").assert().failure();
+    // Force pushes and deletions keep their own rules
+    check("git push -f origin claude/foo").assert().failure();
+    check("git push origin :claude/foo").assert().failure();
+
+    // A protected pattern wins over the prefix
+    state_cmd(&f)
+        .args(["config", "set", "protected_branches", "claude/release-*"])
+        .assert()
+        .success();
+    check("git push origin claude/release-1").assert().failure();
+    check("git push origin claude/foo").assert().success();
+}
+
+#[test]
+fn renamed_pushes_state_source_and_destination() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    // The tracked source name does not make the destination tracked
+    hook("git push origin feature:other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Pushing local 'feature' to remote 'other'"));
+    hook("git push origin HEAD:other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Pushing local 'feature' to remote 'other'"));
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "other"]).assert().success();
+    hook("git push origin feature:other").assert().success();
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--command", "git push origin feature:other", "--dry-run"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("ALLOWED: pushing local 'feature' to remote 'other'"));
+
+    let out = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--branch", "other", "--source", "feature", "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(json["source"], "feature");
+    assert_eq!(json["branch"], "other");
+    state_cmd(&f)
+        .args(["why", "--repo", &repo, "--branch", "other", "--source", "feature"])
+        .assert()
+        .stdout(predicates::str::contains("pushing local 'feature' to remote 'other'"));
+
+    // Opt-in: renamed pushes need authorization even between tracked branches
+    state_cmd(&f)
+        .args(["config", "set", "renamed_push_requires_authorization", "true", "--repo", &repo])
+        .assert()
+        .success();
+    hook("git push origin feature:other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Pushing local 'feature' to remote 'other'"))
+        .stderr(predicates::str::contains("different name require authorization"));
+    hook("git push origin feature").assert().success();
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "other"]).assert().success();
+    hook("git push origin feature:other").assert().success();
+}
+
+#[test]
+fn no_verify_pushes_are_recorded_and_optionally_need_authorization() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    // Allowed by default, but the audit log says the hook was skipped
+    hook("git push origin HEAD --no-verify").assert().success();
+    hook("git push origin HEAD").assert().success();
+    let output = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    let no_verify: Vec<_> = events.iter().map(|e| e["no_verify"].clone()).collect();
+    // Newest first
+    assert_eq!(no_verify, [serde_json::Value::Null, serde_json::json!(true)]);
+    state_cmd(&f)
+        .args(["history"])
+        .assert()
+        .success()
+        .stdout(predicates::str::starts_with("just now  "))
+        .stdout(predicates::str::contains("origin/feature  (no-verify)"));
+
+    state_cmd(&f)
+        .args(["config", "set", "block_no_verify", "true", "--repo", &repo])
+        .assert()
+        .success();
+    hook("git push origin HEAD --no-verify")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("`--no-verify` skips the pre-push hook"));
+    hook("git push origin HEAD").assert().success();
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    hook("git push origin HEAD --no-verify").assert().success();
+}
+
+#[test]
+fn hook_keys_bare_repos_and_git_dirs_by_their_repository() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    let bare = TempDir::new().unwrap();
+    git(bare.path(), &["clone", "-q", "--bare", &repo, "."]);
+    // Its origin is a local path, whose pushes would be allowed outright
+    state_cmd(&f).args(["config", "set", "allow_local_remotes", "false"]).assert().success();
+    let hook = |cwd: &Path, command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(cwd).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    // A bare repo is keyed by its git directory, which `--repo` defaults to there
+    hook(bare.path(), "git push origin feature").assert().failure();
+    state_cmd(&f)
+        .current_dir(bare.path())
+        .args(["track", "--branch", "feature"])
+        .assert()
+        .success();
+    hook(bare.path(), "git push origin feature").assert().success();
+    let bare_key = std::fs::canonicalize(bare.path()).unwrap().to_str().unwrap().to_string();
+    assert!(list_json(&f)["tracked"].get(&bare_key).is_some());
+
+    // From inside a checkout's .git, the checkout's entries apply
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "work"]).assert().success();
+    hook(&dir.path().join(".git"), "git push origin work").assert().success();
+    hook(&dir.path().join(".git/refs"), "git push origin work").assert().success();
+}
+
+#[test]
+fn hook_outside_any_repository_tracks_nothing_and_matches_no_grants() {
+    let f = NamedTempFile::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(outside.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git switch -c feature && git push origin feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Branch 'feature' was not created by me"));
+    hook("git switch -c other").env("PUSH_GUARD_VERBOSE", "1").assert().success().stderr(
+        predicates::str::contains("not inside a git repository; branch creations are not tracked"),
+    );
+    let json = list_json(&f);
+    assert_eq!(json["tracked"], serde_json::json!({}), "{}", json);
+}
+
+#[test]
+fn unresolved_push_targets_follow_empty_branch_policy() {
+    let f = NamedTempFile::new().unwrap();
+    let outside = TempDir::new().unwrap();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(outside.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(outside.path()).args(["check", "--repo", REPO, "--command", command]);
+        c
+    };
+
+    hook("git push")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Could not determine the push target"));
+    check("git push origin x:")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Could not determine the push target"));
+    // Pushes of no branch at all are not caught
+    hook("git push --tags origin").assert().success();
+    hook("git push origin refs/tags/v1.0").assert().success();
+    hook("git push --dry-run origin some-branch").assert().success();
+
+    state_cmd(&f).args(["config", "set", "empty_branch", "allow"]).assert().success();
+    hook("git push").assert().success();
+    check("git push origin x:").assert().success();
+}
+
+#[test]
+fn check_interactive_without_terminal_keeps_blocking() {
+    let f = NamedTempFile::new().unwrap();
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"]);
+        c
+    };
+
+    // Answers on stdin are ignored: the prompt only reads the terminal
+    check()
+        .arg("--interactive")
+        .write_stdin("always\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Allow this push?").not());
+    state_cmd(&f)
+        .args(["config", "set", "interactive", "true"])
+        .assert()
+        .success();
+    check().write_stdin("y\n").assert().failure();
+    assert!(list_json(&f)["authorized"][REPO].is_null());
+}
+
+#[test]
+fn check_command_evaluates_every_push() {
+    let f = NamedTempFile::new().unwrap();
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", REPO, "--command", command]);
+        c
+    };
+
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    // Both pushes are reported even though the second one blocks
+    check("git push origin feature && git push --force origin feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("ALLOWED: push to 'feature'"))
+        .stderr(predicates::str::contains("Force push (--force)"));
+    check("git push origin feature").assert().success();
+
+    // Creations are only tracked when asked
+    check("git checkout -b fresh && git push -u origin fresh").assert().failure();
+    check("git checkout -b fresh && git push -u origin fresh")
+        .arg("--track-creations")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Tracking 'fresh'"));
+
+    check("git push origin feature")
+        .args(["--branch", "feature"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("cannot be used with"));
+}
+
+#[test]
+fn check_command_evaluates_pushes_inside_substitutions() {
+    let f = NamedTempFile::new().unwrap();
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", REPO, "--command", command]);
+        c
+    };
+
+    check("diff <(git push --dry-run origin main 2>&1) expected.txt").assert().success();
+    check("echo \"pushed: $(git push origin main 2>&1)\"")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Branch 'main' was not created by me"));
+    check("echo \"pushed: $(git push origin main 2>&1\"")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("No git push found"));
+}
+
+#[test]
+fn update_ref_creations_are_tracked_and_commit_pushes_to_main_are_blocked() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git update-ref refs/heads/feature HEAD && git push origin feature").assert().success();
+    assert_eq!(list_json(&f)["tracked"][&repo], serde_json::json!(["feature"]));
+    // Moving an existing branch does not claim it
+    hook("git update-ref refs/heads/main HEAD && git push origin main").assert().failure();
+    assert_eq!(list_json(&f)["tracked"][&repo], serde_json::json!(["feature"]));
+
+    // Pushing a commit rather than a branch still targets the destination
+    let out = std::process::Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    let sha = String::from_utf8(out.stdout).unwrap().trim().to_string();
+    for command in [
+        "git push origin $(git rev-parse HEAD):refs/heads/main".to_string(),
+        "git push origin `git rev-parse HEAD`:refs/heads/main".to_string(),
+        format!("git push origin {}:refs/heads/main", sha),
+        format!("git push origin {}:main", &sha[..7]),
+    ] {
+        state_cmd(&f)
+            .args(["check", "--repo", &repo, "--command", &command])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("default branch"));
+    }
+}
+
+#[test]
+fn default_branch_block_suggests_a_new_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "Fix login redirect"]);
+    git(dir.path(), &["branch", "claude/fix-login-redirect"]);
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+
+    check("main").assert().failure().stderr(predicates::str::contains(
+        "git checkout -b claude/fix-login-redirect-2 && \
+         git push -u origin claude/fix-login-redirect-2",
+    ));
+    state_cmd(&f)
+        .args(["config", "set", "branch_prefix", "me/"])
+        .assert()
+        .success();
+    check("main")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("git checkout -b me/fix-login-redirect &&"));
+
+    // Other blocks carry no suggestion
+    check("feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("git checkout -b").not());
+}
+
 // ── List: --json flag ─────────────────────────────────────────────────────────
 
 #[test]
@@ -180,6 +951,36 @@ fn clean_repo_removes_entries() {
         .failure();
 }
 
+#[test]
+fn meta_describes_the_binary_for_wrappers() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let output = state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["meta", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    assert_eq!(json["state_schema_version"], 1);
+    assert_eq!(json["state_file"], f.path().to_string_lossy().as_ref());
+    let repo_config = dir.path().join(".push-guard.toml");
+    assert_eq!(json["config_files"]["repo"], repo_config.to_string_lossy().as_ref());
+    let rules = json["rules"].as_array().unwrap();
+    for rule in ["default_branch", "force_push", "tracked", "authorized", "auto_tracked_prefix"] {
+        assert!(rules.contains(&serde_json::json!(rule)), "{}", rule);
+    }
+    assert_eq!(json["exit_codes"][1]["code"], 1);
+
+    state_cmd(&f).args(["meta", "--check-compat", "1"]).assert().success();
+    state_cmd(&f)
+        .args(["meta", "--check-compat", "2"])
+        .assert()
+        .code(3)
+        .stderr(predicates::str::contains("older than the required 2"));
+}
+
 // ── Clean: --stale removes nonexistent repos ──────────────────────────────────
 
 #[test]
@@ -205,3 +1006,4308 @@ fn clean_stale_removes_ghost_repos() {
         .assert()
         .failure();
 }
+
+#[test]
+fn clean_stale_verify_checks_repos_with_git() {
+    let f = NamedTempFile::new().unwrap();
+    let kept = temp_repo();
+    let kept_repo = repo_str(&kept);
+    // A repo deleted and recreated as a plain directory
+    let plain = TempDir::new().unwrap();
+    let plain_repo = repo_str(&plain);
+    // A repo whose checkout moved, leaving its path a subdirectory of another
+    let outer = temp_repo();
+    let inner = outer.path().join("moved");
+    let inner_repo = inner.to_string_lossy().into_owned();
+    for r in [&kept_repo, &plain_repo, &inner_repo] {
+        state_cmd(&f).args(["track", "--repo", r, "--branch", "feat"]).assert().success();
+    }
+    std::fs::create_dir(&inner).unwrap();
+
+    // The cheap check only looks at existence
+    state_cmd(&f)
+        .args(["clean", "--stale"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("No stale entries found."));
+
+    state_cmd(&f)
+        .args(["clean", "--stale", "--verify"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(format!(
+            "Removed stale repo: {} (is not a git repository)",
+            plain_repo
+        )))
+        .stderr(predicates::str::contains(format!(
+            "Removed stale repo: {} (is now inside the repository at '{}')",
+            inner_repo,
+            repo_str(&outer)
+        )));
+    let tracked = list_json(&f)["tracked"].clone();
+    assert_eq!(tracked.as_object().unwrap().keys().collect::<Vec<_>>(), [&kept_repo]);
+}
+
+// ── Clean: --merged removes merged tracked branches ───────────────────────────
+
+#[test]
+fn clean_merged_removes_only_merged_branches() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+
+    // "done" points at main's commit (merged); "wip" has an extra commit
+    git(dir.path(), &["branch", "done"]);
+    git(dir.path(), &["checkout", "-q", "-b", "wip"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "wip"]);
+
+    for branch in ["done", "wip"] {
+        state_cmd(&f)
+            .args(["track", "--repo", &repo, "--branch", branch])
+            .assert()
+            .success();
+    }
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "done"])
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(["clean", "--merged", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Removed merged branch 'done'"));
+
+    let output = state_cmd(&f)
+        .args(["list", "--repo", &repo, "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["tracked"], serde_json::json!(["wip"]));
+    // Authorized entries are untouched without --include-authorized
+    assert_eq!(json["authorized"], serde_json::json!(["done"]));
+}
+
+#[test]
+fn clean_merged_uses_the_push_remote() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    // The repo pushes to "upstream", whose default branch is "trunk"; origin's is gone
+    git(dir.path(), &["symbolic-ref", "--delete", "refs/remotes/origin/HEAD"]);
+    git(dir.path(), &["update-ref", "refs/remotes/upstream/trunk", "HEAD"]);
+    git(dir.path(), &["symbolic-ref", "refs/remotes/upstream/HEAD", "refs/remotes/upstream/trunk"]);
+    git(dir.path(), &["config", "remote.pushDefault", "upstream"]);
+    git(dir.path(), &["branch", "done"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "done"]).assert().success();
+
+    state_cmd(&f)
+        .args(["clean", "--merged", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Removed merged branch 'done'"));
+
+    git(dir.path(), &["config", "remote.pushDefault", "elsewhere"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "done"]).assert().success();
+    state_cmd(&f)
+        .args(["clean", "--merged", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("could not resolve elsewhere's default branch"));
+}
+
+// ── Clean: --gone removes entries for deleted local branches ──────────────────
+
+#[test]
+fn clean_gone_removes_only_deleted_branches() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+
+    git(dir.path(), &["branch", "kept"]);
+    git(dir.path(), &["branch", "deleted"]);
+    for branch in ["kept", "deleted"] {
+        state_cmd(&f)
+            .args(["track", "--repo", &repo, "--branch", branch])
+            .assert()
+            .success();
+    }
+    git(dir.path(), &["branch", "-D", "deleted"]);
+
+    // Dry run reports but keeps the entry
+    state_cmd(&f)
+        .args(["clean", "--gone", "--dry-run", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("- [claude]  deleted  gone: local branch deleted"));
+
+    state_cmd(&f)
+        .args(["clean", "--gone", "--repo", &repo])
+        .assert()
+        .success();
+
+    let output = state_cmd(&f)
+        .args(["list", "--repo", &repo, "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["tracked"], serde_json::json!(["kept"]));
+}
+
+// ── --repo resolution ─────────────────────────────────────────────────────────
+
+#[test]
+fn repo_dot_resolves_to_the_enclosing_repo_and_typos_suggest_stored_keys() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+
+    let output = state_cmd(&f)
+        .current_dir(dir.path().join("src"))
+        .args(["list", "--repo", ".", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["tracked"], serde_json::json!(["feature"]));
+
+    let typo = &repo[..repo.len() - 1];
+    for command in ["list", "clean"] {
+        state_cmd(&f)
+            .args([command, "--repo", typo])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains(format!("No entries for repo '{}'", typo)))
+            .stderr(predicates::str::contains(format!("did you mean:\n  {}", repo)));
+    }
+    // The failed clean removed nothing
+    state_cmd(&f)
+        .args(["list", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("feature"));
+}
+
+// ── Prune ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn prune_reports_and_removes_each_category() {
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let not_git = TempDir::new().unwrap();
+    let plain = repo_str(&not_git);
+    let ghost = "/definitely/does/not/exist/repo-for-prune";
+
+    // "done" is merged into main; "wip" has its own commit; "deleted" is gone
+    git(dir.path(), &["branch", "done"]);
+    git(dir.path(), &["checkout", "-q", "-b", "wip"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "wip"]);
+    let f = fixture_state(
+        &serde_json::json!({
+            "version": 1,
+            "tracked": {
+                repo.clone(): ["done", "wip", "deleted"],
+                ghost: ["feat"],
+                plain.clone(): ["kept"],
+            },
+            "authorized": {
+                repo.clone(): [{ "branch": "wip", "uses_left": 0 }, "deleted"],
+            },
+        })
+        .to_string(),
+    );
+
+    let output = state_cmd(&f).args(["prune", "--dry-run", "--json"]).output().unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["dry_run"], true);
+    assert_eq!(json["stale"], serde_json::json!([ghost]));
+    assert_eq!(json["exhausted"], serde_json::json!([{ "repo": repo, "branch": "wip" }]));
+    assert_eq!(json["gone"], serde_json::json!([{ "repo": repo, "branch": "deleted" }]));
+    assert_eq!(json["merged"], serde_json::json!([{ "repo": repo, "branch": "done" }]));
+    // A repo git cannot read is skipped, not an error
+    assert_eq!(json["skipped"][0]["repo"], plain.as_str());
+    assert_eq!(list_json(&f)["tracked"][ghost], serde_json::json!(["feat"]));
+
+    state_cmd(&f)
+        .arg("prune")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "Pruned: 1 stale repos, 1 exhausted grants, 1 gone branches, 1 merged branches",
+        ));
+    let state = list_json(&f);
+    assert!(state["tracked"].get(ghost).is_none());
+    assert_eq!(state["tracked"][&repo], serde_json::json!(["wip"]));
+    assert_eq!(state["tracked"][&plain], serde_json::json!(["kept"]));
+    assert!(state["authorized"].get(&repo).is_none());
+
+    state_cmd(&f)
+        .args(["prune", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Pruned: 0 stale repos, 0 exhausted grants"));
+}
+
+#[test]
+fn prune_covers_entries_in_the_repo_local_file() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let local_file = dir.path().join(".git").join("push-guard.json");
+    std::fs::write(
+        &local_file,
+        serde_json::json!({
+            "tracked": ["deleted", "main"],
+            "authorized": [{ "branch": "main", "uses_left": 0 }],
+        })
+        .to_string(),
+    )
+    .unwrap();
+
+    // Found from inside the repo, though the global file never mentions it
+    let output = state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["prune", "--dry-run", "--json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["exhausted"], serde_json::json!([{ "repo": repo, "branch": "main" }]));
+    assert_eq!(json["gone"], serde_json::json!([{ "repo": repo, "branch": "deleted" }]));
+
+    // --repo takes a path, like authorize does
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["prune", "--repo", "."])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("1 exhausted grants, 1 gone branches"));
+    let local = std::fs::read_to_string(&local_file).unwrap();
+    assert!(!local.contains("deleted"), "{}", local);
+    assert!(!local.contains("uses_left"), "{}", local);
+    assert!(local.contains("main"), "{}", local);
+}
+
+#[test]
+fn prune_dry_run_lists_each_removal_with_its_reason() {
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let ghost = "/definitely/does/not/exist/repo-for-prune";
+    git(dir.path(), &["branch", "done"]);
+    git(dir.path(), &["checkout", "-q", "-b", "wip"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "wip"]);
+    let f = fixture_state(
+        &serde_json::json!({
+            "version": 1,
+            "tracked": { repo.clone(): ["done", "wip", "deleted"], ghost: ["feat"] },
+            "authorized": {
+                repo.clone(): [
+                    { "branch": "wip", "uses_left": 0 },
+                    "deleted",
+                    { "branch": "idle", "created_at": "2024-01-01T00:00:00Z" },
+                ],
+            },
+        })
+        .to_string(),
+    );
+
+    let output = state_cmd(&f)
+        .args(["prune", "--dry-run", "--unused-for", "30d", "--color", "never"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap().replace(&repo, "<repo>");
+    // "idle" is unused, and also gone since it has no local branch
+    let created = stdout.lines().find(|l| l.contains("unused: created ")).unwrap().to_string();
+    assert!(created.ends_with(" ago, never used"), "{}", created);
+    assert_eq!(
+        stdout.replace(&created, "<unused>"),
+        "/definitely/does/not/exist/repo-for-prune\n\
+         \x20 - [repo]        (all entries)  stale: no longer exists\n\
+         <repo>\n\
+         \x20 - [authorized]  wip            exhausted: no uses left\n\
+         <unused>\n\
+         \x20 - [claude]      deleted        gone: local branch deleted\n\
+         \x20 - [authorized]  deleted        gone: local branch deleted\n\
+         \x20 - [claude]      done           merged: merged into the remote's default branch\n\
+         Would remove 6 entries from 2 repos (1 stale, 1 exhausted, 1 unused, 2 gone, 1 merged)\n"
+    );
+    assert_eq!(list_json(&f)["tracked"][ghost], serde_json::json!(["feat"]));
+
+    let output = state_cmd(&f).args(["prune", "--dry-run", "--json"]).output().unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let removals = json["removals"].as_array().unwrap();
+    let reasons: Vec<&str> = removals.iter().map(|r| r["reason"].as_str().unwrap()).collect();
+    assert_eq!(reasons, ["stale", "exhausted", "gone", "gone", "gone", "merged"]);
+    assert_eq!(json["removals"][1]["kind"], "authorized");
+    assert_eq!(json["removals"][1]["detail"], "no uses left");
+
+    state_cmd(&f)
+        .args(["prune", "--dry-run", "--color", "always"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("  \x1b[31m- \x1b[0m[repo]"));
+}
+
+// ── Export / import ───────────────────────────────────────────────────────────
+
+fn list_json(f: &NamedTempFile) -> serde_json::Value {
+    let output = state_cmd(f).args(["list", "--json"]).output().unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn export_import_round_trip() {
+    let src = NamedTempFile::new().unwrap();
+    let dst = NamedTempFile::new().unwrap();
+    let doc = NamedTempFile::new().unwrap();
+    let doc_path = doc.path().to_str().unwrap();
+
+    state_cmd(&src)
+        .args(["track", "--repo", REPO, "--branch", "feat"])
+        .assert()
+        .success();
+    state_cmd(&src)
+        .args(["authorize", "--repo", REPO, "--branch", "hotfix"])
+        .assert()
+        .success();
+
+    state_cmd(&src)
+        .args(["export", "-o", doc_path])
+        .assert()
+        .success();
+    let exported: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(doc.path()).unwrap()).unwrap();
+    assert_eq!(exported["format"], "push-guard-export");
+    assert_eq!(exported["version"], 1);
+
+    state_cmd(&dst)
+        .args(["import", doc_path, "--replace"])
+        .assert()
+        .success();
+    assert_eq!(list_json(&src), list_json(&dst));
+}
+
+#[test]
+fn import_single_repo_export_merges() {
+    let src = NamedTempFile::new().unwrap();
+    let dst = NamedTempFile::new().unwrap();
+    let doc = NamedTempFile::new().unwrap();
+    let doc_path = doc.path().to_str().unwrap();
+    let other = "/tmp/push-guard-test-other-repo";
+
+    state_cmd(&src)
+        .args(["track", "--repo", REPO, "--branch", "feat"])
+        .assert()
+        .success();
+    state_cmd(&src)
+        .args(["track", "--repo", other, "--branch", "not-exported"])
+        .assert()
+        .success();
+    state_cmd(&dst)
+        .args(["track", "--repo", REPO, "--branch", "local"])
+        .assert()
+        .success();
+
+    state_cmd(&src)
+        .args(["export", "--repo", REPO, "-o", doc_path])
+        .assert()
+        .success();
+    state_cmd(&dst)
+        .args(["import", doc_path, "--merge"])
+        .assert()
+        .success();
+
+    let json = list_json(&dst);
+    assert_eq!(json["tracked"][REPO], serde_json::json!(["feat", "local"]));
+    assert!(json["tracked"].get(other).is_none());
+}
+
+#[test]
+fn import_rejects_foreign_document() {
+    let f = NamedTempFile::new().unwrap();
+    let doc = NamedTempFile::new().unwrap();
+    std::fs::write(doc.path(), r#"{"format":"something-else","version":1,"exported_at":"2024-01-01T00:00:00Z"}"#)
+        .unwrap();
+    state_cmd(&f)
+        .args(["import", doc.path().to_str().unwrap()])
+        .assert()
+        .failure();
+}
+
+// ── Completions ───────────────────────────────────────────────────────────────
+
+#[test]
+fn completions_scripts_mention_subcommands() {
+    for shell in ["bash", "zsh", "fish", "powershell"] {
+        let output = cmd().args(["completions", shell]).output().unwrap();
+        assert!(output.status.success());
+        let script = String::from_utf8(output.stdout).unwrap();
+        assert!(!script.is_empty(), "{} script is empty", shell);
+        for sub in ["track", "authorize", "revoke", "check"] {
+            assert!(script.contains(sub), "{} script lacks {}", shell, sub);
+        }
+    }
+}
+
+#[test]
+fn dynamic_completion_offers_repos_and_branches_from_state() {
+    let f = NamedTempFile::new().unwrap();
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "feat-complete"])
+        .assert()
+        .success();
+
+    let output = state_cmd(&f)
+        .env("COMPLETE", "fish")
+        .args(["--", "push-guard", "authorize", "--repo", "/tmp/push-guard-test"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains(REPO));
+
+    let output = state_cmd(&f)
+        .env("COMPLETE", "fish")
+        .args(["--", "push-guard", "authorize", "--repo", REPO, "--branch", "feat"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&output.stdout).contains("feat-complete"));
+}
+
+// ── Auto-detection of repo / remote / branch ──────────────────────────────────
+
+#[test]
+fn track_and_check_without_flags_inside_repo() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let root = std::fs::canonicalize(dir.path()).unwrap();
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("track")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(format!(
+            "Tracking 'feature' in '{}'",
+            root.display()
+        )));
+
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("check")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Using remote 'origin'"));
+
+    // Explicit flags stay authoritative
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["check", "--branch", "other"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn track_without_repo_outside_git_fails_clearly() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["track", "--branch", "feature"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Not inside a git repository"));
+}
+
+#[test]
+fn track_all_local_and_stdin_import_in_bulk() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    for b in ["claude/a", "claude/b", "other"] {
+        git(dir.path(), &["branch", b]);
+    }
+    let tracked = || list_json(&f)["tracked"][&repo].clone();
+
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--all-local", "--pattern", "claude/*"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Tracked 2 new branch(es)"))
+        .stderr(predicates::str::contains("0 already tracked"));
+    assert_eq!(tracked(), serde_json::json!(["claude/a", "claude/b"]));
+    // The default branch is never tracked
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--all-local"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Tracked 1 new branch(es)"))
+        .stderr(predicates::str::contains("2 already tracked"));
+    assert_eq!(tracked(), serde_json::json!(["claude/a", "claude/b", "other"]));
+
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--stdin"])
+        .write_stdin("claude/a\n\n  imported  \nimported\n")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Tracked 1 new branch(es)"))
+        .stderr(predicates::str::contains("1 already tracked"));
+    assert!(tracked().as_array().unwrap().contains(&serde_json::json!("imported")));
+    // A bad line aborts the import without writing anything
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--stdin"])
+        .write_stdin("fresh\nbad name\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Line 2"));
+    assert!(!tracked().as_array().unwrap().contains(&serde_json::json!("fresh")));
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--stdin"])
+        .write_stdin("other\n\nhotfix\nother\n")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Authorized 2 new branch(es)"));
+    assert_eq!(list_json(&f)["authorized"][&repo], serde_json::json!(["hotfix", "other"]));
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--stdin", "--branch", "x"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn track_from_reflog_finds_branches_created_by_checkout() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature-a"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "a"]);
+    git(dir.path(), &["switch", "-q", "-c", "feature-b"]);
+    git(dir.path(), &["checkout", "-q", "main"]);
+    // Created without a checkout, or already on the remote: not claimed
+    git(dir.path(), &["branch", "quiet"]);
+    git(dir.path(), &["checkout", "-q", "-b", "shared"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/shared", "HEAD"]);
+    git(dir.path(), &["remote", "add", "origin", "https://example.com/acme/api.git"]);
+    git(dir.path(), &["checkout", "-q", "main"]);
+    let from_reflog = || {
+        let mut c = state_cmd(&f);
+        c.args(["track", "--repo", &repo, "--from-reflog"]);
+        c
+    };
+
+    from_reflog()
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Found 2 branch(es) created in"))
+        .stderr(predicates::str::contains("  feature-b  created just now\n"))
+        .stderr(predicates::str::contains("  feature-a  created just now\n"))
+        .stderr(predicates::str::contains("  shared  skipped: already on origin/shared"))
+        .stderr(predicates::str::contains("quiet").not())
+        .stderr(predicates::str::contains("Nothing changed; pass --yes to track them"));
+    assert_eq!(list_json(&f)["tracked"], serde_json::json!({}));
+
+    from_reflog()
+        .arg("--yes")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Tracked 2 new branch(es)"));
+    let tracked = list_json(&f)["tracked"][&repo].clone();
+    assert_eq!(tracked, serde_json::json!(["feature-a", "feature-b"]));
+    from_reflog()
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("No untracked branch creations"))
+        .stderr(predicates::str::contains("  feature-a  skipped: already tracked"));
+    from_reflog().args(["--since", "0m"]).assert().success().stderr(
+        predicates::str::contains("No untracked branch creations").and(
+            predicates::str::contains("skipped").not(),
+        ),
+    );
+}
+
+#[test]
+fn bulk_authorize_and_revoke_skip_default_and_protected_branches() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["config", "set", "protected_branches", "prod-*"]).assert().success();
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--stdin", "--yes"])
+        .write_stdin("feature
+main
+prod-eu
+wip
+")
+        .assert()
+        .success();
+    let authorized = || list_json(&f)["authorized"][&repo].clone();
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--all-tracked"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Authorized 2 new branch(es)"))
+        .stderr(predicates::str::contains(
+            "Skipped default or protected branch(es) main, prod-eu; \
+             pass --include-protected to authorize them too",
+        ));
+    assert_eq!(authorized(), serde_json::json!(["feature", "wip"]));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--all-tracked", "--include-protected"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Authorized 2 new branch(es)"));
+    assert_eq!(authorized(), serde_json::json!(["feature", "main", "prod-eu", "wip"]));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--all-tracked", "--branch", "x"])
+        .assert()
+        .failure();
+
+    // Dry runs only report
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--all", "--dry-run"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Would revoke 2 authorization(s) in '{}': feature, wip".replace("{}", &repo),
+        ))
+        .stderr(predicates::str::contains("main, prod-eu; pass --include-protected to revoke"));
+    assert_eq!(authorized(), serde_json::json!(["feature", "main", "prod-eu", "wip"]));
+
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--all"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Revoked 2 authorization(s)"));
+    assert_eq!(authorized(), serde_json::json!(["main", "prod-eu"]));
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--all", "--include-protected"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Revoked 2 authorization(s)"));
+    assert_eq!(authorized(), serde_json::Value::Null);
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--all"])
+        .assert()
+        .success()
+        .stderr("No authorizations to revoke in '{}'\n".replace("{}", &repo));
+    // Tracking is left alone
+    let tracked = list_json(&f)["tracked"][&repo].clone();
+    assert_eq!(tracked, serde_json::json!(["feature", "main", "prod-eu", "wip"]));
+    state_cmd(&f).args(["revoke", "--repo", &repo]).assert().failure();
+}
+
+#[test]
+fn authorize_and_revoke_matching_expand_the_glob_against_current_entries() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--stdin", "--yes"])
+        .write_stdin("hotfix/login\nhotfix/pay\nfeature\nhotfixes\n")
+        .assert()
+        .success();
+    let authorized = || list_json(&f)["authorized"][&repo].clone();
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--matching", "hotfix/*", "--dry-run"])
+        .assert()
+        .success()
+        .stderr(format!(
+            "Would authorize 2 tracked branch(es) in '{}': hotfix/login, hotfix/pay\n",
+            repo
+        ));
+    assert_eq!(authorized(), serde_json::Value::Null);
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--matching", "hotfix/*"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Authorized 2 new branch(es)"));
+    assert_eq!(authorized(), serde_json::json!(["hotfix/login", "hotfix/pay"]));
+    // Concrete branches are authorized, so a branch tracked later is not
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "hotfix/new"]).assert().success();
+    assert_eq!(authorized(), serde_json::json!(["hotfix/login", "hotfix/pay"]));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--matching", "release/*"])
+        .assert()
+        .success()
+        .stderr("No tracked branches to authorize in '{}'\n".replace("{}", &repo));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--matching", "[oops"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Invalid pattern '[oops'"));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--matching", "*", "--all-tracked"])
+        .assert()
+        .failure();
+
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--matching", "hotfix/*", "--dry-run"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "Would revoke 2 authorization(s) in '{}': hotfix/login, hotfix/pay"
+                .replace("{}", &repo),
+        ));
+    assert_eq!(authorized(), serde_json::json!(["feature", "hotfix/login", "hotfix/pay"]));
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--matching", "hotfix/*"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Revoked 2 authorization(s)"));
+    assert_eq!(authorized(), serde_json::json!(["feature"]));
+}
+
+#[test]
+fn authorize_uses_allows_a_limited_number_of_pushes() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "shared"]);
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", "shared"]);
+        c
+    };
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "shared", "--uses", "3"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("for 3 push(es)"));
+    // Dry runs don't count
+    check().arg("--dry-run").assert().success();
+    check().assert().success().stderr(predicates::str::contains("allows 2 more push(es)"));
+    assert!(list_stdout(&f, &["--repo", &repo]).contains("shared  2 use(s) left"));
+    assert_eq!(list_json(&f)["uses_left"][&repo]["shared"], 2);
+    // New commits don't void it: it isn't bound to a commit unless --sha is given
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "iterate"]);
+    check().assert().success().stderr(predicates::str::contains("allows 1 more push(es)"));
+    check().assert().success().stderr(predicates::str::contains("is used up"));
+    check()
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Branch 'shared' was not created by me"));
+
+    // A push blocked alongside doesn't use one up
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "shared", "--uses", "1"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push origin shared && git push origin main"))
+        .assert()
+        .failure();
+    check().assert().success().stderr(predicates::str::contains("is used up"));
+
+    // The uses left are confirmed as they are taken: two pushes can't share one
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "shared", "--uses", "1"])
+        .assert()
+        .success();
+    hook_cmd(&f, dir.path(), "git push origin shared && git push origin shared")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("has fewer pushes left than this command makes"));
+    check().assert().success().stderr(predicates::str::contains("is used up"));
+
+    // With --sha as well, whichever limit is reached first wins
+    let tip = String::from_utf8(
+        std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+            .stdout,
+    )
+    .unwrap();
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "shared", "--uses", "2", "--sha"])
+        .arg(tip.trim())
+        .assert()
+        .success();
+    check().assert().success();
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "more"]);
+    check().assert().failure().stderr(predicates::str::contains("has new commits"));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "shared", "--uses", "0"])
+        .assert()
+        .failure();
+}
+
+// ── List: filters and formats ─────────────────────────────────────────────────
+
+const LIST_FIXTURE: &str = r#"{
+  "tracked": {
+    "/repo/b": [{"branch": "zeta", "created_at": "2024-05-01T10:00:00Z"}],
+    "/repo/a": [
+      {"branch": "feat", "created_at": "2024-05-02T09:30:00Z"},
+      "legacy"
+    ]
+  },
+  "authorized": {
+    "/repo/a": [{"branch": "hotfix", "created_at": "2024-05-03T08:15:00Z"}]
+  }
+}"#;
+
+fn fixture_state(contents: &str) -> NamedTempFile {
+    let f = NamedTempFile::new().unwrap();
+    std::fs::write(f.path(), contents).unwrap();
+    f
+}
+
+/// Lists with full timestamps, which are fixed in the fixtures.
+fn list_stdout(f: &NamedTempFile, args: &[&str]) -> String {
+    let output = state_cmd(f).args(["list", "--absolute"]).args(args).output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn list_plain_is_sorted() {
+    let f = fixture_state(LIST_FIXTURE);
+    assert_eq!(
+        list_stdout(&f, &[]),
+        "[claude]      /repo/a  feat    2024-05-02 09:30\n\
+         [authorized]  /repo/a  hotfix  2024-05-03 08:15\n\
+         [claude]      /repo/a  legacy\n\
+         [claude]      /repo/b  zeta    2024-05-01 10:00\n"
+    );
+}
+
+#[test]
+fn list_plain_shortens_home_and_aligns_metadata() {
+    let f = fixture_state(
+        r#"{"tracked": {
+            "/home/dev/src/app": [
+                {"branch": "claude/fix-login", "from_remote": "origin/fix-login"}
+            ],
+            "/srv/tool": ["x"]
+        }}"#,
+    );
+    let output = state_cmd(&f).env("HOME", "/home/dev").arg("list").output().unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "[claude]      ~/src/app  claude/fix-login  from origin/fix-login\n\
+         [claude]      /srv/tool  x\n"
+    );
+    assert_eq!(
+        list_stdout(&f, &["--repo", "/srv/tool"]),
+        "[claude]      x\n"
+    );
+}
+
+#[test]
+fn state_file_is_identical_across_save_cycles() {
+    let f = fixture_state(
+        r#"{"tracked": {
+            "/repo/b": ["zeta", "alpha"],
+            "/repo/a": [{"branch": "mid", "created_at": "2024-05-02T09:30:00Z"}, "early"]
+        }, "authorized": {"/repo/a": ["y", "x"]}}"#,
+    );
+    let cycle = || {
+        state_cmd(&f).args(["track", "--repo", "/repo/a", "--branch", "tmp"]).assert().success();
+        state_cmd(&f).args(["untrack", "--repo", "/repo/a", "--branch", "tmp"]).assert().success();
+        std::fs::read_to_string(f.path()).unwrap()
+    };
+    let first = cycle();
+    assert_eq!(first, cycle());
+    let json: serde_json::Value = serde_json::from_str(&first).unwrap();
+    assert_eq!(json["tracked"]["/repo/b"], serde_json::json!(["alpha", "zeta"]));
+    assert_eq!(json["authorized"]["/repo/a"], serde_json::json!(["x", "y"]));
+    let repos: Vec<&String> = json["tracked"].as_object().unwrap().keys().collect();
+    assert_eq!(repos, ["/repo/a", "/repo/b"]);
+    assert!(first.find("\"/repo/a\"").unwrap() < first.find("\"/repo/b\"").unwrap());
+}
+
+#[test]
+fn state_namespaced_by_host_keeps_each_hosts_entries_apart() {
+    let f = fixture_state(
+        r#"{"tracked": {"/repo/a": ["flat"]},
+            "hosts": {"other": {"tracked": {"/gone/repo": ["theirs"]}}}}"#,
+    );
+    let config = f.path().with_extension("config.toml");
+    std::fs::write(&config, "state_namespace = \"hostname\"\n").unwrap();
+    let on = |host: &str| {
+        let mut c = state_cmd(&f);
+        c.env("HOSTNAME", host);
+        c
+    };
+    let read = || -> serde_json::Value {
+        serde_json::from_str(&std::fs::read_to_string(f.path()).unwrap()).unwrap()
+    };
+    let branches = |entries: &serde_json::Value| -> Vec<String> {
+        let entries = entries.as_array().unwrap().iter();
+        entries.map(|e| e.as_str().or(e["branch"].as_str()).unwrap().to_string()).collect()
+    };
+    let listed = |host: &str| -> serde_json::Value {
+        let output = on(host).args(["list", "--json"]).output().unwrap();
+        assert!(output.status.success());
+        serde_json::from_slice::<serde_json::Value>(&output.stdout).unwrap()["tracked"].clone()
+    };
+
+    // The flat entry moves into the namespace of the first host to save
+    on("alpha").args(["track", "--repo", "/repo/a", "--branch", "feat"]).assert().success();
+    let json = read();
+    assert_eq!(branches(&json["hosts"]["alpha"]["tracked"]["/repo/a"]), ["feat", "flat"]);
+    assert_eq!(json["hosts"]["other"]["tracked"]["/gone/repo"], serde_json::json!(["theirs"]));
+    assert_eq!(json["tracked"], serde_json::json!({}));
+
+    assert_eq!(listed("alpha"), serde_json::json!({"/repo/a": ["feat", "flat"]}));
+    assert_eq!(listed("other"), serde_json::json!({"/gone/repo": ["theirs"]}));
+    let all = String::from_utf8(
+        on("alpha").args(["list", "--all-hosts", "--tracked-only"]).output().unwrap().stdout,
+    )
+    .unwrap();
+    assert!(all.contains("alpha:/repo/a"), "{}", all);
+    assert!(all.contains("other:/gone/repo"), "{}", all);
+
+    // Both repos are missing on disk, but only this host's entries are cleaned
+    on("alpha").args(["clean", "--stale"]).assert().success();
+    let json = read();
+    assert!(json["hosts"].get("alpha").is_none());
+    assert_eq!(json["hosts"]["other"]["tracked"]["/gone/repo"], serde_json::json!(["theirs"]));
+    let _ = std::fs::remove_file(config);
+}
+
+#[test]
+fn list_table_aligns_columns() {
+    let f = fixture_state(LIST_FIXTURE);
+    assert_eq!(
+        list_stdout(&f, &["--format", "table"]),
+        "TAG         REPO     BRANCH  SCOPE   CREATED           USES  LAST USED\n\
+         claude      /repo/a  feat    global  2024-05-02 09:30  0\n\
+         authorized  /repo/a  hotfix  global  2024-05-03 08:15  0\n\
+         claude      /repo/a  legacy  global                    0\n\
+         claude      /repo/b  zeta    global  2024-05-01 10:00  0\n"
+    );
+}
+
+#[test]
+fn allowed_pushes_count_as_uses_of_their_entry() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "hotfix"]).assert().success();
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+
+    check("feature").assert().success();
+    check("feature").assert().success();
+    check("feature").arg("--dry-run").assert().success();
+    check("hotfix").arg("--dry-run").assert().success();
+    let usage = &list_json(&f)["usage"];
+    assert_eq!(usage["tracked"][&repo]["feature"]["use_count"], 2);
+    assert!(usage["tracked"][&repo]["feature"]["last_used_at"].is_string());
+    // Dry runs don't count
+    assert!(usage.get("authorized").is_none());
+
+    check("hotfix").assert().success();
+    let table = list_stdout(&f, &["--repo", &repo, "--format", "table"]);
+    let hotfix = table.lines().find(|l| l.contains("hotfix")).unwrap();
+    assert!(hotfix.contains("  1  "), "{}", table);
+
+
+    // Grants used recently are kept, whenever they were created
+    git(dir.path(), &["branch", "idle"]);
+    git(dir.path(), &["branch", "busy"]);
+    let recent = chrono::Utc::now().to_rfc3339();
+    let old = "2024-01-01T00:00:00Z";
+    let f = fixture_state(
+        &serde_json::json!({
+            "version": 1,
+            "tracked": {},
+            "authorized": {
+                repo.clone(): [
+                    { "branch": "idle", "created_at": old },
+                    { "branch": "busy", "created_at": old, "last_used_at": recent, "use_count": 9 },
+                ],
+            },
+        })
+        .to_string(),
+    );
+    state_cmd(&f)
+        .args(["prune", "--repo", &repo, "--unused-for", "30d"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Removed unused grant 'idle'"))
+        .stdout(predicates::str::contains(", 1 unused grants"));
+    assert_eq!(list_json(&f)["authorized"][&repo], serde_json::json!(["busy"]));
+}
+
+#[test]
+fn list_csv_output() {
+    let f = fixture_state(LIST_FIXTURE);
+    assert_eq!(
+        list_stdout(&f, &["--format", "csv", "--repo", "/repo/a"]),
+        "kind,repo,branch,created_at,scope\n\
+         claude,/repo/a,feat,2024-05-02T09:30:00+00:00,global\n\
+         authorized,/repo/a,hotfix,2024-05-03T08:15:00+00:00,global\n\
+         claude,/repo/a,legacy,,global\n"
+    );
+}
+
+#[test]
+fn list_json_is_sorted_and_filtered() {
+    let f = fixture_state(LIST_FIXTURE);
+    assert_eq!(
+        list_stdout(&f, &["--format", "json", "--tracked-only"]),
+        r#"{
+  "tracked": {
+    "/repo/a": [
+      "feat",
+      "legacy"
+    ],
+    "/repo/b": [
+      "zeta"
+    ]
+  }
+}
+"#
+    );
+}
+
+#[test]
+fn list_authorized_only_and_empty_message() {
+    let f = fixture_state(LIST_FIXTURE);
+    assert_eq!(
+        list_stdout(&f, &["--authorized-only", "--repo", "/repo/a"]),
+        "[authorized]  hotfix  2024-05-03 08:15\n"
+    );
+
+    let empty = NamedTempFile::new().unwrap();
+    state_cmd(&empty)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout("")
+        .stderr(predicates::str::contains("No entries."));
+}
+
+// ── Stats ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn stats_counts_decisions_per_rule() {
+    let f = NamedTempFile::new().unwrap();
+
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    for _ in 0..2 {
+        state_cmd(&f)
+            .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"])
+            .assert()
+            .success();
+    }
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "other"])
+        .assert()
+        .failure();
+    // Dry runs are not counted
+    state_cmd(&f)
+        .args(["check", "--dry-run", "--repo", REPO, "--remote", "origin", "--branch", "other"])
+        .assert()
+        .success();
+
+    let output = state_cmd(&f).args(["stats", "--json"]).output().unwrap();
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["allowed"], 2);
+    assert_eq!(json["blocked"], 1);
+    assert_eq!(json["rules"]["tracked"], 2);
+    assert_eq!(json["rules"]["untracked"], 1);
+    assert!(json["last_block"].is_string());
+
+    state_cmd(&f)
+        .arg("stats")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("3 checks: 2 allowed, 1 blocked"));
+}
+
+// ── History ───────────────────────────────────────────────────────────────────
+
+fn history_json(f: &NamedTempFile, args: &[&str]) -> Vec<serde_json::Value> {
+    let output = state_cmd(f)
+        .args(["history", "--json"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn history_lists_decisions_newest_first() {
+    let f = NamedTempFile::new().unwrap();
+
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "stray"])
+        .assert()
+        .failure();
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature", "--force"])
+        .assert()
+        .failure();
+
+    let events = history_json(&f, &[]);
+    let rules: Vec<&str> = events.iter().map(|e| e["rule"].as_str().unwrap()).collect();
+    assert_eq!(rules, vec!["force_push", "untracked", "tracked"]);
+    assert_eq!(events[0]["force"], true);
+    assert_eq!(events[2]["decision"], "allow");
+
+    let blocked = history_json(&f, &["--blocked-only", "--limit", "1"]);
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0]["rule"], "force_push");
+}
+
+#[test]
+fn hook_records_raw_command_unless_disabled() {
+    let f = NamedTempFile::new().unwrap();
+    let payload = r#"{"tool_input":{"command":"git push origin stray-branch"}}"#;
+
+    state_cmd(&f).arg("hook").write_stdin(payload).assert().failure();
+    let events = history_json(&f, &[]);
+    assert_eq!(events[0]["command"], "git push origin stray-branch");
+
+    state_cmd(&f)
+        .env("PUSH_GUARD_AUDIT_COMMANDS", "0")
+        .arg("hook")
+        .write_stdin(payload)
+        .assert()
+        .failure();
+    let events = history_json(&f, &[]);
+    assert!(events[0].get("command").is_none());
+    assert_eq!(events[0]["rule"], "untracked");
+}
+
+// ── Replay ────────────────────────────────────────────────────────────────────
+
+#[test]
+fn replay_reports_changed_decision() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let root = std::fs::canonicalize(dir.path()).unwrap();
+    let root = root.to_str().unwrap();
+
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(r#"{"tool_input":{"command":"git push origin feature-x"}}"#)
+        .assert()
+        .failure();
+
+    // Unchanged while state is the same
+    state_cmd(&f)
+        .args(["replay", "--last"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("Decision unchanged."));
+
+    state_cmd(&f)
+        .args(["track", "--repo", root, "--branch", "feature-x"])
+        .assert()
+        .success();
+
+    let state_before = std::fs::read_to_string(f.path()).unwrap();
+    state_cmd(&f)
+        .args(["replay", "--last"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("recorded: BLOCKED  untracked"))
+        .stdout(predicates::str::contains("replayed: ALLOWED  tracked"))
+        .stdout(predicates::str::contains("Decision changed."));
+
+    // Replay never mutates state or appends events
+    assert_eq!(std::fs::read_to_string(f.path()).unwrap(), state_before);
+    let output = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(events.len(), 1);
+}
+
+// ── Default branch precedence ─────────────────────────────────────────────────
+
+#[test]
+fn default_branch_entries_need_confirmation_and_allow_default_overrides() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = ["check", "--repo", &repo, "--remote", "origin", "--branch", "main"];
+
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--branch", "main"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Warning: 'main' is the default branch of 'origin'"))
+        .stderr(predicates::str::contains("pass --yes to track anyway"));
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "main"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("authorize with --allow-default to override it"));
+    assert_eq!(list_json(&f)["authorized"], serde_json::json!({}));
+
+    // Confirmed, the authorization is stored but the default-branch rule still wins
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "main", "--yes"])
+        .assert()
+        .success();
+    state_cmd(&f).args(check).assert().failure().stderr(predicates::str::contains(
+        "authorization exists but the default-branch rule takes precedence; \
+         authorize with --allow-default to override",
+    ));
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "main", "--allow-default"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("overriding the default-branch rule"));
+    state_cmd(&f).args(check).assert().success();
+    state_cmd(&f)
+        .args(["list", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("allows the default branch"));
+}
+
+// ── Protected branch patterns ─────────────────────────────────────────────────
+
+#[test]
+fn protected_pattern_blocks_tracked_branch_until_authorized() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    let repo = repo_str(&dir);
+    let check = ["check", "--repo", &repo, "--remote", "origin", "--branch", "release/1.0"];
+
+    state_cmd(&f)
+        .args(["protect", "--repo", &repo, "--pattern", "release/*"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["track", "--repo", &repo, "--branch", "release/1.0", "--yes"])
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(check)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("matches protected branch pattern 'release/*'"));
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "release/1.0"])
+        .assert()
+        .success();
+    state_cmd(&f).args(check).assert().success();
+
+    // Removing the pattern restores the normal tracked rule
+    state_cmd(&f)
+        .args(["revoke", "--repo", &repo, "--branch", "release/1.0"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["unprotect", "--repo", &repo, "--pattern", "release/*"])
+        .assert()
+        .success();
+    state_cmd(&f).args(check).assert().success();
+}
+
+#[test]
+fn protected_pattern_from_user_config() {
+    let f = NamedTempFile::new().unwrap();
+    std::fs::write(
+        f.path().with_extension("config.toml"),
+        "protected_branches = [\"prod-*\"]\n",
+    )
+    .unwrap();
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "prod-eu"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "prod-eu"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn new_branch_publish_is_allowed_only_when_enabled() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/existing", "HEAD"]);
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+    let why = |branch: &str| {
+        let out = state_cmd(&f)
+            .args(["why", "--repo", &repo, "--remote", "origin", "--branch", branch, "--json"])
+            .output()
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&out.stdout).unwrap()
+    };
+
+    // Off by default: untracked branches are blocked either way
+    check("fresh").assert().failure();
+    check("existing").assert().failure();
+    assert_eq!(why("fresh")["target"], "new remote branch");
+    assert_eq!(why("existing")["target"], "existing remote branch");
+
+    state_cmd(&f)
+        .args(["config", "set", "allow_new_branch_publish", "true"])
+        .assert()
+        .success();
+    check("fresh").assert().success();
+    assert_eq!(why("fresh")["rule"], "new_branch_publish");
+    check("existing")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Branch 'existing' was not created by me"));
+    assert_eq!(why("existing")["rule"], "untracked");
+    // Force pushes and the default branch keep the full policy
+    check("fresh").arg("--force").assert().failure();
+    check("main").assert().failure();
+    state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "origin", "--branch", "existing"])
+        .assert()
+        .stdout(predicates::str::contains("target: existing remote branch"));
+}
+
+#[test]
+fn pushes_are_blocked_during_an_unfinished_rebase_or_merge() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["branch", "feature"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", "feature"]);
+        c
+    };
+    check().assert().success();
+
+    std::fs::create_dir(dir.path().join(".git/rebase-merge")).unwrap();
+    check()
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("has an unfinished rebase in progress"))
+        .stderr(predicates::str::contains("git rebase --continue"));
+    std::fs::remove_dir(dir.path().join(".git/rebase-merge")).unwrap();
+    std::fs::write(dir.path().join(".git/MERGE_HEAD"), "").unwrap();
+    check().assert().failure().stderr(predicates::str::contains("git merge --abort"));
+
+    // Authorization overrides the rule, and so does turning it off
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    check().assert().success();
+    state_cmd(&f).args(["revoke", "--repo", &repo, "--branch", "feature"]).assert().success();
+    check().assert().failure();
+    state_cmd(&f)
+        .args(["config", "set", "block_during_rebase_or_merge", "false"])
+        .assert()
+        .success();
+    check().assert().success();
+}
+
+#[test]
+fn recursive_submodule_pushes_require_authorization() {
+    let f = NamedTempFile::new().unwrap();
+    let upstream = temp_repo();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let add = ["-c", "protocol.file.allow=always", "submodule", "add", "-q"];
+    git(dir.path(), &[&add[..], &[upstream.path().to_str().unwrap(), "lib"]].concat());
+    git(dir.path(), &["commit", "-q", "-m", "add lib"]);
+    git(&dir.path().join("lib"), &["commit", "-q", "--allow-empty", "-m", "local only"]);
+    git(dir.path(), &["branch", "feature"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let check = |flag: &str| {
+        let command = format!("git push {} origin feature", flag);
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", &command]);
+        c
+    };
+
+    check("--recurse-submodules=check").assert().success();
+    check("--recurse-submodules=no").assert().success();
+    check("--recurse-submodules=on-demand")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("can't be vetted individually"))
+        .stderr(predicates::str::contains("Submodules with unpushed commits: lib\n"));
+    check("--recurse-submodules only").assert().failure();
+
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    check("--recurse-submodules=on-demand").assert().success();
+}
+
+#[test]
+fn message_templates_from_config_are_used() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    std::fs::write(
+        dir.path().join(".push-guard.toml"),
+        "[message]\n\
+         untracked = \"'{branch}' is not yours. Run `devtool authorize {repo} {branch}`.\"\n\
+         default_branch = \"No direct pushes to {default_branch} on {remote}.\"\n",
+    )
+    .unwrap();
+
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "other"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!(
+            "'other' is not yours. Run `devtool authorize {} other`.",
+            repo
+        )));
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "main"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("No direct pushes to main on origin."));
+    // Messages without an override keep the built-in text
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--branch", "other", "--force"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push (--force) to 'other'"));
+    state_cmd(&f)
+        .args(["config", "show", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("devtool authorize {repo} {branch}"))
+        .stdout(predicates::str::contains("Force push ({flag}) to '{branch}'"));
+
+    state_cmd(&f)
+        .args(["config", "set", "message.force", "Forcing {brnach}", "--repo", &repo])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("unknown placeholder '{brnach}'"));
+    std::fs::write(dir.path().join(".push-guard.toml"), "[message]\ndenied = \"{oops}\"\n")
+        .unwrap();
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--branch", "other"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("message.denied uses unknown placeholder '{oops}'"));
+}
+
+#[test]
+fn config_set_get_unset_round_trip_across_layers() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let get = |key: &str, repo: Option<&str>| -> String {
+        let mut c = state_cmd(&f);
+        c.args(["config", "get", key]);
+        if let Some(r) = repo {
+            c.args(["--repo", r]);
+        }
+        String::from_utf8(c.output().unwrap().stdout).unwrap()
+    };
+
+    state_cmd(&f)
+        .args(["config", "set", "protected_branches", "main, release/*"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["config", "set", "protected_branches", "prod", "--repo", &repo])
+        .assert()
+        .success();
+    state_cmd(&f).args(["config", "set", "verbose", "on"]).assert().success();
+    state_cmd(&f)
+        .args(["config", "set", "verbose", "no", "--repo", &repo])
+        .assert()
+        .success();
+    assert_eq!(get("protected_branches", None), "[\"main\", \"release/*\"]\n");
+    assert_eq!(get("protected_branches", Some(&repo)), "[\"main\", \"release/*\", \"prod\"]\n");
+    assert_eq!(get("verbose", Some(&repo)), "false\n");
+
+    // Unsetting the repo's value lets the user's apply again
+    state_cmd(&f)
+        .args(["config", "unset", "verbose", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Unset verbose in"));
+    assert_eq!(get("verbose", Some(&repo)), "true\n");
+    state_cmd(&f)
+        .args(["config", "unset", "verbose", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("'verbose' is not set in"));
+
+    state_cmd(&f)
+        .args(["config", "set", "verbos", "true"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown config key 'verbos'; did you mean 'verbose'?"));
+    state_cmd(&f)
+        .args(["config", "unset", "protected", "--repo", &repo])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("did you mean 'protected_branches'?"));
+    state_cmd(&f)
+        .args(["config", "set", "verbose", "sometimes"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'verbose' expects true or false"));
+}
+
+#[test]
+fn org_policy_file_is_layered_beneath_user_and_repo_config() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let org = TempDir::new().unwrap();
+    let policy = org.path().join("policy.toml");
+    std::fs::write(
+        &policy,
+        "protected_branches = [\"release/*\"]\nblock_no_verify = true\nverbose = true\n",
+    )
+    .unwrap();
+    std::fs::write(f.path().with_extension("config.toml"), "block_no_verify = false\n").unwrap();
+    std::fs::write(dir.path().join(".push-guard.toml"), "verbose = false\n").unwrap();
+    let with_policy = |path: &Path| {
+        let mut c = state_cmd(&f);
+        c.env("PUSH_GUARD_POLICY_FILE", path);
+        c
+    };
+    // The policy protects the branch even where Claude would be trusted with it
+    with_policy(&policy)
+        .args(["track", "--repo", &repo, "--branch", "release/1.0", "--yes"])
+        .assert()
+        .success();
+    let check = ["check", "--repo", &repo, "--remote", "origin", "--branch", "release/1.0"];
+
+    with_policy(&policy)
+        .args(check)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("matches protected branch pattern 'release/*'"));
+    with_policy(&policy)
+        .args(["config", "show", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("block_no_verify = false\nverbose = false\n"))
+        .stdout(predicates::str::contains(format!(
+            "# Org policy: {} (from PUSH_GUARD_POLICY_FILE)",
+            policy.display()
+        )))
+        .stdout(predicates::str::contains(
+            "#   block_no_verify: org policy, user\n\
+             #   message.default_branch: built-in default\n",
+        ))
+        .stdout(predicates::str::contains(
+            "#   protected_branches: org policy\n\
+             #   verbose: org policy, repo\n",
+        ));
+
+    // A missing or broken policy warns once and is ignored
+    let output = with_policy(&org.path().join("missing.toml")).args(check).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(stderr.matches("missing.toml (PUSH_GUARD_POLICY_FILE) does not exist").count(), 1);
+    std::fs::write(&policy, "protected_branches = \"release/*\"\n").unwrap();
+    with_policy(&policy)
+        .args(check)
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(
+            "warning: ignoring the org policy and using the built-in defaults instead: \
+             Invalid config in",
+        ));
+}
+
+#[test]
+fn profiles_switch_policy_for_the_same_check() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    std::fs::write(
+        f.path().with_extension("config.toml"),
+        "branch_prefix = \"me/\"\n\
+         [profiles.strict]\nblock_no_verify = true\nallow_local_remotes = false\n\
+         [profiles.demo]\nwarn_only = true\n",
+    )
+    .unwrap();
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", "feature"]);
+        c
+    };
+
+    state_cmd(&f)
+        .args(["config", "use-profile", "strict"])
+        .assert()
+        .success()
+        .stderr("Active profile is now 'strict', setting allow_local_remotes, block_no_verify\n");
+    check().assert().failure().stderr(predicates::str::contains("BLOCKED"));
+    state_cmd(&f)
+        .args(["config", "show"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("block_no_verify = true"))
+        .stdout(predicates::str::contains("branch_prefix = \"me/\""))
+        .stdout(predicates::str::contains(
+            "# Active profile: strict (from active_profile)\n\
+             # Set by the profile: allow_local_remotes, block_no_verify\n",
+        ))
+        .stdout(predicates::str::contains("[profiles").not());
+
+    state_cmd(&f).args(["config", "use-profile", "demo"]).assert().success();
+    check()
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("WARNING (warn_only): Branch 'feature'"))
+        .stderr(predicates::str::contains("warn_only is set, so the command is allowed anyway"));
+    // The environment picks a profile for one invocation
+    check().env("PUSH_GUARD_PROFILE", "strict").assert().failure();
+    state_cmd(&f)
+        .args(["config", "show"])
+        .env("PUSH_GUARD_PROFILE", "strict")
+        .assert()
+        .stdout(predicates::str::contains("# Active profile: strict (from PUSH_GUARD_PROFILE)"));
+    check()
+        .env("PUSH_GUARD_PROFILE", "prod")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown profile 'prod'; defined profiles: demo, strict"))
+        .stderr(predicates::str::contains("Invalid config in"));
+    state_cmd(&f)
+        .args(["config", "use-profile", "prod"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown profile 'prod'"));
+    state_cmd(&f).args(["config", "unset", "active_profile"]).assert().success();
+    check().assert().failure();
+}
+
+// ── Disabled repos and trusted directories ────────────────────────────────────
+
+#[test]
+fn trusted_dir_skips_evaluation_only_inside_prefix() {
+    let f = NamedTempFile::new().unwrap();
+    let root = TempDir::new().unwrap();
+    let trusted = root.path().join("scratch");
+    let inside = trusted.join("clone");
+    let outside = root.path().join("scratch-other");
+    for d in [&inside, &outside] {
+        std::fs::create_dir_all(d).unwrap();
+    }
+
+    state_cmd(&f)
+        .args(["config", "set", "trusted_dirs", trusted.to_str().unwrap()])
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(["check", "--repo", inside.to_str().unwrap(), "--remote", "origin", "--branch", "x"])
+        .env("PUSH_GUARD_VERBOSE", "1")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("inside trusted directory"));
+    state_cmd(&f)
+        .args(["check", "--repo", outside.to_str().unwrap(), "--remote", "origin", "--branch", "x"])
+        .assert()
+        .failure();
+
+    // Force pushes stay blocked when the knob is on
+    state_cmd(&f)
+        .args(["config", "set", "force_rule_in_trusted_dirs", "true"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args([
+            "check", "--repo", inside.to_str().unwrap(), "--remote", "origin", "--branch", "x",
+            "--force",
+        ])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn disabled_repo_is_skipped_and_audited() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = TempDir::new().unwrap();
+    let repo = repo_str(&dir);
+
+    state_cmd(&f)
+        .args(["config", "set", "enabled", "false", "--repo", &repo])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["config", "get", "enabled", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout("false\n");
+
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "anything"])
+        .assert()
+        .success();
+
+    let output = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(events[0]["decision"], "skipped");
+    assert_eq!(events[0]["rule"], "disabled");
+
+    // The switch lives in the user config, out of the repo's reach
+    assert!(!dir.path().join(".push-guard.toml").exists());
+    let user = std::fs::read_to_string(f.path().with_extension("config.toml")).unwrap();
+    assert!(user.contains("disabled_repos"), "{}", user);
+    state_cmd(&f)
+        .args(["config", "unset", "enabled", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Enabled push-guard for"));
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "anything"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn repo_config_can_only_tighten_the_policy() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let repo_config = dir.path().join(".push-guard.toml");
+    std::fs::write(
+        &repo_config,
+        "enabled = false\nwarn_only = true\ntrusted_dirs = [\"/\"]\n\
+         auto_tracked_prefixes = [\"\"]\nprotected_branches = [\"prod\"]\n",
+    )
+    .unwrap();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "prod", "--yes"]).assert().success();
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+    check("feature").assert().failure();
+    check("prod").assert().failure().stderr(predicates::str::contains("prod"));
+    let output = state_cmd(&f).args(["config", "show", "--repo", &repo]).output().unwrap();
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(!shown.contains("warn_only"), "{}", shown);
+    for (key, value) in [("warn_only", "true"), ("trusted_dirs", "/"), ("empty_branch", "allow")] {
+        state_cmd(&f)
+            .args(["config", "set", key, value, "--repo", &repo])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("would loosen the policy"));
+    }
+    state_cmd(&f)
+        .args(["config", "set", "warn_only", "false", "--repo", &repo])
+        .assert()
+        .success();
+
+    // A repo config that can't be read is ignored, never a reason to let a push through
+    std::fs::write(&repo_config, "enabled = \"nope\n").unwrap();
+    check("feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("warning: ignoring the repo config"));
+}
+
+// ── Environment overrides ─────────────────────────────────────────────────────
+
+#[test]
+fn env_disable_skips_evaluation_loudly() {
+    let f = NamedTempFile::new().unwrap();
+    state_cmd(&f)
+        .env("PUSH_GUARD_DISABLE", "1")
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "main", "--force"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("WARNING: PUSH_GUARD_DISABLE=1 is set"));
+    state_cmd(&f)
+        .env("PUSH_GUARD_DISABLE", "0")
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "main"])
+        .assert()
+        .failure();
+
+    let output = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: Vec<serde_json::Value> = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(events[1]["decision"], "skipped");
+    assert_eq!(events[1]["rule"], "env_disabled");
+}
+
+#[test]
+fn env_allow_pre_authorizes_matching_pushes() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    // Repo paths are canonicalized, branch parts may be globs
+    let allow = format!("/elsewhere:feature,{}/.:release/*,{}:main", repo, repo);
+    let check = |branch: &str, force: bool| {
+        let mut c = state_cmd(&f);
+        c.env("PUSH_GUARD_ALLOW", &allow)
+            .args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        if force {
+            c.arg("--force");
+        }
+        c
+    };
+
+    check("release/1.0", false).assert().success();
+    check("feature", false).assert().failure();
+    // Like an authorization, it lifts protected patterns but not the
+    // default-branch rule
+    state_cmd(&f)
+        .args(["config", "set", "protected_branches", "release/*"])
+        .assert()
+        .success();
+    check("release/1.0", false).assert().success();
+    check("main", false)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("default branch"));
+
+    // Force pushes stay blocked, unless the config lets it lift the force rule
+    check("release/1.0", true)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push"));
+    hook_cmd(&f, dir.path(), "git push -f origin main")
+        .env("PUSH_GUARD_ALLOW", &allow)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push"));
+    check("release/1.0", true).env("PUSH_GUARD_DISABLE", "1").assert().success();
+    state_cmd(&f)
+        .args(["config", "set", "force_rule_ignores_env_allow", "false"])
+        .assert()
+        .success();
+    check("release/1.0", true).assert().success();
+}
+
+// ── Trusted remote URLs ───────────────────────────────────────────────────────
+
+#[test]
+fn block_messages_and_why_name_the_remote_project() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "origin", "git@github.com:acme/api.git"]);
+    git(dir.path(), &["remote", "add", "mirror", "https://gitlab.example.com/ops/mirrors/api"]);
+
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "main"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Destination: origin → github.com/acme/api\n"));
+    state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "mirror", "--branch", "feature"])
+        .assert()
+        .stdout(predicates::str::contains(
+            "remote: mirror → gitlab.example.com/ops/mirrors/api (https://",
+        ));
+    let out = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "origin", "--branch", "main", "--json"])
+        .output()
+        .unwrap();
+    let why: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(why["destination"], "github.com/acme/api");
+    let out = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(events[0]["destination"], "github.com/acme/api");
+}
+
+#[test]
+fn protected_branches_can_differ_per_remote() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "upstream", "git@github.com:acme/api.git"]);
+    git(dir.path(), &["remote", "add", "fork", "git@github.com:me/api.git"]);
+    std::fs::write(
+        dir.path().join(".push-guard.toml"),
+        "[remotes.upstream]\nprotected = [\"release/*\"]\n\
+         [remotes.\"github.com[:/]acme/*\"]\nprotected = [\"stable\"]\n",
+    )
+    .unwrap();
+    for branch in ["release/1", "stable"] {
+        let track = ["track", "--repo", &repo, "--branch", branch, "--yes"];
+        state_cmd(&f).args(track).assert().success();
+    }
+    let check = |remote: &str, branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", remote, "--branch", branch]);
+        c
+    };
+
+    check("fork", "release/1").assert().success();
+    check("fork", "stable").assert().success();
+    check("upstream", "release/1")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("matches protected branch pattern 'release/*'"));
+    check("upstream", "stable")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("matches protected branch pattern 'stable'"));
+
+    state_cmd(&f)
+        .args(["config", "show", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("[remotes.upstream]"))
+        .stdout(predicates::str::contains("#   fork = []\n"))
+        .stdout(predicates::str::contains("#   upstream = [\"stable\", \"release/*\"]\n"));
+}
+
+#[test]
+fn allow_remote_grants_every_branch_of_one_remote() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "backup", "ssh://nas/backups/tool.git"]);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    state_cmd(&f)
+        .args(["allow-remote", "--repo", &repo, "--remote", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("No remote 'nope'"));
+    state_cmd(&f)
+        .args(["allow-remote", "--repo", &repo, "--remote", "backup"])
+        .assert()
+        .success();
+
+    // An untracked branch may go to the backup, but not to origin
+    check("git push backup feature").assert().success();
+    check("git push origin feature").assert().failure();
+    let out = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "backup", "--branch", "feature", "--json"])
+        .output()
+        .unwrap();
+    let why: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(why["rule"], "remote_grant");
+    // Force pushes and deletions keep their rules unless granted too
+    check("git push -f backup feature").assert().failure();
+    check("git push backup :feature").assert().failure();
+    state_cmd(&f)
+        .args(["allow-remote", "--repo", &repo, "--remote", "backup", "--include-force"])
+        .assert()
+        .success();
+    check("git push -f backup feature").assert().success();
+    check("git push backup :feature").assert().success();
+
+    let list = list_json(&f);
+    assert_eq!(list["remotes"][&repo][0]["remote"], "backup");
+    assert_eq!(list["remotes"][&repo][0]["include_force"], true);
+    state_cmd(&f)
+        .args(["list", "--repo", &repo])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("backup  all branches, force pushes too"));
+
+    // A protected URL wins over the grant, which stays stored but inert
+    state_cmd(&f)
+        .args(["config", "set", "protected_remote_urls", "nas/backups/*"])
+        .assert()
+        .success();
+    check("git push backup feature").assert().failure();
+    assert_eq!(list_json(&f)["remotes"][&repo][0]["inert"], true);
+    state_cmd(&f).args(["config", "unset", "protected_remote_urls"]).assert().success();
+    check("git push backup feature").assert().success();
+
+    // Pointing the remote elsewhere voids the grant, and pointing it back restores it
+    git(dir.path(), &["remote", "set-url", "backup", "git@github.com:acme/tool.git"]);
+    check("git push backup feature").assert().failure();
+    state_cmd(&f)
+        .args(["list", "--repo", &repo])
+        .assert()
+        .stdout(predicates::str::contains("(inert: URL changed since granted)"));
+    git(dir.path(), &["remote", "set-url", "--push", "backup", "ssh://nas/backups/tool.git"]);
+    check("git push backup feature").assert().success();
+    git(dir.path(), &["remote", "set-url", "backup", "ssh://nas/backups/tool.git"]);
+    git(dir.path(), &["config", "--unset", "remote.backup.pushurl"]);
+    check("git push backup feature").assert().success();
+
+    state_cmd(&f)
+        .args(["deny-remote", "--repo", &repo, "--remote", "backup"])
+        .assert()
+        .success();
+    check("git push backup feature").assert().failure();
+    assert!(list_json(&f).get("remotes").is_none());
+}
+
+#[test]
+fn remote_grants_follow_renamed_remotes() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "backup", "ssh://nas/backups/tool.git"]);
+    state_cmd(&f)
+        .args(["allow-remote", "--repo", &repo, "--remote", "backup"])
+        .assert()
+        .success();
+    let check = |remote: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", remote, "--branch", "feature"]);
+        c
+    };
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    // A rename the hook sees moves the grant along before it runs
+    hook("git remote rename backup nas").assert().success();
+    git(dir.path(), &["remote", "rename", "backup", "nas"]);
+    check("nas").assert().success();
+    assert_eq!(list_json(&f)["remotes"][&repo][0]["remote"], "nas");
+
+    // One made outside the hook is picked up by refresh, from the remote's URL
+    git(dir.path(), &["remote", "rename", "nas", "store"]);
+    check("store").assert().failure();
+    state_cmd(&f)
+        .args(["refresh", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Moved the grant of 'nas' to 'store'"));
+    check("store").assert().success();
+
+    // A removed remote loses its grant, so a new remote of that name starts without one
+    git(dir.path(), &["remote", "remove", "store"]);
+    state_cmd(&f)
+        .args(["refresh", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Dropped the grant of 'store', which is gone"));
+    assert!(list_json(&f).get("remotes").is_none());
+    hook("git remote remove origin").assert().success();
+    state_cmd(&f)
+        .args(["refresh", "--repo", &repo])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Nothing is recorded about the remotes of"));
+}
+
+#[test]
+fn restricted_hours_block_even_authorized_pushes_unless_overridden() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    let config = |key: &str, value: &str| {
+        state_cmd(&f).args(["config", "set", key, value]).assert().success();
+    };
+    config("protected_branches", "release");
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "release"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    check("git push origin release").assert().success();
+
+    // Every day is restricted, whatever the clock says
+    config("restricted_hours.days", "Mon, Tue, Wed, Thu, Fri, Sat, Sun");
+    config("restricted_hours.timezone", "utc");
+    check("git push origin release")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Pushes to 'release' are not allowed on Mon, Tue"))
+        .stderr(predicates::str::contains("(UTC)"));
+    // Only default and protected branches are restricted
+    check("git push origin feature").assert().success();
+    let out = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--branch", "release", "--json"])
+        .output()
+        .unwrap();
+    let why: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(why["rule"], "restricted_hours");
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "release", "--override-hours"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("also during restricted hours"));
+    check("git push origin release").assert().success();
+    // The override does not turn the default branch into an allowed one
+    check("git push origin main")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("are not allowed"));
+
+    state_cmd(&f)
+        .args(["config", "set", "restricted_hours.after", "6pm"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("not a time of day"));
+}
+
+#[test]
+fn diverged_branch_push_is_allowed_with_a_note() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    let note = "will likely be rejected as not a fast-forward";
+
+    // Only ahead: a plain fast-forward
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "local"]);
+    check("git push origin feature")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(note).not());
+
+    // The upstream gains a commit the local branch lacks
+    git(dir.path(), &["checkout", "-q", "-b", "upstream-side", "origin/feature"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "upstream"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD"]);
+    git(dir.path(), &["checkout", "-q", "feature"]);
+    check("git push origin feature")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("'feature' is 1 commit(s) ahead of and 1 behind"))
+        .stderr(predicates::str::contains(note))
+        .stderr(predicates::str::contains("git pull --rebase origin feature"));
+    check("git push origin HEAD:feature")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(note));
+
+    // Pulling first or forcing gets no note
+    state_cmd(&f)
+        .args(["config", "set", "allow_force_with_lease_on_tracked", "true"])
+        .assert()
+        .success();
+    for command in [
+        "git pull --rebase && git push origin feature",
+        "git push --force-with-lease origin feature",
+    ] {
+        let out = check(command).output().unwrap();
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        assert!(!stderr.contains(note), "{}: {}", command, stderr);
+    }
+}
+
+#[test]
+fn trusted_remote_url_allows_non_force_pushes() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "fork", "git@github.com:me/tool.git"]);
+    git(dir.path(), &["remote", "add", "upstream", "https://github.com/org/tool.git"]);
+
+    state_cmd(&f)
+        .args(["config", "set", "trusted_remote_urls", "github.com[:/]me/*"])
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "fork", "--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "upstream", "--branch", "feature"])
+        .assert()
+        .failure();
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "fork", "--branch", "feature", "--force"])
+        .assert()
+        .failure();
+
+    let output = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "fork", "--branch", "feature", "--json"])
+        .output()
+        .unwrap();
+    let why: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(why["decision"], "allow");
+    assert_eq!(why["rule"], "trusted_remote");
+    assert_eq!(why["remote_url"], "git@github.com:me/tool.git");
+
+    state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "upstream", "--branch", "feature"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("untracked"));
+}
+
+// ── Local remotes ─────────────────────────────────────────────────────────────
+
+#[test]
+fn pushes_to_local_path_remotes_are_allowed_unless_forced_or_turned_off() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let fixture = TempDir::new().unwrap();
+    git(fixture.path(), &["init", "-q", "--bare"]);
+    git(dir.path(), &["remote", "add", "test", &repo_str(&fixture)]);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+
+    check("git push test feature").assert().success();
+    let output = state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "test", "--branch", "feature", "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["rule"], "local_remote");
+    // Force pushes and deletions keep their own rules
+    check("git push --force test feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push"));
+    check("git push test --delete feature").assert().failure();
+
+    // Inline settings that send the push elsewhere are checked where it goes
+    let fixture_path = repo_str(&fixture);
+    let rewrite = format!("url.https://github.com/acme/api.pushInsteadOf={}", fixture_path);
+    for command in [
+        "git -c remote.test.pushurl=https://github.com/acme/api push test feature".to_string(),
+        "git -c remote.test.url=git@github.com:acme/api.git push test feature".to_string(),
+        format!("git -c {} push test feature", rewrite),
+        format!("git -c {} push {} feature", rewrite, fixture_path),
+    ] {
+        check(&command)
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("not created by me"));
+    }
+    check(&format!("git -c remote.test.pushurl={} push test feature", fixture_path))
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(["config", "set", "allow_local_remotes", "false", "--repo", &repo])
+        .assert()
+        .success();
+    check("git push test feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("not created by me"));
+}
+
+// ── Remote HEAD freshness ─────────────────────────────────────────────────────
+
+#[test]
+fn stale_remote_head_is_checked_against_the_remote() {
+    let f = NamedTempFile::new().unwrap();
+    let upstream = TempDir::new().unwrap();
+    let seed = temp_repo();
+    git(seed.path(), &["branch", "master"]);
+    git(upstream.path(), &["clone", "-q", "--bare", &repo_str(&seed), "."]);
+    git(upstream.path(), &["symbolic-ref", "HEAD", "refs/heads/master"]);
+    let clone = TempDir::new().unwrap();
+    git(clone.path(), &["clone", "-q", &repo_str(&upstream), "."]);
+    let repo = repo_str(&clone);
+    // Upstream renames its default branch after the clone
+    git(upstream.path(), &["symbolic-ref", "HEAD", "refs/heads/main"]);
+    state_cmd(&f).args(["config", "set", "allow_local_remotes", "false"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "main"]).assert().success();
+    let push_main = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", "main"]);
+        c
+    };
+
+    // Checking is off, or offline: the stale origin/HEAD says master
+    state_cmd(&f).args(["config", "set", "offline", "true"]).assert().success();
+    push_main().assert().success();
+    state_cmd(&f).args(["config", "unset", "offline"]).assert().success();
+    state_cmd(&f)
+        .args(["config", "set", "remote_head_max_age_hours", "0"])
+        .assert()
+        .success();
+    push_main().assert().success();
+
+    state_cmd(&f)
+        .args(["config", "set", "remote_head_max_age_hours", "24"])
+        .assert()
+        .success();
+    push_main().assert().failure().stderr(predicates::str::contains("default branch"));
+    let state: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(f.path()).unwrap()).unwrap();
+    let cached = &state["remote_heads"][format!("{}:origin", repo)];
+    assert_eq!(cached["branch"], "main");
+    // The local ref is only updated when asked to
+    let head = || {
+        let out = std::process::Command::new("git")
+            .args(["symbolic-ref", "refs/remotes/origin/HEAD"])
+            .current_dir(clone.path())
+            .output()
+            .unwrap();
+        String::from_utf8(out.stdout).unwrap().trim().to_string()
+    };
+    assert_eq!(head(), "refs/remotes/origin/master");
+
+    state_cmd(&f).args(["config", "set", "update_remote_head", "true"]).assert().success();
+    state_cmd(&f)
+        .args(["config", "set", "remote_head_max_age_hours", "abc"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("expects a whole number"));
+    // The cached answer is used within the period; an expired one asks again
+    push_main().assert().failure();
+    assert_eq!(head(), "refs/remotes/origin/master");
+    let mut state = state;
+    let key = format!("{}:origin", repo);
+    state["remote_heads"][&key]["checked_at"] = "2020-01-01T00:00:00Z".into();
+    std::fs::write(f.path(), state.to_string()).unwrap();
+    push_main().assert().failure();
+    assert_eq!(head(), "refs/remotes/origin/main");
+}
+
+#[test]
+fn default_branch_of_a_remote_overridden_with_config_is_asked() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let other = TempDir::new().unwrap();
+    git(other.path(), &["init", "-q", "-b", "trunk"]);
+    git(other.path(), &["commit", "-q", "--allow-empty", "-m", "init"]);
+    git(dir.path(), &["branch", "trunk"]);
+    git(dir.path(), &["remote", "add", "origin", &repo]);
+    state_cmd(&f).args(["config", "set", "allow_local_remotes", "false"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "trunk"]).assert().success();
+
+    // origin's default is main, but the repository pushed to has trunk as default
+    hook_cmd(&f, dir.path(), "git push origin trunk").assert().success();
+    let command = format!("git -c remote.origin.url={} push origin trunk", repo_str(&other));
+    hook_cmd(&f, dir.path(), &command)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("default branch"));
+}
+
+// ── GitHub branch protection ──────────────────────────────────────────────────
+
+/// Installs a fake `gh` that reports `guarded` as requiring pull requests,
+/// `open` as unprotected, pull request 482 as branch `feature-x`, and fails
+/// for everything else. Each call is logged.
+#[cfg(unix)]
+fn fake_gh(dir: &Path) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let script = dir.join("gh");
+    std::fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\n\
+             echo \"$*\" >> {log}\n\
+             case \"$2\" in\n\
+               view) [ \"$3\" = 482 ] || {{ echo 'no pull requests found' >&2; exit 1; }}\n\
+                     echo '{{\"headRefName\":\"feature-x\"}}' ;;\n\
+               */branches/guarded/protection) echo '{{\"required_pull_request_reviews\":{{}}}}' ;;\n\
+               */branches/open/protection) echo 'gh: Branch not protected (HTTP 404)' >&2; exit 1 ;;\n\
+               *) echo 'error connecting to api.github.com' >&2; exit 1 ;;\n\
+             esac\n",
+            log = dir.join("calls.log").display()
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    format!("{}:{}", dir.display(), std::env::var("PATH").unwrap_or_default())
+}
+
+#[cfg(unix)]
+#[test]
+fn github_protection_blocks_branches_requiring_pull_requests() {
+    let f = NamedTempFile::new().unwrap();
+    let bin = TempDir::new().unwrap();
+    let path = fake_gh(bin.path());
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "upstream", "git@github.com:org/tool.git"]);
+
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.env("PATH", &path)
+            .args(["check", "--repo", &repo, "--remote", "upstream", "--branch", branch]);
+        c
+    };
+
+    for branch in ["guarded", "open", "flaky"] {
+        state_cmd(&f).args(["track", "--repo", &repo, "--branch", branch]).assert().success();
+    }
+    // Disabled by default: gh is never consulted
+    check("guarded").assert().success();
+    assert!(!bin.path().join("calls.log").exists());
+
+    state_cmd(&f)
+        .args(["config", "set", "github_protection", "true"])
+        .assert()
+        .success();
+    check("guarded")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("protected on GitHub (org/tool)"));
+    check("open").assert().success();
+    // API failures leave the decision to the other rules
+    check("flaky").assert().success();
+
+    // Known answers are cached; failures are not
+    check("guarded").assert().failure();
+    check("open").assert().success();
+    let calls = std::fs::read_to_string(bin.path().join("calls.log")).unwrap();
+    assert_eq!(calls.matches("branches/guarded/").count(), 1);
+    assert_eq!(calls.matches("branches/open/").count(), 1);
+    assert_eq!(calls.matches("branches/flaky/").count(), 1);
+}
+
+#[cfg(unix)]
+#[test]
+fn authorize_pr_records_the_pull_requests_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let bin = TempDir::new().unwrap();
+    let path = fake_gh(bin.path());
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "upstream", "git@github.com:org/tool.git"]);
+    let authorize = |args: &[&str]| {
+        let mut c = state_cmd(&f);
+        c.env("PATH", &path).args(["authorize", "--repo", &repo]).args(args);
+        c
+    };
+
+    authorize(&["--pr", "9"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Could not look up pull request #9"));
+    authorize(&["--pr", "482", "--remote", "origin"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("No remote 'origin'"));
+    assert!(list_json(&f)["authorized"].get(&repo).is_none());
+
+    authorize(&["--pr", "482", "--remote", "upstream", "--uses", "2"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains("Pull request #482 is branch 'feature-x'"))
+        .stderr(predicates::str::contains("Authorized push to 'feature-x'"));
+    let calls = std::fs::read_to_string(bin.path().join("calls.log")).unwrap();
+    assert!(calls.contains("pr view 482 --json headRefName -R org/tool"), "{}", calls);
+    let state = list_json(&f);
+    assert_eq!(state["authorized"][&repo], serde_json::json!(["feature-x"]));
+    assert_eq!(state["uses_left"][&repo]["feature-x"], 2);
+
+    // --pr only picks the branch; the other flags apply as with --branch
+    authorize(&["--pr", "482", "--remote", "upstream", "--uses", "1", "--sha", "HEAD"])
+        .arg("--allow-default")
+        .assert()
+        .success();
+    let head = String::from_utf8(
+        std::process::Command::new("git")
+            .args(["rev-parse", "HEAD"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap()
+            .stdout,
+    )
+    .unwrap();
+    let raw: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(f.path()).unwrap()).unwrap();
+    let entry = &raw["authorized"][&repo][0];
+    assert_eq!(entry["branch"], "feature-x");
+    assert_eq!(entry["uses_left"], 1);
+    assert_eq!(entry["sha"], head.trim());
+    assert_eq!(entry["allow_default"], true);
+
+    // Without gh, nothing is written
+    state_cmd(&f)
+        .env("PATH", "/nonexistent")
+        .args(["authorize", "--repo", &repo, "--pr", "7"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("install the GitHub CLI"));
+}
+
+// ── Commit-bound authorizations ───────────────────────────────────────────────
+
+#[test]
+fn sha_bound_authorization_requires_exact_tip() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "A"]);
+
+    // Defaults to the branch's current tip
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "feature"])
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(" at "));
+    let check = || {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", "feature"]);
+        c
+    };
+    check().assert().success();
+
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "B"]);
+    check()
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("new commits since it was authorized"));
+
+    git(dir.path(), &["reset", "-q", "--hard", "HEAD~1"]);
+    check().assert().success();
+
+    // An explicit --sha binds to that commit instead
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "feature", "--sha", "HEAD~1"])
+        .assert()
+        .success();
+    check().assert().failure();
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "feature", "--sha", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Unknown commit"));
+
+    // The binding is checked against the commit pushed, not the local 'feature'
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "feature"])
+        .assert()
+        .success();
+    git(dir.path(), &["checkout", "-q", "-b", "other", "main"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "Unreviewed"]);
+    let source = |source: &str| {
+        let mut c = check();
+        c.args(["--source", source]);
+        c
+    };
+    source("feature").assert().success();
+    source("other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'other' is not the commit 'feature' was authorized at"));
+    source("missing")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Could not resolve the commit pushed to 'feature'"));
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push origin other:feature"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule=sha_mismatch"));
+}
+
+// ── Hook input ────────────────────────────────────────────────────────────────
+
+fn hook_payload(command: &str) -> String {
+    serde_json::json!({ "tool_input": { "command": command } }).to_string()
+}
+
+fn hook_cmd(f: &NamedTempFile, dir: &Path, command: &str) -> Command {
+    let mut c = state_cmd(f);
+    c.current_dir(dir).arg("hook").write_stdin(hook_payload(command));
+    c
+}
+
+#[test]
+fn hook_checks_every_command_of_the_tool_input() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let hook = |tool_input: serde_json::Value| {
+        let mut c = state_cmd(&f);
+        let payload = serde_json::json!({ "tool_input": tool_input, "tool_name": "Bash" });
+        c.current_dir(dir.path()).arg("hook").write_stdin(payload.to_string());
+        c
+    };
+    let blocked = predicates::str::contains("'main' is the default branch");
+
+    for tool_input in [
+        serde_json::json!({ "command": "git push origin main" }),
+        serde_json::json!({ "command": ["git", "push", "origin", "main"] }),
+        serde_json::json!({ "command": ["git status", "git push origin main"] }),
+        serde_json::json!({ "commands": ["git status", "git push origin main"] }),
+        serde_json::json!({ "commands": [["git", "push", "origin", "main"]], "cwd": "/x" }),
+        serde_json::json!({ "command": "git status", "commands": ["git push origin main"] }),
+    ] {
+        hook(tool_input.clone()).assert().failure().stderr(blocked.clone());
+    }
+
+    // A branch created by one command may be pushed by the next
+    hook(serde_json::json!({ "commands": ["git checkout -b fresh", "git push origin fresh"] }))
+        .assert()
+        .success();
+
+    for tool_input in [
+        serde_json::json!({}),
+        serde_json::json!({ "commands": [] }),
+        serde_json::json!({ "command": 42, "description": "nothing to run" }),
+    ] {
+        hook(tool_input).assert().success().stderr("");
+    }
+}
+
+#[test]
+fn hook_checks_refspecs_pushed_without_a_remote() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git push HEAD:feature").assert().success();
+    hook("git push HEAD:refs/heads/feature").assert().success();
+    hook("git push HEAD:main")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule=default_branch branch=main remote=origin"));
+    hook("git push HEAD:other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule=untracked branch=other remote=origin"));
+    // The configured push remote is used, as git would
+    git(dir.path(), &["config", "remote.pushDefault", "fork"]);
+    hook("git push HEAD:other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("branch=other remote=fork"));
+    git(dir.path(), &["config", "--unset", "remote.pushDefault"]);
+
+    // `:` pushes every branch the remote has, so each needs to be allowed
+    git(dir.path(), &["branch", "feature"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD"]);
+    hook("git push origin :")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule=default_branch branch=main"))
+        .stderr(predicates::str::contains("branch=feature").not());
+    git(dir.path(), &["update-ref", "-d", "refs/remotes/origin/main"]);
+    hook("git push :").assert().success();
+    // With nothing known to match, the push can't be checked
+    git(dir.path(), &["update-ref", "-d", "refs/remotes/origin/feature"]);
+    hook("git push :")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rule=unresolved_target"));
+}
+
+#[test]
+fn hook_shortens_the_message_for_a_push_blocked_repeatedly() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let hook = |session: &str| {
+        let mut c = state_cmd(&f);
+        let payload = serde_json::json!({
+            "session_id": session,
+            "tool_input": { "command": "git push origin feature" },
+        });
+        c.current_dir(dir.path()).arg("hook").write_stdin(payload.to_string());
+        c
+    };
+    let full = predicates::str::contains("BLOCKED: Branch 'feature'");
+    let terse = |nth: &str| {
+        predicates::str::contains(format!(
+            "BLOCKED: this is the {} time in a row that the push to 'feature' on origin was \
+             blocked (untracked); stop retrying and ask the user to run: \
+             push-guard authorize --repo {} --branch feature",
+            nth, repo
+        ))
+    };
+
+    hook("s1").assert().failure().stderr(full.clone());
+    hook("s1").assert().failure().stderr(full.clone());
+    hook("s1")
+        .assert()
+        .failure()
+        .stderr(terse("3rd"))
+        .stderr(predicates::str::contains("PUSH_GUARD_BLOCK rule=untracked"))
+        .stderr(full.clone().not());
+    hook("s1").assert().failure().stderr(terse("4th"));
+    // Another session hears the full message
+    hook("s2").assert().failure().stderr(full.clone());
+
+    // Once the push is allowed in between, the count starts over
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    hook("s1").assert().success();
+    state_cmd(&f).args(["revoke", "--repo", &repo, "--branch", "feature"]).assert().success();
+    hook("s1").assert().failure().stderr(full.clone());
+
+    // Outside the window, or with the limit at 0, repeats are not shortened
+    let config = f.path().with_extension("config.toml");
+    std::fs::write(&config, "repeat_block_window_minutes = 0\n").unwrap();
+    hook("s2").assert().failure().stderr(full.clone());
+    hook("s2").assert().failure().stderr(full.clone());
+    std::fs::write(&config, "repeat_block_limit = 0\n").unwrap();
+    hook("s2").assert().failure().stderr(full);
+}
+
+#[test]
+fn session_summary_reports_only_that_sessions_activity() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let event = |session: &str, branch: &str, decision: &str, rule: &str| {
+        serde_json::json!({
+            "id": format!("{}-{}", session, branch),
+            "timestamp": "2026-10-16T09:00:00Z",
+            "repo": "/seeded",
+            "remote": "origin",
+            "branch": branch,
+            "force": false,
+            "decision": decision,
+            "rule": rule,
+            "session": session,
+        })
+        .to_string()
+    };
+    let seeded = [
+        event("s1", "release", "allow", "authorized"),
+        event("s1", "main", "block", "default_branch"),
+        event("s2", "elsewhere", "block", "untracked"),
+    ];
+    std::fs::write(f.path().with_extension("audit.jsonl"), seeded.join("\n") + "\n").unwrap();
+    let summary = |session: &str| {
+        let mut c = state_cmd(&f);
+        let input = serde_json::json!({ "session_id": session });
+        c.arg("session-summary").write_stdin(input.to_string());
+        c
+    };
+
+    // The hook tags what it records with the session
+    let payload = serde_json::json!({
+        "session_id": "s1",
+        "tool_input": { "command": "git checkout -b fresh && git push origin fresh" },
+    });
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(payload.to_string())
+        .assert()
+        .success();
+
+    let out = summary("s1").output().unwrap();
+    assert!(out.status.success());
+    assert!(out.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.starts_with("push-guard: summary of session s1\n"), "{}", stderr);
+    assert!(stderr.contains("    allowed: release → origin (authorized)\n"), "{}", stderr);
+    assert!(stderr.contains("    blocked: main → origin (default_branch)\n"), "{}", stderr);
+    assert!(stderr.contains("    authorizations used: release"), "{}", stderr);
+    assert!(stderr.contains("    created: fresh\n"), "{}", stderr);
+    assert!(stderr.contains("fresh → origin (tracked)"), "{}", stderr);
+    assert!(stderr.contains(&format!("  {}\n", repo)), "{}", stderr);
+    assert!(!stderr.contains("elsewhere"), "{}", stderr);
+
+    let out = summary("s1").arg("--json").output().unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert!(json["systemMessage"].as_str().unwrap().contains("release → origin"));
+
+    summary("quiet").assert().success().stdout("").stderr("");
+    state_cmd(&f).arg("session-summary").write_stdin("not json").assert().success().stderr("");
+}
+
+#[test]
+fn session_summary_includes_branches_in_repo_local_storage() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    std::fs::write(dir.path().join(".push-guard.toml"), "storage = \"repo\"\n").unwrap();
+    let local_file = dir.path().join(".git").join("push-guard.json");
+    let payload = serde_json::json!({
+        "session_id": "s1",
+        "tool_input": { "command": "git checkout -b fresh && git push origin fresh" },
+    });
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(payload.to_string())
+        .assert()
+        .success();
+    assert!(std::fs::read_to_string(&local_file).unwrap().contains("fresh"));
+
+    // A local-scope entry the global file does not point to is found as well
+    let mut local: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&local_file).unwrap()).unwrap();
+    local["tracked"]
+        .as_array_mut()
+        .unwrap()
+        .push(serde_json::json!({ "branch": "handmade", "session_id": "s1" }));
+    std::fs::write(&local_file, local.to_string()).unwrap();
+    std::fs::write(f.path(), "{}").unwrap();
+
+    let input = serde_json::json!({ "session_id": "s1" });
+    let out = state_cmd(&f).arg("session-summary").write_stdin(input.to_string()).output();
+    let stderr = String::from_utf8_lossy(&out.unwrap().stderr).into_owned();
+    assert!(stderr.contains("    created: fresh, handmade\n"), "{}", stderr);
+}
+
+#[test]
+fn pushes_behind_wrappers_and_pipelines_are_checked() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+
+    for command in [
+        "yes | git push origin main",
+        "unbuffer git push origin main",
+        "script -q -c \"git push origin main\" /dev/null",
+        "expect -c 'spawn git push origin main; expect eof'",
+    ] {
+        check(command)
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("default branch"));
+    }
+    check("git log | grep push").assert().success();
+}
+
+#[test]
+fn pushes_run_by_xargs_are_blocked_and_literal_loops_are_unrolled() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    for branch in ["feat-a", "feat-b"] {
+        state_cmd(&f).args(["track", "--repo", &repo, "--branch", branch]).assert().success();
+    }
+
+    check("git branch | grep feat | xargs -I{} git push origin {}")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Could not determine the push target"))
+        .stderr(predicates::str::contains("only known once `xargs` runs the push"));
+    check("find . -maxdepth 0 -exec git push origin feat-a {} +")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("`find -exec`"));
+
+    // Each branch of a literal loop is evaluated on its own
+    check("for b in feat-a feat-b; do git push origin \"$b\"; done").assert().success();
+    check("for b in feat-a main; do git push origin $b; done")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'main'"));
+    check("git branch | while read b; do git push origin $b; done")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("a `while` loop"));
+}
+
+#[test]
+fn pushes_with_refspecs_only_known_at_run_time_are_unresolved() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feat-a"]).assert().success();
+    let literal = "Re-run the push with the literal branch names instead, \
+                   e.g. `git push origin <branch> <branch>...`.";
+
+    check("git push origin \"$BRANCH\"")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(
+            "BLOCKED: Could not determine the push target: the branches of this push to \
+             'origin' come from `$BRANCH`, which is only known when the command runs.",
+        ))
+        .stderr(predicates::str::contains(literal))
+        .stderr(predicates::str::contains("Set dynamic_refspecs = \"warn\""));
+    check("git push origin --delete $(cat branches.txt)")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("come from a command substitution"))
+        .stderr(predicates::str::contains(literal));
+    check("git push --stdin origin < branches.txt")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("are read from stdin (--stdin)"));
+    // Literal refspecs next to a computed one are still checked on their own
+    check("git push origin feat-a ${PREFIX}-b")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("1 of 2 pushes in this command are blocked"));
+    // Only the destination has to be literal
+    check("git push origin $(git rev-parse HEAD):refs/heads/feat-a").assert().success();
+
+    let config = f.path().with_extension("config.toml");
+    std::fs::write(&config, "dynamic_refspecs = \"warn\"\n").unwrap();
+    check("git push origin \"$BRANCH\"").assert().success().stderr(predicates::str::contains(
+        "WARNING: the branches of this push to 'origin' come from `$BRANCH`, so it was not \
+         checked; allowed by dynamic_refspecs = \"warn\"",
+    ));
+}
+
+#[test]
+fn destructive_gh_operations_require_authorization() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let check = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--command", command]);
+        c
+    };
+
+    check("gh repo sync --force")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("`gh repo sync --force` hard-resets 'main'"));
+    check("gh api -X DELETE repos/acme/api/git/refs/heads/old")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("deletes the ref 'heads/old' on GitHub"));
+    check("gh release delete v1 --yes")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("deletes the GitHub release 'v1'"))
+        .stderr(predicates::str::contains("say \"authorize push to v1\""));
+    // Even tracked branches need an explicit grant
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "old"]).assert().success();
+    check("gh api -X DELETE repos/acme/api/git/refs/heads/old").assert().failure();
+
+    let authorize = ["authorize", "--repo", &repo, "--branch", "main", "--yes"];
+    state_cmd(&f).args(authorize).assert().success();
+    check("gh repo sync --force").assert().success();
+    check("gh pr view 12").assert().success();
+}
+
+#[test]
+fn renamed_and_copied_branches_keep_their_entries() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+    let branches = |kind: &str| -> Vec<String> {
+        let json = list_json(&f);
+        let entries = json[kind][&repo].as_array().cloned().unwrap_or_default();
+        entries.iter().map(|e| e.as_str().or(e["branch"].as_str()).unwrap().to_string()).collect()
+    };
+    git(dir.path(), &["checkout", "-q", "-b", "draft"]);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "draft"]).assert().success();
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "draft"]).assert().success();
+
+    // Two-argument form, then the one-argument form renaming the current branch
+    hook("git branch -m draft wip").assert().success();
+    git(dir.path(), &["branch", "-m", "draft", "wip"]);
+    assert_eq!(branches("tracked"), ["wip"]);
+    assert_eq!(branches("authorized"), ["wip"]);
+    hook("git branch -M feature && git push origin feature").assert().success();
+    git(dir.path(), &["branch", "-M", "feature"]);
+    assert_eq!(branches("tracked"), ["feature"]);
+    assert_eq!(branches("authorized"), ["feature"]);
+
+    // A copy is tracked, but not authorized
+    hook("git branch -c feature backup").assert().success();
+    assert_eq!(branches("tracked"), ["backup", "feature"]);
+    assert_eq!(branches("authorized"), ["feature"]);
+    // Renaming an untracked branch tracks nothing
+    hook("git branch -m main trunk").assert().success();
+    assert_eq!(branches("tracked"), ["backup", "feature"]);
+}
+
+#[test]
+fn gh_pr_checkout_and_create_are_understood() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    let hook = |payload: serde_json::Value| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(payload.to_string());
+        c
+    };
+
+    // -b names the branch up front
+    hook(serde_json::json!({ "tool_input": { "command": "gh pr checkout 12 -b review" } }))
+        .assert()
+        .success();
+    // The numeric form is resolved after the fact, from the checked-out branch
+    git(dir.path(), &["checkout", "-q", "-b", "fix-typo"]);
+    hook(serde_json::json!({
+        "hook_event_name": "PostToolUse",
+        "tool_input": { "command": "gh pr checkout 34" },
+    }))
+    .assert()
+    .success();
+    let tracked = &list_json(&f)["tracked"][&repo];
+    assert_eq!(tracked, &serde_json::json!(["fix-typo", "review"]));
+
+    // gh pr create pushes a branch without an upstream
+    hook(serde_json::json!({ "tool_input": { "command": "gh pr create --fill" } }))
+        .assert()
+        .success();
+    git(dir.path(), &["checkout", "-q", "-b", "someone-elses"]);
+    hook(serde_json::json!({ "tool_input": { "command": "gh pr create --fill" } }))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("someone-elses"));
+    hook(serde_json::json!({ "tool_input": { "command": "gh pr create --head other" } }))
+        .assert()
+        .success();
+}
+
+#[test]
+fn hook_understands_multi_line_commands() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let command = r#"git checkout -b docs-update
+cat > CHANGELOG.md <<'EOF'
+Remember to git push origin main after release
+EOF
+git add CHANGELOG.md && git commit -qm "docs" \
+  && git push -u origin docs-update"#;
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload(command))
+        .assert()
+        .success();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    assert_eq!(list_json(&f)["tracked"][&repo], serde_json::json!(["docs-update"]));
+}
+
+#[test]
+fn hook_resolves_git_aliases() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    git(dir.path(), &["config", "alias.pushf", "push --force-with-lease"]);
+    git(dir.path(), &["config", "alias.nb", "checkout -b"]);
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("git nb feat && git push origin feat").assert().success();
+    hook("git pushf origin feat")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push (--force-with-lease)"));
+
+    state_cmd(&f)
+        .args(["config", "set", "resolve_git_aliases", "false"])
+        .assert()
+        .success();
+    hook("git pushf origin feat").assert().success();
+}
+
+#[test]
+fn hook_tracks_checkouts_of_existing_remote_branches() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    git(dir.path(), &["remote", "add", "origin", "https://example.com/repo.git"]);
+    for branch in ["feature-x", "feature-y", "feature-z"] {
+        git(dir.path(), &["update-ref", &format!("refs/remotes/origin/{}", branch), "HEAD"]);
+    }
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+    let why = |branch: &str| -> serde_json::Value {
+        let output = state_cmd(&f)
+            .args(["why", "--repo", &repo, "--remote", "origin", "--branch", branch, "--json"])
+            .output()
+            .unwrap();
+        serde_json::from_slice(&output.stdout).unwrap()
+    };
+
+    // --track names the local branch after the remote one
+    hook("git checkout --track origin/feature-x && git push origin feature-x")
+        .assert()
+        .success();
+    assert_eq!(why("feature-x")["rule"], "tracked");
+    assert_eq!(why("feature-x")["tracked_from"], "origin/feature-x");
+
+    // ...unless a local name is given
+    hook("git checkout -b mine --track origin/feature-y").assert().success();
+    assert_eq!(why("mine")["tracked_from"], "origin/feature-y");
+    assert_eq!(why("feature-y")["decision"], "block");
+
+    // switch's DWIM creates a branch only when just a remote one exists
+    hook("git switch feature-z").assert().success();
+    assert_eq!(why("feature-z")["tracked_from"], "origin/feature-z");
+    hook("git switch nowhere").assert().success();
+    assert_eq!(why("nowhere")["decision"], "block");
+    git(dir.path(), &["branch", "local-only"]);
+    hook("git switch local-only").assert().success();
+    assert_eq!(why("local-only")["decision"], "block");
+
+    state_cmd(&f)
+        .args(["why", "--repo", &repo, "--remote", "origin", "--branch", "mine"])
+        .assert()
+        .stdout(predicates::str::contains("from:   origin/feature-y"));
+}
+
+#[test]
+fn command_rules_stand_in_for_wrapped_pushes() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    std::fs::write(
+        dir.path().join(".push-guard.toml"),
+        r#"
+[[command_rules]]
+pattern = "make publish*"
+remote = "origin"
+branch = "main"
+
+[[command_rules]]
+pattern = "./scripts/ship.sh*"
+decision = "block"
+message = "Ship through the release pipeline."
+"#,
+    )
+    .unwrap();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    hook("make build && make publish")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'main' is the default branch"));
+    hook("make test").assert().success();
+    hook("./scripts/ship.sh --prod")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Ship through the release pipeline."));
+
+    state_cmd(&f)
+        .args(["config", "rules", "--repo", &repo_str(&dir)])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("make publish*  →  push to origin/main"))
+        .stdout(predicates::str::contains("block: Ship through the release pipeline."));
+}
+
+#[test]
+fn risky_push_options_require_authorization() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+
+    let gitlab = "git push -o merge_request.create \
+                  -o merge_request.merge_when_pipeline_succeeds origin feature";
+    hook(gitlab)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("'merge_request.merge_when_pipeline_succeeds'"));
+    hook("git push --push-option=merge_request.merge origin feature").assert().failure();
+    hook("git push --push-option merge_request.create -o ci.skip origin feature")
+        .assert()
+        .success();
+
+    state_cmd(&f)
+        .args(["config", "set", "risky_push_options", "ci.*"])
+        .assert()
+        .success();
+    hook("git push -o ci.skip origin feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Push option 'ci.skip'"));
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "feature"]).assert().success();
+    hook("git push -o ci.skip origin feature").assert().success();
+}
+
+// ── History rewrites before force pushes ──────────────────────────────────────
+
+/// A repo on `feature` with commits `one` and `two`, both already on `origin/feature`.
+fn pushed_feature_repo() -> TempDir {
+    let dir = temp_repo();
+    git(dir.path(), &["checkout", "-q", "-b", "feature"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "one"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "two"]);
+    git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD"]);
+    dir
+}
+
+#[test]
+fn amend_then_force_push_lists_replaced_commit() {
+    let (mut c, _f) = with_state();
+    let dir = pushed_feature_repo();
+    c.current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload(
+            "git commit --amend --no-edit && git push --force-with-lease origin feature",
+        ))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rewritten by `git commit --amend`"))
+        .stderr(predicates::str::contains("that would be replaced"))
+        .stderr(predicates::str::contains(" two\n"))
+        .stderr(predicates::str::contains(" one\n").not());
+}
+
+#[test]
+fn rebase_then_force_push_lists_replaced_commits() {
+    let (mut c, _f) = with_state();
+    let dir = pushed_feature_repo();
+    c.current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git rebase -i main; git push -f origin feature"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rewritten by `git rebase`"))
+        .stderr(predicates::str::contains(" one\n"))
+        .stderr(predicates::str::contains(" two\n"));
+
+    // Without a preceding rewrite only the generic force message is shown
+    let (mut c, _f) = with_state();
+    c.current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push -f origin feature"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("rewritten").not());
+}
+
+#[test]
+fn force_push_block_lists_the_commits_it_would_discard() {
+    let dir = pushed_feature_repo();
+    git(dir.path(), &["config", "user.email", "test@example.com"]);
+    for n in 1..=6 {
+        let subject = format!("remote {}", n);
+        let mut args = vec!["commit", "-q", "--allow-empty", "-m", &subject];
+        if n == 3 {
+            args.extend(["--author", "Other <other@example.com>"]);
+        }
+        git(dir.path(), &args);
+    }
+    git(dir.path(), &["update-ref", "refs/remotes/origin/feature", "HEAD"]);
+    git(dir.path(), &["reset", "-q", "--hard", "HEAD~6"]);
+    git(dir.path(), &["commit", "-q", "--allow-empty", "-m", "local"]);
+    let hook = |command: &str| {
+        let (mut c, f) = with_state();
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        (c, f)
+    };
+
+    let (mut c, _f) = hook("git push --force origin feature");
+    let output = c.output().unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("6 commits on 'origin/feature' would be discarded"), "{}", stderr);
+    for n in 2..=6 {
+        assert!(stderr.contains(&format!(" remote {}\n", n)), "{}", stderr);
+    }
+    assert!(!stderr.contains("remote 1\n"), "{}", stderr);
+    assert!(stderr.contains("  ... and 1 more\n"), "{}", stderr);
+    assert!(stderr.contains("Some are not authored by you: other@example.com\n"), "{}", stderr);
+
+    // Pushing a commit that contains the remote branch discards nothing
+    git(dir.path(), &["reset", "-q", "--hard", "origin/feature"]);
+    let (mut c, _f) = hook("git push -f origin feature");
+    c.assert().failure().stderr(predicates::str::contains(
+        "No commits on 'origin/feature' would be discarded as of the last fetch.",
+    ));
+    // Without a remote-tracking branch there is nothing to summarize
+    let (mut c, _f) = hook("git push -f origin feature:other");
+    c.assert().failure().stderr(predicates::str::contains("would be discarded").not());
+}
+
+// ── --force-with-lease vs --force ─────────────────────────────────────────────
+
+#[test]
+fn force_with_lease_allowed_on_tracked_branches_when_enabled() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    for branch in ["feature", "main"] {
+        let track = ["track", "--repo", &repo, "--branch", branch, "--yes"];
+        state_cmd(&f).args(track).assert().success();
+    }
+    let check = |branch: &str, flag: Option<&str>| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c.args(flag);
+        c
+    };
+
+    // Off by default: any kind of force is blocked, and the message names it
+    check("feature", Some("--force-with-lease"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push (--force-with-lease)"));
+
+    state_cmd(&f)
+        .args(["config", "set", "allow_force_with_lease_on_tracked", "true"])
+        .assert()
+        .success();
+
+    check("feature", None).assert().success();
+    check("feature", Some("--force-with-lease")).assert().success();
+    check("feature", Some("--force"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Force push (--force)"));
+
+    check("stray", None).assert().failure();
+    check("stray", Some("--force-with-lease")).assert().failure();
+    check("stray", Some("--force")).assert().failure();
+
+    // Never on the default branch
+    check("main", Some("--force-with-lease")).assert().failure();
+
+    let events = history_json(&f, &["--limit", "8"]);
+    let feature_lease = &events[5];
+    assert_eq!(feature_lease["rule"], "force_with_lease_tracked");
+    assert_eq!(feature_lease["force_with_lease"], true);
+}
+
+// ── Hook resilience ───────────────────────────────────────────────────────────
+
+#[test]
+fn hook_ignores_malformed_input_silently() {
+    let f = NamedTempFile::new().unwrap();
+    let log = f.path().with_extension("debug.log");
+    let inputs: [&[u8]; 4] = [
+        b"\xff\xfe not json at all",
+        b"{}",
+        br#"{"tool_input":["git push origin main"]}"#,
+        br#"{"tool_input":{"command":42}}"#,
+    ];
+    for input in inputs {
+        state_cmd(&f)
+            .env("PUSH_GUARD_DEBUG_LOG", &log)
+            .arg("hook")
+            .write_stdin(input)
+            .assert()
+            .success()
+            .stdout("")
+            .stderr("");
+    }
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("not JSON"));
+    assert_eq!(logged.lines().count(), 4);
+}
+
+#[test]
+fn hook_block_output_starts_with_a_reason_code_line_per_push() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "wip"]).assert().success();
+    state_cmd(&f).args(["config", "set", "protected_branches", "release/*"]).assert().success();
+    state_cmd(&f).args(["config", "set", "block_no_verify", "true"]).assert().success();
+    let first_line = |command: &str| -> String {
+        let out = state_cmd(&f)
+            .current_dir(dir.path())
+            .arg("hook")
+            .write_stdin(hook_payload(command))
+            .output()
+            .unwrap();
+        assert!(!out.status.success(), "{} was allowed", command);
+        let stderr = String::from_utf8(out.stderr).unwrap();
+        stderr.lines().next().unwrap_or_default().to_string()
+    };
+    let hint = |branch: &str, flag: &str| {
+        let command = format!("push-guard authorize --repo {} --branch {}{}", repo, branch, flag);
+        format!("authorize_hint=\"{}\"", command)
+    };
+
+    for (command, line) in [
+        (
+            "git push origin feature",
+            format!("rule=untracked branch=feature remote=origin {}", hint("feature", "")),
+        ),
+        (
+            "git push origin main",
+            format!(
+                "rule=default_branch branch=main remote=origin {}",
+                hint("main", " --allow-default")
+            ),
+        ),
+        (
+            "git push origin release/1.0",
+            format!(
+                "rule=protected_branch branch=release/1.0 remote=origin {}",
+                hint("release/1.0", "")
+            ),
+        ),
+        (
+            "git push --no-verify origin wip",
+            format!("rule=no_verify branch=wip remote=origin {}", hint("wip", "")),
+        ),
+        ("git push -f origin wip", "rule=force_push branch=wip remote=origin".to_string()),
+        (
+            "for b in $(git branch); do git push origin $b; done",
+            "rule=unresolved_target branch=\"\" remote=origin".to_string(),
+        ),
+    ] {
+        assert_eq!(first_line(command), format!("PUSH_GUARD_BLOCK {}", line), "{}", command);
+    }
+
+    state_cmd(&f)
+        .args(["config", "set", "restricted_hours.days", "Mon, Tue, Wed, Thu, Fri, Sat, Sun"])
+        .assert()
+        .success();
+    assert_eq!(
+        first_line("git push origin release/2.0"),
+        format!(
+            "PUSH_GUARD_BLOCK rule=restricted_hours branch=release/2.0 remote=origin {}",
+            hint("release/2.0", " --override-hours")
+        )
+    );
+    state_cmd(&f).args(["config", "unset", "restricted_hours.days"]).assert().success();
+
+    // Every blocked push gets its line before any explanation
+    let out = state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push origin wip && git push origin a && git push origin b"))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(out.stderr).unwrap();
+    let lines: Vec<&str> = stderr.lines().take(3).collect();
+    assert!(lines[0].starts_with("PUSH_GUARD_BLOCK rule=untracked branch=a "), "{}", stderr);
+    assert!(lines[1].starts_with("PUSH_GUARD_BLOCK rule=untracked branch=b "), "{}", stderr);
+    assert!(!lines[2].starts_with("PUSH_GUARD_BLOCK"), "{}", stderr);
+
+    // `check` prints no such line, and the config can turn it off for the hook
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--command", "git push origin feature"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("PUSH_GUARD_BLOCK").not());
+    state_cmd(&f).args(["config", "set", "reason_codes", "false"]).assert().success();
+    assert!(first_line("git push origin feature").starts_with("BLOCKED: "));
+}
+
+#[test]
+fn hook_reads_input_with_a_bom_or_trailing_garbage() {
+    let (mut c, f) = with_state();
+    let payload = hook_payload("git push origin stray-branch");
+    c.arg("hook")
+        .write_stdin(format!("\u{FEFF}{}\n\x1b[0m\n", payload))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("stray-branch"));
+    state_cmd(&f)
+        .arg("hook")
+        .write_stdin(format!("\r\n{}{}", payload, payload))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("stray-branch"));
+}
+
+#[cfg(unix)]
+#[test]
+fn branch_names_that_are_not_utf8_are_blocked_rather_than_mismatched() {
+    use std::os::unix::ffi::OsStrExt;
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let name = std::ffi::OsStr::from_bytes(b"feature-\xff");
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir.path())
+        .args(["checkout", "-q", "-b"])
+        .arg(name)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Tracking what git shows as the current branch must not make it pushable
+    state_cmd(&f).args(["track", "--repo", &repo, "--all-local"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", &repo]).assert().failure();
+    state_cmd(&f).args(["track", "--repo", &repo, "--from-reflog", "--yes"]).assert().success();
+    assert_eq!(list_json(&f)["tracked"], serde_json::json!({}));
+
+    for command in ["git push origin HEAD", "git push -u origin feature-\u{FFFD}"] {
+        state_cmd(&f)
+            .args(["check", "--repo", &repo, "--command", command])
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("Branch name 'feature-\u{FFFD}' contains bytes"))
+            .stderr(predicates::str::contains("git branch -m <new-name>"));
+    }
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .arg("hook")
+        .write_stdin(hook_payload("git push"))
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("not valid UTF-8"));
+}
+
+#[test]
+fn hook_joins_command_arrays() {
+    let (mut c, _f) = with_state();
+    c.arg("hook")
+        .write_stdin(r#"{"tool_input":{"command":["git","push","origin","stray-branch"]}}"#)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("stray-branch"));
+}
+
+#[cfg(unix)]
+#[test]
+fn hook_evaluates_a_read_only_state_without_writing_to_it() {
+    use std::os::unix::fs::PermissionsExt;
+    let data = TempDir::new().unwrap();
+    let tmp = TempDir::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let state = data.path().join("state.json");
+    let run = |args: &[&str]| {
+        let mut c = cmd();
+        c.env("PUSH_GUARD_STATE_FILE", &state)
+            .env("PUSH_GUARD_CONFIG_FILE", data.path().join("config.toml"))
+            .env("XDG_RUNTIME_DIR", tmp.path())
+            .current_dir(dir.path())
+            .args(args);
+        c
+    };
+    run(&["track", "--repo", &repo, "--branch", "feature"]).assert().success();
+    let before = std::fs::read_to_string(&state).unwrap();
+
+    let read_only = std::fs::Permissions::from_mode(0o555);
+    std::fs::set_permissions(data.path(), read_only).unwrap();
+    // Permissions don't stop root, so force the mode there instead
+    let enforced = std::fs::write(data.path().join("probe"), "").is_err();
+    let ro = |args: &[&str]| {
+        let mut c = run(args);
+        if !enforced {
+            c.env("PUSH_GUARD_STATE_READONLY", "1");
+        }
+        c
+    };
+    let hook = |command: &str| {
+        let mut c = ro(&["hook"]);
+        c.write_stdin(hook_payload(command));
+        c
+    };
+
+    let warning = "state is read-only";
+    hook("git push origin feature")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(warning).count(1))
+        .stderr(predicates::str::contains("hook error").not());
+    hook("git push origin other")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("PUSH_GUARD_BLOCK rule=untracked"))
+        .stderr(predicates::str::contains("hook error").not());
+    // The creation can't be recorded, but the push right after it is allowed
+    hook("git checkout -b fresh && git push origin fresh")
+        .assert()
+        .success()
+        .stderr(predicates::str::contains(warning));
+    ro(&["track", "--repo", &repo, "--branch", "fresh"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("State is read-only"));
+    ro(&["doctor"]).assert().stdout(predicates::str::contains("WARN  state is read-only"));
+
+    let writable = std::fs::Permissions::from_mode(0o755);
+    std::fs::set_permissions(data.path(), writable).unwrap();
+    assert_eq!(std::fs::read_to_string(&state).unwrap(), before);
+    assert!(!state.with_extension("audit.jsonl").exists());
+    assert!(!state.with_extension("stats.json").exists());
+    let _ = std::fs::remove_file(data.path().join("probe"));
+}
+
+// ── Repo-local storage ────────────────────────────────────────────────────────
+
+#[test]
+fn local_scope_layers_over_global_state() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let local_file = dir.path().join(".git").join("push-guard.json");
+    let local = || std::fs::read_to_string(&local_file).unwrap();
+    let state = |args: &[&str]| {
+        let mut c = state_cmd(&f);
+        c.args(args).args(["--repo", &repo]);
+        c
+    };
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.args(["check", "--repo", &repo, "--remote", "origin", "--branch", branch]);
+        c
+    };
+
+    state(&["authorize", "--branch", "shared"]).assert().success();
+    let global = std::fs::read(f.path()).unwrap();
+
+    // A local authorization allows the push without touching the global file
+    state(&["authorize", "--branch", "feature", "--scope", "local"]).assert().success();
+    assert!(local().contains("feature"));
+    assert_eq!(std::fs::read(f.path()).unwrap(), global);
+    check("feature").assert().success();
+    state(&["list"])
+        .assert()
+        .stdout(predicates::str::contains("feature  (local)  just now"))
+        .stdout(predicates::str::is_match(r"shared +just now\n").unwrap());
+    state(&["list", "--absolute"])
+        .assert()
+        .stdout(predicates::str::is_match(r"shared +\d{4}-\d\d-\d\d \d\d:\d\d\n").unwrap());
+
+    // Saving without reading the local file first leaves its entries alone
+    git(dir.path(), &["remote", "add", "fork", "https://example.com/me/api.git"]);
+    state(&["allow-remote", "--remote", "fork"]).assert().success();
+    assert!(local().contains("feature"));
+    check("feature").assert().success();
+    let global = std::fs::read(f.path()).unwrap();
+
+    // Revoking locally leaves the global entry in place
+    state(&["authorize", "--branch", "shared", "--scope", "local"]).assert().success();
+    state(&["revoke", "--branch", "shared", "--scope", "local"]).assert().success();
+    assert!(!local().contains("shared"));
+    assert_eq!(std::fs::read(f.path()).unwrap(), global);
+    check("shared").assert().success();
+
+    state(&["revoke", "--branch", "feature", "--scope", "global"]).assert().success();
+    check("feature").assert().success();
+    state(&["revoke", "--branch", "feature"]).assert().success();
+    check("feature").assert().failure();
+}
+
+#[test]
+fn repo_storage_writes_into_git_dir_and_list_aggregates() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let local_file = dir.path().join(".git").join("push-guard.json");
+    let global = || std::fs::read_to_string(f.path()).unwrap();
+
+    // Existing global entries move into the repo on migrate
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "before"]).assert().success();
+    state_cmd(&f).args(["track", "--repo", REPO, "--branch", "elsewhere"]).assert().success();
+    state_cmd(&f).args(["migrate", "--to", "repo"]).assert().success();
+    assert!(std::fs::read_to_string(&local_file).unwrap().contains("before"));
+    assert!(!global().contains("before"));
+    assert!(global().contains("elsewhere"), "repos not on disk stay global");
+
+    // New entries go straight to the repo file
+    state_cmd(&f).args(["authorize", "--repo", &repo, "--branch", "after"]).assert().success();
+    assert!(std::fs::read_to_string(&local_file).unwrap().contains("after"));
+    assert!(!global().contains("after"));
+
+    let json = list_json(&f);
+    assert_eq!(json["tracked"][&repo], serde_json::json!(["before"]));
+    assert_eq!(json["authorized"][&repo], serde_json::json!(["after"]));
+    assert_eq!(json["tracked"][REPO], serde_json::json!(["elsewhere"]));
+    state_cmd(&f)
+        .args(["check", "--repo", &repo, "--remote", "origin", "--branch", "before"])
+        .assert()
+        .success();
+
+    // And back
+    state_cmd(&f).args(["migrate", "--to", "global"]).assert().success();
+    assert!(global().contains("before"));
+    assert_eq!(list_json(&f)["authorized"][&repo], serde_json::json!(["after"]));
+}
+
+// ── State schema versions ─────────────────────────────────────────────────────
+
+#[test]
+fn newer_state_file_is_used_read_only() {
+    let future = include_str!("fixtures/state/future.json");
+    let f = fixture_state(future);
+
+    let output = state_cmd(&f).args(["list"]).output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("newer push-guard").count(), 1);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("feature"));
+
+    state_cmd(&f)
+        .args(["track", "--repo", REPO, "--branch", "other"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("refusing to overwrite"));
+    assert_eq!(std::fs::read_to_string(f.path()).unwrap(), future);
+}
+
+#[test]
+fn old_state_file_is_upgraded_on_write() {
+    let f = fixture_state(include_str!("fixtures/state/v0-bare.json"));
+    state_cmd(&f).args(["track", "--repo", REPO, "--branch", "new"]).assert().success();
+    let written: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(f.path()).unwrap()).unwrap();
+    assert_eq!(written["version"], 1);
+    assert_eq!(written["tracked"]["/home/me/project"], serde_json::json!(["feature", "fix/login"]));
+}
+
+#[test]
+fn denied_remote_urls_block_pushes_whatever_the_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    git(dir.path(), &["remote", "add", "origin", "git@github.com:acme/api.git"]);
+    git(dir.path(), &["remote", "add", "corp", "git@git.corp.example:team/api.git"]);
+    for args in [
+        vec!["track", "--repo", &repo, "--branch", "feature"],
+        vec!["authorize", "--repo", &repo, "--branch", "feature"],
+        vec!["config", "set", "denied_remote_urls", "github.com[:/]*"],
+    ] {
+        state_cmd(&f).args(args).assert().success();
+    }
+    let hook = |command: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(hook_payload(command));
+        c
+    };
+    let denied = |url: &str| {
+        predicates::str::contains(format!(
+            "Pushing to {} is not allowed on this machine: it matches 'github.com[:/]*' in \
+             denied_remote_urls.",
+            url
+        ))
+    };
+
+    // Tracked and authorized, yet denied: no authorization is offered either
+    hook("git push origin feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("PUSH_GUARD_BLOCK rule=denied_remote"))
+        .stderr(denied("git@github.com:acme/api.git"))
+        .stderr(predicates::str::contains("push-guard authorize").not());
+    hook("git push https://github.com/acme/other.git feature")
+        .assert()
+        .failure()
+        .stderr(denied("https://github.com/acme/other.git"));
+    // The same host, however it is spelled
+    for url in ["https://GitHub.com/acme/api.git", "https://github.com./acme/api.git"] {
+        hook(&format!("git push {} feature", url)).assert().failure().stderr(denied(url));
+    }
+    hook("git push corp feature").assert().success();
+
+    let output = state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["why", "--repo", &repo, "--remote", "origin", "--branch", "feature", "--json"])
+        .output()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["rule"], "denied_remote");
+    assert_eq!(json["denied_pattern"], "github.com[:/]*");
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["why", "--repo", &repo, "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .stdout(predicates::str::contains(
+            "  denied: git@github.com:acme/api.git \
+             (matches denied_remote_urls pattern 'github.com[:/]*')",
+        ));
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["why", "--repo", &repo, "--remote", "corp", "--branch", "feature"])
+        .assert()
+        .stdout(predicates::str::contains("denied:").not());
+}
+
+#[test]
+fn check_remote_url_applies_url_policies() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    git(dir.path(), &["remote", "add", "origin", "https://github.com/acme/api.git"]);
+    for args in [
+        vec!["config", "set", "denied_remote_urls", "*evil.example*"],
+        vec!["config", "set", "trusted_remote_urls", "https://fork.example/me/*"],
+        vec!["config", "set", "offline", "true"],
+    ] {
+        state_cmd(&f).args(args).assert().success();
+    }
+    let check = |url: &str, branch: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).args(["check", "--remote-url", url, "--branch", branch]);
+        c
+    };
+
+    check("https://evil.example/acme/api.git", "feature")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("Pushing to https://evil.example/acme/api.git"))
+        .stderr(predicates::str::contains("Destination: https://evil.example/acme/api.git"));
+    check("https://fork.example/me/api.git", "feature").assert().success();
+    // The URL is judged, not the named remote's, which is neither denied nor trusted
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["check", "--remote", "origin", "--remote-url", "https://fork.example/me/api.git"])
+        .args(["--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .current_dir(dir.path())
+        .args(["check", "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .failure();
+
+    let history = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: serde_json::Value = serde_json::from_slice(&history.stdout).unwrap();
+    let denied = events.as_array().unwrap().iter().find(|e| e["rule"] == "denied_remote").unwrap();
+    assert_eq!(denied["remote_url"], "https://evil.example/acme/api.git");
+    assert_eq!(denied["remote"], "https://evil.example/acme/api.git");
+    assert_eq!(denied["destination"], "evil.example/acme/api");
+}
+
+#[test]
+fn check_remote_url_asks_the_url_for_its_default_branch() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    // A repository standing in for the remote, whose HEAD is "trunk"
+    let upstream = TempDir::new().unwrap();
+    git(upstream.path(), &["init", "-q", "--bare", "-b", "trunk"]);
+    git(dir.path(), &["push", "-q", upstream.path().to_str().unwrap(), "HEAD:trunk"]);
+    let url = upstream.path().to_str().unwrap().to_string();
+    for args in [
+        vec!["config", "set", "allow_local_remotes", "false"],
+        vec!["track", "--repo", &repo, "--branch", "trunk"],
+        vec!["track", "--repo", &repo, "--branch", "feature"],
+    ] {
+        state_cmd(&f).args(args).assert().success();
+    }
+    let check = |branch: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).args(["check", "--remote-url", &url, "--branch", branch]);
+        c
+    };
+
+    check("trunk")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains(format!("'trunk' is the default branch of '{}'", url)));
+    check("feature").assert().success();
+    check("feature").arg("--force").assert().failure();
+    // origin's own default branch is not the URL's
+    state_cmd(&f).args(["track", "--repo", &repo, "--branch", "main", "--yes"]).assert().success();
+    check("main").assert().success();
+}
+
+// ── Session-scoped tracking ───────────────────────────────────────────────────
+
+fn session_payload(command: &str, session: &str) -> String {
+    serde_json::json!({ "session_id": session, "tool_input": { "command": command } }).to_string()
+}
+
+#[test]
+fn session_scoped_tracking_blocks_other_sessions() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    let hook = |command: &str, session: &str| {
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(session_payload(command, session));
+        c
+    };
+
+    hook("git checkout -b feat", "session-a").assert().success();
+    let json = list_json(&f);
+    assert_eq!(json["sessions"][&repo]["feat"], "session-a");
+
+    // Without session scoping any session may push
+    hook("git push origin feat", "session-b").assert().success();
+
+    state_cmd(&f)
+        .args(["config", "set", "session_scoped_tracking", "true"])
+        .assert()
+        .success();
+    hook("git push origin feat", "session-b")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("different session"));
+    hook("git push origin feat", "session-a").assert().success();
+
+    // Ending the session forgets its branches
+    state_cmd(&f)
+        .arg("session-end")
+        .write_stdin(r#"{"session_id":"session-a"}"#)
+        .assert()
+        .success();
+    assert!(list_json(&f)["tracked"][&repo].is_null());
+    hook("git push origin feat", "session-a").assert().failure();
+}
+
+#[test]
+fn subagent_pushes_are_attributed_and_can_require_authorization() {
+    let f = NamedTempFile::new().unwrap();
+    let dir = temp_repo();
+    let repo = std::fs::canonicalize(dir.path()).unwrap().to_str().unwrap().to_string();
+    let hook = |command: &str, agent: Option<&str>| {
+        let mut payload =
+            serde_json::json!({ "session_id": "s1", "tool_input": { "command": command } });
+        if let Some(id) = agent {
+            payload["agent_id"] = serde_json::json!(id);
+            payload["agent_type"] = serde_json::json!("general-purpose");
+        }
+        let mut c = state_cmd(&f);
+        c.current_dir(dir.path()).arg("hook").write_stdin(payload.to_string());
+        c
+    };
+
+    hook("git checkout -b main-work", None).assert().success();
+    hook("git checkout -b sub-work", Some("agent-7")).assert().success();
+    let json = list_json(&f);
+    assert_eq!(
+        json["agents"][&repo],
+        serde_json::json!({ "sub-work": { "id": "agent-7", "type": "general-purpose" } })
+    );
+    assert_eq!(json["sessions"][&repo]["sub-work"], "s1");
+
+    // By default subagents push tracked branches like the main agent
+    hook("git push origin sub-work", Some("agent-7")).assert().success();
+    state_cmd(&f)
+        .args(["config", "set", "subagents_require_authorization", "true"])
+        .assert()
+        .success();
+    hook("git push origin main-work", None).assert().success();
+    for branch in ["main-work", "sub-work"] {
+        hook(&format!("git push origin {}", branch), Some("agent-9"))
+            .assert()
+            .failure()
+            .stderr(predicates::str::contains("PUSH_GUARD_BLOCK rule=subagent_push"))
+            .stderr(predicates::str::contains(format!(
+                "Branch '{}' was created by me, but this push comes from \
+                 subagent general-purpose (agent-9),",
+                branch
+            )));
+    }
+    state_cmd(&f)
+        .args(["authorize", "--repo", &repo, "--branch", "sub-work"])
+        .assert()
+        .success();
+    hook("git push origin sub-work", Some("agent-9")).assert().success();
+
+    let output = state_cmd(&f).args(["history", "--json"]).output().unwrap();
+    let events: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(events[0]["agent"]["id"], "agent-9");
+    assert_eq!(events[0]["rule"], "authorized");
+    assert!(events[1]["agent"].is_object() && events[2]["agent"].is_object());
+    assert!(events[3]["agent"].is_null());
+    state_cmd(&f)
+        .args(["history", "--blocked-only"])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "origin/sub-work  (by subagent general-purpose (agent-9))",
+        ));
+}
+
+// ── Webhook ───────────────────────────────────────────────────────────────────
+
+/// Accepts HTTP requests on a local port and forwards each JSON body.
+#[cfg(feature = "webhook")]
+fn webhook_listener() -> (String, std::sync::mpsc::Receiver<serde_json::Value>) {
+    use std::io::{BufRead, BufReader, Read, Write};
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let _ = stream.write_all(b"HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+            let _ = tx.send(serde_json::from_slice(&body).unwrap());
+        }
+    });
+    (url, rx)
+}
+
+#[cfg(feature = "webhook")]
+#[test]
+fn webhook_posts_blocks_and_authorizations() {
+    let f = NamedTempFile::new().unwrap();
+    let (url, events) = webhook_listener();
+    state_cmd(&f).args(["config", "set", "webhook_url", &url]).assert().success();
+    let next = || events.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .failure();
+    let event = next();
+    assert_eq!(event["event"], "blocked");
+    assert_eq!(event["repo"], REPO);
+    assert_eq!(event["branch"], "feature");
+    assert_eq!(event["remote"], "origin");
+    assert_eq!(event["rule"], "untracked");
+    assert!(event["timestamp"].is_string());
+    assert!(event.get("hostname").is_some());
+
+    state_cmd(&f)
+        .args(["authorize", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    let event = next();
+    assert_eq!(event["event"], "authorized");
+    assert_eq!(event["branch"], "feature");
+    assert!(event["rule"].is_null());
+
+    // Allowed pushes, dry runs and disabled deliveries post nothing
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["check", "--repo", REPO, "--branch", "other", "--dry-run"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .env("PUSH_GUARD_WEBHOOK", "0")
+        .args(["revoke", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .args(["revoke", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    assert_eq!(next()["event"], "revoked");
+}
+
+#[test]
+fn unreachable_webhook_never_changes_decisions() {
+    let f = NamedTempFile::new().unwrap();
+    let log = f.path().with_extension("debug.log");
+    state_cmd(&f)
+        .args(["config", "set", "webhook_url", "http://127.0.0.1:1/hook"])
+        .assert()
+        .success();
+    state_cmd(&f)
+        .env("PUSH_GUARD_DEBUG_LOG", &log)
+        .args(["check", "--repo", REPO, "--remote", "origin", "--branch", "feature"])
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("BLOCKED"));
+    state_cmd(&f)
+        .env("PUSH_GUARD_DEBUG_LOG", &log)
+        .args(["authorize", "--repo", REPO, "--branch", "feature"])
+        .assert()
+        .success();
+    let logged = std::fs::read_to_string(&log).unwrap();
+    assert!(logged.contains("webhook: blocked event not delivered"));
+    assert!(logged.contains("webhook: authorized event not delivered"));
+}
+
+// ── Settings and doctor ───────────────────────────────────────────────────────
+
+fn print_config(args: &[&str]) -> serde_json::Value {
+    let output = cmd().args(["hook", "--print-config"]).args(args).output().unwrap();
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+#[test]
+fn hook_print_config_emits_mergeable_settings() {
+    let json = print_config(&[]);
+    let pre = &json["hooks"]["PreToolUse"][0];
+    assert_eq!(pre["matcher"], "Bash");
+    assert_eq!(pre["hooks"][0]["type"], "command");
+    let command = pre["hooks"][0]["command"].as_str().unwrap();
+    let binary = command.strip_suffix(" hook").unwrap();
+    assert!(Path::new(binary).is_absolute() && Path::new(binary).exists());
+    assert!(json["hooks"].get("PostToolUse").is_none());
+
+    let json = print_config(&["--scope", "project", "--post"]);
+    assert_eq!(json["hooks"]["PostToolUse"], json["hooks"]["PreToolUse"]);
+    cmd().args(["hook", "--post"]).assert().failure();
+}
+
+#[test]
+fn doctor_checks_hook_registration() {
+    let home = TempDir::new().unwrap();
+    let settings = home.path().join(".claude").join("settings.json");
+    let doctor = || {
+        let mut c = cmd();
+        c.env("HOME", home.path()).env("PUSH_GUARD_STATE_FILE", home.path().join("state.json"));
+        c.current_dir(home.path()).arg("doctor");
+        c
+    };
+
+    doctor().assert().success().stdout(predicates::str::contains("WARN  no Claude settings"));
+
+    std::fs::create_dir_all(settings.parent().unwrap()).unwrap();
+    std::fs::write(&settings, r#"{ "hooks": {} }"#).unwrap();
+    doctor().assert().failure().stdout(predicates::str::contains("FAIL  push-guard hook is not"));
+
+    std::fs::write(&settings, print_config(&[]).to_string()).unwrap();
+    doctor()
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("PASS  hook registered"))
+        .stdout(predicates::str::contains("PASS  hook runs this binary"));
+
+    let missing = serde_json::json!({ "hooks": { "PreToolUse": [{ "matcher": "Bash", "hooks": [
+        { "type": "command", "command": "/nonexistent/push-guard hook" },
+    ]}]}});
+    std::fs::write(&settings, missing.to_string()).unwrap();
+    doctor()
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("'/nonexistent/push-guard' does not exist"));
+}
+
+#[test]
+fn doctor_checks_state_file() {
+    let home = TempDir::new().unwrap();
+    let state = home.path().join("data").join("state.json");
+    let doctor = || {
+        let mut c = cmd();
+        c.env("HOME", home.path()).env("PUSH_GUARD_STATE_FILE", &state);
+        c.current_dir(home.path()).arg("doctor");
+        c
+    };
+
+    doctor()
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("PASS  git found (git version"))
+        .stdout(predicates::str::contains("does not exist yet"))
+        .stdout(predicates::str::contains("PASS  state lock can be acquired"));
+
+    std::fs::write(&state, r#"{ "version": 1, "tracked": {}, "authorized": {} }"#).unwrap();
+    doctor()
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("PASS  state file"))
+        .stdout(predicates::str::contains("PASS  state schema version 1"));
+
+    std::fs::write(&state, r#"{ "tracked": {} }"#).unwrap();
+    doctor().assert().success().stdout(predicates::str::contains(
+        "WARN  state schema version 0 (current is 1)",
+    ));
+
+    std::fs::write(&state, r#"{ "version": 99, "tracked": {}, "authorized": {} }"#).unwrap();
+    doctor().assert().success().stdout(predicates::str::contains("newer than supported"));
+
+    std::fs::write(&state, "not json").unwrap();
+    doctor()
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("FAIL  state file cannot be parsed"));
+}
+
+#[test]
+fn doctor_checks_remote_head() {
+    let home = TempDir::new().unwrap();
+    let dir = temp_repo();
+    let repo = repo_str(&dir);
+    let doctor = || {
+        let mut c = cmd();
+        c.env("HOME", home.path()).env("PUSH_GUARD_STATE_FILE", home.path().join("state.json"));
+        c.args(["doctor", "--repo", &repo]);
+        c
+    };
+
+    doctor()
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("PASS  origin/HEAD resolves to 'main'"));
+
+    git(dir.path(), &["symbolic-ref", "--delete", "refs/remotes/origin/HEAD"]);
+    doctor().assert().success().stdout(predicates::str::contains("has no remote 'origin'"));
+
+    git(dir.path(), &["remote", "add", "origin", "https://example.com/repo.git"]);
+    doctor()
+        .assert()
+        .failure()
+        .stdout(predicates::str::contains("FAIL  origin/HEAD is not set"))
+        .stdout(predicates::str::contains("git remote set-head origin --auto"));
+}
+
+// ── Selftest ──────────────────────────────────────────────────────────────────
+
+#[test]
+fn selftest_passes_without_touching_the_users_setup() {
+    let home = TempDir::new().unwrap();
+    let state = home.path().join("state.json");
+    let config = home.path().join("config.toml");
+    let before = serde_json::json!({ "version": 1, "tracked": { REPO: [{ "branch": "kept" }] } });
+    std::fs::write(&state, before.to_string()).unwrap();
+    // Settings that would let every push through if the scenarios used them
+    std::fs::write(&config, "allow_local_remotes = true\n").unwrap();
+
+    cmd()
+        .current_dir(home.path())
+        .env("PUSH_GUARD_STATE_FILE", &state)
+        .env("PUSH_GUARD_CONFIG_FILE", &config)
+        .env("PUSH_GUARD_ALLOW", "/:*")
+        .arg("selftest")
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("PASS  branch creation is tracked from a hook payload"))
+        .stdout(predicates::str::contains("PASS  push to the default branch is blocked"))
+        .stdout(predicates::str::contains("PASS  push is allowed once authorized"))
+        .stdout(predicates::str::contains("5 of 5 scenarios passed"))
+        .stdout(predicates::str::contains("FAIL").not());
+
+    assert_eq!(std::fs::read_to_string(&state).unwrap(), before.to_string());
+    let left: Vec<_> = std::fs::read_dir(home.path()).unwrap().collect();
+    assert_eq!(left.len(), 2);
+}